@@ -0,0 +1,122 @@
+//! Parsing of `!<arch>` archives, ie. the `.lib` files in the output, and the
+//! little endian fields of the COFF and PE structures within them and the
+//! images they import from.
+
+use anyhow::{Context as _, Error};
+
+/// The size of the header preceding each archive member
+pub(crate) const MEMBER_HEADER_LEN: usize = 60;
+
+/// The name of the linker members, padded with spaces
+pub(crate) const LINKER_MEMBER_NAME: &[u8] = b"/               ";
+
+pub(crate) fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+pub(crate) fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+pub(crate) struct Member<'lib> {
+    pub(crate) header: &'lib [u8],
+    pub(crate) data: std::borrow::Cow<'lib, [u8]>,
+    /// The offset of the member's header in the original archive
+    pub(crate) offset: usize,
+}
+
+impl Member<'_> {
+    pub(crate) fn name(&self) -> &[u8] {
+        &self.header[..16]
+    }
+
+    /// Short import members, and the anonymous objects used for eg. bigobj,
+    /// start with a null machine followed by `0xffff`
+    pub(crate) fn is_anonymous(&self) -> bool {
+        self.data.starts_with(&[0, 0, 0xff, 0xff])
+    }
+
+    /// Short import members are anonymous objects with a version of 0
+    pub(crate) fn is_short_import(&self) -> bool {
+        self.is_anonymous() && u16_at(&self.data, 4) == Some(0)
+    }
+
+    /// The name of the DLL a short import member imports from, which follows
+    /// the null terminated name of the symbol after the 20 byte header
+    pub(crate) fn import_dll(&self) -> Option<&[u8]> {
+        if !self.is_short_import() {
+            return None;
+        }
+
+        let names = self.data.get(20..)?;
+        let dll = &names[memchr::memchr(0, names)? + 1..];
+        Some(&dll[..memchr::memchr(0, dll)?])
+    }
+}
+
+/// Iterates over the members of an archive, see [`members`]
+pub(crate) struct Members<'lib> {
+    contents: &'lib [u8],
+    offset: usize,
+}
+
+impl<'lib> Members<'lib> {
+    fn read(&mut self) -> Result<Member<'lib>, Error> {
+        let offset = self.offset;
+        let header = self
+            .contents
+            .get(offset..offset + MEMBER_HEADER_LEN)
+            .context("truncated member header")?;
+        anyhow::ensure!(&header[58..] == b"`\n", "invalid member header");
+
+        let size: usize = std::str::from_utf8(&header[48..58])
+            .ok()
+            .and_then(|size| size.trim().parse().ok())
+            .context("invalid member size")?;
+        let start = offset + MEMBER_HEADER_LEN;
+        let data = self
+            .contents
+            .get(start..start + size)
+            .context("truncated member")?;
+
+        // Members are aligned to 2 bytes
+        self.offset = start + size + (size & 1);
+
+        Ok(Member {
+            header,
+            data: data.into(),
+            offset,
+        })
+    }
+}
+
+impl<'lib> Iterator for Members<'lib> {
+    type Item = Result<Member<'lib>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.contents.len() {
+            return None;
+        }
+
+        let member = self.read();
+
+        // Nothing after a malformed member can be trusted
+        if member.is_err() {
+            self.offset = self.contents.len();
+        }
+
+        Some(member)
+    }
+}
+
+/// Iterates over the members of an archive, in order
+pub(crate) fn members(contents: &[u8]) -> Result<Members<'_>, Error> {
+    anyhow::ensure!(contents.starts_with(b"!<arch>\n"), "not an archive");
+
+    Ok(Members {
+        contents,
+        offset: 8,
+    })
+}
@@ -0,0 +1,465 @@
+//! A high level API for embedding xwin in other tools, eg. cargo wrappers,
+//! that selects the payloads the same way the CLI does, so that they don't
+//! need to shell out to it
+//!
+//! ```no_run
+//! let plan = xwin::Builder::new()
+//!     .arches(&[xwin::Arch::X86_64, xwin::Arch::Aarch64])
+//!     .cache_dir(".xwin-cache")
+//!     .plan()?;
+//!
+//! for payload in plan.payloads() {
+//!     println!("{} {}", payload.filename, payload.version);
+//! }
+//!
+//! plan.execute(xwin::Ops::Splat(xwin::SplatConfig::new("/xwin")))?;
+//! # Ok::<_, anyhow::Error>(())
+//! ```
+
+use crate::{
+    manifest, progress::ProgressFactory, Arch, Ctx, Ops, PathBuf, Payload, ProgressSink, Stats,
+    Variant,
+};
+use anyhow::{Context as _, Error};
+use std::sync::Arc;
+
+/// Where the VS manifest is retrieved from
+enum ManifestSource {
+    Remote {
+        version: String,
+        channel: String,
+    },
+    Path(PathBuf),
+    /// An offline layout created by `vs_installer.exe --layout`
+    VsLayout(PathBuf),
+}
+
+/// Configures the selection of payloads, and where they are retrieved from,
+/// before creating a [`Plan`]
+pub struct Builder {
+    arches: Vec<Arch>,
+    variants: Vec<Variant>,
+    components: Vec<crate::Component>,
+    cache_dir: Option<PathBuf>,
+    manifest: ManifestSource,
+    custom_manifest: Option<PathBuf>,
+    extras: Vec<crate::extra::ExtraDownload>,
+    sdk_version: Option<crate::version::VersionReq>,
+    crt_version: Option<crate::version::VersionReq>,
+    sdk_tools: crate::SdkTools,
+    pins: manifest::Pins,
+    progress: Option<Box<dyn ProgressFactory>>,
+    cancel: crate::cancel::CancellationToken,
+    client: Option<Box<dyn crate::http::HttpClient>>,
+    upstream: Option<String>,
+    proxy: Option<String>,
+    no_network: bool,
+    download_connections: u32,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self {
+            arches: vec![Arch::X86_64],
+            variants: vec![Variant::Desktop],
+            components: crate::Component::ALL.to_vec(),
+            cache_dir: None,
+            manifest: ManifestSource::Remote {
+                version: "16".to_owned(),
+                channel: "release".to_owned(),
+            },
+            custom_manifest: None,
+            extras: Vec::new(),
+            sdk_version: None,
+            crt_version: None,
+            sdk_tools: Default::default(),
+            pins: Default::default(),
+            progress: None,
+            cancel: Default::default(),
+            client: None,
+            upstream: None,
+            proxy: None,
+            no_network: false,
+            download_connections: 1,
+        }
+    }
+}
+
+impl Builder {
+    /// Creates a builder with the same defaults as the CLI, ie. the `x86_64`
+    /// architecture and `desktop` variant from the VS 16 release manifest
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The architectures to retrieve the CRT and SDK libraries for
+    pub fn arches(mut self, arches: &[Arch]) -> Self {
+        self.arches = arches.to_vec();
+        self
+    }
+
+    /// The variants of the CRT to retrieve
+    pub fn variants(mut self, variants: &[Variant]) -> Self {
+        self.variants = variants.to_vec();
+        self
+    }
+
+    /// The components to retrieve, by default both the CRT and the SDK. The
+    /// payloads of the other component are never downloaded.
+    pub fn components(mut self, components: &[crate::Component]) -> Self {
+        self.components = components.to_vec();
+        self
+    }
+
+    /// The directory that downloaded and unpacked payloads are cached in.
+    /// If not specified, a temporary directory is used, which is deleted once
+    /// the [`Plan`] is dropped.
+    pub fn cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+
+    /// The version of the VS manifest to retrieve
+    pub fn manifest_version(mut self, version: impl Into<String>) -> Self {
+        let version = version.into();
+        self.manifest = match self.manifest {
+            ManifestSource::Remote { channel, .. } => ManifestSource::Remote { version, channel },
+            ManifestSource::Path(_) | ManifestSource::VsLayout(_) => ManifestSource::Remote {
+                version,
+                channel: "release".to_owned(),
+            },
+        };
+        self
+    }
+
+    /// The release channel of the VS manifest to retrieve
+    pub fn channel(mut self, channel: impl Into<String>) -> Self {
+        let channel = channel.into();
+        self.manifest = match self.manifest {
+            ManifestSource::Remote { version, .. } => ManifestSource::Remote { version, channel },
+            ManifestSource::Path(_) | ManifestSource::VsLayout(_) => ManifestSource::Remote {
+                version: "16".to_owned(),
+                channel,
+            },
+        };
+        self
+    }
+
+    /// Uses a VS manifest from disk rather than downloading it
+    pub fn manifest_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.manifest = ManifestSource::Path(path.into());
+        self
+    }
+
+    /// Uses an offline layout created by `vs_installer.exe --layout` as the
+    /// source of the manifests, and of any payloads that are present in it,
+    /// see [`crate::vs_layout`]
+    pub fn vs_layout(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.manifest = ManifestSource::VsLayout(dir.into());
+        self
+    }
+
+    /// Includes the Windows SDK tools, eg. `rc.exe` and `mt.exe`, for the
+    /// selected architectures, see [`crate::prune_sdk_bin`]
+    pub fn include_sdk_bin(mut self, include: bool) -> Self {
+        self.sdk_tools.build = include;
+        self
+    }
+
+    /// Includes `signtool.exe`, and the libraries it loads, for the selected
+    /// architectures, see [`crate::prune_sdk_bin`]
+    pub fn include_signing_tools(mut self, include: bool) -> Self {
+        self.sdk_tools.signing = include;
+        self
+    }
+
+    /// Pins the manifest chain to known checksums, and optionally a different
+    /// channel manifest url, so that the manifests that are retrieved, or
+    /// read from disk, are rejected if they have changed
+    pub fn manifest_pins(mut self, pins: manifest::Pins) -> Self {
+        self.pins = pins;
+        self
+    }
+
+    /// Adds the packages from a [custom manifest](crate::custom) to the
+    /// selection
+    pub fn custom_manifest(mut self, path: impl Into<PathBuf>) -> Self {
+        self.custom_manifest = Some(path.into());
+        self
+    }
+
+    /// Selects the newest Windows SDK that matches the requirement, rather
+    /// than the latest one in the manifest
+    pub fn sdk_version(mut self, req: crate::version::VersionReq) -> Self {
+        self.sdk_version = Some(req);
+        self
+    }
+
+    /// Selects the newest MSVC CRT that matches the requirement, rather than
+    /// the latest one in the manifest
+    pub fn crt_version(mut self, req: crate::version::VersionReq) -> Self {
+        self.crt_version = Some(req);
+        self
+    }
+
+    /// Adds [extra downloads](crate::extra) to the selection, which are
+    /// splatted to their target directory in the output
+    pub fn extras(mut self, extras: Vec<crate::extra::ExtraDownload>) -> Self {
+        self.extras = extras;
+        self
+    }
+
+    /// Creates the sinks that progress is reported to, one for the manifest
+    /// and one for each payload, named after its filename. By default progress
+    /// is discarded.
+    pub fn progress(mut self, factory: impl ProgressFactory + 'static) -> Self {
+        self.progress = Some(Box::new(factory));
+        self
+    }
+
+    /// The token used to cancel the retrieval of the manifest, and the
+    /// execution of the [`Plan`]
+    pub fn cancel_token(mut self, token: crate::cancel::CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Replaces the built-in HTTP client used to download the manifest and
+    /// payloads, see [`crate::http`]
+    pub fn http_client(mut self, client: impl crate::http::HttpClient + 'static) -> Self {
+        self.client = Some(Box::new(client));
+        self
+    }
+
+    /// The url of an `xwin serve` instance that payloads are retrieved from,
+    /// before falling back to the Microsoft CDN
+    pub fn upstream(mut self, url: impl Into<String>) -> Self {
+        self.upstream = Some(url.into());
+        self
+    }
+
+    /// Sends every request through the proxy, eg. `http://proxy:3128` or
+    /// `socks5h://localhost:1080`, rather than the one from the environment,
+    /// see [`Ctx::set_proxy`]. Ignored if the client is replaced with
+    /// [`Self::http_client`].
+    pub fn proxy(mut self, url: impl Into<String>) -> Self {
+        self.proxy = Some(url.into());
+        self
+    }
+
+    /// Fails, rather than retrieving anything, if the manifest or a payload
+    /// isn't already in the cache directory, see [`Ctx::no_network`]
+    pub fn no_network(mut self, no_network: bool) -> Self {
+        self.no_network = no_network;
+        self
+    }
+
+    /// The number of connections used to retrieve each large download, see
+    /// [`Ctx::download_connections`]
+    pub fn download_connections(mut self, connections: u32) -> Self {
+        self.download_connections = connections;
+        self
+    }
+
+    /// Validates the selection and creates the context used to retrieve
+    /// everything
+    fn prepare(&mut self) -> Result<(u32, u32, Ctx, std::sync::Arc<dyn ProgressSink>), Error> {
+        anyhow::ensure!(
+            !self.arches.is_empty(),
+            "at least one architecture must be selected"
+        );
+        anyhow::ensure!(
+            !self.variants.is_empty(),
+            "at least one variant must be selected"
+        );
+
+        let arches = Arch::mask(&self.arches);
+        let variants = Variant::mask(&self.variants);
+
+        let mut ctx = match &self.cache_dir {
+            Some(dir) => Ctx::with_dir(dir.clone(), crate::progress::Hidden)?,
+            None => Ctx::with_temp(crate::progress::Hidden)?,
+        };
+
+        if let Some(factory) = self.progress.take() {
+            ctx.progress = factory;
+        }
+        ctx.cancel = self.cancel.clone();
+        ctx.upstream = self.upstream.clone();
+        ctx.no_network = self.no_network;
+        ctx.download_connections = self.download_connections;
+
+        if let ManifestSource::VsLayout(dir) = &self.manifest {
+            ctx.vs_layout = Some(crate::vs_layout::VsLayout::open(dir)?);
+        }
+
+        if let Some(proxy) = &self.proxy {
+            ctx.set_proxy(proxy)?;
+        }
+
+        if let Some(client) = self.client.take() {
+            ctx.client = client;
+        }
+
+        let progress = ctx.progress.create("Manifest");
+
+        Ok((arches, variants, ctx, progress))
+    }
+
+    /// Resolves the payloads from the package manifest
+    fn finish(
+        self,
+        ctx: Ctx,
+        mut pkg_manifest: manifest::PackageManifest,
+        arches: u32,
+        variants: u32,
+    ) -> Result<Plan, Error> {
+        if let Some(req) = &self.sdk_version {
+            crate::version::select_sdk(&mut pkg_manifest, req)?;
+        }
+        if let Some(req) = &self.crt_version {
+            crate::version::select_crt(&mut pkg_manifest, req)?;
+        }
+
+        let mut payloads = crate::prune_pkg_list(&pkg_manifest, arches, variants)?;
+
+        if self.sdk_tools != crate::SdkTools::default() {
+            payloads.extend(crate::prune_sdk_bin(&pkg_manifest, arches, self.sdk_tools)?);
+        }
+
+        if let Some(path) = &self.custom_manifest {
+            let custom = crate::custom::CustomManifest::load(path)?;
+            payloads.extend(custom.apply(&mut pkg_manifest, arches, variants)?);
+        }
+
+        let extras = crate::extra::payloads(&self.extras, &payloads, arches)?;
+        payloads.extend(extras);
+        crate::prune_components(&mut payloads, &self.components);
+
+        Ok(Plan {
+            ctx: Arc::new(ctx),
+            packages: pkg_manifest.packages,
+            payloads,
+            arches,
+            variants,
+        })
+    }
+
+    /// Retrieves the manifest and resolves the payloads that are needed for
+    /// the selected architectures and variants
+    pub fn plan(mut self) -> Result<Plan, crate::Error> {
+        let (arches, variants, ctx, progress) = self.prepare()?;
+
+        let vs_manifest = match &self.manifest {
+            ManifestSource::Remote { version, channel } => {
+                manifest::get_manifest(&ctx, version, channel, &self.pins, progress.as_ref())?
+            }
+            ManifestSource::Path(path) => manifest::read_manifest(path, &self.pins)?,
+            ManifestSource::VsLayout(_) => return self.finish_vs_layout(ctx, arches, variants),
+        };
+
+        let pkg_manifest =
+            manifest::get_package_manifest(&ctx, &vs_manifest, &self.pins, progress.as_ref())?;
+        Ok(self.finish(ctx, pkg_manifest, arches, variants)?)
+    }
+
+    /// Resolves the payloads from the package manifest of the offline layout
+    fn finish_vs_layout(self, ctx: Ctx, arches: u32, variants: u32) -> Result<Plan, crate::Error> {
+        let pkg_manifest = ctx
+            .vs_layout
+            .as_ref()
+            .context("the VS layout was not opened")?
+            .package_manifest(&self.pins)?;
+        Ok(self.finish(ctx, pkg_manifest, arches, variants)?)
+    }
+
+    /// The async equivalent of [`Self::plan`]
+    #[cfg(feature = "async")]
+    pub async fn plan_async(mut self) -> Result<Plan, crate::Error> {
+        let (arches, variants, ctx, progress) = self.prepare()?;
+
+        let vs_manifest = match &self.manifest {
+            ManifestSource::Remote { version, channel } => {
+                manifest::get_manifest_async(&ctx, version, channel, &self.pins, progress.as_ref())
+                    .await?
+            }
+            ManifestSource::Path(path) => manifest::read_manifest(path, &self.pins)?,
+            ManifestSource::VsLayout(_) => return self.finish_vs_layout(ctx, arches, variants),
+        };
+
+        let pkg_manifest =
+            manifest::get_package_manifest_async(&ctx, &vs_manifest, &self.pins, progress.as_ref())
+                .await?;
+        Ok(self.finish(ctx, pkg_manifest, arches, variants)?)
+    }
+}
+
+/// The payloads resolved by a [`Builder`], ready to be executed
+pub struct Plan {
+    ctx: Arc<Ctx>,
+    packages: std::collections::BTreeMap<String, manifest::ManifestItem>,
+    payloads: Vec<Payload>,
+    arches: u32,
+    variants: u32,
+}
+
+impl Plan {
+    /// The payloads that will be retrieved
+    pub fn payloads(&self) -> &[Payload] {
+        &self.payloads
+    }
+
+    /// The packages in the manifest, including any from a custom manifest
+    pub fn packages(&self) -> &std::collections::BTreeMap<String, manifest::ManifestItem> {
+        &self.packages
+    }
+
+    /// The directory that payloads are cached in
+    pub fn cache_dir(&self) -> &crate::Path {
+        &self.ctx.work_dir
+    }
+
+    fn work_items(&mut self) -> Vec<crate::WorkItem> {
+        let ctx = &self.ctx;
+        self.payloads
+            .drain(..)
+            .map(|payload| crate::WorkItem {
+                progress: ctx.progress.create(payload.filename.as_str()),
+                payload: Arc::new(payload),
+            })
+            .collect()
+    }
+
+    /// Downloads, unpacks, and/or splats every payload, depending on the
+    /// operation
+    pub fn execute(mut self, ops: Ops) -> Result<Stats, crate::Error> {
+        let work_items = self.work_items();
+        self.ctx
+            .execute(self.packages, work_items, self.arches, self.variants, ops)
+    }
+
+    /// The async equivalent of [`Self::execute`], see [`Ctx::execute_async`]
+    #[cfg(feature = "async")]
+    pub async fn execute_async(mut self, ops: Ops) -> Result<Stats, crate::Error> {
+        let work_items = self.work_items();
+        self.ctx
+            .execute_async(self.packages, work_items, self.arches, self.variants, ops)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn requires_selection() {
+        let err = Builder::new().arches(&[]).plan().err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "at least one architecture must be selected"
+        );
+
+        assert!(Builder::new().variants(&[]).plan().is_err());
+    }
+}
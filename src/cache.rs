@@ -0,0 +1,270 @@
+//! Inspection of the cache directory, so that operators managing shared cache
+//! volumes can see what is in it, and what is still being used, before
+//! deciding what to prune
+
+use crate::{Path, PathBuf};
+use anyhow::{Context as _, Error};
+use std::{collections::BTreeMap, time::SystemTime};
+
+/// What an entry in the cache contains
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EntryKind {
+    /// A downloaded VS manifest
+    Manifest,
+    /// A downloaded payload, along with the CAB files for an MSI
+    Download,
+    /// An unpacked payload
+    Unpacked,
+    /// The default splat output
+    Splat,
+    /// Anything else in the cache directory
+    Other,
+}
+
+impl EntryKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Manifest => "manifest",
+            Self::Download => "download",
+            Self::Unpacked => "unpacked",
+            Self::Splat => "splat",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// A single entry in the cache, eg. a downloaded payload
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// The path of the entry, relative to the cache directory
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    /// The version of the CRT or SDK the entry belongs to, if it can be
+    /// determined from its name
+    pub version: Option<String>,
+    /// The total size of the files in the entry
+    pub size: u64,
+    /// The number of files in the entry
+    pub files: u64,
+    /// The most recent access time of any file in the entry. Note that this
+    /// is not updated on file systems mounted with `noatime`.
+    pub accessed: Option<SystemTime>,
+}
+
+/// The totals for the entries that belong to a single version
+#[derive(Debug, Clone, Default)]
+pub struct VersionStats {
+    pub entries: u64,
+    pub size: u64,
+    pub accessed: Option<SystemTime>,
+}
+
+/// The contents of a cache directory
+#[derive(Debug, Clone)]
+pub struct CacheStats {
+    pub root: PathBuf,
+    /// The entries, sorted by kind and then path
+    pub entries: Vec<Entry>,
+}
+
+impl CacheStats {
+    /// The total size of every entry
+    pub fn size(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+
+    /// The totals for each version, entries whose version is unknown are
+    /// grouped under `None`
+    pub fn by_version(&self) -> BTreeMap<Option<&str>, VersionStats> {
+        let mut versions = BTreeMap::<_, VersionStats>::new();
+
+        for entry in &self.entries {
+            let vs = versions.entry(entry.version.as_deref()).or_default();
+            vs.entries += 1;
+            vs.size += entry.size;
+            vs.accessed = vs.accessed.max(entry.accessed);
+        }
+
+        versions
+    }
+}
+
+/// Determines the version of the CRT or SDK a payload belongs to from its
+/// name, eg. `Microsoft.VC.14.29.16.10.CRT.Headers.base.vsix` or
+/// `Win10SDK_10.0.19041_headers.msi`
+fn version_of(name: &str) -> Option<String> {
+    if let Some(rest) = name.strip_prefix("Microsoft.VC.") {
+        let version = &rest[..rest.find(".CRT")?];
+        Some(format!("CRT {}", version))
+    } else if let Some(rest) = name.strip_prefix("Win10SDK_") {
+        let version = rest.split('_').next()?;
+        Some(format!("SDK {}", version))
+    } else {
+        None
+    }
+}
+
+/// Sums the size, file count, and latest access time of every file in the
+/// tree rooted at `path`
+fn measure(path: &Path) -> Result<(u64, u64, Option<SystemTime>), Error> {
+    let md = std::fs::symlink_metadata(path).with_context(|| format!("unable to read {}", path))?;
+
+    if !md.is_dir() {
+        return Ok((md.len(), 1, md.accessed().ok()));
+    }
+
+    let mut totals = (0, 0, None);
+    for entry in std::fs::read_dir(path).with_context(|| format!("unable to read {}", path))? {
+        let entry = entry.with_context(|| format!("unable to read entry from {}", path))?;
+        let child = PathBuf::from_path_buf(entry.path())
+            .map_err(|pb| anyhow::anyhow!("path {} is not a valid utf-8 path", pb.display()))?;
+
+        let (size, files, accessed) = measure(&child)?;
+        totals.0 += size;
+        totals.1 += files;
+        totals.2 = totals.2.max(accessed);
+    }
+
+    Ok(totals)
+}
+
+/// Reads the entries in the cache directory
+pub fn stats(root: &Path) -> Result<CacheStats, Error> {
+    anyhow::ensure!(root.is_dir(), "cache directory {} doesn't exist", root);
+
+    let mut entries = Vec::new();
+
+    let entry = |path: PathBuf, kind: EntryKind| -> Result<Entry, Error> {
+        let (size, files, accessed) = measure(&path)?;
+
+        Ok(Entry {
+            version: version_of(path.file_name().unwrap_or_default()),
+            path: path.strip_prefix(root)?.to_owned(),
+            kind,
+            size,
+            files,
+            accessed,
+        })
+    };
+
+    let children = |dir: &Path| -> Result<Vec<PathBuf>, Error> {
+        let mut children = Vec::new();
+        for entry in std::fs::read_dir(dir).with_context(|| format!("unable to read {}", dir))? {
+            let entry = entry.with_context(|| format!("unable to read entry from {}", dir))?;
+            children.push(PathBuf::from_path_buf(entry.path()).map_err(|pb| {
+                anyhow::anyhow!("path {} is not a valid utf-8 path", pb.display())
+            })?);
+        }
+        children.sort();
+        Ok(children)
+    };
+
+    for path in children(root)? {
+        match path.file_name() {
+            Some("dl") if path.is_dir() => {
+                for path in children(&path)? {
+                    let name = path.file_name().unwrap_or_default();
+
+                    // The CAB files for an MSI are stored in a directory named
+                    // after it, and are counted as part of its entry
+                    if path.is_dir() && path.with_file_name(format!("{}.msi", name)).is_file() {
+                        continue;
+                    }
+
+                    if name.starts_with("manifest_") || name.starts_with("pkg_manifest_") {
+                        entries.push(entry(path, EntryKind::Manifest)?);
+                        continue;
+                    }
+
+                    let mut download = entry(path.clone(), EntryKind::Download)?;
+
+                    let cabs = path.with_extension("");
+                    if path.extension() == Some("msi") && cabs.is_dir() {
+                        let (size, files, accessed) = measure(&cabs)?;
+                        download.size += size;
+                        download.files += files;
+                        download.accessed = download.accessed.max(accessed);
+                    }
+
+                    entries.push(download);
+                }
+            }
+            Some("unpack") if path.is_dir() => {
+                for path in children(&path)? {
+                    entries.push(entry(path, EntryKind::Unpacked)?);
+                }
+            }
+            Some("splat") => entries.push(entry(path, EntryKind::Splat)?),
+            _ => entries.push(entry(path, EntryKind::Other)?),
+        }
+    }
+
+    entries.sort_by(|a, b| a.kind.cmp(&b.kind).then_with(|| a.path.cmp(&b.path)));
+
+    Ok(CacheStats {
+        root: root.to_owned(),
+        entries,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn groups_entries() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        let write = |rel: &str, contents: &str| {
+            let path = root.join(rel);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+
+        write("dl/manifest_16.json", "{}");
+        write("dl/Win10SDK_10.0.19041_headers.msi", "msi");
+        write("dl/Win10SDK_10.0.19041_headers/a.cab", "cab");
+        write("dl/Microsoft.VC.14.29.16.10.CRT.Headers.base.vsix", "vsix");
+        write("unpack/Win10SDK_10.0.19041_headers.msi/include/a.h", "a");
+        write("unpack/Win10SDK_10.0.19041_headers.msi/.unpack", "");
+
+        let stats = stats(&root).unwrap();
+        let kinds: Vec<_> = stats
+            .entries
+            .iter()
+            .map(|entry| (entry.kind, entry.path.as_str(), entry.size, entry.files))
+            .collect();
+
+        assert_eq!(
+            kinds,
+            vec![
+                (EntryKind::Manifest, "dl/manifest_16.json", 2, 1),
+                (
+                    EntryKind::Download,
+                    "dl/Microsoft.VC.14.29.16.10.CRT.Headers.base.vsix",
+                    4,
+                    1
+                ),
+                (
+                    EntryKind::Download,
+                    "dl/Win10SDK_10.0.19041_headers.msi",
+                    6,
+                    2
+                ),
+                (
+                    EntryKind::Unpacked,
+                    "unpack/Win10SDK_10.0.19041_headers.msi",
+                    1,
+                    2
+                ),
+            ]
+        );
+        assert_eq!(stats.size(), 13);
+
+        let versions = stats.by_version();
+        assert_eq!(versions[&Some("SDK 10.0.19041")].entries, 2);
+        assert_eq!(versions[&Some("CRT 14.29.16.10")].size, 4);
+        assert_eq!(versions[&None].entries, 1);
+    }
+}
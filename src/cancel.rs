@@ -0,0 +1,54 @@
+//! Cooperative cancellation, so that a run can be aborted cleanly part way
+//! through, eg. from another thread or a signal handler, rather than tearing
+//! down the process.
+//!
+//! Cancellation is checked between each download chunk, each unpacked file,
+//! and each splatted file. Downloads are only written to the cache once they
+//! have completed and been verified, and unpacks are only marked as complete
+//! once every file has been decompressed, so a cancelled run leaves the cache
+//! in a state that a subsequent run can resume from.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// A cheaply cloneable token that is shared by everything that needs to
+/// observe a cancellation request
+#[derive(Clone, Default, Debug)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests cancellation of all work observing this token
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Returns a [`Cancelled`](crate::error::Cancelled) error if cancellation
+    /// has been requested
+    #[inline]
+    pub fn check(&self) -> Result<(), crate::error::Cancelled> {
+        if self.is_cancelled() {
+            Err(crate::error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Wraps an existing flag, eg. one registered with
+/// `signal_hook::flag::register` so that a signal cancels the run
+impl From<Arc<AtomicBool>> for CancellationToken {
+    fn from(flag: Arc<AtomicBool>) -> Self {
+        Self(flag)
+    }
+}
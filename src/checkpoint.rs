@@ -0,0 +1,208 @@
+//! Tracks the phases each payload has completed, so that a run that was
+//! interrupted, eg. by a crash or a runner being preempted, can resume without
+//! redoing the payloads that were already completed.
+//!
+//! Downloads and unpacks are already skipped if their results are present in
+//! the cache, so the checkpoint is mainly used to resume a splat into an output
+//! that was only partially written, rather than deleting it and starting over.
+
+use crate::{splat::SdkHeaders, util::Sha256, Path, PathBuf};
+use anyhow::Error;
+use std::collections::BTreeMap;
+
+/// The last phase a payload completed
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Completed {
+    Downloaded,
+    Unpacked,
+    Splatted,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct Entry {
+    #[serde(serialize_with = "crate::util::serialize_sha256")]
+    sha256: Sha256,
+    completed: Completed,
+    /// The SDK headers that were splatted, which are needed to finalize the
+    /// splat once every payload has been splatted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) sdk_headers: Option<SdkHeaders>,
+    /// The files that were splatted, relative to the output
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) files: Vec<PathBuf>,
+}
+
+/// Identifies a splat, payloads are only considered splatted if they were
+/// splatted to the same output with the same options
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug)]
+pub(crate) struct SplatKey {
+    pub(crate) output: PathBuf,
+    pub(crate) arches: u32,
+    pub(crate) variants: u32,
+    pub(crate) include_debug_libs: bool,
+    pub(crate) include_debug_symbols: bool,
+    #[serde(default)]
+    pub(crate) include_debug_libs_for: Vec<String>,
+    #[serde(default)]
+    pub(crate) include_debug_symbols_for: Vec<String>,
+    pub(crate) enable_symlinks: bool,
+    pub(crate) preserve_ms_arch_notation: bool,
+    pub(crate) copy: bool,
+}
+
+impl SplatKey {
+    pub(crate) fn new(config: &crate::SplatConfig, arches: u32, variants: u32) -> Self {
+        Self {
+            output: config.output.clone(),
+            arches,
+            variants,
+            include_debug_libs: config.include_debug_libs,
+            include_debug_symbols: config.include_debug_symbols,
+            include_debug_libs_for: config
+                .include_debug_libs_for
+                .iter()
+                .map(|target| target.to_string())
+                .collect(),
+            include_debug_symbols_for: config
+                .include_debug_symbols_for
+                .iter()
+                .map(|target| target.to_string())
+                .collect(),
+            enable_symlinks: config.enable_symlinks,
+            preserve_ms_arch_notation: config.preserve_ms_arch_notation,
+            copy: config.copy,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Default)]
+pub(crate) struct Checkpoint {
+    version: u32,
+    /// The splat that is in progress, if any
+    #[serde(default)]
+    splat: Option<SplatKey>,
+    /// The payloads, keyed by filename
+    #[serde(default)]
+    payloads: BTreeMap<String, Entry>,
+}
+
+impl Checkpoint {
+    pub(crate) const NAME: &'static str = "checkpoint.json";
+    const VERSION: u32 = 1;
+
+    /// Loads the checkpoint, a missing or unreadable checkpoint just means
+    /// nothing is resumed
+    pub(crate) fn load(path: &Path) -> Self {
+        let checkpoint = std::fs::read(path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice::<Self>(&contents).ok());
+
+        match checkpoint {
+            Some(checkpoint) if checkpoint.version == Self::VERSION => checkpoint,
+            _ => Self::default(),
+        }
+    }
+
+    /// Writes the checkpoint via a temporary file, so that the process being
+    /// killed part way through doesn't leave a truncated checkpoint
+    pub(crate) fn save(&mut self, path: &Path) -> Result<(), Error> {
+        self.version = Self::VERSION;
+
+        let partial = PathBuf::from(format!("{}.partial", path));
+        std::fs::write(&partial, serde_json::to_vec(&self)?)?;
+        std::fs::rename(&partial, path)?;
+        Ok(())
+    }
+
+    /// Starts a splat, returning true if it is the same splat as the one that
+    /// was in progress, in which case it is resumed. Otherwise, every payload
+    /// that was splatted as part of the previous splat is reset.
+    pub(crate) fn start_splat(&mut self, key: SplatKey) -> bool {
+        if self.splat.as_ref() == Some(&key) {
+            return true;
+        }
+
+        self.reset_splatted();
+        self.splat = Some(key);
+        false
+    }
+
+    /// Marks the splat as finished, so that a subsequent splat starts from
+    /// scratch rather than being resumed
+    pub(crate) fn finish_splat(&mut self) {
+        self.reset_splatted();
+        self.splat = None;
+    }
+
+    fn reset_splatted(&mut self) {
+        for entry in self.payloads.values_mut() {
+            // Splatting moves the files out of the unpack directory, unless
+            // `--copy` was used, so the download is all that is known to remain
+            if entry.completed == Completed::Splatted {
+                entry.completed = Completed::Downloaded;
+                entry.sdk_headers = None;
+                entry.files = Vec::new();
+            }
+        }
+    }
+
+    /// Records that the payload completed a phase
+    pub(crate) fn complete(
+        &mut self,
+        payload: &crate::Payload,
+        completed: Completed,
+        sdk_headers: Option<SdkHeaders>,
+        files: Vec<PathBuf>,
+    ) {
+        self.payloads.insert(
+            payload.filename.to_string(),
+            Entry {
+                sha256: payload.sha256.clone(),
+                completed,
+                sdk_headers,
+                files,
+            },
+        );
+    }
+
+    /// Returns the payload's entry if it was already splatted
+    pub(crate) fn splatted(&self, payload: &crate::Payload) -> Option<&Entry> {
+        self.payloads
+            .get(payload.filename.as_str())
+            .filter(|entry| {
+                entry.sha256 == payload.sha256 && entry.completed == Completed::Splatted
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resumes_splat() {
+        let payload = crate::Payload {
+            sha256: Sha256([1; 32]),
+            ..crate::Payload::test("sdk.msi", crate::PayloadKind::SdkHeaders)
+        };
+
+        let config = crate::SplatConfig::new("/xwin");
+
+        let mut checkpoint = Checkpoint::default();
+        assert!(!checkpoint.start_splat(SplatKey::new(&config, 1, 1)));
+        checkpoint.complete(&payload, Completed::Splatted, None, vec!["sdk/a.h".into()]);
+
+        let td = tempfile::TempDir::new().unwrap();
+        let path = PathBuf::from_path_buf(td.path().join(Checkpoint::NAME)).unwrap();
+        checkpoint.save(&path).unwrap();
+
+        let mut checkpoint = Checkpoint::load(&path);
+        assert!(checkpoint.start_splat(SplatKey::new(&config, 1, 1)));
+        assert_eq!(checkpoint.splatted(&payload).unwrap().files, ["sdk/a.h"]);
+
+        // A splat with different options starts over
+        assert!(!checkpoint.start_splat(SplatKey::new(&config, 2, 1)));
+        assert!(checkpoint.splatted(&payload).is_none());
+    }
+}
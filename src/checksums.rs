@@ -0,0 +1,131 @@
+//! Generation of checksum files covering every regular file in an output, in
+//! the format read by `sha256sum -c` and `b3sum -c`, so that the integrity of
+//! a splat can be verified with standard tools
+
+use crate::{Path, PathBuf};
+use anyhow::{Context as _, Error};
+use std::fmt::Write;
+
+/// The hash algorithms that checksum files can be written for
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+    Blake3,
+}
+
+impl Algorithm {
+    /// The conventional name of the checksums file for the algorithm
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Self::Sha256 => "SHA256SUMS",
+            Self::Blake3 => "B3SUMS",
+        }
+    }
+
+    fn hash(self, contents: &[u8]) -> String {
+        match self {
+            Self::Sha256 => crate::util::Sha256::digest(contents).to_string(),
+            Self::Blake3 => blake3::hash(contents).to_hex().to_string(),
+        }
+    }
+}
+
+impl std::str::FromStr for Algorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "sha256" => Self::Sha256,
+            "blake3" => Self::Blake3,
+            o => anyhow::bail!("unknown checksum algorithm '{}'", o),
+        })
+    }
+}
+
+/// Writes a checksums file for each algorithm to the root of the directory,
+/// covering every regular file in it, other than the checksum files
+/// themselves. Paths are relative to the root, so the files are verified by
+/// running eg. `sha256sum -c SHA256SUMS` in it.
+pub fn write(root: &Path, algorithms: &[Algorithm]) -> Result<(), Error> {
+    use rayon::prelude::*;
+
+    if algorithms.is_empty() {
+        return Ok(());
+    }
+
+    let paths: Vec<PathBuf> = crate::util::walk_files(root)?
+        .into_iter()
+        .filter(|path| {
+            path.parent() != Some(root)
+                || !algorithms
+                    .iter()
+                    .any(|alg| path.file_name() == Some(alg.file_name()))
+        })
+        .collect();
+
+    let mut hashes = paths
+        .into_par_iter()
+        .map(|path| -> Result<(String, Vec<String>), Error> {
+            let contents =
+                std::fs::read(&path).with_context(|| format!("unable to read {}", path))?;
+            let rel_path = path.strip_prefix(root)?.as_str().replace('\\', "/");
+
+            Ok((
+                rel_path,
+                algorithms.iter().map(|alg| alg.hash(&contents)).collect(),
+            ))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    hashes.sort();
+
+    for (i, alg) in algorithms.iter().enumerate() {
+        let mut sums = String::new();
+        for (rel_path, hashes) in &hashes {
+            writeln!(sums, "{}  {}", hashes[i], rel_path).unwrap();
+        }
+
+        let path = root.join(alg.file_name());
+        std::fs::write(&path, sums).with_context(|| format!("unable to write {}", path))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn writes_sums() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        std::fs::create_dir_all(root.join("crt/include")).unwrap();
+        std::fs::write(root.join("crt/include/a.h"), "a").unwrap();
+        std::fs::write(root.join("b.lib"), "b").unwrap();
+
+        write(&root, &[Algorithm::Sha256, Algorithm::Blake3]).unwrap();
+        // Writing again doesn't include the previous checksum files
+        write(&root, &[Algorithm::Sha256, Algorithm::Blake3]).unwrap();
+
+        let sums = std::fs::read_to_string(root.join("SHA256SUMS")).unwrap();
+        assert_eq!(
+            sums,
+            format!(
+                "{}  b.lib\n{}  crt/include/a.h\n",
+                crate::util::Sha256::digest(b"b"),
+                crate::util::Sha256::digest(b"a"),
+            )
+        );
+
+        // The same format as is read by `xwin diff`
+        let listing = crate::diff::Listing::from_sums(&root.join("SHA256SUMS")).unwrap();
+        assert_eq!(listing.files.len(), 2);
+
+        let b3 = std::fs::read_to_string(root.join("B3SUMS")).unwrap();
+        assert!(b3.ends_with(&format!(
+            "{}  crt/include/a.h\n",
+            blake3::hash(b"a").to_hex()
+        )));
+    }
+}
@@ -0,0 +1,365 @@
+//! Support for specifying options via an `xwin.toml` file, so that projects
+//! don't need to carry around (and keep in sync) long command lines
+
+use crate::{Arch, Path, PathBuf, Selector, Variant};
+use anyhow::{Context as _, Error};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// The name of the config file that is searched for
+pub const CONFIG_NAME: &str = "xwin.toml";
+
+/// Options that can be specified in a config file. Every field is optional,
+/// and any option that is also specified on the command line takes precedence
+/// over the value in the config file.
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Doesn't display the prompt to accept the license
+    pub accept_license: Option<bool>,
+    /// The log level for messages
+    pub log_level: Option<String>,
+    /// A file to write log messages to in addition to the console
+    pub log_file: Option<PathBuf>,
+    /// The log level for messages written to the log file
+    pub log_file_level: Option<String>,
+    /// Output log messages as json
+    pub json: Option<bool>,
+    /// Doesn't display progress bars or phase summaries
+    pub quiet: Option<bool>,
+    /// Displays phase summaries instead of animated progress bars
+    pub no_progress: Option<bool>,
+    /// Draws progress with only ASCII characters
+    pub ascii: Option<bool>,
+    /// Prints a report of the time taken by each phase for each payload
+    pub timings: Option<bool>,
+    /// Writes a JSON report of the run, eg. the outcome of each payload
+    pub report: Option<PathBuf>,
+    /// Use a temporary directory for all files used for creating the archive
+    pub temp: Option<bool>,
+    /// The number of threads used to process payloads in parallel
+    pub jobs: Option<usize>,
+    /// The cache directory used to persist downloaded items to disk
+    pub cache_dir: Option<PathBuf>,
+    /// Restores and saves the downloads or splat with the GitHub Actions cache
+    pub gha_cache: Option<String>,
+    /// The url of an `xwin serve` instance to retrieve payloads from
+    pub upstream: Option<String>,
+    /// The HTTP or SOCKS5 proxy to send every request through
+    pub proxy: Option<String>,
+    /// Fails if anything would be retrieved from the network
+    pub no_network: Option<bool>,
+    /// The number of connections used to retrieve each large download
+    pub download_connections: Option<u32>,
+    /// A VS manifest to use from a file, rather than downloading it
+    pub manifest: Option<PathBuf>,
+    /// An offline layout created by `vs_installer.exe --layout` to use as the
+    /// source of the manifests and payloads
+    pub vs_layout: Option<PathBuf>,
+    /// A custom manifest of additional packages to include
+    pub custom_manifest: Option<PathBuf>,
+    /// Includes the Windows SDK tools, eg. `rc.exe` and `mt.exe`
+    pub include_sdk_bin: Option<bool>,
+    /// Includes `signtool.exe` and the libraries it loads
+    pub include_signing_tools: Option<bool>,
+    /// The version of the Windows SDK to use, which can be a pattern or a
+    /// list of comparisons the same as `--sdk-version`
+    pub sdk_version: Option<String>,
+    /// The version of the MSVC CRT to use, which can be a pattern or a list
+    /// of comparisons the same as `--crt-version`
+    pub crt_version: Option<String>,
+    /// The version of the VS manifest to retrieve
+    pub manifest_version: Option<String>,
+    /// The product channel to use
+    pub channel: Option<String>,
+    /// The url to retrieve the top-level channel manifest from
+    pub manifest_url: Option<String>,
+    /// The expected sha256 of the top-level channel manifest
+    pub manifest_sha256: Option<crate::util::Sha256>,
+    /// The expected sha256 of the package manifest
+    pub package_manifest_sha256: Option<crate::util::Sha256>,
+    /// The architectures to include, which can use `all` and `!` negations
+    /// the same as `--arch`
+    #[serde(default, deserialize_with = "from_str_list")]
+    pub arch: Option<Vec<Selector<Arch>>>,
+    /// The variants to include, which can use `all` and `!` negations the
+    /// same as `--variant`
+    #[serde(default, deserialize_with = "from_str_list")]
+    pub variant: Option<Vec<Selector<Variant>>>,
+    /// The components to include, ie. `crt` and/or `sdk`
+    #[serde(default, deserialize_with = "from_str_list")]
+    pub components: Option<Vec<crate::Component>>,
+    /// Additional downloads that are splatted to a subdirectory of the output
+    #[serde(default, rename = "extra")]
+    pub extras: Vec<crate::extra::ExtraDownload>,
+    /// Options for the `splat` command
+    #[serde(default)]
+    pub splat: SplatOptions,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SplatOptions {
+    pub include_debug_libs: Option<bool>,
+    pub include_debug_symbols: Option<bool>,
+    #[serde(default, deserialize_with = "from_str_list")]
+    pub include_debug_libs_for: Option<Vec<crate::DebugTarget>>,
+    #[serde(default, deserialize_with = "from_str_list")]
+    pub include_debug_symbols_for: Option<Vec<crate::DebugTarget>>,
+    pub disable_symlinks: Option<bool>,
+    pub preserve_ms_arch_notation: Option<bool>,
+    pub output: Option<PathBuf>,
+    pub copy: Option<bool>,
+    pub env_files: Option<bool>,
+    pub lib_symlinks: Option<Vec<String>>,
+    pub strict_includes: Option<bool>,
+    pub verify: Option<bool>,
+    pub check: Option<bool>,
+    pub thin_import_libs: Option<bool>,
+    pub symbol_server_layout: Option<bool>,
+    pub split_output_per_arch: Option<bool>,
+    #[serde(default, deserialize_with = "from_str_list")]
+    pub checksums: Option<Vec<crate::checksums::Algorithm>>,
+    pub skipped_report: Option<PathBuf>,
+    pub provenance: Option<PathBuf>,
+    pub patches: Option<PathBuf>,
+    /// A command, and its arguments, that is run after a successful splat
+    pub post_splat: Option<Vec<String>>,
+    /// Additional outputs that are splatted in the same run, sharing the
+    /// downloaded and unpacked payloads, keyed by name
+    #[serde(default)]
+    pub profiles: BTreeMap<String, SplatProfile>,
+}
+
+/// A named splat output, any options that aren't specified are taken from the
+/// top level splat options
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SplatProfile {
+    pub output: PathBuf,
+    #[serde(default, deserialize_with = "from_str_list")]
+    pub arch: Option<Vec<Selector<Arch>>>,
+    #[serde(default, deserialize_with = "from_str_list")]
+    pub variant: Option<Vec<Selector<Variant>>>,
+    pub include_debug_libs: Option<bool>,
+    pub include_debug_symbols: Option<bool>,
+    #[serde(default, deserialize_with = "from_str_list")]
+    pub include_debug_libs_for: Option<Vec<crate::DebugTarget>>,
+    #[serde(default, deserialize_with = "from_str_list")]
+    pub include_debug_symbols_for: Option<Vec<crate::DebugTarget>>,
+    pub disable_symlinks: Option<bool>,
+    pub preserve_ms_arch_notation: Option<bool>,
+    pub env_files: Option<bool>,
+    pub lib_symlinks: Option<Vec<String>>,
+    pub strict_includes: Option<bool>,
+    pub verify: Option<bool>,
+    pub check: Option<bool>,
+    pub thin_import_libs: Option<bool>,
+    pub symbol_server_layout: Option<bool>,
+    pub split_output_per_arch: Option<bool>,
+    #[serde(default, deserialize_with = "from_str_list")]
+    pub checksums: Option<Vec<crate::checksums::Algorithm>>,
+    pub skipped_report: Option<PathBuf>,
+    pub provenance: Option<PathBuf>,
+    pub patches: Option<PathBuf>,
+    pub post_splat: Option<Vec<String>>,
+}
+
+fn from_str_list<'de, D, T>(deserializer: D) -> Result<Option<Vec<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: std::str::FromStr<Err = Error>,
+{
+    let strs = Vec::<String>::deserialize(deserializer)?;
+
+    strs.iter()
+        .map(|s| s.parse().map_err(serde::de::Error::custom))
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+}
+
+/// Resolves the program of a command relative to the config file if it is a
+/// relative path, eg. `./fixup.sh`, rather than a program in `PATH`
+fn resolve_command(root: &Path, cmd: &mut Option<Vec<String>>) {
+    if let Some(program) = cmd.as_mut().and_then(|cmd| cmd.first_mut()) {
+        let path = Path::new(program.as_str());
+        if path.is_relative() && program.contains(['/', '\\']) {
+            *program = root.join(path).into_string();
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config at the specified path. Any relative paths in the config
+    /// are resolved relative to the directory the config file is located in.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config '{}'", path))?;
+
+        let mut config: Self = toml::from_str(&contents)
+            .with_context(|| format!("failed to deserialize config '{}'", path))?;
+
+        if let Some(root) = path.parent() {
+            let resolve = |pb: &mut Option<PathBuf>| {
+                if let Some(pb) = pb {
+                    if pb.is_relative() {
+                        *pb = root.join(pb.as_path());
+                    }
+                }
+            };
+
+            resolve(&mut config.log_file);
+            resolve(&mut config.report);
+            resolve(&mut config.cache_dir);
+            resolve(&mut config.manifest);
+            resolve(&mut config.vs_layout);
+            resolve(&mut config.custom_manifest);
+            resolve(&mut config.splat.output);
+            resolve(&mut config.splat.skipped_report);
+            resolve(&mut config.splat.provenance);
+            resolve(&mut config.splat.patches);
+            resolve_command(root, &mut config.splat.post_splat);
+
+            for profile in config.splat.profiles.values_mut() {
+                if profile.output.is_relative() {
+                    profile.output = root.join(&profile.output);
+                }
+                resolve(&mut profile.skipped_report);
+                resolve(&mut profile.provenance);
+                resolve(&mut profile.patches);
+                resolve_command(root, &mut profile.post_splat);
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Searches for an `xwin.toml` in the specified directory and each of its
+    /// parents, loading the first one that is found, if any
+    pub fn discover(dir: &Path) -> Result<Option<(PathBuf, Self)>, Error> {
+        for parent in dir.ancestors() {
+            let path = parent.join(CONFIG_NAME);
+
+            if path.exists() {
+                let config = Self::load(&path)?;
+                return Ok(Some((path, config)));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses() {
+        let config: Config = toml::from_str(
+            r#"
+accept_license = true
+arch = ["x86_64", "aarch64"]
+variant = ["desktop"]
+
+[splat]
+output = "splat"
+copy = true
+lib_symlinks = ["WS2_32"]
+checksums = ["sha256", "blake3"]
+include_debug_libs_for = ["crt:x86_64"]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.accept_license, Some(true));
+        assert_eq!(
+            config.arch,
+            Some(vec![
+                Selector::Include(Arch::X86_64),
+                Selector::Include(Arch::Aarch64)
+            ])
+        );
+        assert_eq!(
+            config.variant,
+            Some(vec![Selector::Include(Variant::Desktop)])
+        );
+        assert_eq!(config.splat.output.as_deref(), Some(Path::new("splat")));
+        assert!(config.splat.disable_symlinks.is_none());
+        assert_eq!(config.splat.lib_symlinks, Some(vec!["WS2_32".to_owned()]));
+        assert_eq!(
+            config.splat.checksums,
+            Some(vec![
+                crate::checksums::Algorithm::Sha256,
+                crate::checksums::Algorithm::Blake3
+            ])
+        );
+        assert_eq!(
+            config.splat.include_debug_libs_for,
+            Some(vec![crate::DebugTarget {
+                component: crate::DebugComponent::Crt,
+                arch: Some(Arch::X86_64),
+            }])
+        );
+        assert!(config.splat.include_debug_symbols_for.is_none());
+        assert!(config.splat.profiles.is_empty());
+
+        let config: Config = toml::from_str(
+            r#"
+[splat.profiles.x64]
+output = "x64"
+arch = ["x86_64"]
+
+[splat.profiles.arm64]
+output = "arm64"
+arch = ["aarch64"]
+disable_symlinks = true
+post_splat = ["./fixup.sh", "{output}"]
+"#,
+        )
+        .unwrap();
+
+        let names: Vec<_> = config.splat.profiles.keys().map(String::as_str).collect();
+        assert_eq!(names, ["arm64", "x64"]);
+        assert_eq!(
+            config.splat.profiles["arm64"].arch,
+            Some(vec![Selector::Include(Arch::Aarch64)])
+        );
+        assert_eq!(config.splat.profiles["arm64"].disable_symlinks, Some(true));
+        assert!(config.splat.profiles["x64"].disable_symlinks.is_none());
+        assert_eq!(
+            config.splat.profiles["arm64"].post_splat.as_deref(),
+            Some(&["./fixup.sh".to_owned(), "{output}".to_owned()][..])
+        );
+        assert!(toml::from_str::<Config>("[splat.profiles.x64]\narch = [\"x86_64\"]").is_err());
+
+        // Rust target triples are accepted as well
+        let config: Config =
+            toml::from_str("arch = [\"i686-pc-windows-msvc\", \"aarch64-pc-windows-msvc\"]")
+                .unwrap();
+        assert_eq!(
+            config.arch,
+            Some(vec![
+                Selector::Include(Arch::X86),
+                Selector::Include(Arch::Aarch64)
+            ])
+        );
+
+        let config: Config = toml::from_str("arch = [\"all\", \"!x86\"]").unwrap();
+        assert_eq!(
+            crate::select(&config.arch.unwrap(), Arch::ALL),
+            vec![Arch::X86_64, Arch::Aarch, Arch::Aarch64]
+        );
+
+        let config: Config = toml::from_str("variant = [\"!spectre\", \"!onecore\"]").unwrap();
+        assert_eq!(
+            crate::select(&config.variant.unwrap(), Variant::ALL),
+            vec![Variant::Desktop, Variant::Store]
+        );
+
+        assert!(toml::from_str::<Config>("arch = [\"mips\"]").is_err());
+        assert!(toml::from_str::<Config>("arch = [\"x86_64-pc-windows-gnu\"]").is_err());
+        assert!(toml::from_str::<Config>("[splat]\ninclude_debug_libs_for = [\"sdk\"]").is_err());
+        assert!(toml::from_str::<Config>("not_an_option = 1").is_err());
+    }
+}
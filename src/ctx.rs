@@ -1,5 +1,7 @@
 use crate::{
-    util::{ProgressTarget, Sha256},
+    error,
+    progress::{ProgressFactory, ProgressSink},
+    util::Sha256,
     Path, PathBuf, WorkItem,
 };
 use anyhow::{Context as _, Error};
@@ -14,17 +16,180 @@ pub enum Unpack {
     Needed(PathBuf),
 }
 
+/// Totals for a single phase of an [`Ctx::execute`]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct PhaseStats {
+    /// The number of payloads that completed the phase
+    pub payloads: u32,
+    /// The number of files produced by the phase
+    pub files: u32,
+    /// The number of bytes produced by the phase
+    pub bytes: u64,
+}
+
+impl std::ops::AddAssign for PhaseStats {
+    fn add_assign(&mut self, o: Self) {
+        self.payloads += o.payloads;
+        self.files += o.files;
+        self.bytes += o.bytes;
+    }
+}
+
+/// The time taken and bytes produced by a single phase for a single payload
+#[derive(Debug, Clone, Copy)]
+pub struct PhaseTiming {
+    pub duration: std::time::Duration,
+    pub bytes: u64,
+}
+
+/// The timings of each phase a payload went through
+#[derive(Debug, Clone)]
+pub struct PayloadTimings {
+    /// The filename of the payload
+    pub payload: PathBuf,
+    pub download: Option<PhaseTiming>,
+    pub unpack: Option<PhaseTiming>,
+    pub splat: Option<PhaseTiming>,
+}
+
+impl PayloadTimings {
+    /// The total time taken by all of the phases of the payload
+    pub fn total(&self) -> std::time::Duration {
+        [self.download, self.unpack, self.splat]
+            .iter()
+            .flatten()
+            .map(|pt| pt.duration)
+            .sum()
+    }
+}
+
+/// Totals for each phase of an [`Ctx::execute`]
+#[derive(Default, Debug, Clone)]
+pub struct Stats {
+    pub download: PhaseStats,
+    pub unpack: PhaseStats,
+    pub splat: PhaseStats,
+    /// The timings for each payload, in the order they completed
+    pub timings: Vec<PayloadTimings>,
+    /// The time taken to add symlinks to the splat output once all payloads
+    /// have been splatted
+    pub finalize: Option<std::time::Duration>,
+    /// The number of symlinks that were added to the splat output, including
+    /// the ones for the lib names specified by the user
+    pub symlinks: u64,
+    /// The symlinks whose names were already taken by another file or
+    /// symlink when splatting
+    pub symlink_collisions: Vec<crate::SymlinkCollision>,
+    /// The files that were deliberately not splatted, eg. debug libs
+    pub skipped: Vec<crate::SkippedFile>,
+    /// The files each payload was splatted to, relative to the output, keyed
+    /// by the filename of the payload
+    pub splatted_files: std::collections::BTreeMap<String, Vec<PathBuf>>,
+    /// The import libs that were thinned, see
+    /// [`SplatConfig::thin_import_libs`](crate::SplatConfig::thin_import_libs)
+    pub thinned: Option<crate::thin::Thinned>,
+    /// The patches that were applied to the output, see
+    /// [`SplatConfig::patches`](crate::SplatConfig::patches)
+    pub patches: Vec<crate::patch::AppliedPatch>,
+}
+
 pub struct Ctx {
     pub work_dir: PathBuf,
     pub tempdir: Option<tempfile::TempDir>,
-    pub client: reqwest::blocking::Client,
-    pub draw_target: ProgressTarget,
+    /// The client used to download manifests and payloads, see [`crate::http`]
+    pub client: Box<dyn crate::http::HttpClient>,
+    /// The client used by the async API
+    #[cfg(feature = "async")]
+    pub async_client: reqwest::Client,
+    /// Creates the progress sinks for work that isn't tied to a single
+    /// payload, eg. scanning includes when splatting
+    pub progress: Box<dyn ProgressFactory>,
+    /// Cancels the download, unpack, and splat of every payload when
+    /// triggered, see [`crate::cancel`]
+    pub cancel: crate::cancel::CancellationToken,
+    /// The url of an `xwin serve` instance, or an `xwin mirror`, that payloads
+    /// are retrieved from before falling back to their original url. A
+    /// `file://` url is read directly from disk.
+    pub upstream: Option<String>,
+    /// Fails any download that isn't already in the cache with
+    /// [`ErrorKind::NetworkDisabled`](crate::error::ErrorKind::NetworkDisabled),
+    /// rather than retrieving it, so that hermetic builds can prove that the
+    /// network was never accessed
+    pub no_network: bool,
+    /// The number of connections used to retrieve each download of at least
+    /// 16 MiB, eg. the larger CAB files, with each connection retrieving a
+    /// range of it in parallel, as a single connection to the CDN can be the
+    /// bottleneck on some networks. Defaults to 1, ie. every download is
+    /// retrieved over a single connection. Only used by the blocking API.
+    pub download_connections: u32,
+    /// An offline layout created by `vs_installer.exe --layout` that payloads
+    /// are read from, rather than retrieving them, if they are present in it.
+    /// Payloads read from the layout aren't copied to the download cache.
+    pub vs_layout: Option<crate::vs_layout::VsLayout>,
+    /// Counts the downloads that were served from the cache and the bytes
+    /// that were actually retrieved
+    pub transfers: Transfers,
+    /// Records what happened to each payload as it happens, so that it is
+    /// known even if the run fails
+    pub outcomes: Outcomes,
+}
+
+/// The totals for every download made through a [`Ctx`]
+#[derive(Default, Debug)]
+pub struct Transfers {
+    cache_hits: std::sync::atomic::AtomicU64,
+    downloaded: std::sync::atomic::AtomicU64,
+}
+
+impl Transfers {
+    /// The number of downloads that were already in the download cache
+    pub fn cache_hits(&self) -> u64 {
+        self.cache_hits.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// The number of bytes retrieved from the network or an upstream, ie.
+    /// excluding anything read from the cache or a VS layout
+    pub fn downloaded(&self) -> u64 {
+        self.downloaded.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    fn hit(&self) {
+        self.cache_hits
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn retrieved(&self, bytes: usize) {
+        self.downloaded
+            .fetch_add(bytes as u64, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+/// The outcome of each payload executed through a [`Ctx`], which is updated as
+/// each phase completes, or fails, rather than once every payload is finished
+#[derive(Default, Debug)]
+pub struct Outcomes {
+    outcomes: parking_lot::Mutex<std::collections::BTreeMap<String, crate::report::Outcome>>,
+}
+
+impl Outcomes {
+    /// The outcome of the payload, if it has completed a phase or failed
+    pub fn get(&self, payload: &str) -> Option<crate::report::Outcome> {
+        self.outcomes.lock().get(payload).copied()
+    }
+
+    pub(crate) fn record(&self, payload: &str, outcome: crate::report::Outcome) {
+        self.outcomes.lock().insert(payload.to_owned(), outcome);
+    }
 }
 
+/// The minimum size of a download that is retrieved over several connections,
+/// see [`Ctx::download_connections`]
+const CHUNKED_MIN_SIZE: u64 = 16 * 1024 * 1024;
+
 impl Ctx {
-    pub fn with_temp(dt: ProgressTarget) -> Result<Self, Error> {
+    pub fn with_temp(progress: impl ProgressFactory + 'static) -> Result<Self, crate::Error> {
         let td = tempfile::TempDir::new()?;
-        let client = reqwest::blocking::ClientBuilder::new().build()?;
+        let client = Box::new(reqwest::blocking::ClientBuilder::new().build()?);
 
         Ok(Self {
             work_dir: PathBuf::from_path_buf(td.path().to_owned()).map_err(|pb| {
@@ -32,12 +197,24 @@ impl Ctx {
             })?,
             tempdir: Some(td),
             client,
-            draw_target: dt,
+            #[cfg(feature = "async")]
+            async_client: reqwest::Client::new(),
+            progress: Box::new(progress),
+            cancel: Default::default(),
+            upstream: None,
+            no_network: false,
+            download_connections: 1,
+            vs_layout: None,
+            transfers: Default::default(),
+            outcomes: Default::default(),
         })
     }
 
-    pub fn with_dir(mut work_dir: PathBuf, dt: ProgressTarget) -> Result<Self, Error> {
-        let client = reqwest::blocking::ClientBuilder::new().build()?;
+    pub fn with_dir(
+        mut work_dir: PathBuf,
+        progress: impl ProgressFactory + 'static,
+    ) -> Result<Self, crate::Error> {
+        let client = Box::new(reqwest::blocking::ClientBuilder::new().build()?);
 
         work_dir.push("dl");
         std::fs::create_dir_all(&work_dir)?;
@@ -50,74 +227,130 @@ impl Ctx {
             work_dir,
             tempdir: None,
             client,
-            draw_target: dt,
+            #[cfg(feature = "async")]
+            async_client: reqwest::Client::new(),
+            progress: Box::new(progress),
+            cancel: Default::default(),
+            upstream: None,
+            no_network: false,
+            download_connections: 1,
+            vs_layout: None,
+            transfers: Default::default(),
+            outcomes: Default::default(),
         })
     }
 
+    /// Sends every request through the proxy, eg. `http://proxy:3128` or
+    /// `socks5h://localhost:1080`, rather than the one from the environment,
+    /// replacing the current client
+    pub fn set_proxy(&mut self, url: &str) -> Result<(), crate::Error> {
+        self.client = Box::new(crate::http::client(Some(url))?);
+
+        #[cfg(feature = "async")]
+        {
+            self.async_client = reqwest::Client::builder()
+                .proxy(crate::http::parse_proxy(url)?)
+                .build()?;
+        }
+
+        Ok(())
+    }
+
     pub fn get_and_validate<P>(
         &self,
         url: impl AsRef<str>,
         path: &P,
         checksum: Option<Sha256>,
-        progress: indicatif::ProgressBar,
-    ) -> Result<bytes::Bytes, Error>
+        progress: &dyn ProgressSink,
+    ) -> Result<bytes::Bytes, crate::Error>
     where
         P: AsRef<Path> + std::fmt::Debug,
     {
-        let short_path = path.as_ref();
-        let cache_path = {
-            let mut cp = self.work_dir.clone();
-            cp.push("dl");
-            cp.push(short_path);
-            cp
+        let cache_path = self.cache_path(path.as_ref());
+
+        if let Some(contents) = Self::cached(&cache_path, checksum.as_ref(), progress) {
+            self.transfers.hit();
+            return Ok(contents);
+        }
+
+        if let Some(contents) = self.vs_layout_payload(url.as_ref(), checksum.as_ref(), progress) {
+            return Ok(contents);
+        }
+
+        if let Some(contents) = self.local_upstream(path.as_ref(), checksum.as_ref(), progress) {
+            self.transfers.retrieved(contents.len());
+            return Ok(Self::store(&cache_path, checksum, contents)?);
+        }
+
+        self.cancel.check()?;
+
+        let url = url.as_ref();
+        self.check_network(url)?;
+        let request_failed = || crate::error::RequestFailed {
+            url: url.to_owned(),
         };
 
-        if cache_path.exists() {
-            tracing::debug!("verifying existing cached dl file");
-
-            match std::fs::read(&cache_path) {
-                Ok(contents) => match &checksum {
-                    Some(expected) => {
-                        let chksum = Sha256::digest(&contents);
-
-                        if chksum != *expected {
-                            tracing::warn!(
-                                "checksum mismatch, expected {} != actual {}",
-                                expected,
-                                chksum
-                            );
-                        } else {
-                            progress.inc_length(contents.len() as u64);
-                            progress.inc(contents.len() as u64);
-                            return Ok(contents.into());
-                        }
-                    }
-                    None => {
-                        progress.inc_length(contents.len() as u64);
-                        progress.inc(contents.len() as u64);
-                        return Ok(contents.into());
-                    }
-                },
-                Err(e) => {
-                    tracing::warn!(error = %e, "failed to read cached file");
+        let upstream = self
+            .upstream_url(path.as_ref(), checksum.as_ref())
+            .and_then(|upstream| match self.client.get(&upstream) {
+                Ok(res) => Some((upstream, res)),
+                Err(err) => {
+                    tracing::debug!("failed to retrieve {}: {:#}", upstream, err);
+                    None
                 }
-            }
-        }
+            });
 
-        let mut res = self.client.get(url.as_ref()).send()?.error_for_status()?;
+        let (source, mut res) = match upstream {
+            Some((upstream, res)) => (upstream, res),
+            None => (
+                url.to_owned(),
+                self.client.get(url).with_context(request_failed)?,
+            ),
+        };
 
-        let content_length = res.content_length().unwrap_or_default();
+        let content_length = res.content_length.unwrap_or_default();
         progress.inc_length(content_length);
 
-        let body = bytes::BytesMut::with_capacity(content_length as usize);
+        let body = if self.download_connections > 1 && content_length >= CHUNKED_MIN_SIZE {
+            self.read_chunked(&source, &mut res, content_length, progress)
+        } else {
+            self.read_body(&mut res.body, content_length, progress)
+                .map(bytes::BytesMut::freeze)
+        };
 
-        struct ProgressCopy {
-            progress: indicatif::ProgressBar,
+        // The client may wrap the write error, so check the token directly to
+        // report the cancellation rather than a network failure
+        let body = match body {
+            Ok(body) => body,
+            Err(err) => {
+                self.cancel.check()?;
+                return Err(anyhow::Error::from(err).context(request_failed()).into());
+            }
+        };
+
+        self.transfers.retrieved(body.len());
+        Ok(Self::store(&cache_path, checksum, body)?)
+    }
+
+    /// Reads the body of a response, reporting progress as it goes
+    fn read_body(
+        &self,
+        body: &mut dyn std::io::Read,
+        capacity: u64,
+        progress: &dyn ProgressSink,
+    ) -> std::io::Result<bytes::BytesMut> {
+        struct ProgressCopy<'p> {
+            progress: &'p dyn ProgressSink,
+            cancel: &'p crate::cancel::CancellationToken,
             inner: bytes::buf::Writer<bytes::BytesMut>,
         }
 
-        impl std::io::Write for ProgressCopy {
+        impl std::io::Write for ProgressCopy<'_> {
             fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                if self.cancel.is_cancelled() {
+                    return Err(std::io::Error::other(crate::error::Cancelled));
+                }
+
                 self.progress.inc(buf.len() as u64);
                 self.inner.write(buf)
             }
@@ -131,32 +364,325 @@ impl Ctx {
 
         let mut pc = ProgressCopy {
             progress,
-            inner: body.writer(),
+            cancel: &self.cancel,
+            inner: bytes::BytesMut::with_capacity(capacity as usize).writer(),
         };
 
-        res.copy_to(&mut pc)?;
+        std::io::copy(body, &mut pc)?;
+        Ok(pc.inner.into_inner())
+    }
+
+    /// Reads a large download over several connections. The response to the
+    /// initial request is used for the first segment, while the remaining
+    /// segments are retrieved with range requests in parallel, falling back
+    /// to reading the rest of the initial response if any of them fail, eg.
+    /// if the server doesn't support ranges. The progress of the segments is
+    /// only reported once they have all been retrieved, so that a fallback
+    /// doesn't count their bytes twice. The reassembled download is verified
+    /// against its checksum the same as any other.
+    fn read_chunked(
+        &self,
+        url: &str,
+        res: &mut crate::http::Response,
+        len: u64,
+        progress: &dyn ProgressSink,
+    ) -> std::io::Result<bytes::Bytes> {
+        let connections = u64::from(self.download_connections);
+        let segment_len = len.div_ceil(connections);
+
+        let ranges: Vec<_> = (1..connections)
+            .map(|i| i * segment_len..((i + 1) * segment_len).min(len))
+            .filter(|range| !range.is_empty())
+            .collect();
+
+        tracing::debug!(
+            "retrieving {} in {} segments of {} bytes",
+            url,
+            ranges.len() + 1,
+            segment_len
+        );
+
+        let (first, segments) = std::thread::scope(|scope| {
+            let segments: Vec<_> = ranges
+                .iter()
+                .map(|range| {
+                    scope.spawn(move || -> Result<bytes::BytesMut, Error> {
+                        let expected = range.end - range.start;
+                        let mut res = self.client.get_range(url, range.clone())?;
+                        let segment = self.read_body(
+                            &mut std::io::Read::take(&mut res.body, expected),
+                            expected,
+                            &crate::progress::Hidden,
+                        )?;
+
+                        anyhow::ensure!(
+                            segment.len() as u64 == expected,
+                            "expected {} bytes for range {:?}, but received {}",
+                            expected,
+                            range,
+                            segment.len()
+                        );
+                        Ok(segment)
+                    })
+                })
+                .collect();
+
+            let first = self
+                .read_body(
+                    &mut std::io::Read::take(&mut res.body, segment_len),
+                    len,
+                    progress,
+                )
+                .map_err(Error::from)
+                .and_then(|first| {
+                    anyhow::ensure!(
+                        first.len() as u64 == segment_len,
+                        "expected {} bytes for the first segment, but received {}",
+                        segment_len,
+                        first.len()
+                    );
+                    Ok(first)
+                });
 
-        let body = pc.inner.into_inner().freeze();
+            let segments = segments
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err(anyhow::anyhow!("segment download panicked")))
+                })
+                .collect::<Result<Vec<_>, _>>();
 
+            (first, segments)
+        });
+
+        let mut body = first.map_err(std::io::Error::other)?;
+        match segments {
+            Ok(segments) => {
+                for segment in segments {
+                    progress.inc(segment.len() as u64);
+                    body.extend_from_slice(&segment);
+                }
+            }
+            Err(err) => {
+                if self.cancel.is_cancelled() {
+                    return Err(std::io::Error::other(crate::error::Cancelled));
+                }
+
+                tracing::debug!(
+                    "unable to retrieve {} over multiple connections, falling back to a single one: {:#}",
+                    url,
+                    err
+                );
+                body.extend_from_slice(&self.read_body(&mut res.body, len, progress)?);
+            }
+        }
+
+        Ok(body.freeze())
+    }
+
+    /// Fails if the network is disabled, as `url` would need to be retrieved
+    fn check_network(&self, url: &str) -> Result<(), error::NetworkDisabled> {
+        if self.no_network {
+            return Err(error::NetworkDisabled {
+                url: url.to_owned(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// The url of a download on the upstream `xwin serve` instance, if there
+    /// is one. Only downloads with a checksum are retrieved from it, so that
+    /// the upstream can't substitute different contents.
+    fn upstream_url(&self, path: &Path, checksum: Option<&Sha256>) -> Option<String> {
+        checksum?;
+
+        let mut url = reqwest::Url::parse(self.upstream.as_ref()?).ok()?;
+        url.path_segments_mut()
+            .ok()?
+            .pop_if_empty()
+            .push("dl")
+            .extend(path.iter());
+        Some(url.into())
+    }
+
+    /// Reads a download from a `file://` upstream, eg. an `xwin mirror` on a
+    /// local or network filesystem, which is used even if the network is
+    /// disabled
+    fn local_upstream(
+        &self,
+        path: &Path,
+        checksum: Option<&Sha256>,
+        progress: &dyn ProgressSink,
+    ) -> Option<bytes::Bytes> {
+        let url = reqwest::Url::parse(&self.upstream_url(path, checksum)?).ok()?;
+        if url.scheme() != "file" {
+            return None;
+        }
+
+        let local = PathBuf::from_path_buf(url.to_file_path().ok()?).ok()?;
+        Self::cached(&local, checksum, progress)
+    }
+
+    /// Reads a payload from the offline layout, if there is one and the
+    /// payload is present in it
+    fn vs_layout_payload(
+        &self,
+        url: &str,
+        checksum: Option<&Sha256>,
+        progress: &dyn ProgressSink,
+    ) -> Option<bytes::Bytes> {
+        let path = self.vs_layout.as_ref()?.payload(url)?;
+        Self::cached(path, checksum, progress)
+    }
+
+    /// The path in the download cache for a download
+    fn cache_path(&self, path: &Path) -> PathBuf {
+        let mut cp = self.work_dir.clone();
+        cp.push("dl");
+        cp.push(path);
+        cp
+    }
+
+    /// Retrieves a previous download from the cache, if it is present and
+    /// matches the checksum
+    fn cached(
+        cache_path: &Path,
+        checksum: Option<&Sha256>,
+        progress: &dyn ProgressSink,
+    ) -> Option<bytes::Bytes> {
+        if !cache_path.exists() {
+            return None;
+        }
+
+        tracing::debug!("verifying existing cached dl file");
+
+        match std::fs::read(cache_path) {
+            Ok(contents) => {
+                if let Some(expected) = checksum {
+                    let chksum = Sha256::digest(&contents);
+
+                    if chksum != *expected {
+                        tracing::warn!(
+                            "checksum mismatch, expected {} != actual {}",
+                            expected,
+                            chksum
+                        );
+                        return None;
+                    }
+                }
+
+                progress.inc_length(contents.len() as u64);
+                progress.inc(contents.len() as u64);
+                Some(contents.into())
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "failed to read cached file");
+                None
+            }
+        }
+    }
+
+    /// Validates a completed download and writes it to the cache
+    fn store(
+        cache_path: &Path,
+        checksum: Option<Sha256>,
+        body: bytes::Bytes,
+    ) -> Result<bytes::Bytes, Error> {
         if let Some(expected) = checksum {
             let chksum = Sha256::digest(&body);
 
-            anyhow::ensure!(
-                chksum == expected,
-                "checksum mismatch, expected {} != actual {}",
-                expected,
-                chksum
-            );
+            if chksum != expected {
+                return Err(crate::error::ChecksumMismatch {
+                    expected,
+                    actual: chksum,
+                }
+                .into());
+            }
         }
 
         if let Some(parent) = cache_path.parent() {
             std::fs::create_dir_all(parent)?;
         }
 
-        std::fs::write(cache_path, &body)?;
+        // Write to a temporary file first so that the process being killed
+        // part way through can't leave a truncated file in the cache
+        let partial = PathBuf::from(format!("{}.partial", cache_path));
+        std::fs::write(&partial, &body)?;
+        std::fs::rename(&partial, cache_path)?;
         Ok(body)
     }
 
+    /// The async equivalent of [`Self::get_and_validate`]
+    #[cfg(feature = "async")]
+    pub async fn get_and_validate_async<P>(
+        &self,
+        url: impl AsRef<str>,
+        path: &P,
+        checksum: Option<Sha256>,
+        progress: &dyn ProgressSink,
+    ) -> Result<bytes::Bytes, crate::Error>
+    where
+        P: AsRef<Path> + std::fmt::Debug,
+    {
+        let cache_path = self.cache_path(path.as_ref());
+
+        if let Some(contents) = Self::cached(&cache_path, checksum.as_ref(), progress) {
+            self.transfers.hit();
+            return Ok(contents);
+        }
+
+        if let Some(contents) = self.vs_layout_payload(url.as_ref(), checksum.as_ref(), progress) {
+            return Ok(contents);
+        }
+
+        if let Some(contents) = self.local_upstream(path.as_ref(), checksum.as_ref(), progress) {
+            self.transfers.retrieved(contents.len());
+            return Ok(Self::store(&cache_path, checksum, contents)?);
+        }
+
+        self.cancel.check()?;
+        self.check_network(url.as_ref())?;
+
+        let mut upstream = None;
+        if let Some(upstream_url) = self.upstream_url(path.as_ref(), checksum.as_ref()) {
+            match self
+                .async_client
+                .get(&upstream_url)
+                .send()
+                .await
+                .and_then(|res| res.error_for_status())
+            {
+                Ok(res) => upstream = Some(res),
+                Err(err) => tracing::debug!("failed to retrieve {}: {:#}", upstream_url, err),
+            }
+        }
+
+        let mut res = match upstream {
+            Some(res) => res,
+            None => self
+                .async_client
+                .get(url.as_ref())
+                .send()
+                .await?
+                .error_for_status()?,
+        };
+
+        let content_length = res.content_length().unwrap_or_default();
+        progress.inc_length(content_length);
+
+        let mut body = bytes::BytesMut::with_capacity(content_length as usize);
+        while let Some(chunk) = res.chunk().await? {
+            self.cancel.check()?;
+            progress.inc(chunk.len() as u64);
+            body.extend_from_slice(&chunk);
+        }
+
+        self.transfers.retrieved(body.len());
+        Ok(Self::store(&cache_path, checksum, body.freeze())?)
+    }
+
     pub fn execute(
         self: std::sync::Arc<Self>,
         packages: std::collections::BTreeMap<String, crate::manifest::ManifestItem>,
@@ -164,66 +690,327 @@ impl Ctx {
         arches: u32,
         variants: u32,
         ops: crate::Ops,
-    ) -> Result<(), Error> {
-        use rayon::prelude::*;
+    ) -> Result<Stats, crate::Error> {
+        let packages = std::sync::Arc::new(packages);
+        let ctx = self.clone();
+
+        Ok(
+            self.execute_with(payloads, arches, variants, ops, move |wi| {
+                crate::download::download(ctx.clone(), packages.clone(), wi)
+            })?,
+        )
+    }
 
+    /// The async equivalent of [`Self::execute`]. The payloads are downloaded
+    /// concurrently on the current tokio runtime, and are then unpacked and
+    /// splatted on the rayon thread pool, so no tokio threads are blocked.
+    #[cfg(feature = "async")]
+    pub async fn execute_async(
+        self: std::sync::Arc<Self>,
+        packages: std::collections::BTreeMap<String, crate::manifest::ManifestItem>,
+        payloads: Vec<WorkItem>,
+        arches: u32,
+        variants: u32,
+        ops: crate::Ops,
+    ) -> Result<Stats, crate::Error> {
         let packages = std::sync::Arc::new(packages);
 
-        let (splat_roots, enable_symlinks) = if let crate::Ops::Splat(config) = &ops {
+        let mut downloads = tokio::task::JoinSet::new();
+        for (i, wi) in payloads.iter().enumerate() {
+            let ctx = self.clone();
+            let packages = packages.clone();
+            let wi = wi.clone();
+
+            downloads.spawn(async move {
+                crate::download::download_async(ctx, packages, &wi)
+                    .await
+                    .map(|contents| (i, contents))
+            });
+        }
+
+        let mut contents: Vec<_> = payloads.iter().map(|_| None).collect();
+        while let Some(res) = downloads.join_next().await {
+            let (i, pc) = res.map_err(Error::from)??;
+            contents[i] = Some(pc);
+        }
+
+        // Payloads are looked up by their work item, as rayon doesn't process
+        // them in order
+        let contents = parking_lot::Mutex::new(
+            payloads
+                .iter()
+                .map(|wi| wi.payload.filename.clone())
+                .zip(contents)
+                .collect::<std::collections::BTreeMap<_, _>>(),
+        );
+
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        rayon::spawn(move || {
+            let res = self.execute_with(payloads, arches, variants, ops, |wi| {
+                contents
+                    .lock()
+                    .get_mut(&wi.payload.filename)
+                    .and_then(Option::take)
+                    .with_context(|| format!("{} was not downloaded", wi.payload.filename))
+            });
+
+            let _ = tx.send(res);
+        });
+
+        Ok(rx.await.map_err(Error::from)??)
+    }
+
+    /// Executes the operation for each payload, using the provided function
+    /// to retrieve the contents of each payload
+    fn execute_with<D>(
+        self: std::sync::Arc<Self>,
+        payloads: Vec<WorkItem>,
+        arches: u32,
+        variants: u32,
+        ops: crate::Ops,
+        download: D,
+    ) -> Result<Stats, Error>
+    where
+        D: Fn(&WorkItem) -> Result<crate::download::PayloadContents, Error> + Sync,
+    {
+        use rayon::prelude::*;
+
+        use crate::checkpoint::{Checkpoint, Completed};
+
+        let checkpoint_path = self.work_dir.join(Checkpoint::NAME);
+        let mut checkpoint = Checkpoint::load(&checkpoint_path);
+
+        // Failing to record progress only means less can be resumed, so it
+        // isn't worth failing the run over
+        let save_checkpoint = |checkpoint: &mut Checkpoint| {
+            if let Err(e) = checkpoint.save(&checkpoint_path) {
+                tracing::warn!("failed to save {}: {:#}", checkpoint_path, e);
+            }
+        };
+
+        // The lock is held until every payload has been splatted and the
+        // splat has been finalized
+        let (_output_lock, splat_roots) = if let crate::Ops::Splat(config) = &ops {
+            let lock = crate::splat::lock_output(&config.output)?;
+
+            // A splat can only be resumed if what it had written is still there
+            if !config.output.join("crt").exists() || !config.output.join("sdk").exists() {
+                checkpoint.finish_splat();
+            }
+
+            let resume =
+                checkpoint.start_splat(crate::checkpoint::SplatKey::new(config, arches, variants));
+            if resume {
+                tracing::info!("resuming interrupted splat to {}", config.output);
+            }
+            save_checkpoint(&mut checkpoint);
+
             (
-                Some(crate::splat::prep_splat(self.clone(), config)?),
-                config.enable_symlinks,
+                Some(lock),
+                Some(crate::splat::prep_splat(self.clone(), config, resume)?),
             )
         } else {
-            (None, false)
+            (None, None)
+        };
+
+        let checkpoint = parking_lot::Mutex::new(checkpoint);
+        let complete = |wi: &WorkItem, completed, sdk_headers, files| {
+            use crate::report::Outcome;
+
+            self.outcomes.record(
+                wi.payload.filename.as_str(),
+                match completed {
+                    Completed::Downloaded => Outcome::Downloaded,
+                    Completed::Unpacked => Outcome::Unpacked,
+                    Completed::Splatted => Outcome::Splatted,
+                },
+            );
+
+            let mut checkpoint = checkpoint.lock();
+            checkpoint.complete(&wi.payload, completed, sdk_headers, files);
+            save_checkpoint(&mut checkpoint);
         };
 
         let mut results = Vec::new();
+        let stats = parking_lot::Mutex::new(Stats::default());
 
-        payloads
-            .into_par_iter()
-            .map(|wi| -> Result<Option<crate::splat::SdkHeaders>, Error> {
-                let payload_contents =
-                    crate::download::download(self.clone(), packages.clone(), &wi)?;
+        let execute = |wi: WorkItem| -> Result<Option<crate::splat::SdkHeaders>, Error> {
+            use std::time::Instant;
 
-                if let crate::Ops::Download = ops {
-                    return Ok(None);
-                }
+            let mut timings = PayloadTimings {
+                payload: wi.payload.filename.clone(),
+                download: None,
+                unpack: None,
+                splat: None,
+            };
+
+            self.cancel.check()?;
 
-                let ft = crate::unpack::unpack(self.clone(), &wi, payload_contents)?;
+            if let crate::Ops::Splat(_) = &ops {
+                let splatted = checkpoint
+                    .lock()
+                    .splatted(&wi.payload)
+                    .map(|entry| (entry.sdk_headers.clone(), entry.files.clone()));
 
-                if let crate::Ops::Unpack = ops {
-                    return Ok(None);
+                if let Some((sdk_headers, files)) = splatted {
+                    tracing::debug!("{} was already splatted", wi.payload.filename);
+                    self.outcomes.record(
+                        wi.payload.filename.as_str(),
+                        crate::report::Outcome::Resumed,
+                    );
+                    stats
+                        .lock()
+                        .splatted_files
+                        .insert(wi.payload.filename.to_string(), files);
+                    return Ok(sdk_headers);
                 }
+            }
 
-                let sdk_headers = if let crate::Ops::Splat(config) = &ops {
-                    crate::splat::splat(
-                        config,
-                        splat_roots.as_ref().unwrap(),
-                        &wi,
-                        ft,
-                        arches,
-                        variants,
-                    )
-                    .with_context(|| format!("failed to splat {}", wi.payload.filename))?
-                } else {
-                    None
+            let start = Instant::now();
+            let payload_contents = download(&wi)?;
+            complete(&wi, Completed::Downloaded, None, Vec::new());
+
+            {
+                let bytes = payload_contents.size();
+                timings.download = Some(PhaseTiming {
+                    duration: start.elapsed(),
+                    bytes,
+                });
+                stats.lock().download += PhaseStats {
+                    payloads: 1,
+                    files: payload_contents.num_files(),
+                    bytes,
                 };
+            }
 
-                Ok(sdk_headers)
+            if let crate::Ops::Download = ops {
+                stats.lock().timings.push(timings);
+                return Ok(None);
+            }
+
+            self.cancel.check()?;
+
+            let start = Instant::now();
+            let ft = crate::unpack::unpack(self.clone(), &wi, payload_contents)
+                .with_context(|| format!("failed to unpack {}", wi.payload.filename))
+                .context(error::UnpackFailed)?;
+            complete(&wi, Completed::Unpacked, None, Vec::new());
+
+            {
+                let (files, bytes) = ft.stats();
+                timings.unpack = Some(PhaseTiming {
+                    duration: start.elapsed(),
+                    bytes,
+                });
+                stats.lock().unpack += PhaseStats {
+                    payloads: 1,
+                    files,
+                    bytes,
+                };
+            }
+
+            if let crate::Ops::Unpack = ops {
+                stats.lock().timings.push(timings);
+                return Ok(None);
+            }
+
+            self.cancel.check()?;
+
+            let sdk_headers = if let crate::Ops::Splat(config) = &ops {
+                let start = Instant::now();
+                let (sdk_headers, splat_stats, files) = crate::splat::splat(
+                    config,
+                    splat_roots.as_ref().unwrap(),
+                    &wi,
+                    ft,
+                    arches,
+                    variants,
+                )
+                .with_context(|| format!("failed to splat {}", wi.payload.filename))
+                .context(crate::error::PartialSplat)?;
+                complete(&wi, Completed::Splatted, sdk_headers.clone(), files.clone());
+
+                timings.splat = Some(PhaseTiming {
+                    duration: start.elapsed(),
+                    bytes: splat_stats.bytes,
+                });
+
+                let mut stats = stats.lock();
+                stats.splat += splat_stats;
+                stats.timings.push(timings);
+                stats
+                    .splatted_files
+                    .insert(wi.payload.filename.to_string(), files);
+                sdk_headers
+            } else {
+                None
+            };
+
+            Ok(sdk_headers)
+        };
+
+        payloads
+            .into_par_iter()
+            .map(|wi| {
+                let filename = wi.payload.filename.clone();
+                execute(wi).map_err(|err| {
+                    // Payloads that were cancelled didn't cause the failure
+                    if crate::error::classify(&err) != Some(crate::error::ErrorKind::Cancelled) {
+                        self.outcomes
+                            .record(filename.as_str(), crate::report::Outcome::Failed);
+                    }
+                    err
+                })
             })
             .collect_into_vec(&mut results);
 
         let sdk_headers = results.into_iter().collect::<Result<Vec<_>, _>>()?;
         let sdk_headers = sdk_headers.into_iter().flatten().collect();
 
+        let mut stats = stats.into_inner();
+
         if let Some(roots) = splat_roots {
-            if enable_symlinks {
-                crate::splat::finalize_splat(&self, &roots, sdk_headers)?;
+            if roots.enable_symlinks {
+                self.cancel.check().context(crate::error::PartialSplat)?;
+
+                let start = std::time::Instant::now();
+                crate::splat::finalize_splat(&self, &roots, sdk_headers)
+                    .context(crate::error::PartialSplat)?;
+                stats.finalize = Some(start.elapsed());
+            }
+
+            if let crate::Ops::Splat(crate::SplatConfig {
+                thin_import_libs: true,
+                output,
+                ..
+            }) = &ops
+            {
+                let files: Vec<_> = stats.splatted_files.values().flatten().cloned().collect();
+                stats.thinned = Some(
+                    crate::thin::thin_libs(output, &files).context(crate::error::PartialSplat)?,
+                );
+            }
+
+            if let crate::Ops::Splat(crate::SplatConfig {
+                patches: Some(patches),
+                output,
+                ..
+            }) = &ops
+            {
+                stats.patches =
+                    crate::patch::apply_dir(patches, output).context(crate::error::PartialSplat)?;
             }
+
+            let mut checkpoint = checkpoint.into_inner();
+            checkpoint.finish_splat();
+            save_checkpoint(&mut checkpoint);
+
+            stats.symlinks = roots.symlink_count();
+            stats.skipped = roots.take_skipped();
+            stats.symlink_collisions = roots.into_collisions();
         }
 
-        Ok(())
+        Ok(stats)
     }
 
     pub(crate) fn prep_unpack(&self, payload: &crate::Payload) -> Result<Unpack, Error> {
@@ -256,12 +1043,20 @@ impl Ctx {
         // If we didn't validate the .unpack file, ensure that we clean up anything
         // that might be leftover from a failed unpack
         if unpack_dir.exists() {
-            std::fs::remove_dir_all(&unpack_dir)
-                .with_context(|| format!("unable to remove invalid unpack dir '{}'", unpack_dir))?;
+            std::fs::remove_dir_all(&unpack_dir).with_context(|| {
+                error::io(
+                    format!("unable to remove invalid unpack dir '{}'", unpack_dir),
+                    &unpack_dir,
+                )
+            })?;
         }
 
-        std::fs::create_dir_all(&unpack_dir)
-            .with_context(|| format!("unable to create unpack dir '{}'", unpack_dir))?;
+        std::fs::create_dir_all(&unpack_dir).with_context(|| {
+            error::io(
+                format!("unable to create unpack dir '{}'", unpack_dir),
+                &unpack_dir,
+            )
+        })?;
 
         Ok(Unpack::Needed(unpack_dir))
     }
@@ -276,7 +1071,7 @@ impl Ctx {
         let um = serde_json::to_vec(&um)?;
 
         std::fs::write(&unpack_dir, &um)
-            .with_context(|| format!("unable to write {}", unpack_dir))?;
+            .with_context(|| error::io(format!("unable to write {}", unpack_dir), &unpack_dir))?;
         Ok(())
     }
 }
@@ -0,0 +1,269 @@
+//! Support for user-authored manifests describing additional packages, eg.
+//! internal or third party SDK add-ons, which are downloaded, unpacked, and
+//! splatted alongside the packages from the VS manifest
+
+use crate::{manifest, util::Sha256, Arch, Path, Payload, PayloadKind, Variant};
+use anyhow::{Context as _, Error};
+use serde::Deserialize;
+
+/// A custom manifest, which can be either TOML or JSON, depending on the file
+/// extension
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CustomManifest {
+    #[serde(default, rename = "package")]
+    pub packages: Vec<CustomPackage>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CustomPackage {
+    /// The unique id of the package, which must not conflict with the id of
+    /// any package in the VS manifest
+    pub id: String,
+    pub version: String,
+    /// The kind of the payload, which determines where its contents are
+    /// splatted, and is one of `crt-headers`, `crt-libs`, `sdk-headers`,
+    /// `sdk-libs`, `sdk-store-libs`, or `ucrt`
+    pub kind: PayloadKind,
+    /// The architecture the payload targets, which is required for the
+    /// `crt-libs` and `sdk-libs` kinds. If set, the package is only included
+    /// if the architecture is selected.
+    #[serde(default, deserialize_with = "from_str_opt")]
+    pub arch: Option<Arch>,
+    /// The variant the payload targets, which is required for the `crt-libs`
+    /// kind. If set, the package is only included if the variant is selected.
+    #[serde(default, deserialize_with = "from_str_opt")]
+    pub variant: Option<Variant>,
+    /// The url of the `.msi` or `.vsix` payload
+    pub url: String,
+    pub sha256: Sha256,
+    /// The download size of the payload, only used for display purposes
+    #[serde(default)]
+    pub size: u64,
+    /// The filename of the payload, defaults to the last segment of the url
+    pub file_name: Option<String>,
+    /// The CAB files referenced by an MSI payload
+    #[serde(default)]
+    pub cabs: Vec<CustomCab>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CustomCab {
+    pub url: String,
+    pub sha256: Sha256,
+    #[serde(default)]
+    pub size: u64,
+    /// The filename of the CAB, which must match the name of the cabinet in
+    /// the MSI's `Media` table. Defaults to the last segment of the url.
+    pub file_name: Option<String>,
+}
+
+fn from_str_opt<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: std::str::FromStr<Err = Error>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| s.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+fn file_name(file_name: Option<String>, url: &str) -> Result<String, Error> {
+    match file_name {
+        Some(fname) => Ok(fname),
+        None => url
+            .rsplit('/')
+            .next()
+            .filter(|fname| !fname.is_empty())
+            .map(String::from)
+            .with_context(|| format!("unable to determine filename from url '{}'", url)),
+    }
+}
+
+impl CustomManifest {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let contents = std::fs::read(path)
+            .with_context(|| format!("failed to read custom manifest '{}'", path))?;
+
+        let manifest: Self = if path.extension() == Some("json") {
+            serde_json::from_slice(&contents)?
+        } else {
+            toml::from_slice(&contents)?
+        };
+
+        Ok(manifest)
+    }
+
+    /// Adds the packages that match the selected architectures and variants
+    /// to the package manifest, returning their payloads
+    pub fn apply(
+        self,
+        pkg_manifest: &mut manifest::PackageManifest,
+        arches: u32,
+        variants: u32,
+    ) -> Result<Vec<Payload>, Error> {
+        let mut payloads = Vec::new();
+
+        for pkg in self.packages {
+            match pkg.kind {
+                PayloadKind::CrtLibs => anyhow::ensure!(
+                    pkg.arch.is_some() && pkg.variant.is_some(),
+                    "custom package '{}' is crt-libs, but doesn't specify an arch and variant",
+                    pkg.id
+                ),
+                PayloadKind::SdkLibs => anyhow::ensure!(
+                    pkg.arch.is_some(),
+                    "custom package '{}' is sdk-libs, but doesn't specify an arch",
+                    pkg.id
+                ),
+                PayloadKind::Extra => anyhow::bail!(
+                    "custom package '{}' is extra, which is only supported for `[[extra]]` downloads in the config",
+                    pkg.id
+                ),
+                _ => {}
+            }
+
+            if matches!(pkg.arch, Some(arch) if arch as u32 & arches == 0)
+                || matches!(pkg.variant, Some(var) if var as u32 & variants == 0)
+            {
+                continue;
+            }
+
+            anyhow::ensure!(
+                !pkg_manifest.packages.contains_key(&pkg.id),
+                "custom package '{}' conflicts with an existing package",
+                pkg.id
+            );
+
+            let fname = file_name(pkg.file_name, &pkg.url)?;
+
+            let kind = if fname.ends_with(".msi") {
+                manifest::ItemKind::Msi
+            } else if fname.ends_with(".vsix") {
+                anyhow::ensure!(
+                    pkg.cabs.is_empty(),
+                    "custom package '{}' specifies CABs, but is not an MSI",
+                    pkg.id
+                );
+                manifest::ItemKind::Vsix
+            } else {
+                anyhow::bail!(
+                    "custom package '{}' has payload '{}' which is neither an .msi nor .vsix",
+                    pkg.id,
+                    fname
+                );
+            };
+
+            let mut item_payloads = vec![manifest::Payload {
+                file_name: fname.clone(),
+                sha256: pkg.sha256.clone(),
+                size: pkg.size,
+                url: pkg.url.clone(),
+            }];
+
+            for cab in pkg.cabs {
+                item_payloads.push(manifest::Payload {
+                    file_name: file_name(cab.file_name, &cab.url)?,
+                    sha256: cab.sha256,
+                    size: cab.size,
+                    url: cab.url,
+                });
+            }
+
+            // The CABs for an MSI are looked up via the manifest item that
+            // contains it, so the package needs to be part of the manifest
+            pkg_manifest.packages.insert(
+                pkg.id.clone(),
+                manifest::ManifestItem {
+                    id: pkg.id,
+                    version: pkg.version.clone(),
+                    kind,
+                    chip: None,
+                    payloads: item_payloads,
+                    dependencies: Default::default(),
+                    install_sizes: None,
+                },
+            );
+
+            payloads.push(Payload {
+                filename: fname.into(),
+                version: pkg.version,
+                sha256: pkg.sha256,
+                url: pkg.url,
+                size: pkg.size,
+                install_size: None,
+                kind: pkg.kind,
+                target_arch: pkg.arch,
+                variant: pkg.variant,
+                extra: None,
+            });
+        }
+
+        Ok(payloads)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn applies() {
+        let custom: CustomManifest = toml::from_str(
+            r#"
+[[package]]
+id = "Contoso.AddOn.Headers"
+version = "1.2.3"
+kind = "sdk-headers"
+url = "https://example.com/addon/headers.msi"
+sha256 = "0000000000000000000000000000000000000000000000000000000000000000"
+cabs = [
+    { url = "https://example.com/addon/1.cab", sha256 = "1111111111111111111111111111111111111111111111111111111111111111" },
+]
+
+[[package]]
+id = "Contoso.AddOn.Libs.arm64"
+version = "1.2.3"
+kind = "sdk-libs"
+arch = "aarch64"
+url = "https://example.com/addon/libs.vsix"
+sha256 = "2222222222222222222222222222222222222222222222222222222222222222"
+"#,
+        )
+        .unwrap();
+
+        let mut pkg_manifest = manifest::PackageManifest {
+            packages: Default::default(),
+        };
+
+        let payloads = custom
+            .apply(
+                &mut pkg_manifest,
+                Arch::X86_64 as u32,
+                Variant::Desktop as u32,
+            )
+            .unwrap();
+
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0].filename, "headers.msi");
+        assert_eq!(payloads[0].kind, PayloadKind::SdkHeaders);
+
+        let item = &pkg_manifest.packages["Contoso.AddOn.Headers"];
+        assert_eq!(item.payloads.len(), 2);
+        assert_eq!(item.payloads[1].file_name, "1.cab");
+
+        assert!(toml::from_str::<CustomManifest>(
+            r#"
+[[package]]
+id = "Contoso.AddOn.Headers"
+version = "1.2.3"
+kind = "not-a-kind"
+url = "https://example.com/addon/headers.msi"
+sha256 = "0000000000000000000000000000000000000000000000000000000000000000"
+"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Comparison of the files in two splat outputs, so that it's easy to see
+//! exactly what changed in the CRT and SDK between eg. two toolchain versions
+
+use crate::{util::Sha256, Path};
+use anyhow::{Context as _, Error};
+use std::collections::BTreeMap;
+
+/// The relative paths and checksums of every file in an output
+pub struct Listing {
+    pub files: BTreeMap<String, Sha256>,
+}
+
+impl Listing {
+    /// Creates a listing from either a directory, or a file in the format
+    /// output by `sha256sum`
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        if path.is_dir() {
+            Self::from_dir(path)
+        } else {
+            Self::from_sums(path)
+        }
+    }
+
+    /// Hashes every file in the directory. Symlinks are ignored, as the only
+    /// symlinks in a splat output are the ones generated to fix casing issues.
+    pub fn from_dir(root: &Path) -> Result<Self, Error> {
+        use rayon::prelude::*;
+
+        let paths = crate::util::walk_files(root)?;
+
+        let files = paths
+            .into_par_iter()
+            .map(|path| -> Result<(String, Sha256), Error> {
+                let contents =
+                    std::fs::read(&path).with_context(|| format!("unable to read {}", path))?;
+                let rel_path = path.strip_prefix(root)?.as_str().replace('\\', "/");
+
+                Ok((rel_path, Sha256::digest(&contents)))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { files })
+    }
+
+    /// Parses a file in the format output by `sha256sum`, ie. a checksum and a
+    /// relative path per line
+    pub fn from_sums(path: &Path) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read checksums from {}", path))?;
+
+        let files = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| -> Result<(String, Sha256), Error> {
+                let (hash, rel_path) = line
+                    .split_once(char::is_whitespace)
+                    .with_context(|| format!("invalid checksum line '{}'", line))?;
+
+                // sha256sum prefixes the path with `*` in binary mode
+                let rel_path = rel_path.trim_start();
+                let rel_path = rel_path.strip_prefix('*').unwrap_or(rel_path);
+
+                Ok((rel_path.replace('\\', "/"), hash.parse()?))
+            })
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("invalid checksums file {}", path))?;
+
+        Ok(Self { files })
+    }
+}
+
+pub enum Change<'listing> {
+    Added {
+        path: &'listing str,
+        hash: &'listing Sha256,
+    },
+    Removed {
+        path: &'listing str,
+        hash: &'listing Sha256,
+    },
+    Changed {
+        path: &'listing str,
+        old: &'listing Sha256,
+        new: &'listing Sha256,
+    },
+}
+
+/// Compares two listings, returning the changes from `old` to `new` sorted by
+/// path
+pub fn diff<'listing>(old: &'listing Listing, new: &'listing Listing) -> Vec<Change<'listing>> {
+    let mut changes = Vec::new();
+
+    for (path, old_hash) in &old.files {
+        match new.files.get(path) {
+            Some(new_hash) if new_hash != old_hash => changes.push(Change::Changed {
+                path,
+                old: old_hash,
+                new: new_hash,
+            }),
+            Some(_) => {}
+            None => changes.push(Change::Removed {
+                path,
+                hash: old_hash,
+            }),
+        }
+    }
+
+    for (path, hash) in &new.files {
+        if !old.files.contains_key(path) {
+            changes.push(Change::Added { path, hash });
+        }
+    }
+
+    changes.sort_by(|a, b| a.path().cmp(b.path()));
+    changes
+}
+
+impl<'listing> Change<'listing> {
+    pub fn path(&self) -> &'listing str {
+        match self {
+            Self::Added { path, .. } | Self::Removed { path, .. } | Self::Changed { path, .. } => {
+                path
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn diffs() {
+        let listing = |files: &[(&str, u8)]| Listing {
+            files: files
+                .iter()
+                .map(|(path, hash)| ((*path).to_owned(), Sha256([*hash; 32])))
+                .collect(),
+        };
+
+        let old = listing(&[
+            ("crt/include/a.h", 1),
+            ("sdk/include/um/b.h", 2),
+            ("c.lib", 3),
+        ]);
+        let new = listing(&[
+            ("crt/include/a.h", 1),
+            ("sdk/include/um/b.h", 4),
+            ("d.lib", 5),
+        ]);
+
+        let changes = diff(&old, &new);
+        assert_eq!(changes.len(), 3);
+        assert!(matches!(changes[0], Change::Removed { path: "c.lib", .. }));
+        assert!(matches!(changes[1], Change::Added { path: "d.lib", .. }));
+        assert!(matches!(
+            changes[2],
+            Change::Changed {
+                path: "sdk/include/um/b.h",
+                ..
+            }
+        ));
+    }
+}
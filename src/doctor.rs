@@ -0,0 +1,448 @@
+//! Diagnostics of the environment xwin, and the splats it produces, are used
+//! in, eg. that a usable `clang-cl` is in `PATH`, that the output's filesystem
+//! supports symlinks, and that the cache isn't littered with the remains of
+//! interrupted runs, along with hints on how to fix anything that isn't right
+
+use crate::{layout::Layout, stamp::Stamp, Arch, Component, Path, PathBuf};
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Ok,
+    Warning,
+    Error,
+}
+
+/// The outcome of a single check
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Finding {
+    /// What was checked, eg. `clang-cl`
+    pub check: String,
+    pub severity: Severity,
+    pub message: String,
+    /// How to fix the issue, if there is one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl Finding {
+    fn ok(check: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            check: check.into(),
+            severity: Severity::Ok,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn warning(
+        check: impl Into<String>,
+        message: impl Into<String>,
+        hint: impl Into<String>,
+    ) -> Self {
+        Self {
+            check: check.into(),
+            severity: Severity::Warning,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn error(
+        check: impl Into<String>,
+        message: impl Into<String>,
+        hint: impl Into<String>,
+    ) -> Self {
+        Self {
+            check: check.into(),
+            severity: Severity::Error,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Ok => "ok",
+            Severity::Warning => "warn",
+            Severity::Error => "error",
+        };
+
+        write!(f, "[{}] {}: {}", severity, self.check, self.message)?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n       hint: {}", hint)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that `clang-cl` and `lld-link` are in `PATH`, and can actually be
+/// run
+pub fn toolchain() -> Vec<Finding> {
+    ["clang-cl", "lld-link"]
+        .iter()
+        .map(|name| {
+            let path = match crate::selftest::find_program(name) {
+                Some(path) => path,
+                None => {
+                    return Finding::error(
+                        *name,
+                        "not found in PATH",
+                        format!(
+                            "install LLVM, eg. `apt install clang lld`, and make sure `{}` is in PATH, some distributions only install versioned names, eg. `{}-17`, which need to be symlinked",
+                            name, name
+                        ),
+                    )
+                }
+            };
+
+            match std::process::Command::new(&path).arg("--version").output() {
+                Ok(output) if output.status.success() => {
+                    let version = String::from_utf8_lossy(&output.stdout);
+                    let version = version.lines().next().unwrap_or_default().trim();
+                    Finding::ok(*name, format!("{} ({})", path, version))
+                }
+                Ok(output) => Finding::error(
+                    *name,
+                    format!("{} --version failed with {}", path, output.status),
+                    "reinstall LLVM, or put a working version earlier in PATH",
+                ),
+                Err(err) => Finding::error(
+                    *name,
+                    format!("unable to run {}: {}", path, err),
+                    "check that the file is executable, and is built for this platform",
+                ),
+            }
+        })
+        .collect()
+}
+
+/// Checks that the filesystem of the directory supports symlinks, and
+/// whether it is case sensitive
+fn filesystem(dir: &Path) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    let probe = dir.join(".xwin-doctor-probe");
+    if let Err(err) = std::fs::write(&probe, "") {
+        findings.push(Finding::error(
+            "filesystem",
+            format!("unable to write to {}: {}", dir, err),
+            "check the permissions of the output directory, or splat to a different --output",
+        ));
+        return findings;
+    }
+
+    if dir.join(".XWIN-DOCTOR-PROBE").exists() {
+        findings.push(Finding::warning(
+            "case sensitivity",
+            format!("{} is on a case insensitive filesystem", dir),
+            "this works, but symlinks aren't added, so the output is unusable if it is copied to a case sensitive filesystem, eg. in a container image",
+        ));
+    } else {
+        findings.push(Finding::ok(
+            "case sensitivity",
+            format!("{} is on a case sensitive filesystem", dir),
+        ));
+    }
+
+    #[cfg(unix)]
+    {
+        let link = dir.join(".xwin-doctor-link");
+        match std::os::unix::fs::symlink(".xwin-doctor-probe", &link) {
+            Ok(()) => {
+                findings.push(Finding::ok("symlinks", "supported"));
+                let _ = std::fs::remove_file(&link);
+            }
+            Err(err) => findings.push(Finding::error(
+                "symlinks",
+                format!("unable to create a symlink in {}: {}", dir, err),
+                "symlinks are needed to fix the casing of the SDK headers and libs, splat to a filesystem that supports them, or pass --disable-symlinks and compile with a case insensitive toolchain",
+            )),
+        }
+    }
+
+    let _ = std::fs::remove_file(&probe);
+    findings
+}
+
+/// Finds a file in the directories, ignoring case, as the SDK isn't
+/// consistent about the casing of eg. `kernel32.Lib`
+fn find_in(dirs: &[PathBuf], name: &str) -> Option<PathBuf> {
+    dirs.iter().find_map(|dir| {
+        std::fs::read_dir(dir)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .find(|entry| entry.eq_ignore_ascii_case(name))
+            .map(|entry| dir.join(entry))
+    })
+}
+
+/// Counts the symlinks in the tree that don't point to anything, eg. because
+/// the output was partially copied
+fn dangling_symlinks(dir: &Path, count: &mut usize) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = match PathBuf::from_path_buf(entry.path()) {
+            Ok(path) => path,
+            Err(_) => continue,
+        };
+
+        match entry.file_type() {
+            Ok(ft) if ft.is_dir() => dangling_symlinks(&path, count),
+            Ok(ft) if ft.is_symlink() && !path.exists() => *count += 1,
+            _ => {}
+        }
+    }
+}
+
+/// Checks an existing splat output, ie. that it has a stamp, that the key
+/// headers and libs exist for each of the architectures it was splatted for,
+/// and that its filesystem supports what the splat needs. The architectures
+/// and variants are only used if the output doesn't have a stamp.
+pub fn output(root: &Path, arches: &[Arch], variants: u32) -> Vec<Finding> {
+    if !root.is_dir() {
+        return vec![Finding::error(
+            "output",
+            format!("{} does not exist", root),
+            "run `xwin splat`, or pass the --output it was splatted to",
+        )];
+    }
+
+    let mut findings = filesystem(root);
+
+    let (arches, variants) = match Stamp::load(root) {
+        Ok(stamp) => {
+            findings.push(Finding::ok(
+                "stamp",
+                format!(
+                    "CRT {}, SDK {}",
+                    stamp.crt_version().unwrap_or("unknown"),
+                    stamp.sdk_version().unwrap_or("unknown")
+                ),
+            ));
+
+            let arches = stamp
+                .arch_mask()
+                .map_or_else(|_| arches.to_vec(), |mask| Arch::iter(mask).collect());
+            (arches, stamp.variant_mask().unwrap_or(variants))
+        }
+        Err(err) => {
+            findings.push(Finding::warning(
+                "stamp",
+                format!("unable to read {}: {:#}", Stamp::NAME, err),
+                "the output wasn't splatted by this version of xwin, or was only partially copied, splat it again to be able to use `xwin update`",
+            ));
+            (arches.to_vec(), variants)
+        }
+    };
+
+    for arch in arches {
+        let check = format!("layout {}", arch);
+
+        let layout = match Layout::resolve(root, arch, variants) {
+            Ok(layout) => layout,
+            Err(err) => {
+                findings.push(Finding::error(
+                    check,
+                    format!("{:#}", err),
+                    format!("splat the output again with --arch {}", arch),
+                ));
+                continue;
+            }
+        };
+
+        // Only the files of the components that were splatted are expected
+        let components = layout
+            .include
+            .iter()
+            .chain(&layout.lib)
+            .filter_map(|dir| dir.strip_prefix(root).ok()?.iter().next())
+            .collect::<Vec<_>>();
+        let missing: Vec<_> = [
+            (Component::Sdk, &layout.include, "windows.h"),
+            (Component::Sdk, &layout.include, "stdio.h"),
+            (Component::Crt, &layout.include, "vcruntime.h"),
+            (Component::Sdk, &layout.lib, "kernel32.lib"),
+            (Component::Sdk, &layout.lib, "ucrt.lib"),
+            (Component::Crt, &layout.lib, "msvcrt.lib"),
+        ]
+        .iter()
+        .filter(|(component, dirs, name)| {
+            components.contains(&component.as_str()) && find_in(dirs, name).is_none()
+        })
+        .map(|(_, _, name)| *name)
+        .collect();
+
+        if missing.is_empty() {
+            findings.push(Finding::ok(check, "the key headers and libs are present"));
+        } else {
+            findings.push(Finding::error(
+                check,
+                format!("missing {}", missing.join(", ")),
+                "the output is incomplete, eg. a splat was interrupted or the output was partially copied, splat it again",
+            ));
+        }
+    }
+
+    let mut dangling = 0;
+    dangling_symlinks(root, &mut dangling);
+    if dangling > 0 {
+        findings.push(Finding::warning(
+            "symlinks",
+            format!("{} symlinks don't point to anything", dangling),
+            "the output was probably copied without the files the symlinks point to, splat it again, or copy it with eg. `cp -a`",
+        ));
+    }
+
+    findings
+}
+
+/// Checks the cache directory for the remains of interrupted runs
+pub fn cache(root: &Path) -> Vec<Finding> {
+    if !root.is_dir() {
+        return vec![Finding::ok("cache", format!("{} does not exist yet", root))];
+    }
+
+    let mut findings = Vec::new();
+
+    if root.join(crate::checkpoint::Checkpoint::NAME).exists() {
+        findings.push(Finding::warning(
+            "cache",
+            "a previous run was interrupted",
+            "run the same command again to resume it",
+        ));
+    }
+
+    let partial = std::fs::read_dir(root.join("dl"))
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_name().to_string_lossy().ends_with(".partial"))
+                .count()
+        })
+        .unwrap_or_default();
+    if partial > 0 {
+        findings.push(Finding::warning(
+            "downloads",
+            format!("{} partial downloads", partial),
+            "they are resumed by the next download, or can be removed with `xwin clean --downloads`",
+        ));
+    }
+
+    let incomplete = std::fs::read_dir(root.join("unpack"))
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| !entry.path().join(".unpack").exists())
+                .count()
+        })
+        .unwrap_or_default();
+    if incomplete > 0 {
+        findings.push(Finding::warning(
+            "unpacked",
+            format!("{} payloads were only partially unpacked", incomplete),
+            "they are unpacked again by the next run, or can be removed with `xwin clean --unpacked`",
+        ));
+    }
+
+    match crate::cache::stats(root) {
+        Ok(stats) => findings.push(Finding::ok(
+            "cache",
+            format!(
+                "{} entries, {} bytes in {}",
+                stats.entries.len(),
+                stats.size(),
+                root
+            ),
+        )),
+        Err(err) => findings.push(Finding::error(
+            "cache",
+            format!("{:#}", err),
+            "check the permissions of the --cache-dir, or remove it",
+        )),
+    }
+
+    findings
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Variant;
+
+    #[test]
+    fn diagnoses_output() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        let findings = output(
+            &root.join("missing"),
+            &[Arch::X86_64],
+            Variant::Desktop as u32,
+        );
+        assert!(findings
+            .iter()
+            .any(|finding| finding.severity == Severity::Error));
+
+        // An output with only the include directories is missing the libs
+        for dir in &[
+            "crt/include",
+            "sdk/include/ucrt",
+            "sdk/include/um",
+            "sdk/include/shared",
+            "sdk/include/winrt",
+            "sdk/include/cppwinrt",
+            "crt/lib/x86_64",
+            "sdk/lib/um/x86_64",
+            "sdk/lib/ucrt/x86_64",
+        ] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in &[
+            "crt/include/vcruntime.h",
+            "sdk/include/ucrt/stdio.h",
+            "sdk/include/um/Windows.h",
+            "crt/lib/x86_64/msvcrt.lib",
+            "sdk/lib/ucrt/x86_64/ucrt.lib",
+        ] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+
+        let findings = output(&root, &[Arch::X86_64], Variant::Desktop as u32);
+        let layout = findings
+            .iter()
+            .find(|finding| finding.check == "layout x86_64")
+            .unwrap();
+        assert_eq!(layout.severity, Severity::Error);
+        assert_eq!(layout.message, "missing kernel32.lib");
+
+        std::fs::write(root.join("sdk/lib/um/x86_64/kernel32.Lib"), "").unwrap();
+        let findings = output(&root, &[Arch::X86_64], Variant::Desktop as u32);
+        assert!(
+            findings
+                .iter()
+                .all(|finding| finding.severity != Severity::Error),
+            "{:?}",
+            findings
+        );
+        // Without a stamp the output still works, but can't be updated
+        assert!(findings
+            .iter()
+            .any(|finding| finding.check == "stamp" && finding.severity == Severity::Warning));
+
+        std::fs::create_dir_all(root.join("cache/unpack/a.vsix")).unwrap();
+        let findings = cache(&root.join("cache"));
+        assert!(findings
+            .iter()
+            .any(|finding| finding.check == "unpacked" && finding.severity == Severity::Warning));
+    }
+}
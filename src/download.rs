@@ -1,15 +1,15 @@
-use crate::{manifest, util::Sha256, Ctx, Error};
-use anyhow::Context as _;
+use crate::{manifest, progress::Phase, util::Sha256, Ctx};
+use anyhow::{Context as _, Error};
 use camino::Utf8PathBuf as PathBuf;
 use std::sync::Arc;
 
 #[derive(Debug)]
-struct Cab {
-    filename: PathBuf,
-    sha256: Sha256,
-    url: String,
+pub(crate) struct Cab {
+    pub(crate) filename: PathBuf,
+    pub(crate) sha256: Sha256,
+    pub(crate) url: String,
     #[allow(dead_code)]
-    size: u64,
+    pub(crate) size: u64,
 }
 
 pub(crate) struct CabContents {
@@ -20,85 +20,109 @@ pub(crate) struct CabContents {
 
 pub(crate) enum PayloadContents {
     Vsix(bytes::Bytes),
+    /// An [extra download](crate::extra), which is unpacked according to its
+    /// layout
+    Extra(bytes::Bytes),
     Msi {
         msi: bytes::Bytes,
         cabs: Vec<CabContents>,
     },
 }
 
+impl PayloadContents {
+    /// The total size of the payload and any additional files it needed
+    pub(crate) fn size(&self) -> u64 {
+        match self {
+            Self::Vsix(contents) | Self::Extra(contents) => contents.len() as u64,
+            Self::Msi { msi, cabs } => {
+                msi.len() as u64 + cabs.iter().map(|cab| cab.content.len() as u64).sum::<u64>()
+            }
+        }
+    }
+
+    /// The number of files that make up the payload
+    pub(crate) fn num_files(&self) -> u32 {
+        match self {
+            Self::Vsix(_) | Self::Extra(_) => 1,
+            Self::Msi { cabs, .. } => 1 + cabs.len() as u32,
+        }
+    }
+}
+
 pub(crate) fn download(
     ctx: Arc<Ctx>,
     pkgs: Arc<std::collections::BTreeMap<String, manifest::ManifestItem>>,
     item: &crate::WorkItem,
 ) -> Result<PayloadContents, Error> {
-    item.progress.set_message("📥 downloading..");
+    item.progress.phase(Phase::Download);
 
     let contents = ctx.get_and_validate(
         &item.payload.url,
         &item.payload.filename,
         Some(item.payload.sha256.clone()),
-        item.progress.clone(),
+        item.progress.as_ref(),
     )?;
 
     let pc = match item.payload.filename.extension() {
+        _ if item.payload.extra.is_some() => Ok(PayloadContents::Extra(contents)),
         Some("msi") => {
-            let cabs: Vec<_> = match pkgs.values().find(|mi| {
-                mi.payloads
-                    .iter()
-                    .any(|mi_payload| mi_payload.sha256 == item.payload.sha256)
-            }) {
-                Some(mi) => mi
-                    .payloads
-                    .iter()
-                    .filter_map(|pay| {
-                        pay.file_name.ends_with(".cab").then(|| Cab {
-                            filename: pay
-                                .file_name
-                                .strip_prefix("Installers\\")
-                                .unwrap_or(&pay.file_name)
-                                .into(),
-                            sha256: pay.sha256.clone(),
-                            url: pay.url.clone(),
-                            size: pay.size,
-                        })
-                    })
-                    .collect(),
-                None => anyhow::bail!(
-                    "unable to find manifest parent for {}",
-                    item.payload.filename
-                ),
-            };
-
+            let cabs = cabs(&pkgs, &item.payload)?;
             download_cabs(ctx, &cabs, item, contents)
         }
         Some("vsix") => Ok(PayloadContents::Vsix(contents)),
         ext => anyhow::bail!("unknown extension {:?}", ext),
     };
 
-    item.progress.finish_with_message("downloaded");
+    item.progress.finish(Phase::Download);
 
     pc
 }
 
+/// Retrieves the CAB files listed alongside an MSI payload in the manifest
+/// item that contains it
+pub(crate) fn cabs(
+    pkgs: &std::collections::BTreeMap<String, manifest::ManifestItem>,
+    payload: &crate::Payload,
+) -> Result<Vec<Cab>, Error> {
+    match pkgs.values().find(|mi| {
+        mi.payloads
+            .iter()
+            .any(|mi_payload| mi_payload.sha256 == payload.sha256)
+    }) {
+        Some(mi) => Ok(mi
+            .payloads
+            .iter()
+            .filter(|pay| pay.file_name.ends_with(".cab"))
+            .map(|pay| Cab {
+                filename: pay
+                    .file_name
+                    .strip_prefix("Installers\\")
+                    .unwrap_or(&pay.file_name)
+                    .into(),
+                sha256: pay.sha256.clone(),
+                url: pay.url.clone(),
+                size: pay.size,
+            })
+            .collect()),
+        None => anyhow::bail!("unable to find manifest parent for {}", payload.filename),
+    }
+}
+
 /// Each SDK MSI has 1 or more cab files associated with it containing the actual
-/// data we need that must be downloaded separately and indexed from the MSI
-fn download_cabs(
-    ctx: Arc<Ctx>,
+/// data we need that must be downloaded separately and indexed from the MSI.
+/// Returns the cache path, checksum, url, and last sequence number of each CAB.
+fn cab_files(
     cabs: &[Cab],
-    msi: &crate::WorkItem,
-    msi_content: bytes::Bytes,
-) -> Result<PayloadContents, Error> {
-    use rayon::prelude::*;
-
-    let msi_filename = &msi.payload.filename;
-
+    msi_filename: &crate::Path,
+    msi_content: &bytes::Bytes,
+) -> Result<Vec<(PathBuf, Sha256, String, u32)>, Error> {
     let mut msi_pkg = msi::Package::open(std::io::Cursor::new(msi_content.clone()))
         .with_context(|| format!("invalid MSI for {}", msi_filename))?;
 
     // The `Media` table contains the list of cabs by name, which we then need
     // to lookup in the list of payloads.
     // Columns: [DiskId, LastSequence, DiskPrompt, Cabinet, VolumeLabel, Source]
-    let cab_files: Vec<_> = msi_pkg
+    let cab_files = msi_pkg
         .select_rows(msi::Select::table("Media"))
         .with_context(|| format!("{} does not contain a list of CAB files", msi_filename))?
         .filter_map(|row| {
@@ -138,12 +162,23 @@ fn download_cabs(
         })
         .collect();
 
-    let cabs = cab_files
+    Ok(cab_files)
+}
+
+fn download_cabs(
+    ctx: Arc<Ctx>,
+    cabs: &[Cab],
+    msi: &crate::WorkItem,
+    msi_content: bytes::Bytes,
+) -> Result<PayloadContents, Error> {
+    use rayon::prelude::*;
+
+    let cabs = cab_files(cabs, &msi.payload.filename, &msi_content)?
         .into_par_iter()
         .map(
             |(cab_name, chksum, url, sequence)| -> Result<CabContents, Error> {
                 let cab_contents =
-                    ctx.get_and_validate(&url, &cab_name, Some(chksum), msi.progress.clone())?;
+                    ctx.get_and_validate(&url, &cab_name, Some(chksum), msi.progress.as_ref())?;
                 Ok(CabContents {
                     path: cab_name,
                     content: cab_contents,
@@ -158,3 +193,65 @@ fn download_cabs(
         cabs,
     })
 }
+
+/// The async equivalent of [`download`], which downloads the CABs for an MSI
+/// concurrently
+#[cfg(feature = "async")]
+pub(crate) async fn download_async(
+    ctx: Arc<Ctx>,
+    pkgs: Arc<std::collections::BTreeMap<String, manifest::ManifestItem>>,
+    item: &crate::WorkItem,
+) -> Result<PayloadContents, Error> {
+    item.progress.phase(Phase::Download);
+
+    let contents = ctx
+        .get_and_validate_async(
+            &item.payload.url,
+            &item.payload.filename,
+            Some(item.payload.sha256.clone()),
+            item.progress.as_ref(),
+        )
+        .await?;
+
+    let pc = match item.payload.filename.extension() {
+        _ if item.payload.extra.is_some() => PayloadContents::Extra(contents),
+        Some("msi") => {
+            let cabs = cabs(&pkgs, &item.payload)?;
+
+            let mut downloads = tokio::task::JoinSet::new();
+            for (cab_name, chksum, url, sequence) in
+                cab_files(&cabs, &item.payload.filename, &contents)?
+            {
+                let ctx = ctx.clone();
+                let progress = item.progress.clone();
+
+                downloads.spawn(async move {
+                    let content = ctx
+                        .get_and_validate_async(&url, &cab_name, Some(chksum), progress.as_ref())
+                        .await?;
+                    Ok::<_, Error>(CabContents {
+                        path: cab_name,
+                        content,
+                        sequence,
+                    })
+                });
+            }
+
+            let mut cabs = Vec::new();
+            while let Some(cab) = downloads.join_next().await {
+                cabs.push(cab??);
+            }
+
+            PayloadContents::Msi {
+                msi: contents,
+                cabs,
+            }
+        }
+        Some("vsix") => PayloadContents::Vsix(contents),
+        ext => anyhow::bail!("unknown extension {:?}", ext),
+    };
+
+    item.progress.finish(Phase::Download);
+
+    Ok(pc)
+}
@@ -0,0 +1,480 @@
+//! Classification of errors into broad kinds, so that callers can decide eg.
+//! whether a failure is worth retrying without inspecting error messages
+
+use crate::{util::Sha256, Path, PathBuf};
+use std::fmt;
+
+/// The error returned by the download, unpack, and splat pipeline, ie.
+/// [`Builder`](crate::Builder), [`Plan`](crate::Plan), and [`Ctx`](crate::Ctx).
+///
+/// Every variant retains the original error along with all of the context
+/// that was attached to it, which is what is displayed.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// A network request failed, which is usually transient
+    Network(anyhow::Error),
+    /// Downloaded content didn't match its expected checksum
+    HashMismatch {
+        expected: Sha256,
+        actual: Sha256,
+        source: anyhow::Error,
+    },
+    /// The disk ran out of space
+    DiskFull(anyhow::Error),
+    /// A file system operation failed, `path` is the path being operated on,
+    /// if known
+    Io {
+        path: Option<PathBuf>,
+        source: anyhow::Error,
+    },
+    /// A payload couldn't be unpacked, eg. due to a corrupt archive
+    Unpack(anyhow::Error),
+    /// A package or payload required by the selection couldn't be found in
+    /// the manifest
+    MissingPayload(anyhow::Error),
+    /// One or more payloads failed to be splatted, leaving the output
+    /// incomplete
+    PartialSplat(anyhow::Error),
+    /// The run was cancelled via a [`CancellationToken`](crate::cancel::CancellationToken)
+    Cancelled(anyhow::Error),
+    /// The network was accessed while it was disabled via
+    /// [`Ctx::no_network`](crate::Ctx::no_network)
+    NetworkDisabled(anyhow::Error),
+    /// Any other failure
+    Other(anyhow::Error),
+}
+
+impl Error {
+    /// The broad kind of the error, if it is one that callers may want to
+    /// handle specially
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::Network(_) => Some(ErrorKind::Network),
+            Self::HashMismatch { .. } => Some(ErrorKind::ChecksumMismatch),
+            Self::DiskFull(_) => Some(ErrorKind::DiskFull),
+            Self::MissingPayload(_) => Some(ErrorKind::MissingPayload),
+            Self::PartialSplat(_) => Some(ErrorKind::PartialSplat),
+            Self::Cancelled(_) => Some(ErrorKind::Cancelled),
+            Self::NetworkDisabled(_) => Some(ErrorKind::NetworkDisabled),
+            Self::Io { .. } | Self::Unpack(_) | Self::Other(_) => None,
+        }
+    }
+
+    /// The underlying error, including its context
+    pub fn inner(&self) -> &anyhow::Error {
+        match self {
+            Self::Network(err)
+            | Self::HashMismatch { source: err, .. }
+            | Self::DiskFull(err)
+            | Self::Io { source: err, .. }
+            | Self::Unpack(err)
+            | Self::MissingPayload(err)
+            | Self::PartialSplat(err)
+            | Self::Cancelled(err)
+            | Self::NetworkDisabled(err)
+            | Self::Other(err) => err,
+        }
+    }
+
+    /// Creates a constructor for the same variant with a different source,
+    /// used when an already classified error has had more context added to it
+    fn rewrap(&self) -> Box<dyn FnOnce(anyhow::Error) -> Self> {
+        match self {
+            Self::Network(_) => Box::new(Self::Network),
+            Self::HashMismatch {
+                expected, actual, ..
+            } => {
+                let (expected, actual) = (expected.clone(), actual.clone());
+                Box::new(move |source| Self::HashMismatch {
+                    expected,
+                    actual,
+                    source,
+                })
+            }
+            Self::DiskFull(_) => Box::new(Self::DiskFull),
+            Self::Io { path, .. } => {
+                let path = path.clone();
+                Box::new(move |source| Self::Io { path, source })
+            }
+            Self::Unpack(_) => Box::new(Self::Unpack),
+            Self::MissingPayload(_) => Box::new(Self::MissingPayload),
+            Self::PartialSplat(_) => Box::new(Self::PartialSplat),
+            Self::Cancelled(_) => Box::new(Self::Cancelled),
+            Self::NetworkDisabled(_) => Box::new(Self::NetworkDisabled),
+            Self::Other(_) => Box::new(Self::Other),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.inner(), f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        (**self.inner()).source()
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(mut err: anyhow::Error) -> Self {
+        // Downcasting by value discards any context, so only do it if the
+        // error hasn't had any added since it was converted
+        if matches!(err.chain().next(), Some(outer) if outer.is::<Self>()) {
+            match err.downcast::<Self>() {
+                Ok(classified) => return classified,
+                Err(unchanged) => err = unchanged,
+            }
+        }
+
+        if let Some(rewrap) = err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<Self>())
+            .map(Self::rewrap)
+        {
+            return rewrap(err);
+        }
+
+        match classify(&err) {
+            Some(ErrorKind::Network) => Self::Network(err),
+            Some(ErrorKind::ChecksumMismatch) => {
+                let (expected, actual) = err
+                    .chain()
+                    .find_map(|cause| cause.downcast_ref::<ChecksumMismatch>())
+                    .map_or_else(
+                        || (Sha256([0; 32]), Sha256([0; 32])),
+                        |cm| (cm.expected.clone(), cm.actual.clone()),
+                    );
+
+                Self::HashMismatch {
+                    expected,
+                    actual,
+                    source: err,
+                }
+            }
+            Some(ErrorKind::DiskFull) => Self::DiskFull(err),
+            Some(ErrorKind::MissingPayload) => Self::MissingPayload(err),
+            Some(ErrorKind::PartialSplat) => Self::PartialSplat(err),
+            Some(ErrorKind::Cancelled) => Self::Cancelled(err),
+            Some(ErrorKind::NetworkDisabled) => Self::NetworkDisabled(err),
+            None => {
+                if err.downcast_ref::<UnpackFailed>().is_some() {
+                    Self::Unpack(err)
+                } else if err.chain().any(|cause| cause.is::<std::io::Error>()) {
+                    Self::Io {
+                        path: err.downcast_ref::<IoContext>().map(|ioc| ioc.path.clone()),
+                        source: err,
+                    }
+                } else {
+                    Self::Other(err)
+                }
+            }
+        }
+    }
+}
+
+macro_rules! from_via_anyhow {
+    ($($ty:ty),+) => {
+        $(
+            impl From<$ty> for Error {
+                fn from(err: $ty) -> Self {
+                    anyhow::Error::from(err).into()
+                }
+            }
+        )+
+    };
+}
+
+from_via_anyhow!(
+    std::io::Error,
+    reqwest::Error,
+    serde_json::Error,
+    ChecksumMismatch,
+    Cancelled,
+    NetworkDisabled
+);
+
+/// Context attached to file system errors so that the path being operated on
+/// is available when the error is classified
+pub(crate) struct IoContext {
+    msg: String,
+    path: PathBuf,
+}
+
+impl fmt::Display for IoContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl fmt::Debug for IoContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+/// Creates the context for a file system error, `msg` is displayed as is
+pub(crate) fn io(msg: String, path: &Path) -> IoContext {
+    IoContext {
+        msg,
+        path: path.to_owned(),
+    }
+}
+
+/// The broad kind of an error
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A network request failed, which is usually transient
+    Network,
+    /// Downloaded content didn't match its expected checksum
+    ChecksumMismatch,
+    /// The disk ran out of space
+    DiskFull,
+    /// A package or payload required by the selection couldn't be found in
+    /// the manifest
+    MissingPayload,
+    /// One or more payloads failed to be splatted, leaving the output
+    /// incomplete
+    PartialSplat,
+    /// The run was cancelled via a [`CancellationToken`](crate::cancel::CancellationToken)
+    Cancelled,
+    /// The network was accessed while it was disabled
+    NetworkDisabled,
+}
+
+/// The checksum of downloaded content didn't match the expected checksum
+#[derive(Debug)]
+pub struct ChecksumMismatch {
+    pub expected: Sha256,
+    pub actual: Sha256,
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "checksum mismatch, expected {} != actual {}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+/// Context attached to errors that occur while resolving the payloads that are
+/// needed for the selected architectures and variants
+#[derive(Debug)]
+pub struct MissingPayload;
+
+impl fmt::Display for MissingPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("unable to resolve the required packages from the manifest")
+    }
+}
+
+/// Context attached to errors that occur after the splat output has started
+/// being written to
+#[derive(Debug)]
+pub struct PartialSplat;
+
+impl fmt::Display for PartialSplat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the splat output is incomplete")
+    }
+}
+
+/// The run was cancelled before it completed
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the operation was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Something needed to be retrieved from the network while it was disabled,
+/// eg. because it wasn't present in the download cache
+#[derive(Debug)]
+pub struct NetworkDisabled {
+    pub url: String,
+}
+
+impl fmt::Display for NetworkDisabled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "attempted to access '{}', but network access is disabled",
+            self.url
+        )
+    }
+}
+
+impl std::error::Error for NetworkDisabled {}
+
+/// The server responded with a 404 for the url, which is returned by
+/// [`HttpClient`](crate::http::HttpClient)s so that callers can tell content
+/// that doesn't exist, eg. a PDB a symbol server doesn't have, apart from
+/// other failures
+#[derive(Debug)]
+pub struct NotFound {
+    pub url: String,
+}
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' was not found", self.url)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+/// Context attached to errors returned by an [`HttpClient`](crate::http::HttpClient)
+#[derive(Debug)]
+pub struct RequestFailed {
+    pub url: String,
+}
+
+impl fmt::Display for RequestFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the request for '{}' failed", self.url)
+    }
+}
+
+/// Context attached to errors that occur while unpacking a payload
+#[derive(Debug)]
+pub struct UnpackFailed;
+
+impl fmt::Display for UnpackFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the payload could not be unpacked")
+    }
+}
+
+/// Determines the kind of the error by inspecting its context and chain of
+/// causes. If several kinds apply, eg. the disk filled up during a splat, the
+/// more specific kind is returned.
+pub fn classify(err: &anyhow::Error) -> Option<ErrorKind> {
+    fn has<T: std::error::Error + Send + Sync + 'static>(err: &anyhow::Error) -> bool {
+        err.downcast_ref::<T>().is_some() || err.chain().any(|cause| cause.is::<T>())
+    }
+
+    // Context markers aren't errors themselves so can only be found via
+    // anyhow's downcasting, not by walking the chain of causes
+    fn has_context<C: fmt::Display + fmt::Debug + Send + Sync + 'static>(
+        err: &anyhow::Error,
+    ) -> bool {
+        err.downcast_ref::<C>().is_some()
+    }
+
+    if let Some(classified) = err.chain().find_map(|cause| cause.downcast_ref::<Error>()) {
+        return classified.kind();
+    }
+
+    if has::<Cancelled>(err) {
+        Some(ErrorKind::Cancelled)
+    } else if has::<NetworkDisabled>(err) {
+        Some(ErrorKind::NetworkDisabled)
+    } else if has::<ChecksumMismatch>(err) {
+        Some(ErrorKind::ChecksumMismatch)
+    } else if has::<reqwest::Error>(err) || has_context::<RequestFailed>(err) {
+        Some(ErrorKind::Network)
+    } else if err
+        .downcast_ref::<std::io::Error>()
+        .filter(|err| is_disk_full(err))
+        .is_some()
+        || err
+            .chain()
+            .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+            .any(is_disk_full)
+    {
+        Some(ErrorKind::DiskFull)
+    } else if has_context::<MissingPayload>(err) {
+        Some(ErrorKind::MissingPayload)
+    } else if has_context::<PartialSplat>(err) {
+        Some(ErrorKind::PartialSplat)
+    } else {
+        None
+    }
+}
+
+fn is_disk_full(err: &std::io::Error) -> bool {
+    // ENOSPC
+    #[cfg(unix)]
+    const CODES: &[i32] = &[28];
+    // ERROR_HANDLE_DISK_FULL, ERROR_DISK_FULL
+    #[cfg(windows)]
+    const CODES: &[i32] = &[39, 112];
+    #[cfg(not(any(unix, windows)))]
+    const CODES: &[i32] = &[];
+
+    matches!(err.raw_os_error(), Some(code) if CODES.contains(&code))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Context as _;
+
+    #[test]
+    fn classifies() {
+        let mismatch = anyhow::Error::from(ChecksumMismatch {
+            expected: Sha256([0; 32]),
+            actual: Sha256([1; 32]),
+        });
+        assert_eq!(
+            classify(&mismatch.context("failed to download")),
+            Some(ErrorKind::ChecksumMismatch)
+        );
+
+        let disk_full: anyhow::Error =
+            std::io::Error::from_raw_os_error(if cfg!(windows) { 112 } else { 28 }).into();
+        assert_eq!(
+            classify(&disk_full.context(PartialSplat)),
+            Some(ErrorKind::DiskFull)
+        );
+
+        let missing = Err::<(), _>(anyhow::anyhow!("unable to find Universal CRT"))
+            .context(MissingPayload)
+            .unwrap_err();
+        assert_eq!(classify(&missing), Some(ErrorKind::MissingPayload));
+
+        let cancelled = anyhow::Error::from(Cancelled).context(PartialSplat);
+        assert_eq!(classify(&cancelled), Some(ErrorKind::Cancelled));
+
+        assert_eq!(classify(&anyhow::anyhow!("something else")), None);
+    }
+
+    #[test]
+    fn converts() {
+        let not_found = Err::<(), _>(std::io::Error::from(std::io::ErrorKind::NotFound))
+            .with_context(|| io("unable to read a.h".to_owned(), Path::new("a.h")))
+            .unwrap_err();
+        match Error::from(not_found) {
+            Error::Io { path, source } => {
+                assert_eq!(path.as_deref(), Some(Path::new("a.h")));
+                assert_eq!(source.to_string(), "unable to read a.h");
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+
+        // Adding context to an error that was already converted retains the
+        // variant it was converted to
+        let mismatch = Error::from(ChecksumMismatch {
+            expected: Sha256([0; 32]),
+            actual: Sha256([1; 32]),
+        });
+        let wrapped = Error::from(anyhow::Error::from(mismatch).context("failed to download"));
+        assert!(matches!(
+            &wrapped,
+            Error::HashMismatch { actual, .. } if *actual == Sha256([1; 32])
+        ));
+        assert_eq!(wrapped.to_string(), "failed to download");
+        assert_eq!(wrapped.kind(), Some(ErrorKind::ChecksumMismatch));
+    }
+}
@@ -0,0 +1,249 @@
+//! Support for additional downloads declared in the config file, eg. a vendor
+//! SDK that needs to be installed next to the Microsoft one, which are
+//! downloaded, verified, cached, and unpacked the same way as the payloads from
+//! the VS manifest, and are then splatted to a subdirectory of the output
+
+use crate::{util::Sha256, Arch, PathBuf, Payload, PayloadKind};
+use anyhow::{Context as _, Error};
+use serde::Deserialize;
+
+/// How the contents of an extra download are unpacked
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Unpack {
+    /// A zip archive, including `.vsix` and `.nupkg` files
+    Zip,
+    /// An uncompressed tarball
+    Tar,
+    /// A gzip compressed tarball
+    TarGz,
+    /// The download is a single file that is copied as is
+    None,
+}
+
+impl std::str::FromStr for Unpack {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "zip" => Self::Zip,
+            "tar" => Self::Tar,
+            "tar.gz" | "tgz" => Self::TarGz,
+            "none" => Self::None,
+            o => anyhow::bail!("unknown unpack type '{}'", o),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Unpack {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Where, and how, the contents of an extra download are placed in the output
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtraLayout {
+    pub unpack: Unpack,
+    /// The directory the contents are splatted to, relative to the output
+    pub target: PathBuf,
+    /// The number of leading path components that are removed from the paths
+    /// in the archive, eg. to remove the top level directory of a tarball
+    pub strip_components: u32,
+}
+
+/// An additional download, from an `[[extra]]` table in `xwin.toml`
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ExtraDownload {
+    /// The url of the download
+    pub url: String,
+    pub sha256: Sha256,
+    /// How the download is unpacked, one of `zip`, `tar`, `tar.gz`, or `none`
+    pub unpack: Unpack,
+    /// The directory the contents are splatted to, relative to the output
+    pub target: PathBuf,
+    /// The number of leading path components that are removed from the paths
+    /// in the archive
+    #[serde(default)]
+    pub strip_components: u32,
+    /// The version of the download, only used for display purposes
+    #[serde(default)]
+    pub version: String,
+    /// The download size, only used for display purposes
+    #[serde(default)]
+    pub size: u64,
+    /// The filename the download is cached as, defaults to the last segment
+    /// of the url, and must not conflict with the filename of any other
+    /// payload
+    pub file_name: Option<String>,
+    /// If set, the download is only included if the architecture is selected
+    #[serde(default, deserialize_with = "from_str_opt")]
+    pub arch: Option<Arch>,
+}
+
+fn from_str_opt<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: std::str::FromStr<Err = Error>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| s.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+impl ExtraDownload {
+    /// The payload for the download, which is processed the same as the
+    /// payloads from the VS manifest
+    pub fn payload(&self) -> Result<Payload, Error> {
+        anyhow::ensure!(
+            self.target.is_relative()
+                && self
+                    .target
+                    .components()
+                    .all(|comp| matches!(comp, camino::Utf8Component::Normal(_))),
+            "the target '{}' of extra download '{}' must be a relative path inside the output",
+            self.target,
+            self.url
+        );
+
+        let filename = match &self.file_name {
+            Some(fname) => fname.clone(),
+            None => self
+                .url
+                .rsplit('/')
+                .next()
+                .filter(|fname| !fname.is_empty())
+                .map(String::from)
+                .with_context(|| format!("unable to determine filename from url '{}'", self.url))?,
+        };
+
+        Ok(Payload {
+            filename: filename.into(),
+            version: self.version.clone(),
+            sha256: self.sha256.clone(),
+            url: self.url.clone(),
+            size: self.size,
+            install_size: None,
+            kind: PayloadKind::Extra,
+            target_arch: self.arch,
+            variant: None,
+            extra: Some(ExtraLayout {
+                unpack: self.unpack,
+                target: self.target.clone(),
+                strip_components: self.strip_components,
+            }),
+        })
+    }
+}
+
+/// Returns the payloads for the extra downloads that match the selected
+/// architectures, failing if any of them conflict with an existing payload
+pub fn payloads(
+    extras: &[ExtraDownload],
+    existing: &[Payload],
+    arches: u32,
+) -> Result<Vec<Payload>, Error> {
+    let mut payloads: Vec<Payload> = Vec::new();
+
+    for extra in extras {
+        if matches!(extra.arch, Some(arch) if arch as u32 & arches == 0) {
+            continue;
+        }
+
+        let payload = extra.payload()?;
+        anyhow::ensure!(
+            !existing
+                .iter()
+                .chain(payloads.iter())
+                .any(|pay| pay.filename == payload.filename),
+            "extra download '{}' conflicts with an existing payload '{}', specify a different `file_name`",
+            extra.url,
+            payload.filename
+        );
+
+        payloads.push(payload);
+    }
+
+    Ok(payloads)
+}
+
+/// Determines the path of a file in an archive relative to the root of the
+/// extra download's contents, returning `None` if it is removed by
+/// `strip_components`, or if it would escape the root
+pub(crate) fn archive_path(path: &str, strip_components: u32) -> Option<PathBuf> {
+    let mut components = Vec::new();
+    for comp in path.split(['/', '\\']) {
+        match comp {
+            "" | "." => {}
+            ".." => return None,
+            comp => components.push(comp),
+        }
+    }
+
+    let components = components.get(strip_components as usize..)?;
+    if components.is_empty() {
+        return None;
+    }
+
+    Some(components.join("/").into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resolves_payloads() {
+        let extras: Vec<ExtraDownload> = toml::from_str::<crate::config::Config>(
+            r#"
+[[extra]]
+url = "https://example.com/contoso/contoso-sdk-1.2.3.tar.gz"
+sha256 = "0000000000000000000000000000000000000000000000000000000000000000"
+unpack = "tar.gz"
+target = "vendor/contoso"
+strip_components = 1
+
+[[extra]]
+url = "https://example.com/contoso/arm64/contoso.lib"
+sha256 = "1111111111111111111111111111111111111111111111111111111111111111"
+unpack = "none"
+target = "vendor/contoso/lib/aarch64"
+arch = "aarch64"
+"#,
+        )
+        .unwrap()
+        .extras;
+
+        let payloads = payloads(&extras, &[], Arch::X86_64 as u32).unwrap();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0].filename, "contoso-sdk-1.2.3.tar.gz");
+        assert_eq!(payloads[0].kind, PayloadKind::Extra);
+        assert_eq!(
+            payloads[0].extra,
+            Some(ExtraLayout {
+                unpack: Unpack::TarGz,
+                target: "vendor/contoso".into(),
+                strip_components: 1,
+            })
+        );
+
+        // Filenames must be unique, as they are used to cache the download
+        assert!(super::payloads(&extras[..1], &payloads, Arch::X86_64 as u32).is_err());
+
+        let mut escapes = extras[0].clone();
+        escapes.target = "../contoso".into();
+        assert!(escapes.payload().is_err());
+
+        assert_eq!(
+            archive_path("contoso-sdk/include/contoso.h", 1),
+            Some("include/contoso.h".into())
+        );
+        assert_eq!(archive_path("./contoso-sdk/", 1), None);
+        assert_eq!(archive_path("contoso-sdk/../../etc/passwd", 1), None);
+    }
+}
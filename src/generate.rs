@@ -0,0 +1,1084 @@
+//! Generation of files for consuming a splat output from various build systems
+//! and tools, so that users don't need to write (and keep in sync) the paths
+//! and flags for the layout by hand
+
+use crate::{layout::Layout, Arch, Path};
+use anyhow::Error;
+use std::fmt::Write;
+
+/// The name of the processor as used by cmake when targeting Windows
+fn cmake_processor(arch: Arch) -> &'static str {
+    match arch {
+        Arch::X86 => "X86",
+        Arch::X86_64 => "AMD64",
+        Arch::Aarch => "ARM",
+        Arch::Aarch64 => "ARM64",
+    }
+}
+
+/// Paths are emitted with forward slashes, as backslashes are escape characters
+/// in most of the formats we generate, and every tool accepts forward slashes
+fn fwd(path: &Path) -> String {
+    path.as_str().replace('\\', "/")
+}
+
+/// Generates a cmake toolchain file that uses `clang-cl` and `lld-link` to
+/// target the specified architecture with the includes and libs in the layout
+pub fn cmake_toolchain(layout: &Layout, arch: Arch) -> String {
+    let mut tc = String::new();
+
+    writeln!(tc, "# Generated by xwin, targeting {}", arch.as_triple()).unwrap();
+    tc.push_str("set(CMAKE_SYSTEM_NAME Windows)\n");
+    tc.push_str("set(CMAKE_SYSTEM_VERSION 10.0)\n");
+    writeln!(tc, "set(CMAKE_SYSTEM_PROCESSOR {})", cmake_processor(arch)).unwrap();
+
+    tc.push_str(
+        r#"
+set(CMAKE_C_COMPILER clang-cl CACHE FILEPATH "")
+set(CMAKE_CXX_COMPILER clang-cl CACHE FILEPATH "")
+set(CMAKE_LINKER lld-link CACHE FILEPATH "")
+set(CMAKE_AR llvm-lib CACHE FILEPATH "")
+set(CMAKE_RC_COMPILER llvm-rc CACHE FILEPATH "")
+set(CMAKE_MT llvm-mt CACHE FILEPATH "")
+
+"#,
+    );
+
+    // Use /imsvc rather than -I so that the CRT and SDK headers are treated as
+    // system headers, otherwise they emit an ocean of warnings
+    tc.push_str("set(_xwin_compile_flags\n");
+    writeln!(tc, "    \"--target={}\"", arch.as_triple()).unwrap();
+    for inc in &layout.include {
+        writeln!(tc, "    \"/imsvc{}\"", fwd(inc)).unwrap();
+    }
+    tc.push_str(")\n");
+
+    // The manifest tool is frequently unavailable or nonfunctional outside of
+    // Windows, so don't embed manifests by default
+    tc.push_str("set(_xwin_link_flags\n    \"/manifest:no\"\n");
+    for lib in &layout.lib {
+        writeln!(tc, "    \"/libpath:{}\"", fwd(lib)).unwrap();
+    }
+    tc.push_str(")\n");
+
+    writeln!(tc, "\nset(CMAKE_FIND_ROOT_PATH \"{}\")", fwd(&layout.root)).unwrap();
+
+    tc.push_str(
+        r#"
+list(JOIN _xwin_compile_flags " " _xwin_compile_flags)
+list(JOIN _xwin_link_flags " " _xwin_link_flags)
+
+set(CMAKE_C_FLAGS_INIT "${_xwin_compile_flags}")
+set(CMAKE_CXX_FLAGS_INIT "${_xwin_compile_flags}")
+set(CMAKE_EXE_LINKER_FLAGS_INIT "${_xwin_link_flags}")
+set(CMAKE_SHARED_LINKER_FLAGS_INIT "${_xwin_link_flags}")
+set(CMAKE_MODULE_LINKER_FLAGS_INIT "${_xwin_link_flags}")
+
+# Don't search the host for libraries or headers
+set(CMAKE_FIND_ROOT_PATH_MODE_PROGRAM NEVER)
+set(CMAKE_FIND_ROOT_PATH_MODE_LIBRARY ONLY)
+set(CMAKE_FIND_ROOT_PATH_MODE_INCLUDE ONLY)
+set(CMAKE_FIND_ROOT_PATH_MODE_PACKAGE ONLY)
+"#,
+    );
+
+    tc
+}
+
+/// Generates a Meson cross file that uses `clang-cl` and `lld-link` to target
+/// the specified architecture with the includes and libs in the layout
+pub fn meson_cross(layout: &Layout, arch: Arch) -> String {
+    let (cpu_family, cpu) = match arch {
+        Arch::X86 => ("x86", "i686"),
+        Arch::X86_64 => ("x86_64", "x86_64"),
+        Arch::Aarch => ("arm", "armv7"),
+        Arch::Aarch64 => ("aarch64", "aarch64"),
+    };
+
+    let array = |prefix: &str, paths: &[crate::PathBuf], first: &str| {
+        let mut arr = String::from("[\n");
+        if !first.is_empty() {
+            writeln!(arr, "    '{}',", first).unwrap();
+        }
+        for path in paths {
+            writeln!(arr, "    '{}{}',", prefix, fwd(path).replace('\'', "\\'")).unwrap();
+        }
+        arr.push(']');
+        arr
+    };
+
+    let mut cf = String::new();
+
+    writeln!(cf, "# Generated by xwin, targeting {}", arch.as_triple()).unwrap();
+    cf.push_str("[constants]\n");
+    writeln!(
+        cf,
+        "xwin_compile_args = {}",
+        array(
+            "/imsvc",
+            &layout.include,
+            &format!("--target={}", arch.as_triple())
+        )
+    )
+    .unwrap();
+    writeln!(
+        cf,
+        "xwin_link_args = {}",
+        array("/libpath:", &layout.lib, "/manifest:no")
+    )
+    .unwrap();
+
+    cf.push_str(
+        r#"
+[binaries]
+c = 'clang-cl'
+cpp = 'clang-cl'
+c_ld = 'lld-link'
+cpp_ld = 'lld-link'
+ar = 'llvm-lib'
+windres = 'llvm-rc'
+
+[built-in options]
+c_args = xwin_compile_args
+cpp_args = xwin_compile_args
+c_link_args = xwin_link_args
+cpp_link_args = xwin_link_args
+
+[host_machine]
+system = 'windows'
+"#,
+    );
+    writeln!(cf, "cpu_family = '{}'", cpu_family).unwrap();
+    writeln!(cf, "cpu = '{}'", cpu).unwrap();
+    cf.push_str("endian = 'little'\n");
+
+    cf
+}
+
+/// Generates a Cargo config that links with `lld-link` and the libs in each
+/// layout for its architecture's target, or its `*-uwp-windows-msvc` target
+/// for Store layouts, and sets the environment variables
+/// used by the `cc` crate to compile with `clang-cl` and the includes
+pub fn cargo_config(layouts: &[(Arch, Layout)]) -> String {
+    let quote = |s: &str| toml::Value::String(s.to_owned()).to_string();
+
+    let mut cc = String::new();
+    writeln!(cc, "# Generated by xwin").unwrap();
+
+    let triple = |arch: &Arch, layout: &Layout| {
+        if layout.store {
+            arch.as_uwp_triple()
+        } else {
+            arch.as_triple()
+        }
+    };
+
+    for (arch, layout) in layouts {
+        writeln!(cc, "[target.{}]", triple(arch, layout)).unwrap();
+        cc.push_str("linker = \"lld-link\"\n");
+        cc.push_str("rustflags = [\n");
+        for lib in &layout.lib {
+            writeln!(cc, "    {},", quote(&format!("-Lnative={}", fwd(lib)))).unwrap();
+        }
+        cc.push_str("]\n\n");
+    }
+
+    cc.push_str("[env]\n");
+    for (arch, layout) in layouts {
+        let triple = triple(arch, layout).replace('-', "_");
+
+        let cflags = layout
+            .include
+            .iter()
+            .map(|inc| format!("/imsvc{}", fwd(inc)))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        writeln!(cc, "CC_{} = \"clang-cl\"", triple).unwrap();
+        writeln!(cc, "CXX_{} = \"clang-cl\"", triple).unwrap();
+        writeln!(cc, "AR_{} = \"llvm-lib\"", triple).unwrap();
+        writeln!(cc, "CFLAGS_{} = {}", triple, quote(&cflags)).unwrap();
+        writeln!(cc, "CXXFLAGS_{} = {}", triple, quote(&cflags)).unwrap();
+    }
+
+    cc
+}
+
+/// The path relative to the root of the layout, as Bazel only allows paths
+/// inside of a package
+fn rel(layout: &Layout, path: &Path) -> String {
+    fwd(path.strip_prefix(&layout.root).unwrap_or(path))
+}
+
+/// Lists the libraries in the layout's lib directories as (name, relative path)
+/// pairs. If the same library is present in multiple directories only the first
+/// is used, just as the linker would, and the symlinks that splat adds for
+/// casing issues are ignored.
+fn libs(layout: &Layout) -> Result<Vec<(String, String)>, Error> {
+    use anyhow::Context as _;
+
+    let mut libs = Vec::new();
+    let mut seen = std::collections::BTreeSet::new();
+
+    for dir in &layout.lib {
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(dir).with_context(|| format!("unable to read {}", dir))? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+
+            if let Ok(name) = entry.file_name().into_string() {
+                entries.push(name);
+            }
+        }
+        entries.sort();
+
+        for name in entries {
+            let stem = match name.rsplit_once('.') {
+                Some((stem, ext)) if ext.eq_ignore_ascii_case("lib") => stem,
+                _ => continue,
+            };
+
+            if seen.insert(stem.to_lowercase()) {
+                libs.push((stem.to_owned(), rel(layout, &dir.join(&name))));
+            }
+        }
+    }
+
+    Ok(libs)
+}
+
+/// Generates a `BUILD.bazel` for the root of the layouts, with a `headers`
+/// `cc_library` for the CRT and SDK includes, and a `cc_import` named
+/// `<arch>_<lib>` for every library of each layout's architecture
+pub fn bazel_build(layouts: &[(Arch, Layout)]) -> Result<String, Error> {
+    let quote = |s: &str| serde_json::Value::String(s.to_owned()).to_string();
+
+    let mut build = String::new();
+    build.push_str("# Generated by xwin\n");
+    build.push_str("package(default_visibility = [\"//visibility:public\"])\n");
+
+    if let Some((_, layout)) = layouts.first() {
+        build.push_str("\ncc_library(\n    name = \"headers\",\n");
+        build.push_str("    hdrs = glob([\n");
+        for inc in &layout.include {
+            writeln!(
+                build,
+                "        {},",
+                quote(&format!("{}/**", rel(layout, inc)))
+            )
+            .unwrap();
+        }
+        build.push_str("    ]),\n    includes = [\n");
+        for inc in &layout.include {
+            writeln!(build, "        {},", quote(&rel(layout, inc))).unwrap();
+        }
+        build.push_str("    ],\n)\n");
+    }
+
+    for (arch, layout) in layouts {
+        for (name, path) in libs(layout)? {
+            writeln!(
+                build,
+                "\ncc_import(\n    name = {},\n    static_library = {},\n)",
+                quote(&format!("{}_{}", arch, name)),
+                quote(&path)
+            )
+            .unwrap();
+        }
+    }
+
+    Ok(build)
+}
+
+/// Generates a JSON descriptor of the layouts for use by Bazel repository
+/// rules, eg. to configure a `cc_toolchain`, with every path relative to the
+/// root of the layouts
+pub fn bazel_descriptor(layouts: &[(Arch, Layout)]) -> Result<String, Error> {
+    let mut targets = Vec::new();
+    for (arch, layout) in layouts {
+        let libs: std::collections::BTreeMap<_, _> = libs(layout)?.into_iter().collect();
+
+        targets.push(serde_json::json!({
+            "arch": arch.as_str(),
+            "triple": arch.as_triple(),
+            "lib_dirs": layout.lib.iter().map(|lib| rel(layout, lib)).collect::<Vec<_>>(),
+            "libs": libs,
+        }));
+    }
+
+    let mut desc = serde_json::to_string_pretty(&serde_json::json!({
+        "root": layouts.first().map(|(_, layout)| fwd(&layout.root)),
+        "include_dirs": layouts.first().map(|(_, layout)| {
+            layout.include.iter().map(|inc| rel(layout, inc)).collect::<Vec<_>>()
+        }),
+        "targets": targets,
+    }))?;
+    desc.push('\n');
+    Ok(desc)
+}
+
+/// Quotes a string for a POSIX shell
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+fn wrapper(program: &str, arch: Arch, args: &[String]) -> String {
+    let mut script = String::from("#!/bin/sh\n");
+    writeln!(
+        script,
+        "# Generated by xwin, targeting {}",
+        arch.as_triple()
+    )
+    .unwrap();
+    writeln!(script, "exec {} \\", program).unwrap();
+    for arg in args {
+        writeln!(script, "    {} \\", sh_quote(arg)).unwrap();
+    }
+    script.push_str("    \"$@\"\n");
+    script
+}
+
+/// Generates a shell script that invokes `clang-cl` targeting the specified
+/// architecture with the includes in the layout, as well as any additional
+/// arguments passed to the script
+pub fn clang_cl_wrapper(layout: &Layout, arch: Arch) -> String {
+    let mut args = vec![
+        format!("--target={}", arch.as_triple()),
+        "-fuse-ld=lld-link".to_owned(),
+    ];
+    args.extend(
+        layout
+            .include
+            .iter()
+            .map(|inc| format!("/imsvc{}", fwd(inc))),
+    );
+
+    wrapper("clang-cl", arch, &args)
+}
+
+/// Generates a shell script that invokes `lld-link` with the libs in the
+/// layout, as well as any additional arguments passed to the script
+pub fn lld_link_wrapper(layout: &Layout, arch: Arch) -> String {
+    let args: Vec<_> = layout
+        .lib
+        .iter()
+        .map(|lib| format!("/libpath:{}", fwd(lib)))
+        .collect();
+
+    wrapper("lld-link", arch, &args)
+}
+
+/// The target zig uses for the architecture, which differs from both the Rust
+/// triple and the LLVM one
+pub fn zig_target(arch: Arch) -> &'static str {
+    match arch {
+        Arch::X86 => "x86-windows-msvc",
+        Arch::X86_64 => "x86_64-windows-msvc",
+        Arch::Aarch => "thumb-windows-msvc",
+        Arch::Aarch64 => "aarch64-windows-msvc",
+    }
+}
+
+/// Generates a libc installation file, the format output by `zig libc`, that
+/// points zig at the CRT and SDK in the layout, as it can only locate them
+/// itself when running on Windows. It is passed to `zig cc` via the `ZIG_LIBC`
+/// environment variable. Both the CRT and the SDK must have been splatted.
+pub fn zig_libc(layout: &Layout) -> Result<String, Error> {
+    let find = |dirs: &[crate::PathBuf], prefix: &str, what: &str| {
+        let prefix = layout.root.join(prefix);
+        dirs.iter()
+            .find(|dir| dir.starts_with(&prefix))
+            .map(|dir| fwd(dir))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "unable to find the {} in '{}', zig requires both the CRT and SDK to be splatted",
+                    what,
+                    layout.root
+                )
+            })
+    };
+
+    let mut libc = String::new();
+
+    libc.push_str(
+        "# Generated by xwin
+",
+    );
+    writeln!(
+        libc,
+        "include_dir={}",
+        find(&layout.include, "sdk/include/ucrt", "UCRT headers")?
+    )
+    .unwrap();
+    writeln!(
+        libc,
+        "sys_include_dir={}",
+        find(&layout.include, "crt/include", "CRT headers")?
+    )
+    .unwrap();
+    writeln!(
+        libc,
+        "crt_dir={}",
+        find(&layout.lib, "sdk/lib/ucrt", "UCRT libraries")?
+    )
+    .unwrap();
+    writeln!(
+        libc,
+        "msvc_lib_dir={}",
+        find(&layout.lib, "crt/lib", "CRT libraries")?
+    )
+    .unwrap();
+    writeln!(
+        libc,
+        "kernel32_lib_dir={}",
+        find(&layout.lib, "sdk/lib/um", "SDK libraries")?
+    )
+    .unwrap();
+    libc.push_str("gcc_dir=\n");
+
+    Ok(libc)
+}
+
+/// Generates a shell script that invokes `zig cc`, or `zig c++`, targeting the
+/// specified architecture with the libc installation file at `libc`, see
+/// [`zig_libc`]. The remaining includes are passed as system includes, and
+/// every library directory is passed in the layout's order, so that they are
+/// searched in the same order as `vcvars` would, before the ones from the libc
+/// file.
+pub fn zig_wrapper(layout: &Layout, arch: Arch, libc: &Path, cxx: bool) -> String {
+    let mut args = vec!["-target".to_owned(), zig_target(arch).to_owned()];
+    args.extend(
+        layout
+            .include
+            .iter()
+            .map(|inc| format!("-isystem{}", fwd(inc))),
+    );
+    args.extend(layout.lib.iter().map(|lib| format!("-L{}", fwd(lib))));
+
+    let program = format!(
+        "env ZIG_LIBC={} zig {}",
+        sh_quote(&fwd(libc)),
+        if cxx { "c++" } else { "cc" }
+    );
+    wrapper(&program, arch, &args)
+}
+
+/// Generates a script to be sourced by a POSIX shell that sets up `INCLUDE`
+/// and `LIB`, like `vcvars`, for running the MSVC tools under Wine against the
+/// layout. The paths are translated to Windows paths with `winepath` when the
+/// script is sourced, as the drive mappings depend on the Wine prefix. If
+/// specified, the directory containing the MSVC tools, eg. `cl.exe`, is added
+/// to both `PATH` and `WINEPATH`.
+pub fn wine_vcvars(layout: &Layout, arch: Arch, bin_dir: Option<&Path>) -> String {
+    let quoted = |dirs: &mut dyn Iterator<Item = &crate::PathBuf>| {
+        dirs.map(|dir| sh_quote(&fwd(dir)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    let mut vv = String::new();
+    writeln!(vv, "# Generated by xwin, targeting {}", arch.as_triple()).unwrap();
+    vv.push_str(
+        r#"# Source this script to run the MSVC tools under Wine against the splat
+
+if ! command -v winepath >/dev/null 2>&1; then
+    echo "winepath was not found in PATH" >&2
+    return 1 2>/dev/null || exit 1
+fi
+
+_xwin_winpaths() {
+    winepath -w "$@" | tr -d '\r' | paste -s -d ';' -
+}
+
+"#,
+    );
+
+    writeln!(
+        vv,
+        "INCLUDE=\"$(_xwin_winpaths {})\"",
+        quoted(&mut layout.include.iter())
+    )
+    .unwrap();
+    writeln!(
+        vv,
+        "LIB=\"$(_xwin_winpaths {})\"",
+        quoted(&mut layout.lib.iter())
+    )
+    .unwrap();
+    vv.push_str("export INCLUDE LIB\n");
+
+    if let Some(bin_dir) = bin_dir {
+        let bin_dir = sh_quote(&fwd(bin_dir));
+        writeln!(
+            vv,
+            "WINEPATH=\"$(_xwin_winpaths {})${{WINEPATH:+;$WINEPATH}}\"",
+            bin_dir
+        )
+        .unwrap();
+        writeln!(vv, "PATH={}\":$PATH\"", bin_dir).unwrap();
+        vv.push_str("export WINEPATH PATH\n");
+    }
+
+    vv.push_str("\nunset -f _xwin_winpaths\n");
+    vv
+}
+
+/// The name of the platform used by `MSBuild` for the architecture
+fn msbuild_platform(arch: Arch) -> &'static str {
+    match arch {
+        Arch::X86 => "Win32",
+        Arch::X86_64 => "x64",
+        Arch::Aarch => "ARM",
+        Arch::Aarch64 => "ARM64",
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\'', "&apos;")
+}
+
+/// The path as seen under Wine, which maps the root of the host filesystem
+/// to the `Z:` drive by default
+fn wine_path(path: &Path) -> String {
+    format!("Z:{}", path.as_str().replace('/', "\\"))
+}
+
+/// Generates a `Toolchain.props` that points the `MSBuild` VC targets at the
+/// includes and libs of each layout for its architecture's platform, along
+/// with the SDK tools if they were splatted. The props file is written to the
+/// root of the splat, and paths are relative to it, so it works no matter
+/// which drive the splat is mapped to under Wine.
+pub fn msbuild_toolchain_props(root: &Path, layouts: &[(Arch, Layout)]) -> String {
+    let path = |path: &Path| {
+        xml_escape(&match path.strip_prefix(root) {
+            Ok(rel) => format!(
+                "$(MSBuildThisFileDirectory){}",
+                rel.as_str().replace('/', "\\")
+            ),
+            Err(_) => wine_path(path),
+        })
+    };
+    let paths = |dirs: &[crate::PathBuf]| {
+        dirs.iter()
+            .map(|dir| path(dir))
+            .collect::<Vec<_>>()
+            .join(";")
+    };
+
+    let mut props = String::new();
+    props.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<!-- Generated by xwin -->\n");
+    props.push_str("<Project xmlns=\"http://schemas.microsoft.com/developer/msbuild/2003\">\n");
+
+    // The tools are run on the host, so only the x86 and x64 ones are usable
+    let sdk_bin = [Arch::X86_64, Arch::X86].iter().find_map(|arch| {
+        [arch.as_str(), arch.as_ms_str()]
+            .iter()
+            .map(|name| root.join("sdk/bin").join(name))
+            .find(|dir| dir.exists())
+    });
+
+    if let Some(sdk_bin) = sdk_bin {
+        props.push_str("  <PropertyGroup>\n");
+        writeln!(
+            props,
+            "    <ExecutablePath>{};$(ExecutablePath)</ExecutablePath>",
+            path(&sdk_bin)
+        )
+        .unwrap();
+        props.push_str("  </PropertyGroup>\n");
+    }
+
+    for (arch, layout) in layouts {
+        writeln!(
+            props,
+            "  <PropertyGroup Condition=\"'$(Platform)' == '{}'\">",
+            msbuild_platform(*arch)
+        )
+        .unwrap();
+        writeln!(
+            props,
+            "    <IncludePath>{}</IncludePath>",
+            paths(&layout.include)
+        )
+        .unwrap();
+        writeln!(
+            props,
+            "    <LibraryPath>{}</LibraryPath>",
+            paths(&layout.lib)
+        )
+        .unwrap();
+        props.push_str("  </PropertyGroup>\n");
+    }
+
+    props.push_str("</Project>\n");
+    props
+}
+
+/// Generates a `Directory.Build.props` that imports the `Toolchain.props` in
+/// the root of the splat, which is located via the `XwinRoot` property so
+/// that it can be overridden, eg. via an environment variable, if the splat
+/// isn't at the same path under Wine's `Z:` drive
+pub fn msbuild_directory_props(root: &Path) -> String {
+    let mut props = String::new();
+    props.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<!-- Generated by xwin -->\n");
+    props.push_str("<Project xmlns=\"http://schemas.microsoft.com/developer/msbuild/2003\">\n");
+    props.push_str("  <PropertyGroup>\n");
+    writeln!(
+        props,
+        "    <XwinRoot Condition=\"'$(XwinRoot)' == ''\">{}\\</XwinRoot>",
+        xml_escape(wine_path(root).trim_end_matches('\\'))
+    )
+    .unwrap();
+    props.push_str("  </PropertyGroup>\n");
+    props.push_str("  <Import Project=\"$(XwinRoot)Toolchain.props\" />\n");
+    props.push_str("</Project>\n");
+    props
+}
+
+/// Generates a `compile_flags.txt` for clangd, targeting the specified
+/// architecture with the includes in the layout as system includes, in the
+/// same order they are searched by the compiler
+pub fn compile_flags(layout: &Layout, arch: Arch) -> String {
+    let mut cf = String::new();
+
+    writeln!(cf, "--target={}", arch.as_triple()).unwrap();
+    for inc in &layout.include {
+        writeln!(cf, "-isystem{}", fwd(inc)).unwrap();
+    }
+
+    cf
+}
+
+/// Generates a pkg-config file for a `windows-sdk` package, with the includes
+/// and lib directories in the layout, relative to a `prefix` of its root
+pub fn pkg_config(layout: &Layout, arch: Arch) -> String {
+    // pkg-config splits on unescaped whitespace, and paths under the root are
+    // relative to the prefix so it can be overridden via `--define-prefix`
+    let path = |path: &Path| {
+        let path = match path.strip_prefix(&layout.root) {
+            Ok(rel) => format!("${{prefix}}/{}", fwd(rel)),
+            Err(_) => fwd(path),
+        };
+        path.replace(' ', "\\ ")
+    };
+
+    let mut pc = String::new();
+
+    writeln!(pc, "# Generated by xwin, targeting {}", arch.as_triple()).unwrap();
+    writeln!(pc, "prefix={}\n", fwd(&layout.root).replace(' ', "\\ ")).unwrap();
+    pc.push_str("Name: windows-sdk\n");
+    writeln!(
+        pc,
+        "Description: The MSVC CRT and Windows SDK for {}",
+        arch.as_triple()
+    )
+    .unwrap();
+    pc.push_str("Version: 10.0\n");
+
+    pc.push_str("Cflags:");
+    for inc in &layout.include {
+        write!(pc, " -imsvc{}", path(inc)).unwrap();
+    }
+    pc.push_str("\nLibs:");
+    for lib in &layout.lib {
+        write!(pc, " -L{}", path(lib)).unwrap();
+    }
+    pc.push('\n');
+
+    pc
+}
+
+/// Generates a VS Code `c_cpp_properties.json` with a configuration for each
+/// layout's architecture
+pub fn c_cpp_properties(layouts: &[(Arch, Layout)]) -> String {
+    let configurations: Vec<_> = layouts
+        .iter()
+        .map(|(arch, layout)| {
+            let mode = match arch {
+                Arch::X86 => "windows-clang-x86",
+                Arch::X86_64 => "windows-clang-x64",
+                Arch::Aarch => "windows-clang-arm",
+                Arch::Aarch64 => "windows-clang-arm64",
+            };
+
+            let mut include_path = vec!["${workspaceFolder}/**".to_owned()];
+            include_path.extend(layout.include.iter().map(|inc| fwd(inc)));
+
+            serde_json::json!({
+                "name": format!("xwin-{}", arch),
+                "includePath": include_path,
+                "intelliSenseMode": mode,
+                "compilerArgs": [format!("--target={}", arch.as_triple())],
+            })
+        })
+        .collect();
+
+    let mut props = serde_json::to_string_pretty(&serde_json::json!({
+        "configurations": configurations,
+        "version": 4,
+    }))
+    .unwrap();
+    props.push('\n');
+    props
+}
+
+/// The format of the environment output by [`env`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EnvFormat {
+    /// `export` statements for POSIX shells
+    Sh,
+    /// `set -gx` statements for fish
+    Fish,
+    /// `$env:` assignments for PowerShell
+    Powershell,
+    /// Unquoted `NAME=value` lines, as read by `docker --env-file` or a
+    /// Makefile `include`
+    Dotenv,
+    /// A JSON object of names to values
+    Json,
+}
+
+impl std::str::FromStr for EnvFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "sh" => Self::Sh,
+            "fish" => Self::Fish,
+            "powershell" => Self::Powershell,
+            "dotenv" => Self::Dotenv,
+            "json" => Self::Json,
+            o => anyhow::bail!("unknown env format '{}'", o),
+        })
+    }
+}
+
+/// The environment variables needed to compile and link against the layout,
+/// ie. `INCLUDE`, `LIB`, `CL`, `LINK`, the `CFLAGS_<target>`/`CXXFLAGS_<target>`
+/// used by the `cc` crate, and `BINDIR`, the directory `xwin generate wrappers`
+/// writes to by default
+pub fn env_vars(layout: &Layout, arch: Arch) -> Vec<(String, String)> {
+    let join = |dirs: &[crate::PathBuf], prefix: &str, sep: &str| {
+        dirs.iter()
+            .map(|dir| format!("{}{}", prefix, dir))
+            .collect::<Vec<_>>()
+            .join(sep)
+    };
+
+    // clang-cl and lld-link split `INCLUDE` and `LIB` on `;` regardless of
+    // the host platform, just like cl and link
+    let cflags = join(&layout.include, "-imsvc ", " ");
+    let triple = arch.as_triple().replace('-', "_");
+
+    vec![
+        ("INCLUDE".to_owned(), join(&layout.include, "", ";")),
+        ("LIB".to_owned(), join(&layout.lib, "", ";")),
+        ("CL".to_owned(), join(&layout.include, "/imsvc", " ")),
+        ("LINK".to_owned(), join(&layout.lib, "/libpath:", " ")),
+        (format!("CFLAGS_{}", triple), cflags.clone()),
+        (format!("CXXFLAGS_{}", triple), cflags),
+        (
+            "BINDIR".to_owned(),
+            layout.root.join("bin").join(arch.as_str()).into_string(),
+        ),
+    ]
+}
+
+/// Formats the [`env_vars`] for the layout in the specified format
+pub fn env(layout: &Layout, arch: Arch, format: EnvFormat) -> Result<String, Error> {
+    let vars = env_vars(layout, arch);
+    let mut env = String::new();
+
+    match format {
+        EnvFormat::Sh => {
+            for (name, value) in &vars {
+                writeln!(env, "export {}={}", name, sh_quote(value)).unwrap();
+            }
+        }
+        EnvFormat::Fish => {
+            for (name, value) in &vars {
+                writeln!(
+                    env,
+                    "set -gx {} '{}'",
+                    name,
+                    value.replace('\\', r"\\").replace('\'', r"\'")
+                )
+                .unwrap();
+            }
+        }
+        EnvFormat::Powershell => {
+            for (name, value) in &vars {
+                writeln!(env, "$env:{} = '{}'", name, value.replace('\'', "''")).unwrap();
+            }
+        }
+        EnvFormat::Dotenv => {
+            for (name, value) in &vars {
+                writeln!(env, "{}={}", name, value).unwrap();
+            }
+        }
+        EnvFormat::Json => {
+            let map: std::collections::BTreeMap<_, _> = vars.into_iter().collect();
+            env = serde_json::to_string_pretty(&map)?;
+            env.push('\n');
+        }
+    }
+
+    Ok(env)
+}
+
+/// Writes `env.sh`, `env.ps1`, and `.env` files for the layout to its root, so
+/// that eg. Docker entrypoints and Makefiles can source the environment rather
+/// than hard coding paths
+pub fn write_env_files(layout: &Layout, arch: Arch) -> Result<(), Error> {
+    use anyhow::Context as _;
+
+    for (name, format) in [
+        ("env.sh", EnvFormat::Sh),
+        ("env.ps1", EnvFormat::Powershell),
+        (".env", EnvFormat::Dotenv),
+    ]
+    .iter()
+    {
+        let path = layout.root.join(name);
+        std::fs::write(&path, env(layout, arch, *format)?)
+            .with_context(|| format!("unable to write {}", path))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn layout() -> Layout {
+        Layout {
+            root: "/xwin".into(),
+            include: vec!["/xwin/crt/include".into(), "/xwin/sdk/include/um".into()],
+            lib: vec![
+                "/xwin/crt/lib/x86_64".into(),
+                "/xwin/sdk/lib/um/x86_64".into(),
+            ],
+            store: false,
+        }
+    }
+
+    #[test]
+    fn cmake() {
+        let tc = cmake_toolchain(&layout(), Arch::X86_64);
+
+        assert!(tc.contains("set(CMAKE_SYSTEM_PROCESSOR AMD64)"));
+        assert!(tc.contains("\"--target=x86_64-pc-windows-msvc\"\n    \"/imsvc/xwin/crt/include\"\n    \"/imsvc/xwin/sdk/include/um\"\n)"));
+        assert!(tc.contains("\"/libpath:/xwin/sdk/lib/um/x86_64\""));
+    }
+
+    #[test]
+    fn meson() {
+        let cf = meson_cross(&layout(), Arch::Aarch64);
+
+        assert!(cf.contains("xwin_compile_args = [\n    '--target=aarch64-pc-windows-msvc',\n    '/imsvc/xwin/crt/include',\n    '/imsvc/xwin/sdk/include/um',\n]"));
+        assert!(cf.contains("'/libpath:/xwin/crt/lib/x86_64',"));
+        assert!(cf.contains("cpu_family = 'aarch64'"));
+    }
+
+    #[test]
+    fn wrappers() {
+        assert_eq!(
+            lld_link_wrapper(&layout(), Arch::X86_64),
+            "#!/bin/sh
+# Generated by xwin, targeting x86_64-pc-windows-msvc
+exec lld-link \\
+    '/libpath:/xwin/crt/lib/x86_64' \\
+    '/libpath:/xwin/sdk/lib/um/x86_64' \\
+    \"$@\"
+"
+        );
+
+        assert_eq!(sh_quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn zig() {
+        let full = Layout {
+            root: "/xwin".into(),
+            include: vec![
+                "/xwin/crt/include".into(),
+                "/xwin/sdk/include/ucrt".into(),
+                "/xwin/sdk/include/um".into(),
+            ],
+            lib: vec![
+                "/xwin/crt/lib/x86_64".into(),
+                "/xwin/sdk/lib/ucrt/x86_64".into(),
+                "/xwin/sdk/lib/um/x86_64".into(),
+            ],
+            store: false,
+        };
+
+        assert_eq!(
+            zig_libc(&full).unwrap(),
+            "# Generated by xwin
+include_dir=/xwin/sdk/include/ucrt
+sys_include_dir=/xwin/crt/include
+crt_dir=/xwin/sdk/lib/ucrt/x86_64
+msvc_lib_dir=/xwin/crt/lib/x86_64
+kernel32_lib_dir=/xwin/sdk/lib/um/x86_64
+gcc_dir=
+"
+        );
+
+        let cc = zig_wrapper(
+            &full,
+            Arch::X86_64,
+            Path::new("/xwin/zig-libc-x86_64.txt"),
+            false,
+        );
+        assert!(cc.contains("exec env ZIG_LIBC='/xwin/zig-libc-x86_64.txt' zig cc \\\n    '-target' \\\n    'x86_64-windows-msvc' \\\n"));
+        assert!(
+            cc.contains("    '-isystem/xwin/sdk/include/um' \\\n    '-L/xwin/crt/lib/x86_64' \\\n")
+        );
+
+        // The libs from layout() are missing the UCRT
+        assert!(zig_libc(&layout()).is_err());
+    }
+
+    #[test]
+    fn vcvars() {
+        let vv = wine_vcvars(&layout(), Arch::X86_64, Some(Path::new("/msvc/bin/x64")));
+        assert!(vv.contains(
+            "INCLUDE=\"$(_xwin_winpaths '/xwin/crt/include' '/xwin/sdk/include/um')\"\n"
+        ));
+        assert!(
+            vv.contains("WINEPATH=\"$(_xwin_winpaths '/msvc/bin/x64')${WINEPATH:+;$WINEPATH}\"\n")
+        );
+        assert!(vv.contains("PATH='/msvc/bin/x64'\":$PATH\"\n"));
+
+        assert!(!wine_vcvars(&layout(), Arch::X86_64, None).contains("WINEPATH"));
+    }
+
+    #[test]
+    fn editors() {
+        assert_eq!(
+            compile_flags(&layout(), Arch::X86),
+            "--target=i686-pc-windows-msvc\n-isystem/xwin/crt/include\n-isystem/xwin/sdk/include/um\n"
+        );
+
+        let props: serde_json::Value =
+            serde_json::from_str(&c_cpp_properties(&[(Arch::Aarch64, layout())])).unwrap();
+        let config = &props["configurations"][0];
+        assert_eq!(config["intelliSenseMode"], "windows-clang-arm64");
+        assert_eq!(config["includePath"][1], "/xwin/crt/include");
+    }
+
+    #[test]
+    fn pkg_config_file() {
+        let mut layout = layout();
+        layout.lib.push("/other lib".into());
+
+        let pc = pkg_config(&layout, Arch::X86_64);
+        assert!(pc.contains("prefix=/xwin\n\nName: windows-sdk\n"));
+        assert!(pc.contains("Cflags: -imsvc${prefix}/crt/include -imsvc${prefix}/sdk/include/um\n"));
+        assert!(pc.contains(
+            "Libs: -L${prefix}/crt/lib/x86_64 -L${prefix}/sdk/lib/um/x86_64 -L/other\\ lib\n"
+        ));
+    }
+
+    #[test]
+    fn msbuild() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = crate::PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        std::fs::create_dir_all(root.join("sdk/bin/x86_64")).unwrap();
+
+        let layout = Layout {
+            root: root.clone(),
+            include: vec![root.join("crt/include")],
+            lib: vec![root.join("crt/lib/aarch64"), "/other/lib".into()],
+            store: false,
+        };
+
+        let props = msbuild_toolchain_props(&root, &[(Arch::Aarch64, layout)]);
+        assert!(props.contains("<ExecutablePath>$(MSBuildThisFileDirectory)sdk\\bin\\x86_64;$(ExecutablePath)</ExecutablePath>"));
+        assert!(props.contains("<PropertyGroup Condition=\"'$(Platform)' == 'ARM64'\">\n    <IncludePath>$(MSBuildThisFileDirectory)crt\\include</IncludePath>\n"));
+        assert!(props.contains("<LibraryPath>$(MSBuildThisFileDirectory)crt\\lib\\aarch64;Z:\\other\\lib</LibraryPath>"));
+
+        let props = msbuild_directory_props(Path::new("/xwin/"));
+        assert!(props.contains("<XwinRoot Condition=\"'$(XwinRoot)' == ''\">Z:\\xwin\\</XwinRoot>"));
+        assert!(props.contains("<Import Project=\"$(XwinRoot)Toolchain.props\" />"));
+    }
+
+    #[test]
+    fn envs() {
+        let dotenv = env(&layout(), Arch::X86_64, EnvFormat::Dotenv).unwrap();
+        assert!(dotenv.contains("INCLUDE=/xwin/crt/include;/xwin/sdk/include/um\n"));
+        assert!(dotenv.contains("BINDIR=/xwin/bin/x86_64\n"));
+
+        let sh = env(&layout(), Arch::X86_64, EnvFormat::Sh).unwrap();
+        assert!(sh.contains(
+            "export LINK='/libpath:/xwin/crt/lib/x86_64 /libpath:/xwin/sdk/lib/um/x86_64'\n"
+        ));
+    }
+
+    #[test]
+    fn bazel() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = crate::PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        let layout = Layout {
+            root: root.clone(),
+            include: vec![root.join("crt/include")],
+            lib: vec![root.join("crt/lib/x86_64"), root.join("sdk/lib/um/x86_64")],
+            store: false,
+        };
+
+        for lib in &layout.lib {
+            std::fs::create_dir_all(lib).unwrap();
+        }
+        std::fs::write(root.join("crt/lib/x86_64/libcmt.lib"), "").unwrap();
+        std::fs::write(root.join("sdk/lib/um/x86_64/kernel32.Lib"), "").unwrap();
+        std::fs::write(root.join("sdk/lib/um/x86_64/LIBCMT.lib"), "").unwrap();
+        std::fs::write(root.join("sdk/lib/um/x86_64/readme.txt"), "").unwrap();
+
+        let layouts = [(Arch::X86_64, layout)];
+        let build = bazel_build(&layouts).unwrap();
+        assert!(build.contains("    includes = [\n        \"crt/include\",\n    ],"));
+        assert!(build.contains(
+            "name = \"x86_64_kernel32\",\n    static_library = \"sdk/lib/um/x86_64/kernel32.Lib\","
+        ));
+        assert!(!build.contains("LIBCMT"));
+        assert!(!build.contains("readme"));
+
+        let desc: serde_json::Value =
+            serde_json::from_str(&bazel_descriptor(&layouts).unwrap()).unwrap();
+        let target = &desc["targets"][0];
+        assert_eq!(target["triple"], "x86_64-pc-windows-msvc");
+        assert_eq!(target["libs"]["libcmt"], "crt/lib/x86_64/libcmt.lib");
+    }
+
+    #[test]
+    fn cargo() {
+        let cc = cargo_config(&[(Arch::X86_64, layout())]);
+
+        let config: toml::Value = toml::from_str(&cc).unwrap();
+        let target = &config["target"]["x86_64-pc-windows-msvc"];
+        assert_eq!(target["linker"].as_str(), Some("lld-link"));
+        assert_eq!(
+            target["rustflags"][1].as_str(),
+            Some("-Lnative=/xwin/sdk/lib/um/x86_64")
+        );
+        assert_eq!(
+            config["env"]["CFLAGS_x86_64_pc_windows_msvc"].as_str(),
+            Some("/imsvc/xwin/crt/include /imsvc/xwin/sdk/include/um")
+        );
+
+        // Store layouts are for the UWP targets
+        let mut store = layout();
+        store.store = true;
+        let config: toml::Value = toml::from_str(&cargo_config(&[(Arch::Aarch64, store)])).unwrap();
+        assert!(config["target"].get("aarch64-uwp-windows-msvc").is_some());
+        assert!(config["env"].get("CC_aarch64_uwp_windows_msvc").is_some());
+    }
+}
@@ -0,0 +1,326 @@
+//! Restoring and saving the download cache, or a finished splat, with the
+//! [GitHub Actions cache service](https://docs.github.com/en/actions/using-workflows/caching-dependencies-to-speed-up-workflows),
+//! so that workflows don't need to wrap xwin in their own `actions/cache`
+//! steps.
+//!
+//! Entries are gzipped tarballs, keyed by the checksums of the payloads that
+//! the selection resolved to, so a cache entry is only used if it was created
+//! from exactly the same payloads.
+
+use crate::{util::Sha256, Path, Payload};
+use anyhow::{Context as _, Error};
+use std::io::{Read, Seek, SeekFrom};
+
+/// What is restored from and saved to the cache
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CacheMode {
+    /// The downloaded payloads in the cache directory, which are then
+    /// unpacked and splatted as normal
+    Downloads,
+    /// The finished splat output, in which case nothing needs to be done at
+    /// all if it is restored
+    Splat,
+}
+
+impl CacheMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Downloads => "downloads",
+            Self::Splat => "splat",
+        }
+    }
+}
+
+impl std::str::FromStr for CacheMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "downloads" => Self::Downloads,
+            "splat" => Self::Splat,
+            o => anyhow::bail!("unknown cache mode '{}'", o),
+        })
+    }
+}
+
+/// Creates the key for a cache entry from the payloads that were selected,
+/// along with anything else that affects the contents of the entry, eg. the
+/// splat options
+pub fn key(mode: CacheMode, payloads: &[Payload], extra: &[String]) -> String {
+    let mut ids: Vec<_> = payloads
+        .iter()
+        .map(|payload| format!("{} {}", payload.sha256, payload.filename))
+        .collect();
+    ids.sort();
+    ids.extend(extra.iter().cloned());
+
+    let hash = Sha256::digest(ids.join("\n").as_bytes());
+    format!("xwin-{}-{}", mode.as_str(), hash)
+}
+
+/// Hashes the relative path and contents of every file in the directory, so
+/// that the key changes if any of them do, eg. the patches applied to a splat
+pub fn dir_digest(dir: &Path) -> Result<Sha256, Error> {
+    let mut files = crate::util::walk_files(dir)?;
+    files.sort();
+
+    let mut ids = Vec::with_capacity(files.len());
+    for path in files {
+        let contents = std::fs::read(&path).with_context(|| format!("unable to read {}", path))?;
+        ids.push(format!(
+            "{} {}",
+            Sha256::digest(&contents),
+            path.strip_prefix(dir)?
+        ));
+    }
+
+    Ok(Sha256::digest(ids.join("\n").as_bytes()))
+}
+
+/// Blocks are uploaded 32MiB at a time
+const BLOCK_SIZE: usize = 32 * 1024 * 1024;
+
+/// A client for the cache service used by GitHub Actions
+pub struct Client {
+    base_url: String,
+    token: String,
+    http: reqwest::blocking::Client,
+}
+
+impl Client {
+    /// Creates a client from the environment variables that are set in a
+    /// GitHub Actions job, returning `None` if they are not present
+    pub fn from_env() -> Result<Option<Self>, Error> {
+        let (base_url, token) = match (
+            std::env::var("ACTIONS_RESULTS_URL"),
+            std::env::var("ACTIONS_RUNTIME_TOKEN"),
+        ) {
+            (Ok(base_url), Ok(token)) => (base_url, token),
+            _ => return Ok(None),
+        };
+
+        Ok(Some(Self {
+            base_url: format!(
+                "{}/twirp/github.actions.results.api.v1.CacheService",
+                base_url.trim_end_matches('/')
+            ),
+            token,
+            http: reqwest::blocking::Client::builder()
+                // Entries can be large, so don't time out while transferring them
+                .timeout(None)
+                .build()?,
+        }))
+    }
+
+    /// The version of an entry must match for it to be restored, which
+    /// ensures that entries in a different format are never used
+    fn version(mode: CacheMode) -> String {
+        Sha256::digest(format!("xwin {} tar.gz", mode.as_str()).as_bytes()).to_string()
+    }
+
+    fn call<T: serde::de::DeserializeOwned>(
+        &self,
+        method: &str,
+        body: serde_json::Value,
+    ) -> Result<T, Error> {
+        let res = self
+            .http
+            .post(format!("{}/{}", self.base_url, method))
+            .bearer_auth(&self.token)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(&body)?)
+            .send()
+            .with_context(|| format!("failed to call {}", method))?
+            .error_for_status()
+            .with_context(|| format!("{} failed", method))?;
+
+        serde_json::from_reader(res).with_context(|| format!("invalid {} response", method))
+    }
+
+    /// Restores the entry for the key into `dir`, returning false if there
+    /// is no entry for the key. When restoring a splat, the output is deleted
+    /// first so that no stale files are left in it.
+    pub fn restore(&self, mode: CacheMode, key: &str, dir: &Path) -> Result<bool, Error> {
+        #[derive(serde::Deserialize)]
+        struct Response {
+            ok: bool,
+            #[serde(default)]
+            signed_download_url: String,
+        }
+
+        let res: Response = self.call(
+            "GetCacheEntryDownloadURL",
+            serde_json::json!({
+                "key": key,
+                "restore_keys": [],
+                "version": Self::version(mode),
+            }),
+        )?;
+
+        if !res.ok || res.signed_download_url.is_empty() {
+            return Ok(false);
+        }
+
+        let mut archive = tempfile::tempfile().context("unable to create temporary file")?;
+        self.http
+            .get(&res.signed_download_url)
+            .send()
+            .and_then(|res| res.error_for_status())
+            .context("failed to download cache entry")?
+            .copy_to(&mut archive)
+            .context("failed to download cache entry")?;
+        archive.seek(SeekFrom::Start(0))?;
+
+        if mode == CacheMode::Splat && dir.exists() {
+            std::fs::remove_dir_all(dir).with_context(|| format!("unable to remove {}", dir))?;
+        }
+        std::fs::create_dir_all(dir).with_context(|| format!("unable to create {}", dir))?;
+
+        tar::Archive::new(flate2::read::GzDecoder::new(archive))
+            .unpack(dir)
+            .with_context(|| format!("unable to unpack cache entry into {}", dir))?;
+
+        Ok(true)
+    }
+
+    /// Saves the contents of `dir` as the entry for the key. Entries are
+    /// immutable, so this fails if an entry for the key was already created,
+    /// eg. by a concurrent job.
+    pub fn save(&self, mode: CacheMode, key: &str, dir: &Path) -> Result<(), Error> {
+        #[derive(serde::Deserialize)]
+        struct CreateResponse {
+            ok: bool,
+            #[serde(default)]
+            signed_upload_url: String,
+        }
+
+        #[derive(serde::Deserialize)]
+        struct FinalizeResponse {
+            ok: bool,
+        }
+
+        let mut archive = tempfile::tempfile().context("unable to create temporary file")?;
+        crate::util::tar_gz(dir, &mut archive)?;
+        let size = archive.seek(SeekFrom::End(0))?;
+        archive.seek(SeekFrom::Start(0))?;
+
+        let version = Self::version(mode);
+
+        let res: CreateResponse = self.call(
+            "CreateCacheEntry",
+            serde_json::json!({
+                "key": key,
+                "version": version,
+            }),
+        )?;
+
+        anyhow::ensure!(
+            res.ok && !res.signed_upload_url.is_empty(),
+            "unable to create cache entry '{}', it may already exist",
+            key
+        );
+
+        // The upload url is for an Azure blob, which is uploaded in blocks so
+        // that large entries don't need to be sent in a single request
+        let mut block_ids = Vec::new();
+        let mut block = vec![0u8; BLOCK_SIZE];
+        loop {
+            let mut len = 0;
+            while len < block.len() {
+                let read = archive.read(&mut block[len..])?;
+                if read == 0 {
+                    break;
+                }
+                len += read;
+            }
+
+            if len == 0 {
+                break;
+            }
+
+            let block_id = base64::encode(format!("{:08}", block_ids.len()));
+            self.http
+                .put(format!(
+                    "{}&comp=block&blockid={}",
+                    res.signed_upload_url,
+                    url_encode(&block_id)
+                ))
+                .body(block[..len].to_vec())
+                .send()
+                .and_then(|res| res.error_for_status())
+                .context("failed to upload cache entry")?;
+            block_ids.push(block_id);
+        }
+
+        let mut block_list = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?><BlockList>");
+        for block_id in &block_ids {
+            block_list.push_str("<Latest>");
+            block_list.push_str(block_id);
+            block_list.push_str("</Latest>");
+        }
+        block_list.push_str("</BlockList>");
+
+        self.http
+            .put(format!("{}&comp=blocklist", res.signed_upload_url))
+            .body(block_list)
+            .send()
+            .and_then(|res| res.error_for_status())
+            .context("failed to commit cache entry")?;
+
+        let res: FinalizeResponse = self.call(
+            "FinalizeCacheEntryUpload",
+            serde_json::json!({
+                "key": key,
+                "version": version,
+                "size_bytes": size.to_string(),
+            }),
+        )?;
+
+        anyhow::ensure!(res.ok, "unable to finalize cache entry '{}'", key);
+        Ok(())
+    }
+}
+
+/// Block ids are base64, so only the characters that are significant in a
+/// query string need to be escaped
+fn url_encode(s: &str) -> String {
+    s.replace('+', "%2B")
+        .replace('/', "%2F")
+        .replace('=', "%3D")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keys_are_order_independent() {
+        let payload = |filename: &str, sha: u8| Payload {
+            sha256: Sha256([sha; 32]),
+            ..Payload::test(filename, crate::PayloadKind::SdkLibs)
+        };
+
+        let a = payload("a.msi", 1);
+        let b = payload("b.msi", 2);
+
+        let splat = key(CacheMode::Splat, &[a.clone(), b.clone()], &[]);
+        assert!(splat.starts_with("xwin-splat-"));
+        assert_eq!(splat, key(CacheMode::Splat, &[b, a.clone()], &[]));
+        assert_ne!(splat, key(CacheMode::Downloads, &[a], &[]));
+    }
+
+    #[test]
+    fn patches_change_the_digest() {
+        let td = tempfile::TempDir::new().unwrap();
+        let dir = crate::PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        std::fs::create_dir_all(dir.join("files/sdk")).unwrap();
+        std::fs::write(dir.join("a.patch"), "a").unwrap();
+        std::fs::write(dir.join("files/sdk/b.h"), "b").unwrap();
+
+        let digest = dir_digest(&dir).unwrap();
+        assert_eq!(digest, dir_digest(&dir).unwrap());
+
+        std::fs::write(dir.join("files/sdk/b.h"), "c").unwrap();
+        assert_ne!(digest, dir_digest(&dir).unwrap());
+    }
+}
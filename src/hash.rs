@@ -0,0 +1,146 @@
+//! A stable, Merkle style hash of an output directory, so that a splat can be
+//! identified by a single value, eg. to key CI caches or detect drift between
+//! machines.
+//!
+//! Every node in the tree is hashed separately, and a directory's hash covers
+//! the name and hash of each of its entries, sorted by the bytes of their
+//! name. Only file contents, symlink targets, and relative paths are hashed,
+//! so the hash doesn't depend on timestamps, permissions, or the order the
+//! filesystem returns entries in.
+
+use crate::{util::Sha256, Path};
+use anyhow::{Context as _, Error};
+
+/// Hashes a single node, prefixed by its kind so that eg. a file can't have
+/// the same hash as a symlink whose target is the file's contents
+fn digest(kind: &[u8], parts: &[&[u8]]) -> Sha256 {
+    let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+    ctx.update(kind);
+    ctx.update(b"\0");
+    for part in parts {
+        ctx.update(part);
+    }
+
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(ctx.finish().as_ref());
+    Sha256(hash)
+}
+
+/// Hashes a directory from the names and hashes of the specified entries
+fn dir(path: &Path, names: &[String]) -> Result<Sha256, Error> {
+    use rayon::prelude::*;
+
+    let hashes = names
+        .par_iter()
+        .map(|name| node(&path.join(name)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut parts = Vec::with_capacity(names.len() * 3);
+    for (name, hash) in names.iter().zip(&hashes) {
+        parts.push(name.as_bytes());
+        parts.push(b"\0");
+        parts.push(&hash.0[..]);
+    }
+
+    Ok(digest(b"dir", &parts))
+}
+
+fn node(path: &Path) -> Result<Sha256, Error> {
+    let md = std::fs::symlink_metadata(path)
+        .with_context(|| format!("unable to read metadata for {}", path))?;
+
+    if md.file_type().is_symlink() {
+        let target =
+            std::fs::read_link(path).with_context(|| format!("unable to read symlink {}", path))?;
+        let target = target
+            .to_str()
+            .with_context(|| format!("symlink {} has a non utf-8 target", path))?
+            .replace('\\', "/");
+
+        Ok(digest(b"symlink", &[target.as_bytes()]))
+    } else if md.is_dir() {
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(path).with_context(|| format!("unable to read {}", path))? {
+            let entry = entry?;
+            names.push(
+                entry.file_name().into_string().map_err(|name| {
+                    anyhow::anyhow!("{} contains non utf-8 entry {:?}", path, name)
+                })?,
+            );
+        }
+
+        names.sort();
+        dir(path, &names)
+    } else {
+        let contents = std::fs::read(path).with_context(|| format!("unable to read {}", path))?;
+        Ok(digest(b"file", &[&Sha256::digest(&contents).0[..]]))
+    }
+}
+
+/// Computes the hash of the tree at `root`
+pub fn tree_hash(root: &Path) -> Result<Sha256, Error> {
+    anyhow::ensure!(root.is_dir(), "{} is not a directory", root);
+    node(root)
+}
+
+/// Computes the hash of only the `crt` and `sdk` directories of the splat at
+/// `root`, ignoring the files that are generated into the root of the output,
+/// eg. env files and checksums, which can differ between machines for the
+/// same splat
+pub fn content_hash(root: &Path) -> Result<Sha256, Error> {
+    anyhow::ensure!(root.is_dir(), "{} is not a directory", root);
+
+    let names: Vec<_> = ["crt", "sdk"]
+        .iter()
+        .filter(|name| root.join(name).exists())
+        .map(|name| (*name).to_owned())
+        .collect();
+    dir(root, &names)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn is_stable() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = crate::PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        let make = |name: &str, contents: &str| {
+            let dir = root.join(name);
+            std::fs::create_dir_all(dir.join("include")).unwrap();
+            std::fs::write(dir.join("include/a.h"), contents).unwrap();
+            std::fs::write(dir.join("b.lib"), "lib").unwrap();
+            dir
+        };
+
+        let one = make("one", "a");
+        let two = make("two", "a");
+        let three = make("three", "changed");
+
+        assert_eq!(tree_hash(&one).unwrap(), tree_hash(&two).unwrap());
+        assert_ne!(tree_hash(&one).unwrap(), tree_hash(&three).unwrap());
+
+        // Moving a file changes the hash even though the contents are the same
+        std::fs::rename(two.join("b.lib"), two.join("include/b.lib")).unwrap();
+        assert_ne!(tree_hash(&one).unwrap(), tree_hash(&two).unwrap());
+    }
+
+    #[test]
+    fn content_ignores_generated_files() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = crate::PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        std::fs::create_dir_all(root.join("sdk/include")).unwrap();
+        std::fs::write(root.join("sdk/include/a.h"), "a").unwrap();
+
+        let before = content_hash(&root).unwrap();
+        std::fs::write(root.join("env.sh"), "export LIB=/xwin").unwrap();
+        assert_eq!(before, content_hash(&root).unwrap());
+        assert_ne!(before, tree_hash(&root).unwrap());
+
+        std::fs::write(root.join("sdk/include/a.h"), "changed").unwrap();
+        assert_ne!(before, content_hash(&root).unwrap());
+    }
+}
@@ -0,0 +1,247 @@
+//! The HTTP client used to retrieve manifests and payloads, which can be
+//! replaced by embedders, eg. to add authentication or instrumentation, or to
+//! serve recorded fixtures in tests so that the rest of the pipeline can be
+//! run offline
+
+/// The response to a successful request
+pub struct Response {
+    /// The length of the body, if known, which is used to report progress
+    pub content_length: Option<u64>,
+    pub body: Box<dyn std::io::Read + Send>,
+}
+
+/// Performs the requests used to retrieve manifests and payloads.
+///
+/// Any error returned by the client, or while reading the body of a
+/// response, is classified as a network error. Note that this is only used by
+/// the blocking API, the async API always uses [`reqwest`].
+pub trait HttpClient: Send + Sync {
+    /// Sends a GET request for `url`, returning an error if the request
+    /// failed or the response doesn't have a success status, which should be
+    /// a [`NotFound`](crate::error::NotFound) for a 404
+    fn get(&self, url: &str) -> Result<Response, anyhow::Error>;
+
+    /// Sends a GET request for a byte range of `url`, which is used to
+    /// retrieve large downloads over several connections, see
+    /// [`Ctx::download_connections`](crate::Ctx::download_connections).
+    /// Returns an error if the server doesn't support ranges, in which case
+    /// the download falls back to a single connection, which is the default
+    /// for clients that don't implement this.
+    fn get_range(
+        &self,
+        url: &str,
+        _range: std::ops::Range<u64>,
+    ) -> Result<Response, anyhow::Error> {
+        anyhow::bail!("range requests to {} are not supported", url)
+    }
+}
+
+/// Creates the client that is used by default, which sends every request
+/// through the proxy, if one is specified, eg. `http://proxy:3128` or
+/// `socks5h://localhost:1080`. Otherwise the proxy is taken from the
+/// `HTTPS_PROXY`, `HTTP_PROXY`, or `ALL_PROXY` environment variables, if any
+/// of them are set.
+pub fn client(proxy: Option<&str>) -> Result<reqwest::blocking::Client, anyhow::Error> {
+    let mut builder = reqwest::blocking::ClientBuilder::new();
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(parse_proxy(proxy)?);
+    }
+
+    Ok(builder.build()?)
+}
+
+pub(crate) fn parse_proxy(url: &str) -> Result<reqwest::Proxy, anyhow::Error> {
+    use anyhow::Context as _;
+
+    anyhow::ensure!(
+        cfg!(feature = "socks") || !url.starts_with("socks"),
+        "the SOCKS proxy '{}' requires xwin to be built with the `socks` feature",
+        url
+    );
+
+    reqwest::Proxy::all(url).with_context(|| format!("invalid proxy '{}'", url))
+}
+
+impl HttpClient for reqwest::blocking::Client {
+    fn get(&self, url: &str) -> Result<Response, anyhow::Error> {
+        let res = reqwest::blocking::Client::get(self, url).send()?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(crate::error::NotFound {
+                url: url.to_owned(),
+            }
+            .into());
+        }
+        let res = res.error_for_status()?;
+
+        Ok(Response {
+            content_length: res.content_length(),
+            body: Box::new(res),
+        })
+    }
+
+    fn get_range(&self, url: &str, range: std::ops::Range<u64>) -> Result<Response, anyhow::Error> {
+        let res = reqwest::blocking::Client::get(self, url)
+            .header(
+                reqwest::header::RANGE,
+                format!("bytes={}-{}", range.start, range.end - 1),
+            )
+            .send()?
+            .error_for_status()?;
+
+        // A server that doesn't support ranges responds with the entire body
+        anyhow::ensure!(
+            res.status() == reqwest::StatusCode::PARTIAL_CONTENT,
+            "{} responded to a range request with {}",
+            url,
+            res.status()
+        );
+
+        Ok(Response {
+            content_length: res.content_length(),
+            body: Box::new(res),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::Sha256;
+
+    struct Fixtures(std::collections::BTreeMap<&'static str, &'static [u8]>);
+
+    impl HttpClient for Fixtures {
+        fn get(&self, url: &str) -> Result<Response, anyhow::Error> {
+            let body = self
+                .0
+                .get(url)
+                .ok_or_else(|| anyhow::anyhow!("404 for {}", url))?;
+
+            Ok(Response {
+                content_length: Some(body.len() as u64),
+                body: Box::new(std::io::Cursor::new(*body)),
+            })
+        }
+    }
+
+    /// Serves a single body, optionally supporting range requests
+    struct Ranges {
+        body: Vec<u8>,
+        ranges: bool,
+        requests: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl HttpClient for Ranges {
+        fn get(&self, _url: &str) -> Result<Response, anyhow::Error> {
+            self.requests
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            Ok(Response {
+                content_length: Some(self.body.len() as u64),
+                body: Box::new(std::io::Cursor::new(self.body.clone())),
+            })
+        }
+
+        fn get_range(
+            &self,
+            url: &str,
+            range: std::ops::Range<u64>,
+        ) -> Result<Response, anyhow::Error> {
+            anyhow::ensure!(self.ranges, "{} doesn't support ranges", url);
+            self.requests
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+            let body = self.body[range.start as usize..range.end as usize].to_vec();
+            Ok(Response {
+                content_length: Some(body.len() as u64),
+                body: Box::new(std::io::Cursor::new(body)),
+            })
+        }
+    }
+
+    #[test]
+    fn chunked_downloads() {
+        let body: Vec<u8> = (0..17 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let checksum = Sha256::digest(&body);
+
+        for ranges in [true, false] {
+            let mut ctx = crate::Ctx::with_temp(crate::progress::Hidden).unwrap();
+            ctx.download_connections = 4;
+            let requests = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+            ctx.client = Box::new(Ranges {
+                body: body.clone(),
+                ranges,
+                requests: requests.clone(),
+            });
+
+            let contents = ctx
+                .get_and_validate(
+                    "https://example.com/a.cab",
+                    &"a.cab",
+                    Some(checksum.clone()),
+                    &crate::progress::Hidden,
+                )
+                .unwrap();
+            assert!(contents[..] == body[..]);
+
+            // Servers that don't support ranges fall back to the initial request
+            assert_eq!(
+                requests.load(std::sync::atomic::Ordering::Relaxed),
+                if ranges { 4 } else { 1 }
+            );
+        }
+    }
+
+    #[test]
+    fn parses_proxies() {
+        assert!(client(Some("http://proxy:3128")).is_ok());
+        assert!(client(Some("not a proxy")).is_err());
+    }
+
+    #[test]
+    fn fixtures() {
+        let mut ctx = crate::Ctx::with_temp(crate::progress::Hidden).unwrap();
+        let mut fixtures = std::collections::BTreeMap::new();
+        fixtures.insert("https://example.com/a.vsix", &b"contents"[..]);
+        ctx.client = Box::new(Fixtures(fixtures));
+
+        let hidden = crate::progress::Hidden;
+        let contents = ctx
+            .get_and_validate(
+                "https://example.com/a.vsix",
+                &"a.vsix",
+                Some(Sha256::digest(b"contents")),
+                &hidden,
+            )
+            .unwrap();
+        assert_eq!(&contents[..], b"contents");
+
+        let err = ctx
+            .get_and_validate("https://example.com/b.vsix", &"b.vsix", None, &hidden)
+            .unwrap_err();
+        assert_eq!(err.kind(), Some(crate::error::ErrorKind::Network));
+
+        let err = ctx
+            .get_and_validate(
+                "https://example.com/a.vsix",
+                &"c.vsix",
+                Some(Sha256::digest(b"other")),
+                &hidden,
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), Some(crate::error::ErrorKind::ChecksumMismatch));
+
+        // Cached downloads are still usable when the network is disabled,
+        // but anything else fails rather than falling back to the client
+        ctx.no_network = true;
+        assert!(ctx
+            .get_and_validate("https://example.com/a.vsix", &"a.vsix", None, &hidden)
+            .is_ok());
+        assert_eq!(ctx.transfers.cache_hits(), 1);
+        // The mismatched download was still retrieved
+        assert_eq!(ctx.transfers.downloaded(), 16);
+        let err = ctx
+            .get_and_validate("https://example.com/a.vsix", &"d.vsix", None, &hidden)
+            .unwrap_err();
+        assert_eq!(err.kind(), Some(crate::error::ErrorKind::NetworkDisabled));
+    }
+}
@@ -0,0 +1,389 @@
+//! Resolution of the directories in a splat output that are needed to compile
+//! and link for a particular target
+
+use crate::{Arch, Path, PathBuf, Variant};
+use anyhow::Error;
+
+/// The include and library directories in a splat output for a single target
+/// architecture, in the order they should be searched, which matches the order
+/// that `vcvars` uses for the `INCLUDE` and `LIB` environment variables
+#[derive(Debug)]
+pub struct Layout {
+    /// The root of the splat output
+    pub root: PathBuf,
+    /// The directories containing the CRT and SDK headers
+    pub include: Vec<PathBuf>,
+    /// The directories containing the CRT and SDK import/static libraries
+    pub lib: Vec<PathBuf>,
+    /// The layout targets the Store/UWP variant, ie. the `*-uwp-windows-msvc`
+    /// targets, so the Store versions of the CRT libs take precedence
+    pub store: bool,
+}
+
+impl Layout {
+    /// Resolves the layout of the splat output at `root` for the specified
+    /// architecture and variants.
+    ///
+    /// The CRT libs are taken from the `onecore` directory only if `onecore`
+    /// is the only non-spectre variant, and from the `spectre` directory if
+    /// `spectre` is one of the variants. If the output was splatted with
+    /// several variants, the CRT libs are taken from the directory of the
+    /// variant, followed by the `store` directory. If `store` is the only non-spectre
+    /// variant, the Store CRT libs, eg. `vccorlib.lib`, are searched before
+    /// the rest of the CRT libs, and there is no spectre version of them.
+    /// Both the LLVM and MS notations of the architecture directories are
+    /// recognized. An output splatted with only one of the
+    /// [`Component`](crate::Component)s only has its directories.
+    pub fn resolve(root: &Path, arch: Arch, variants: u32) -> Result<Self, Error> {
+        let crt = root.join("crt");
+        let sdk = root.join("sdk");
+
+        // An output splatted with only one of the components has an empty
+        // root for the other one
+        let has_crt = is_splatted(&crt);
+        let has_sdk = is_splatted(&sdk);
+        if !has_crt && !has_sdk {
+            anyhow::bail!(
+                "neither '{}' nor '{}' contain anything, has '{}' been splatted?",
+                crt,
+                sdk,
+                root
+            );
+        }
+
+        let mut include = Vec::new();
+        if has_crt {
+            include.push(crt.join("include"));
+        }
+        if has_sdk {
+            include.extend(
+                ["ucrt", "um", "shared", "winrt", "cppwinrt"]
+                    .iter()
+                    .map(|dir| sdk.join("include").join(dir)),
+            );
+        }
+
+        for dir in &include {
+            if !dir.exists() {
+                anyhow::bail!(
+                    "include directory '{}' does not exist, has '{}' been splatted?",
+                    dir,
+                    root
+                );
+            }
+        }
+
+        let non_spectre = variants & !(Variant::Spectre as u32);
+        let store = non_spectre == Variant::Store as u32;
+
+        let mut lib = Vec::new();
+        if has_crt {
+            crt_libs(&crt, arch, variants, store, &mut lib)?;
+        }
+
+        if has_sdk {
+            lib.push(arch_dir(&sdk.join("lib/ucrt"), arch)?);
+            lib.push(arch_dir(&sdk.join("lib/um"), arch)?);
+        }
+
+        Ok(Self {
+            root: root.to_owned(),
+            include,
+            lib,
+            store,
+        })
+    }
+}
+
+/// The directories of a splat output that a build script needs to compile and
+/// link for a target, see [`paths`]
+#[derive(Clone, Debug)]
+pub struct Paths {
+    /// The root of the splat output, which differs from the output passed to
+    /// [`paths`] if it was splatted with `--split-output-per-arch`
+    pub root: PathBuf,
+    /// The include directories, in the order they should be searched
+    pub include: Vec<PathBuf>,
+    /// The library directories, in the order they should be searched
+    pub lib: Vec<PathBuf>,
+    /// The library directories that contain runtime DLLs, eg. ones added by a
+    /// [custom manifest](crate::custom), which need to be next to, or in the
+    /// `PATH` of, executables that are run, eg. under Wine
+    pub dll: Vec<PathBuf>,
+}
+
+/// Resolves the include, library, and runtime DLL directories in a splat
+/// output for an architecture, for use from `build.rs` scripts, eg. to pass
+/// to the `cc` crate, rather than hard-coding the layout of the output.
+///
+/// The variants are taken from the output's stamp, and default to `desktop`
+/// if it doesn't have one. If the output was splatted with
+/// `--split-output-per-arch`, the directory for the architecture is used.
+///
+/// ```no_run
+/// let paths = xwin::paths("/xwin", xwin::Arch::X86_64)?;
+///
+/// for dir in &paths.lib {
+///     println!("cargo:rustc-link-search=native={}", dir);
+/// }
+///
+/// let mut build = std::process::Command::new("clang-cl");
+/// build.args(paths.include.iter().map(|dir| format!("/imsvc{}", dir)));
+/// # Ok::<_, anyhow::Error>(())
+/// ```
+pub fn paths(output: impl AsRef<Path>, arch: Arch) -> Result<Paths, Error> {
+    let mut root = output.as_ref().to_owned();
+
+    let stamp = crate::stamp::Stamp::NAME;
+    let per_arch = root.join(arch.as_str());
+    if !root.join(stamp).exists() && per_arch.join(stamp).exists() {
+        root = per_arch;
+    }
+
+    let variants = if root.join(stamp).exists() {
+        crate::stamp::Stamp::load(&root)?.variant_mask()?
+    } else {
+        Variant::Desktop as u32
+    };
+
+    let layout = Layout::resolve(&root, arch, variants)?;
+
+    let dll = layout
+        .lib
+        .iter()
+        .filter(|dir| {
+            std::fs::read_dir(dir)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .any(|entry| {
+                    Path::new(entry.file_name().to_str().unwrap_or_default())
+                        .extension()
+                        .filter(|ext| ext.eq_ignore_ascii_case("dll"))
+                        .is_some()
+                })
+        })
+        .cloned()
+        .collect();
+
+    Ok(Paths {
+        root,
+        include: layout.include,
+        lib: layout.lib,
+        dll,
+    })
+}
+
+/// Adds the CRT library directories for the architecture and variants
+fn crt_libs(
+    crt: &Path,
+    arch: Arch,
+    variants: u32,
+    store: bool,
+    lib: &mut Vec<PathBuf>,
+) -> Result<(), Error> {
+    let crt_lib = crt.join("lib");
+    // Outputs splatted with several variants have a directory for each
+    // variant, and always have one for the store libs
+    let variant_dirs = crt_lib.join("store").is_dir();
+    let store_lib = if variant_dirs {
+        crt_lib.join("store")
+    } else {
+        crt_lib.clone()
+    };
+
+    if store {
+        // The Store CRT libs are splatted without a spectre directory
+        let arch_lib = arch_dir(&store_lib, arch)?;
+        let store_lib = arch_lib.join("store");
+        if !store_lib.exists() {
+            anyhow::bail!(
+                "unable to find Store CRT libraries in '{}', has it been splatted with --variant store?",
+                arch_lib
+            );
+        }
+
+        lib.push(store_lib);
+        lib.push(arch_lib);
+    } else {
+        let mut variant_lib = crt_lib.clone();
+        if variants & Variant::Spectre as u32 != 0 {
+            variant_lib.push("spectre");
+        }
+        if variants & Variant::Desktop as u32 == 0 && variants & Variant::OneCore as u32 != 0 {
+            variant_lib.push("onecore");
+        } else if variant_dirs {
+            variant_lib.push("desktop");
+        }
+
+        lib.push(arch_dir(&variant_lib, arch)?);
+
+        // The libraries that are linked by default, eg. `oldnames.lib`,
+        // are in the store libs
+        if variant_dirs {
+            lib.push(arch_dir(&store_lib, arch)?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a component's root contains anything
+fn is_splatted(root: &Path) -> bool {
+    std::fs::read_dir(root)
+        .ok()
+        .and_then(|mut entries| entries.next())
+        .is_some()
+}
+
+/// Splat uses the LLVM notation for architecture directories unless
+/// `--preserve-ms-arch-notation` was passed, so we just check for both
+fn arch_dir(parent: &Path, arch: Arch) -> Result<PathBuf, Error> {
+    [arch.as_str(), arch.as_ms_str()]
+        .iter()
+        .map(|name| parent.join(name))
+        .find(|dir| dir.exists())
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "unable to find '{}' libraries in '{}', has it been splatted for that architecture?",
+                arch,
+                parent
+            )
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn store_libs_come_first() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        for dir in [
+            "crt/include",
+            "sdk/include/ucrt",
+            "sdk/include/um",
+            "sdk/include/shared",
+            "sdk/include/winrt",
+            "sdk/include/cppwinrt",
+            "crt/lib/x86_64/store",
+            "sdk/lib/ucrt/x86_64",
+            "sdk/lib/um/x86_64",
+        ] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+
+        let store = Variant::Store as u32 | Variant::Spectre as u32;
+        let layout = Layout::resolve(&root, Arch::X86_64, store).unwrap();
+        assert!(layout.store);
+        assert_eq!(
+            layout.lib,
+            vec![
+                root.join("crt/lib/x86_64/store"),
+                root.join("crt/lib/x86_64"),
+                root.join("sdk/lib/ucrt/x86_64"),
+                root.join("sdk/lib/um/x86_64"),
+            ]
+        );
+
+        // The Store libs are only used if it's the only variant
+        let both = Variant::Store as u32 | Variant::Desktop as u32;
+        let layout = Layout::resolve(&root, Arch::X86_64, both).unwrap();
+        assert!(!layout.store);
+        assert_eq!(layout.lib[0], root.join("crt/lib/x86_64"));
+
+        // An SDK only output has an empty CRT root
+        std::fs::remove_dir_all(root.join("crt")).unwrap();
+        std::fs::create_dir(root.join("crt")).unwrap();
+        let layout = Layout::resolve(&root, Arch::X86_64, both).unwrap();
+        assert_eq!(layout.include[0], root.join("sdk/include/ucrt"));
+        assert_eq!(
+            layout.lib,
+            vec![
+                root.join("sdk/lib/ucrt/x86_64"),
+                root.join("sdk/lib/um/x86_64")
+            ]
+        );
+    }
+
+    #[test]
+    fn variant_dirs() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        for dir in [
+            "crt/include",
+            "sdk/include/ucrt",
+            "sdk/include/um",
+            "sdk/include/shared",
+            "sdk/include/winrt",
+            "sdk/include/cppwinrt",
+            "crt/lib/desktop/x86_64",
+            "crt/lib/onecore/x86_64",
+            "crt/lib/store/x86_64",
+            "sdk/lib/ucrt/x86_64",
+            "sdk/lib/um/x86_64",
+        ] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+
+        let layout = Layout::resolve(
+            &root,
+            Arch::X86_64,
+            Variant::Desktop as u32 | Variant::OneCore as u32,
+        )
+        .unwrap();
+        assert_eq!(
+            &layout.lib[..2],
+            &[
+                root.join("crt/lib/desktop/x86_64"),
+                root.join("crt/lib/store/x86_64")
+            ]
+        );
+
+        let layout = Layout::resolve(&root, Arch::X86_64, Variant::OneCore as u32).unwrap();
+        assert_eq!(layout.lib[0], root.join("crt/lib/onecore/x86_64"));
+    }
+
+    #[test]
+    fn build_script_paths() {
+        let td = tempfile::TempDir::new().unwrap();
+        let output = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let root = output.join("aarch64");
+
+        for dir in [
+            "crt/include",
+            "sdk/include/ucrt",
+            "sdk/include/um",
+            "sdk/include/shared",
+            "sdk/include/winrt",
+            "sdk/include/cppwinrt",
+            "crt/lib/aarch64",
+            "sdk/lib/ucrt/aarch64",
+            "sdk/lib/um/aarch64",
+        ] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        std::fs::write(root.join("crt/lib/aarch64/vcruntime140.DLL"), "").unwrap();
+
+        crate::stamp::Stamp::new(
+            &crate::SplatConfig::new(root.clone()),
+            Arch::Aarch64 as u32,
+            Variant::Desktop as u32,
+            crate::SdkTools::default(),
+            &[],
+            &Default::default(),
+        )
+        .save(&root)
+        .unwrap();
+
+        // The directory for the arch is used for a split output
+        let paths = paths(&output, Arch::Aarch64).unwrap();
+        assert_eq!(paths.root, root);
+        assert_eq!(paths.include[0], root.join("crt/include"));
+        assert_eq!(paths.lib.len(), 3);
+        assert_eq!(paths.dll, vec![root.join("crt/lib/aarch64")]);
+    }
+}
@@ -81,20 +81,75 @@
 // END - Embark standard lints v0.5 for Rust 1.55+
 // crate-specific exceptions:
 
-use anyhow::{Context as _, Error};
+use anyhow::Context as _;
 pub use camino::{Utf8Path as Path, Utf8PathBuf as PathBuf};
 use std::{collections::BTreeMap, fmt};
 
+mod ar;
+mod builder;
+pub mod cache;
+pub mod cancel;
+mod checkpoint;
+pub mod checksums;
+pub mod config;
 mod ctx;
+pub mod custom;
+pub mod diff;
+pub mod doctor;
 mod download;
+pub mod error;
+pub mod extra;
+pub mod generate;
+pub mod gha;
+pub mod hash;
+pub mod http;
+pub mod layout;
 pub mod manifest;
+pub mod mirror;
+pub mod nix;
+pub mod patch;
+pub mod pdb;
+pub mod progress;
+pub mod provenance;
+pub mod report;
+pub mod selftest;
+pub mod serve;
 mod splat;
+pub mod stamp;
+pub mod symbols;
+pub mod thin;
 mod unpack;
 pub mod util;
+pub mod verify;
+pub mod version;
+pub mod vs_layout;
 
-pub use ctx::Ctx;
-pub use splat::SplatConfig;
+pub use builder::{Builder, Plan};
+pub use ctx::{Ctx, Outcomes, PayloadTimings, PhaseStats, PhaseTiming, Stats, Transfers};
+pub use error::Error;
+pub use layout::{paths, Paths};
+pub use progress::{ProgressFactory, ProgressSink};
+pub use splat::{
+    share_headers, DebugComponent, DebugTarget, SharedHeaders, SkipReason, SkippedFile,
+    SplatConfig, SymlinkCollision,
+};
 
+/// A target architecture.
+///
+/// Selections of several architectures are represented as a mask of the
+/// discriminants, see [`Arch::mask`] and [`Arch::iter`]. Architectures are
+/// parsed from, and displayed as, the LLVM names, eg. `x86_64`, and can also
+/// be parsed from a Rust target triple, or from the names Microsoft uses in
+/// its packages and directories, eg. `x64`, with [`Arch::from_ms_str`].
+///
+/// ```
+/// use xwin::Arch;
+///
+/// let mask = Arch::mask(&["x86_64".parse()?, Arch::from_ms_str("ARM64").unwrap()]);
+/// assert_eq!(Arch::iter(mask).collect::<Vec<_>>(), [Arch::X86_64, Arch::Aarch64]);
+/// assert_eq!(Arch::Aarch64.as_ms_str(), "arm64");
+/// # Ok::<_, anyhow::Error>(())
+/// ```
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Arch {
     X86 = 0x1,
@@ -104,7 +159,7 @@ pub enum Arch {
 }
 
 impl std::str::FromStr for Arch {
-    type Err = Error;
+    type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(match s {
@@ -112,7 +167,10 @@ impl std::str::FromStr for Arch {
             "x86_64" => Self::X86_64,
             "aarch" => Self::Aarch,
             "aarch64" => Self::Aarch64,
-            o => anyhow::bail!("unknown architecture '{}'", o),
+            o => match Self::TRIPLES.iter().find(|(triple, _)| *triple == o) {
+                Some((_, arch)) => *arch,
+                None => anyhow::bail!("unknown architecture '{}'", o),
+            },
         })
     }
 }
@@ -124,6 +182,25 @@ impl fmt::Display for Arch {
 }
 
 impl Arch {
+    /// Every architecture, which is what `all` selects
+    pub const ALL: &'static [Self] = &[Self::X86, Self::X86_64, Self::Aarch, Self::Aarch64];
+
+    /// The Rust `*-windows-msvc` target triples that are also accepted when
+    /// parsing an architecture, and the architecture each one maps onto
+    pub const TRIPLES: &'static [(&'static str, Self)] = &[
+        ("i586-pc-windows-msvc", Self::X86),
+        ("i686-pc-windows-msvc", Self::X86),
+        ("i686-uwp-windows-msvc", Self::X86),
+        ("x86_64-pc-windows-msvc", Self::X86_64),
+        ("x86_64-uwp-windows-msvc", Self::X86_64),
+        ("thumbv7a-pc-windows-msvc", Self::Aarch),
+        ("thumbv7a-uwp-windows-msvc", Self::Aarch),
+        ("aarch64-pc-windows-msvc", Self::Aarch64),
+        ("aarch64-uwp-windows-msvc", Self::Aarch64),
+    ];
+
+    /// The LLVM name of the architecture, which is used for the directories in
+    /// a splat output
     #[inline]
     pub fn as_str(&self) -> &'static str {
         match self {
@@ -134,6 +211,9 @@ impl Arch {
         }
     }
 
+    /// The name Microsoft uses for the architecture, eg. `x64`, which is used
+    /// for the directories in the packages, and in a splat output if
+    /// `--preserve-ms-arch-notation` is used
     #[inline]
     pub fn as_ms_str(&self) -> &'static str {
         match self {
@@ -144,6 +224,44 @@ impl Arch {
         }
     }
 
+    /// The Rust target triple for the architecture
+    #[inline]
+    pub fn as_triple(&self) -> &'static str {
+        match self {
+            Self::X86 => "i686-pc-windows-msvc",
+            Self::X86_64 => "x86_64-pc-windows-msvc",
+            Self::Aarch => "thumbv7a-pc-windows-msvc",
+            Self::Aarch64 => "aarch64-pc-windows-msvc",
+        }
+    }
+
+    /// The Rust target triple for the architecture when targeting the
+    /// Store/UWP variant
+    #[inline]
+    pub fn as_uwp_triple(&self) -> &'static str {
+        match self {
+            Self::X86 => "i686-uwp-windows-msvc",
+            Self::X86_64 => "x86_64-uwp-windows-msvc",
+            Self::Aarch => "thumbv7a-uwp-windows-msvc",
+            Self::Aarch64 => "aarch64-uwp-windows-msvc",
+        }
+    }
+
+    /// Parses the name Microsoft uses for an architecture, ie. the inverse of
+    /// [`Self::as_ms_str`], ignoring case, as eg. the CRT libs use `ARM64`
+    pub fn from_ms_str(s: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|arch| arch.as_ms_str().eq_ignore_ascii_case(s))
+            .copied()
+    }
+
+    /// The mask of the architectures
+    pub fn mask(arches: &[Self]) -> u32 {
+        arches.iter().fold(0, |acc, arch| acc | *arch as u32)
+    }
+
+    /// Iterates over the architectures in the mask
     pub fn iter(val: u32) -> impl Iterator<Item = Self> {
         [Self::X86, Self::X86_64, Self::Aarch, Self::Aarch64]
             .iter()
@@ -157,6 +275,10 @@ impl Arch {
     }
 }
 
+/// A variant of the CRT libraries.
+///
+/// Selections of several variants are represented as a mask of the
+/// discriminants, see [`Variant::mask`] and [`Variant::iter_variants`].
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Variant {
     Desktop = 0x1,
@@ -173,13 +295,13 @@ impl fmt::Display for Variant {
 }
 
 impl std::str::FromStr for Variant {
-    type Err = Error;
+    type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(match s {
             "desktop" => Self::Desktop,
             "onecore" => Self::OneCore,
-            //"store" => Self::Store,
+            "store" => Self::Store,
             "spectre" => Self::Spectre,
             o => anyhow::bail!("unknown variant '{}'", o),
         })
@@ -187,6 +309,10 @@ impl std::str::FromStr for Variant {
 }
 
 impl Variant {
+    /// Every variant that can be selected, which is what `all` selects
+    pub const ALL: &'static [Self] = &[Self::Desktop, Self::OneCore, Self::Store, Self::Spectre];
+
+    /// The name of the variant, as accepted by `--variant`
     pub fn as_str(&self) -> &'static str {
         match self {
             Self::Desktop => "desktop",
@@ -196,6 +322,22 @@ impl Variant {
         }
     }
 
+    /// The mask of the variants
+    pub fn mask(variants: &[Self]) -> u32 {
+        variants.iter().fold(0, |acc, var| acc | *var as u32)
+    }
+
+    /// Iterates over the variants in the mask, including [`Self::Spectre`]
+    pub fn iter_variants(val: u32) -> impl Iterator<Item = Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .filter(move |var| *var as u32 & val != 0)
+    }
+
+    /// Iterates over the names Microsoft uses in the ids of the CRT lib
+    /// packages for the variants in the mask, eg. `OneCore.Desktop`. Spectre
+    /// isn't included, as it is a suffix of the other variants' ids.
     pub fn iter(val: u32) -> impl Iterator<Item = &'static str> {
         [Self::Desktop, Self::OneCore, Self::Store]
             .iter()
@@ -214,15 +356,87 @@ impl Variant {
     }
 }
 
+/// One element of an architecture or variant selection, ie. `all`, a single
+/// value, or a value negated with `!`, eg. `all,!x86`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Selector<T> {
+    All,
+    Include(T),
+    Exclude(T),
+}
+
+impl<T> std::str::FromStr for Selector<T>
+where
+    T: std::str::FromStr<Err = anyhow::Error>,
+{
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "all" => Self::All,
+            s => match s.strip_prefix('!') {
+                Some(negated) => Self::Exclude(negated.parse()?),
+                None => Self::Include(s.parse()?),
+            },
+        })
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Selector<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::All => f.write_str("all"),
+            Self::Include(val) => write!(f, "{}", val),
+            Self::Exclude(val) => write!(f, "!{}", val),
+        }
+    }
+}
+
+/// Resolves the selectors, in order, into the values they select out of
+/// `all`. If the first selector is a negation, the selection starts from every
+/// value, so eg. `!x86` selects everything except `x86`.
+pub fn select<T: Copy + PartialEq>(selectors: &[Selector<T>], all: &[T]) -> Vec<T> {
+    let mut selected = Vec::new();
+
+    if let Some(Selector::Exclude(_)) = selectors.first() {
+        selected.extend_from_slice(all);
+    }
+
+    for selector in selectors {
+        match selector {
+            Selector::All => {
+                for val in all {
+                    if !selected.contains(val) {
+                        selected.push(*val);
+                    }
+                }
+            }
+            Selector::Include(val) => {
+                if !selected.contains(val) {
+                    selected.push(*val);
+                }
+            }
+            Selector::Exclude(val) => selected.retain(|sel| sel != val),
+        }
+    }
+
+    selected
+}
+
+/// The operation to perform on each payload, each of which includes the
+/// operations before it
 pub enum Ops {
+    /// Downloads the payloads to the cache
     Download,
+    /// Unpacks the payloads in the cache
     Unpack,
+    /// Splats the unpacked payloads to an output directory
     Splat(crate::splat::SplatConfig),
 }
 
 #[derive(Clone)]
 pub struct WorkItem {
-    pub progress: indicatif::ProgressBar,
+    pub progress: std::sync::Arc<dyn progress::ProgressSink>,
     pub payload: std::sync::Arc<Payload>,
 }
 
@@ -230,6 +444,8 @@ pub struct WorkItem {
 pub struct Payload {
     /// The "suggested" filename for the payload when stored on disk
     pub filename: PathBuf,
+    /// The version of the package the payload belongs to
+    pub version: String,
     /// The sha-256 checksum of the payload
     pub sha256: util::Sha256,
     /// The url from which to acquire the payload
@@ -245,16 +461,111 @@ pub struct Payload {
     pub target_arch: Option<Arch>,
     /// Specific variant this payload targets
     pub variant: Option<Variant>,
+    /// Where the contents of an [extra download](crate::extra) are placed
+    pub extra: Option<extra::ExtraLayout>,
+}
+
+#[cfg(test)]
+impl Payload {
+    /// A payload for tests, the other fields can be set with the struct
+    /// update syntax, eg. `Payload { size: 1, ..Payload::test("a.msi", kind) }`
+    pub(crate) fn test(filename: &str, kind: PayloadKind) -> Self {
+        Self {
+            filename: filename.into(),
+            version: String::new(),
+            sha256: util::Sha256([0; 32]),
+            url: format!("https://example.com/{}", filename),
+            size: 0,
+            install_size: None,
+            kind,
+            target_arch: None,
+            variant: None,
+            extra: None,
+        }
+    }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum PayloadKind {
     CrtHeaders,
     CrtLibs,
     SdkHeaders,
     SdkLibs,
     SdkStoreLibs,
+    /// The SDK tools in `bin/<version>/<arch>`, eg. `rc.exe` and `mt.exe`
+    SdkBin,
     Ucrt,
+    /// An [extra download](crate::extra) declared in the config file
+    Extra,
+}
+
+impl PayloadKind {
+    /// The component the payload is splatted to, or `None` for
+    /// [extra downloads](crate::extra), which are always included
+    pub fn component(self) -> Option<Component> {
+        match self {
+            Self::CrtHeaders | Self::CrtLibs => Some(Component::Crt),
+            Self::SdkHeaders | Self::SdkLibs | Self::SdkStoreLibs | Self::SdkBin | Self::Ucrt => {
+                Some(Component::Sdk)
+            }
+            Self::Extra => None,
+        }
+    }
+}
+
+/// The two roots of a splat output, which can be selected separately, eg. to
+/// only splat the SDK and UCRT for a toolchain that brings its own CRT
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Component {
+    /// The MSVC CRT, splatted to `crt`
+    Crt,
+    /// The Windows SDK, including the UCRT, splatted to `sdk`
+    Sdk,
+}
+
+impl Component {
+    /// Every component, which is what is selected by default
+    pub const ALL: &'static [Self] = &[Self::Crt, Self::Sdk];
+
+    /// The name of the component, as accepted by `--components`, which is
+    /// also the name of its root in the output
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Crt => "crt",
+            Self::Sdk => "sdk",
+        }
+    }
+}
+
+impl fmt::Display for Component {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Component {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "crt" => Self::Crt,
+            "sdk" => Self::Sdk,
+            o => anyhow::bail!("unknown component '{}'", o),
+        })
+    }
+}
+
+/// Removes the payloads that don't belong to one of the components, so that
+/// they are never downloaded
+pub fn prune_components(payloads: &mut Vec<Payload>, components: &[Component]) {
+    payloads.retain(|payload| {
+        payload
+            .kind
+            .component()
+            .filter(|component| !components.contains(component))
+            .is_none()
+    });
 }
 
 /// Returns the list of packages that are actually needed for cross compilation
@@ -268,18 +579,42 @@ pub fn prune_pkg_list(
     let pkgs = &pkg_manifest.packages;
     let mut pruned = Vec::new();
 
-    get_crt(pkgs, arches, variants, &mut pruned)?;
-    get_sdk(pkgs, arches, &mut pruned)?;
+    get_crt(pkgs, arches, variants, &mut pruned).context(error::MissingPayload)?;
+    get_sdk(pkgs, arches, &mut pruned).context(error::MissingPayload)?;
 
     Ok(pruned)
 }
 
+/// The sets of Windows SDK tools that can be included, see [`prune_sdk_bin`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SdkTools {
+    /// The build tools, eg. `rc.exe`, `mt.exe`, and `midl.exe`
+    pub build: bool,
+    /// `signtool.exe`, along with the libraries it loads
+    pub signing: bool,
+}
+
+/// Returns the payloads for the selected Windows SDK tools for the specified
+/// architectures. These aren't needed for cross compilation, but are for eg.
+/// compiling resources, embedding manifests, and signing binaries under Wine,
+/// so they are only included if explicitly requested.
+pub fn prune_sdk_bin(
+    pkg_manifest: &manifest::PackageManifest,
+    arches: u32,
+    tools: SdkTools,
+) -> Result<Vec<Payload>, Error> {
+    let mut pruned = Vec::new();
+    get_sdk_bin(&pkg_manifest.packages, arches, tools, &mut pruned)
+        .context(error::MissingPayload)?;
+    Ok(pruned)
+}
+
 fn get_crt(
     pkgs: &BTreeMap<String, manifest::ManifestItem>,
     arches: u32,
     variants: u32,
     pruned: &mut Vec<Payload>,
-) -> Result<(), Error> {
+) -> Result<(), anyhow::Error> {
     fn to_payload(mi: &manifest::ManifestItem, payload: &manifest::Payload) -> Payload {
         // These are really the only two we care about
         let kind = if mi.id.contains("Headers") {
@@ -319,6 +654,7 @@ fn get_crt(
             } else {
                 payload.file_name.clone().into()
             },
+            version: mi.version.clone(),
             sha256: payload.sha256.clone(),
             url: payload.url.clone(),
             size: payload.size,
@@ -328,6 +664,7 @@ fn get_crt(
             install_size: (mi.payloads.len() == 1)
                 .then(|| mi)
                 .and_then(|mi| mi.install_sizes.as_ref().and_then(|is| is.target_drive)),
+            extra: None,
         }
     }
 
@@ -415,12 +752,8 @@ fn get_sdk(
     pkgs: &BTreeMap<String, manifest::ManifestItem>,
     arches: u32,
     pruned: &mut Vec<Payload>,
-) -> Result<(), Error> {
-    let sdk = pkgs
-        .values()
-        .filter(|mi| mi.id.starts_with("Win10SDK_10."))
-        .max()
-        .context("unable to find latest Win10SDK version")?;
+) -> Result<(), anyhow::Error> {
+    let sdk = latest_sdk(pkgs)?;
 
     // So. There are multiple SDK Desktop Headers, one per architecture. However,
     // all of the non-x86 ones include either 0 or few files, with x86 containing
@@ -440,6 +773,7 @@ fn get_sdk(
 
         pruned.push(Payload {
             filename: format!("{}_headers.msi", sdk.id).into(),
+            version: sdk.version.clone(),
             sha256: header_payload.sha256.clone(),
             url: header_payload.url.clone(),
             size: header_payload.size,
@@ -447,6 +781,7 @@ fn get_sdk(
             install_size: None,
             kind: PayloadKind::SdkHeaders,
             variant: None,
+            extra: None,
             target_arch: None,
         });
 
@@ -462,12 +797,14 @@ fn get_sdk(
 
         pruned.push(Payload {
             filename: format!("{}_store_headers.msi", sdk.id).into(),
+            version: sdk.version.clone(),
             sha256: header_payload.sha256.clone(),
             url: header_payload.url.clone(),
             size: header_payload.size,
             install_size: None,
             kind: PayloadKind::SdkHeaders,
             variant: Some(Variant::Store),
+            extra: None,
             target_arch: None,
         });
 
@@ -490,12 +827,14 @@ fn get_sdk(
 
             pruned.push(Payload {
                 filename: format!("{}_{}_headers.msi", sdk.id, arch.as_ms_str()).into(),
+                version: sdk.version.clone(),
                 sha256: header_payload.sha256.clone(),
                 url: header_payload.url.clone(),
                 size: header_payload.size,
                 install_size: None,
                 kind: PayloadKind::SdkHeaders,
                 variant: None,
+                extra: None,
                 target_arch: Some(arch),
             });
         }
@@ -520,12 +859,14 @@ fn get_sdk(
 
             pruned.push(Payload {
                 filename: format!("{}_libs_{}.msi", sdk.id, arch).into(),
+                version: sdk.version.clone(),
                 sha256: lib.sha256.clone(),
                 url: lib.url.clone(),
                 size: lib.size,
                 install_size: None,
                 kind: PayloadKind::SdkLibs,
                 variant: None,
+                extra: None,
                 target_arch: Some(arch),
             });
         }
@@ -547,12 +888,14 @@ fn get_sdk(
 
         pruned.push(Payload {
             filename: format!("{}_store_libs.msi", sdk.id).into(),
+            version: sdk.version.clone(),
             sha256: lib_payload.sha256.clone(),
             url: lib_payload.url.clone(),
             size: lib_payload.size,
             install_size: None,
             kind: PayloadKind::SdkStoreLibs,
             variant: None,
+            extra: None,
             target_arch: None,
         });
     }
@@ -573,15 +916,114 @@ fn get_sdk(
 
         pruned.push(Payload {
             filename: "ucrt.msi".into(),
+            version: ucrt.version.clone(),
             sha256: msi.sha256.clone(),
             url: msi.url.clone(),
             size: msi.size,
             install_size: None,
             kind: PayloadKind::Ucrt,
             variant: None,
+            extra: None,
+            target_arch: None,
+        });
+    }
+
+    Ok(())
+}
+
+fn latest_sdk(
+    pkgs: &BTreeMap<String, manifest::ManifestItem>,
+) -> Result<&manifest::ManifestItem, anyhow::Error> {
+    pkgs.values()
+        .filter(|mi| mi.id.starts_with("Win10SDK_10."))
+        .max()
+        .context("unable to find latest Win10SDK version")
+}
+
+fn get_sdk_bin(
+    pkgs: &BTreeMap<String, manifest::ManifestItem>,
+    arches: u32,
+    tools: SdkTools,
+    pruned: &mut Vec<Payload>,
+) -> Result<(), anyhow::Error> {
+    let sdk = latest_sdk(pkgs)?;
+
+    let find = |name: &str| {
+        sdk.payloads
+            .iter()
+            .find(|payload| payload.file_name.ends_with(name))
+            .with_context(|| format!("unable to find {} for {}", name, sdk.id))
+    };
+
+    // signtool and the libraries it loads, eg. mssign32.dll, are in their own
+    // installer with the tools for every architecture
+    if tools.signing {
+        let signing_payload = find("Windows SDK Signing Tools-x86_en-us.msi")?;
+
+        pruned.push(Payload {
+            filename: format!("{}_signing_tools.msi", sdk.id).into(),
+            version: sdk.version.clone(),
+            sha256: signing_payload.sha256.clone(),
+            url: signing_payload.url.clone(),
+            size: signing_payload.size,
+            install_size: None,
+            kind: PayloadKind::SdkBin,
+            variant: None,
+            extra: None,
             target_arch: None,
         });
     }
 
+    if !tools.build {
+        return Ok(());
+    }
+
+    // The bulk of the tools, including rc, mt, and midl, are in the Store Apps
+    // Tools, which has the tools for every architecture in a single MSI
+    let tools_payload = find("Windows SDK for Windows Store Apps Tools-x86_en-us.msi")?;
+
+    pruned.push(Payload {
+        filename: format!("{}_store_tools.msi", sdk.id).into(),
+        version: sdk.version.clone(),
+        sha256: tools_payload.sha256.clone(),
+        url: tools_payload.url.clone(),
+        size: tools_payload.size,
+        install_size: None,
+        kind: PayloadKind::SdkBin,
+        variant: None,
+        extra: None,
+        target_arch: None,
+    });
+
+    // The rest are in an installer per architecture, which not every
+    // architecture has
+    for arch in Arch::iter(arches) {
+        let tools_payload = if let Some(payload) = sdk.payloads.iter().find(|payload| {
+            payload
+                .file_name
+                .strip_prefix("Installers\\Windows SDK Desktop Tools ")
+                .and_then(|fname| fname.strip_suffix("-x86_en-us.msi"))
+                == Some(arch.as_ms_str())
+        }) {
+            payload
+        } else {
+            tracing::debug!("unable to find SDK desktop tools for '{}'", arch);
+            continue;
+        };
+
+        pruned.push(Payload {
+            filename: format!("{}_tools_{}.msi", sdk.id, arch).into(),
+            version: sdk.version.clone(),
+            sha256: tools_payload.sha256.clone(),
+            url: tools_payload.url.clone(),
+            size: tools_payload.size,
+            install_size: None,
+            kind: PayloadKind::SdkBin,
+            variant: None,
+            extra: None,
+            target_arch: Some(arch),
+        });
+    }
+
     Ok(())
 }
@@ -1,42 +1,129 @@
 use anyhow::{Context as _, Error};
 use camino::Utf8PathBuf as PathBuf;
-use clap::{Parser, Subcommand};
+use clap::{Parser, PossibleValue, Subcommand};
+#[cfg(feature = "ui")]
 use indicatif as ia;
 use tracing_subscriber::filter::LevelFilter;
 
-fn setup_logger(json: bool, log_level: LevelFilter) -> Result<(), Error> {
-    let mut env_filter = tracing_subscriber::EnvFilter::from_default_env();
+/// Cancels the run on the first Ctrl-C or termination signal, so that the
+/// workers stop at the next download chunk or file and leave the cache in a
+/// state the next run can resume from. A second signal exits immediately.
+fn cancel_on_signal() -> Result<xwin::cancel::CancellationToken, Error> {
+    use signal_hook::{consts::TERM_SIGNALS, flag};
 
-    // If a user specifies a log level, we assume it only pertains to xwin,
-    // if they want to trace other crates they can use the RUST_LOG env approach
-    env_filter = env_filter.add_directive(format!("xwin={}", log_level).parse()?);
+    let cancelled = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
 
-    let subscriber = tracing_subscriber::FmtSubscriber::builder()
-        .with_env_filter(env_filter)
-        .with_writer(std::io::stderr);
+    for sig in TERM_SIGNALS {
+        // The shutdown is registered first so that it only fires if the flag
+        // was already set by a previous signal
+        flag::register_conditional_shutdown(*sig, 130, cancelled.clone())
+            .context("failed to register signal handler")?;
+        flag::register(*sig, cancelled.clone()).context("failed to register signal handler")?;
+    }
 
-    if json {
-        tracing::subscriber::set_global_default(subscriber.json().finish())
-            .context("failed to set default subscriber")?;
-    } else {
-        tracing::subscriber::set_global_default(subscriber.finish())
-            .context("failed to set default subscriber")?;
+    Ok(cancelled.into())
+}
+
+/// The number of warnings that were logged during the run, see [`CountWarnings`]
+static WARNINGS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+/// Counts the warnings that are logged, regardless of the console or log file
+/// level, so that they can be reported in the summary at the end of the run
+struct CountWarnings;
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for CountWarnings {
+    fn on_event(
+        &self,
+        event: &tracing::Event<'_>,
+        _ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        if *event.metadata().level() == tracing::Level::WARN {
+            WARNINGS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
     }
+}
+
+fn setup_logger(
+    json: bool,
+    log_level: LevelFilter,
+    log_file: Option<(&PathBuf, LevelFilter)>,
+) -> Result<(), Error> {
+    use tracing_subscriber::{layer::SubscriberExt as _, EnvFilter, Layer as _};
+
+    let make_filter = |level: LevelFilter| -> Result<EnvFilter, Error> {
+        // If a user specifies a log level, we assume it only pertains to xwin,
+        // if they want to trace other crates they can use the RUST_LOG env approach
+        Ok(EnvFilter::from_default_env().add_directive(format!("xwin={}", level).parse()?))
+    };
+
+    // The `ansi` feature is only enabled so that colors can be turned off for
+    // the log file, the console has never been colored either
+    let console = tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .with_writer(std::io::stderr);
+    let console = if json {
+        console.json().with_filter(make_filter(log_level)?).boxed()
+    } else {
+        console.with_filter(make_filter(log_level)?).boxed()
+    };
+
+    // The log file gets its own level so that it can capture everything, eg.
+    // why a particular file was skipped, without spamming the console
+    let file = match log_file {
+        Some((path, level)) => {
+            let file = std::fs::File::create(path)
+                .with_context(|| format!("failed to create log file '{}'", path))?;
+            let file = tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(std::sync::Mutex::new(file));
+
+            Some(if json {
+                file.json().with_filter(make_filter(level)?).boxed()
+            } else {
+                file.with_filter(make_filter(level)?).boxed()
+            })
+        }
+        None => None,
+    };
+
+    tracing::subscriber::set_global_default(
+        tracing_subscriber::registry()
+            .with(console)
+            .with(file)
+            .with(CountWarnings.with_filter(LevelFilter::WARN)),
+    )
+    .context("failed to set default subscriber")?;
 
     Ok(())
 }
 
 #[derive(Subcommand)]
 pub enum Command {
-    /// Displays a summary of the packages that would be downloaded.
+    /// Displays the packages that would be downloaded for the current arch and
+    /// variant selection, along with their versions and sizes.
     ///
     /// Note that this is not a full list as the SDK uses MSI files for many
     /// packages, so they would need to be downloaded and inspected to determine
     /// which CAB files must also be downloaded to get the content needed.
     List,
+    /// Interactively selects the architectures, variants, and splat options to
+    /// use, displays the packages the selection resolves to, and writes the
+    /// selection to the config file.
+    ///
+    /// The config file is the one specified by `--config`, or the one that was
+    /// discovered, or `./xwin.toml` if neither exists. Any other options already
+    /// present in the config file are preserved.
+    Configure,
     /// Downloads all the selected packages that aren't already present in
     /// the download cache
     Download,
+    /// Downloads all the selected packages, and copies them along with the
+    /// manifests to a directory that other xwin instances can use as their
+    /// `--upstream`, eg. in an air-gapped network
+    Mirror {
+        /// The directory to write the mirror to
+        dir: PathBuf,
+    },
     /// Unpacks all of the downloaded packages to disk
     Unpack,
     /// Fixes the packages to prune unneeded files and adds symlinks to address
@@ -44,47 +131,537 @@ pub enum Command {
     Splat {
         /// The MSVCRT includes (non-redistributable) debug versions of the
         /// various libs that are generally uninteresting to keep for most usage
-        #[clap(long)]
+        #[clap(long, env = "XWIN_INCLUDE_DEBUG_LIBS")]
         include_debug_libs: bool,
         /// The MSVCRT includes PDB (debug symbols) files for several of the
         /// libraries that are generally uninteresting to keep for most usage
-        #[clap(long)]
+        #[clap(long, env = "XWIN_INCLUDE_DEBUG_SYMBOLS")]
         include_debug_symbols: bool,
+        /// Keeps the debug versions of the libs for only the specified
+        /// components, ie. `crt` or `ucrt`, optionally for a single
+        /// architecture, eg. `crt:x86_64`
+        #[clap(long, env = "XWIN_INCLUDE_DEBUG_LIBS_FOR", use_value_delimiter = true)]
+        include_debug_libs_for: Vec<xwin::DebugTarget>,
+        /// Keeps the PDBs for only the specified components, ie. `crt` or
+        /// `ucrt`, optionally for a single architecture, eg. `ucrt:aarch64`
+        #[clap(
+            long,
+            env = "XWIN_INCLUDE_DEBUG_SYMBOLS_FOR",
+            use_value_delimiter = true
+        )]
+        include_debug_symbols_for: Vec<xwin::DebugTarget>,
         /// By default, symlinks are added to both the CRT and WindowsSDK to
         /// address casing issues in general usage. For example, if you are
         /// compiling C/C++ code that does `#include <windows.h>`, it will break
         /// on a case-sensitive file system, as the actual path in the WindowsSDK
         /// is `Windows.h`. This also applies even if the C/C++ you are compiling
         /// uses correct casing for all CRT/SDK includes, as the internal headers
-        /// also use incorrect casing in most cases.
-        #[clap(long)]
+        /// also use incorrect casing in most cases. Symlinks are never added if
+        /// the output is on a case-insensitive filesystem, eg. the macOS default.
+        #[clap(long, env = "XWIN_DISABLE_SYMLINKS")]
         disable_symlinks: bool,
         /// By default, we convert the MS specific `x64`, `arm`, and `arm64`
         /// target architectures to the more canonical `x86_64`, `aarch`, and
         /// `aarch64` of LLVM etc when creating directories/names. Passing this
         /// flag will preserve the MS names for those targets.
-        #[clap(long)]
+        #[clap(long, env = "XWIN_PRESERVE_MS_ARCH_NOTATION")]
         preserve_ms_arch_notation: bool,
         /// The root output directory. Defaults to `./.xwin-cache/splat` if not
         /// specified.
-        #[clap(long)]
+        #[clap(long, env = "XWIN_OUTPUT")]
         output: Option<PathBuf>,
         /// Copies files from the unpack directory to the splat directory instead
         /// of moving them, which preserves the original unpack directories but
         /// increases overall time and disk usage
-        #[clap(long)]
+        #[clap(long, env = "XWIN_COPY")]
         copy: bool,
+        /// Writes `env.sh`, `env.ps1`, and `.env` files to the root output
+        /// directory that export the same environment variables as `xwin env`
+        /// for the single `--arch` being splatted
+        #[clap(long, env = "XWIN_ENV_FILES")]
+        env_files: bool,
+        /// Additional names to symlink libraries as, eg. `WS2_32` for
+        /// `ws2_32.lib`, for when something links them with different casing
+        /// than the libraries themselves. The libraries referenced by the
+        /// `/DEFAULTLIB` directives in the CRT are always symlinked.
+        #[clap(
+            long = "lib-symlink",
+            env = "XWIN_LIB_SYMLINKS",
+            use_value_delimiter = true
+        )]
+        lib_symlinks: Vec<String>,
+        /// Fails if any of the includes in the SDK headers can't be resolved
+        /// to a header in the splat, which usually means a payload is missing,
+        /// eg. the headers for C++/WinRT
+        #[clap(long, env = "XWIN_STRICT_INCLUDES")]
+        strict_includes: bool,
+        /// Checks that every file was written to the output with the same
+        /// size it had in the unpack directory once each payload is splatted,
+        /// catching copies or moves that silently failed
+        #[clap(long, env = "XWIN_VERIFY")]
+        verify: bool,
+        /// Checks that the finished output has the headers and libs that any
+        /// build needs, eg. `windows.h`, `kernel32.lib`, and `libcmt.lib`, for
+        /// each of the selected architectures and variants, failing the splat
+        /// if any of them are missing, eg. because of a payload selection
+        /// mistake
+        #[clap(long, env = "XWIN_CHECK")]
+        check: bool,
+        /// Only runs the same checks as `--check` against an existing output,
+        /// without splatting anything. The architectures and variants are
+        /// taken from the output's stamp if it has one.
+        #[clap(long, env = "XWIN_CHECK_ONLY", conflicts_with = "check")]
+        check_only: Option<PathBuf>,
+        /// Rewrites the splatted import libs without the debug sections of
+        /// their import descriptors and their redundant second linker member,
+        /// which shrinks the output for when the libs are only linked against
+        #[clap(long, env = "XWIN_THIN_IMPORT_LIBS")]
+        thin_import_libs: bool,
+        /// Stores the PDBs kept by `--include-debug-symbols` or
+        /// `--include-debug-symbols-for` in symbol server layout, ie.
+        /// `<output>/symbols/<name>/<GUID><age>/<name>`, rather than next to
+        /// their libs, so that the directory can be served directly to
+        /// debuggers and symbolizers
+        #[clap(long, env = "XWIN_SYMBOL_SERVER_LAYOUT")]
+        symbol_server_layout: bool,
+        /// Splats each `--arch` to its own `<output>/<arch>` directory, with
+        /// its own `crt` and `sdk` trees, so that eg. the jobs of a CI matrix
+        /// only need the one for their architecture. The arch independent
+        /// headers are hardlinked between them.
+        #[clap(long, env = "XWIN_SPLIT_OUTPUT_PER_ARCH")]
+        split_output_per_arch: bool,
+        /// A directory of unified diffs, ie. `*.patch` or `*.diff` files, that
+        /// are applied to the output once it has been splatted, along with an
+        /// optional `files` directory whose contents replace the files at the
+        /// same paths in the output
+        #[clap(long, env = "XWIN_PATCHES")]
+        patches: Option<PathBuf>,
+        /// Writes a checksums file for each of the algorithms, ie. `SHA256SUMS`
+        /// for `sha256` and `B3SUMS` for `blake3`, to the root output directory,
+        /// covering every regular file in it, that can be verified with eg.
+        /// `sha256sum -c SHA256SUMS`
+        #[clap(
+            long,
+            env = "XWIN_CHECKSUMS",
+            possible_values(CHECKSUM_ALGORITHMS),
+            use_value_delimiter = true
+        )]
+        checksums: Vec<xwin::checksums::Algorithm>,
+        /// Writes a JSON report of every file that was deliberately not
+        /// splatted, ie. debug libs and PDBs that weren't included, CRT
+        /// directories only used by the Store variant, and SDK headers that
+        /// duplicate another header, along with the header that was used
+        #[clap(long, env = "XWIN_SKIPPED_REPORT")]
+        skipped_report: Option<PathBuf>,
+        /// Writes an in-toto statement with the SLSA provenance of the output,
+        /// ie. the manifest version, the digest of every payload, the xwin
+        /// version, and the parameters that produced the output's hash, as
+        /// reported by `xwin hash`. The path can't be within the output.
+        #[clap(long, env = "XWIN_PROVENANCE")]
+        provenance: Option<PathBuf>,
+        /// Only splats the specified profiles from the `[splat.profiles]` in
+        /// the config file, rather than all of them. Each profile is splatted
+        /// to its own output, sharing the same downloaded and unpacked payloads.
+        #[clap(long = "profile", env = "XWIN_PROFILES", use_value_delimiter = true)]
+        profiles: Vec<String>,
         // Splits the CRT and SDK into architecture and variant specific
         // directories. The shared headers in the CRT and SDK are duplicated
         // for each output so that each combination is self-contained.
         // #[clap(long)]
         // isolated: bool,
     },
+    /// Updates a previous splat in place to the latest SDK and CRT, or the
+    /// ones in the manifest specified by `--manifest-version`, `--channel`, or
+    /// `--manifest`. Only the payloads that changed are downloaded and
+    /// splatted, and the files of the payloads that no longer exist upstream
+    /// are removed. The architectures, variants, and splat options are the
+    /// ones the output was originally splatted with.
+    Update {
+        /// The root output directory of the splat. Defaults to
+        /// `./.xwin-cache/splat` if not specified.
+        #[clap(long, env = "XWIN_OUTPUT")]
+        output: Option<PathBuf>,
+        /// Prints the payloads that would be added, removed, or changed,
+        /// without actually updating the output
+        #[clap(long, env = "XWIN_UPDATE_DRY_RUN")]
+        dry_run: bool,
+    },
+    /// Prints the environment variables needed to compile and link against a
+    /// previous splat for a single `--arch`, ie. `INCLUDE`, `LIB`, `CL`, `LINK`,
+    /// and the `CFLAGS_<target>`/`CXXFLAGS_<target>` used by the `cc` crate
+    Env {
+        /// The root output directory of the splat. Defaults to
+        /// `./.xwin-cache/splat` if not specified.
+        #[clap(long, env = "XWIN_OUTPUT")]
+        output: Option<PathBuf>,
+        /// The format to print the environment in. Defaults to `sh` if not
+        /// specified.
+        #[clap(long, env = "XWIN_ENV_FORMAT", possible_values(ENV_FORMATS))]
+        format: Option<xwin::generate::EnvFormat>,
+    },
+    /// Generates files for consuming a previous splat for a single `--arch`
+    /// from various build systems and tools
+    Generate {
+        /// The root output directory of the splat. Defaults to
+        /// `./.xwin-cache/splat` if not specified.
+        #[clap(long, env = "XWIN_OUTPUT")]
+        output: Option<PathBuf>,
+        #[clap(subcommand)]
+        generator: Generator,
+    },
+    /// Prints the url and SRI hash of every file that needs to be downloaded
+    /// for the selection, along with the SRI hash of a previous splat as used
+    /// for the `outputHash` of a Nix fixed-output derivation with
+    /// `outputHashMode = "recursive"`, as JSON
+    Nix {
+        /// The root output directory of the splat. Defaults to
+        /// `./.xwin-cache/splat` if not specified.
+        #[clap(long, env = "XWIN_OUTPUT")]
+        output: Option<PathBuf>,
+        /// Fails if the SRI hash of the output doesn't match, eg. `sha256-<base64>`
+        #[clap(long, env = "XWIN_NIX_HASH")]
+        expected_hash: Option<String>,
+    },
+    /// Compares two splat outputs and prints the files that were added,
+    /// removed, or changed between them, along with their checksums. Either
+    /// side can also be a file of checksums in the format output by `sha256sum`.
+    Diff {
+        /// The old output directory or checksums file
+        old: PathBuf,
+        /// The new output directory or checksums file
+        new: PathBuf,
+    },
+    /// Prints a stable hash of a splat output, covering the relative path and
+    /// contents of every file and the target of every symlink, that can be
+    /// compared across machines, eg. to key CI caches or detect drift
+    Hash {
+        /// The root output directory of the splat. Defaults to
+        /// `./.xwin-cache/splat` if not specified.
+        #[clap(long, env = "XWIN_OUTPUT")]
+        output: Option<PathBuf>,
+        /// Fails if the hash of the output doesn't match
+        #[clap(long, env = "XWIN_EXPECTED_HASH")]
+        expected_hash: Option<String>,
+    },
+    /// Looks up which libraries in a previous splat export a symbol, eg. to
+    /// find the import lib that needs to be linked for a Windows API function,
+    /// or retrieves the PDBs matching its DLLs
+    Symbols {
+        /// The root output directory of the splat. Defaults to
+        /// `./.xwin-cache/splat` if not specified.
+        #[clap(long, env = "XWIN_OUTPUT")]
+        output: Option<PathBuf>,
+        #[clap(subcommand)]
+        cmd: SymbolsCommand,
+    },
+    /// Runs checks against a previous splat, failing if any of them find an
+    /// issue
+    Verify {
+        /// The root output directory of the splat. Defaults to
+        /// `./.xwin-cache/splat` if not specified.
+        #[clap(long, env = "XWIN_OUTPUT")]
+        output: Option<PathBuf>,
+        /// Checks that the output can be moved or mounted at a different
+        /// prefix, ie. that every symlink is relative and stays within the
+        /// output, and that no file, eg. a generated script, contains the
+        /// absolute path of the output
+        #[clap(long, env = "XWIN_RELOCATABLE")]
+        relocatable: bool,
+        /// Checks that the files changed by the patches applied with
+        /// `splat --patches` still have the changes, eg. that they weren't
+        /// reverted by `xwin update` or edited afterwards
+        #[clap(long, env = "XWIN_VERIFY_PATCHES")]
+        patches: bool,
+        /// Checks that the output matches the hash in a list of known-good
+        /// hashes, eg. one published for an xwin release, which is read from
+        /// a path or an http(s) url
+        #[clap(long, env = "XWIN_KNOWN_GOOD")]
+        known_good: Option<String>,
+    },
+    /// Deletes downloaded payloads, unpacked payloads, and/or the splat output.
+    /// If none of `--downloads`, `--unpacked`, or `--splat` are passed, the
+    /// downloaded and unpacked payloads are deleted.
+    Clean {
+        /// Deletes the downloaded payloads in the `--cache-dir`
+        #[clap(long, env = "XWIN_CLEAN_DOWNLOADS")]
+        downloads: bool,
+        /// Deletes the unpacked payloads in the `--cache-dir`
+        #[clap(long, env = "XWIN_CLEAN_UNPACKED")]
+        unpacked: bool,
+        /// Deletes the splat output
+        #[clap(long, env = "XWIN_CLEAN_SPLAT")]
+        splat: bool,
+        /// The root output directory of the splat. Defaults to
+        /// `./.xwin-cache/splat` if not specified.
+        #[clap(long, env = "XWIN_OUTPUT")]
+        output: Option<PathBuf>,
+        /// Prints what would be deleted and how much space would be reclaimed,
+        /// without actually deleting anything
+        #[clap(long, env = "XWIN_CLEAN_DRY_RUN")]
+        dry_run: bool,
+    },
+    /// Inspects the cache directory
+    Cache {
+        #[clap(subcommand)]
+        cmd: CacheCommand,
+    },
+    /// Serves the download cache, and a gzipped tarball of a previous splat,
+    /// over HTTP, so that other xwin instances can use it as their
+    /// `--upstream` rather than each retrieving payloads from the Microsoft CDN
+    Serve {
+        /// The address to listen on
+        #[clap(long, env = "XWIN_SERVE_ADDR", default_value = "0.0.0.0:8000")]
+        addr: String,
+        /// The root output directory of the splat served as
+        /// `/splat.tar.gz`. Defaults to `./.xwin-cache/splat` if not specified.
+        #[clap(long, env = "XWIN_OUTPUT")]
+        output: Option<PathBuf>,
+    },
+    /// Compiles and links a small C and C++ program against a previous splat
+    /// for each `--arch` with `clang-cl` and `lld-link`, and reports whether
+    /// each succeeded, to check that the splat is usable
+    Selftest {
+        /// The root output directory of the splat. Defaults to
+        /// `./.xwin-cache/splat` if not specified.
+        #[clap(long, env = "XWIN_OUTPUT")]
+        output: Option<PathBuf>,
+        /// The `clang-cl` to compile with. Defaults to the one in `PATH`.
+        #[clap(long, env = "XWIN_CLANG_CL")]
+        clang_cl: Option<PathBuf>,
+        /// The `lld-link` to link with. Defaults to the one in `PATH`.
+        #[clap(long, env = "XWIN_LLD_LINK")]
+        lld_link: Option<PathBuf>,
+    },
+    /// Diagnoses common problems with the environment, ie. that `clang-cl`
+    /// and `lld-link` are in `PATH` and can be run, that a previous splat has
+    /// the key headers and libs for each of its architectures, and that its
+    /// filesystem supports symlinks, and that the cache doesn't contain the
+    /// remains of interrupted runs, along with hints on how to fix any issues.
+    /// The findings are printed as JSON if `--json` is also passed.
+    Doctor {
+        /// The root output directory of the splat. Defaults to
+        /// `./.xwin-cache/splat` if not specified.
+        #[clap(long, env = "XWIN_OUTPUT")]
+        output: Option<PathBuf>,
+    },
+    /// Symlinks the runtime DLLs in the library directories of a previous
+    /// splat into a Wine prefix for each `--arch`, and registers them as
+    /// `native,builtin` DLL overrides, so that cross compiled executables can
+    /// be run under Wine.
+    ///
+    /// The `x86_64` DLLs are placed in `system32`, and the `x86` DLLs in
+    /// `syswow64`, or `system32` if the prefix is 32-bit.
+    WineSetup {
+        /// The root output directory of the splat. Defaults to
+        /// `./.xwin-cache/splat` if not specified.
+        #[clap(long, env = "XWIN_OUTPUT")]
+        output: Option<PathBuf>,
+        /// The Wine prefix to set up. Defaults to `~/.wine` if not specified.
+        #[clap(long, env = "WINEPREFIX")]
+        prefix: Option<PathBuf>,
+        /// Copies the DLLs into the prefix instead of symlinking them
+        #[clap(long, env = "XWIN_WINE_SETUP_COPY")]
+        copy: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum Generator {
+    /// Generates a CMake toolchain file that uses clang-cl and lld-link with
+    /// the includes and libs of the splat
+    CmakeToolchain {
+        /// The path to write the toolchain file to, or `-` to print it to
+        /// stdout. Defaults to `<output>/toolchain-<arch>.cmake` if not
+        /// specified.
+        #[clap(long, env = "XWIN_CMAKE_TOOLCHAIN_PATH")]
+        path: Option<PathBuf>,
+    },
+    /// Generates a Meson cross file that uses clang-cl and lld-link with the
+    /// includes and libs of the splat
+    MesonCross {
+        /// The path to write the cross file to, or `-` to print it to stdout.
+        /// Defaults to `<output>/meson-<arch>.ini` if not specified.
+        #[clap(long, env = "XWIN_MESON_CROSS_PATH")]
+        path: Option<PathBuf>,
+    },
+    /// Generates `xwin-clang-cl` and `xwin-lld-link` shell scripts that
+    /// invoke clang-cl and lld-link with the includes and libs of the splat,
+    /// so that they can be used directly as eg. `CC` and `LINKER`
+    Wrappers {
+        /// The directory to write the scripts to. Defaults to
+        /// `<output>/bin/<arch>` if not specified.
+        #[clap(long, env = "XWIN_WRAPPERS_DIR")]
+        dir: Option<PathBuf>,
+    },
+    /// Generates a `zig-libc-<arch>.txt` libc installation file in the root of
+    /// the splat, along with `xwin-zig-cc` and `xwin-zig-c++` shell scripts
+    /// that invoke `zig cc` and `zig c++` targeting `<arch>-windows-msvc` with
+    /// it, and with the includes and libs of the splat
+    Zig {
+        /// The directory to write the scripts to. Defaults to
+        /// `<output>/bin/<arch>` if not specified.
+        #[clap(long, env = "XWIN_ZIG_DIR")]
+        dir: Option<PathBuf>,
+    },
+    /// Generates a script that sets up `INCLUDE` and `LIB` like `vcvars` does,
+    /// translated to Windows paths with `winepath`, for running the genuine
+    /// MSVC tools under Wine against the splat. It must be sourced, eg.
+    /// `. vcvars-x86_64.sh && wine cl.exe main.c`.
+    WineVcvars {
+        /// The path to write the script to, or `-` to print it to stdout.
+        /// Defaults to `<output>/vcvars-<arch>.sh` if not specified.
+        #[clap(long, env = "XWIN_WINE_VCVARS_PATH")]
+        path: Option<PathBuf>,
+        /// The directory containing the MSVC tools, eg. `cl.exe` and
+        /// `link.exe`, which is added to `PATH` and `WINEPATH`
+        #[clap(long, env = "XWIN_WINE_VCVARS_BIN_DIR")]
+        bin_dir: Option<PathBuf>,
+    },
+    /// Generates a `compile_flags.txt` for clangd with the includes of the
+    /// splat as system includes
+    CompileFlags {
+        /// The path to write the file to, or `-` to print it to stdout.
+        /// Defaults to `./compile_flags.txt` if not specified.
+        #[clap(long, env = "XWIN_COMPILE_FLAGS_PATH")]
+        path: Option<PathBuf>,
+    },
+    /// Generates a pkg-config file for a `windows-sdk` package with the
+    /// includes and libs of the splat, for build systems that probe for
+    /// dependencies with pkg-config
+    PkgConfig {
+        /// The path to write the file to, or `-` to print it to stdout.
+        /// Defaults to `<output>/pkgconfig/<arch>/windows-sdk.pc` if not
+        /// specified.
+        #[clap(long, env = "XWIN_PKG_CONFIG_PATH")]
+        path: Option<PathBuf>,
+    },
+    /// Generates a VS Code `c_cpp_properties.json` with a configuration for
+    /// each `--arch` with the includes of the splat
+    #[clap(name = "c-cpp-properties")]
+    CCppProperties {
+        /// The path to write the file to, or `-` to print it to stdout.
+        /// Defaults to `./.vscode/c_cpp_properties.json` if not specified.
+        #[clap(long, env = "XWIN_C_CPP_PROPERTIES_PATH")]
+        path: Option<PathBuf>,
+    },
+    /// Generates a `BUILD.bazel` in the root of the splat, with a `headers`
+    /// `cc_library` for the includes and a `<arch>_<lib>` `cc_import` for every
+    /// library of each `--arch`, along with an `xwin.json` descriptor of the
+    /// include and library directories for use in repository rules
+    Bazel,
+    /// Generates a `Toolchain.props` in the root of the splat that points the
+    /// MSBuild VC targets at the includes and libs of the splat for each
+    /// `--arch` platform, along with a `Directory.Build.props` that imports
+    /// it, for building `.vcxproj` projects with MSBuild under Wine
+    Msbuild {
+        /// The directory to write `Directory.Build.props` to, ie. the
+        /// directory containing the projects. Defaults to the current
+        /// directory if not specified.
+        #[clap(long, env = "XWIN_MSBUILD_DIR")]
+        dir: Option<PathBuf>,
+    },
+    /// Generates a Cargo config with the `linker` and `rustflags` for each
+    /// `--arch` target, as well as the `[env]` used by the `cc` crate to
+    /// compile C/C++ code with clang-cl
+    CargoConfig {
+        /// The path to write the config to, or `-` to print it to stdout.
+        /// Defaults to `<output>/cargo-config.toml` if not specified.
+        #[clap(long, env = "XWIN_CARGO_CONFIG_PATH")]
+        path: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// Prints the location and total size of the `--cache-dir`, along with
+    /// the size, number of files, and last access time of each downloaded
+    /// and unpacked payload, and totals for each CRT and SDK version. The
+    /// report is printed as JSON if `--json` is also passed.
+    Stats,
+}
+
+#[derive(Subcommand)]
+pub enum SymbolsCommand {
+    /// Reads the symbols exported by every `.lib` in the output and writes an
+    /// index of them to `.xwin-symbols.json` in the root of the output
+    Index,
+    /// Prints the libraries that export the symbol, along with the matching
+    /// symbols in each of them, building the index first if it doesn't exist
+    /// or is older than the output. The symbol can contain `*` to match any
+    /// sequence of characters, eg. `CreateFile*`, and also matches the names
+    /// decorated by the x86 calling conventions, eg. `_CreateFileW@28`. The
+    /// libraries are printed as JSON if `--json` is also passed.
+    Find {
+        /// The name of the symbol, eg. `CreateFileW`
+        symbol: String,
+    },
+    /// Retrieves the PDBs matching the DLLs and executables in the output,
+    /// eg. the debug CRT and UCRT, from a symbol server, and stores them in
+    /// symbol server layout, ie. `<name>/<GUID><age>/<name>`, so that the
+    /// store can be passed to debuggers and symbolizers. Import libs don't
+    /// have PDBs of their own, so they are matched to the DLLs they import
+    /// from, which are looked for in the output and the `--image-dir`s, and
+    /// the DLLs that aren't found are listed as unresolved. PDBs that are
+    /// already present in the store aren't retrieved again. The PDBs are
+    /// printed as JSON if `--json` is also passed.
+    Fetch {
+        /// The directory to store the PDBs in. Defaults to
+        /// `./.xwin-cache/symbols` if not specified.
+        #[clap(long, env = "XWIN_SYMBOL_STORE")]
+        store: Option<PathBuf>,
+        /// The symbol server to retrieve the PDBs from
+        #[clap(
+            long,
+            env = "XWIN_SYMBOL_SERVER",
+            default_value = xwin::pdb::MICROSOFT_SYMBOL_SERVER
+        )]
+        symbol_server: String,
+        /// Directories, eg. the `system32` directory of the Windows install a
+        /// crash dump came from, that the DLLs imported by the import libs in
+        /// the output are looked for in, if they aren't in the output
+        #[clap(
+            long = "image-dir",
+            env = "XWIN_IMAGE_DIRS",
+            use_value_delimiter = true
+        )]
+        image_dirs: Vec<PathBuf>,
+    },
 }
 
 const ARCHES: &[&str] = &["x86", "x86_64", "aarch", "aarch64"];
-const VARIANTS: &[&str] = &["desktop", "onecore", /*"store",*/ "spectre"];
+const CHECKSUM_ALGORITHMS: &[&str] = &["sha256", "blake3"];
+const VARIANTS: &[&str] = &["desktop", "onecore", "store", "spectre"];
+const COMPONENTS: &[&str] = &["crt", "sdk"];
 const LOG_LEVELS: &[&str] = &["off", "error", "warn", "info", "debug", "trace"];
+const ENV_FORMATS: &[&str] = &["sh", "fish", "powershell", "dotenv", "json"];
+const GHA_CACHE_MODES: &[&str] = &["downloads", "splat"];
+
+const ARCH_NEGATIONS: [&str; 4] = ["!x86", "!x86_64", "!aarch", "!aarch64"];
+const VARIANT_NEGATIONS: [&str; 4] = ["!desktop", "!onecore", "!store", "!spectre"];
+
+/// The Rust target triples that map onto the architectures, which are accepted
+/// but not listed in the help
+fn arch_triples() -> impl Iterator<Item = &'static str> {
+    xwin::Arch::TRIPLES.iter().map(|(triple, _)| *triple)
+}
+
+/// The values accepted by a selection, of which only `all` and the plain
+/// values are listed in the help
+fn selector_values<'help>(
+    values: &'help [&'help str],
+    negations: &'help [&'help str],
+    hidden: impl Iterator<Item = &'static str>,
+) -> impl Iterator<Item = PossibleValue<'help>> {
+    std::iter::once("all")
+        .chain(values.iter().copied())
+        .map(PossibleValue::new)
+        .chain(
+            negations
+                .iter()
+                .map(|val| PossibleValue::new(val).hide(true))
+                .chain(hidden.map(|val| PossibleValue::new(val).hide(true))),
+        )
+}
 
 fn parse_level(s: &str) -> Result<LevelFilter, Error> {
     s.parse::<LevelFilter>()
@@ -97,286 +674,3831 @@ pub struct Args {
     /// Doesn't display the prompt to accept the license
     #[clap(long, env = "XWIN_ACCEPT_LICENSE")]
     accept_license: bool,
+    /// Path to a config file to use. If not specified, an `xwin.toml` is
+    /// searched for in the current directory and each of its parents.
+    #[clap(long, env = "XWIN_CONFIG")]
+    config: Option<PathBuf>,
     /// The log level for messages, only log messages at or above the level will be emitted.
+    /// Defaults to `info` if not specified.
     #[clap(
         short = 'L',
         long = "log-level",
-        default_value = "info",
+        env = "XWIN_LOG_LEVEL",
+        parse(try_from_str = parse_level),
+        possible_values(LOG_LEVELS),
+    )]
+    level: Option<LevelFilter>,
+    /// Writes log messages to the specified file in addition to the console.
+    /// The file has its own log level, so it can capture eg. `debug` messages
+    /// about which files were skipped, without affecting the console output.
+    #[clap(long, env = "XWIN_LOG_FILE")]
+    log_file: Option<PathBuf>,
+    /// The log level for messages written to the `--log-file`. Defaults to
+    /// `debug` if not specified.
+    #[clap(
+        long,
+        env = "XWIN_LOG_FILE_LEVEL",
         parse(try_from_str = parse_level),
         possible_values(LOG_LEVELS),
     )]
-    level: LevelFilter,
+    log_file_level: Option<LevelFilter>,
     /// Output log messages as json
-    #[clap(long)]
+    #[clap(long, env = "XWIN_JSON")]
     json: bool,
+    /// Doesn't display progress bars or phase summaries, only log messages
+    #[clap(short, long, env = "XWIN_QUIET")]
+    quiet: bool,
+    /// Doesn't display animated progress bars, instead printing a concise
+    /// summary line for each phase once it completes. This is the default if
+    /// stderr is not a TTY.
+    #[clap(long, env = "XWIN_NO_PROGRESS")]
+    no_progress: bool,
+    /// Draws progress with only ASCII characters, without any emoji. This is
+    /// the default if the locale is not UTF-8.
+    #[clap(long, env = "XWIN_ASCII")]
+    ascii: bool,
+    /// Prints a report of the time taken and bytes produced by each phase for
+    /// each payload once finished, sorted by the total time taken. The report
+    /// is printed as JSON if `--json` is also passed.
+    #[clap(long, env = "XWIN_TIMINGS")]
+    timings: bool,
+    /// Writes a JSON report of the run to the path once finished, even if it
+    /// failed, with the versions that were resolved, the outcome, sizes, and
+    /// durations of each payload, and the files that were skipped
+    #[clap(long, env = "XWIN_REPORT")]
+    report: Option<PathBuf>,
     /// If set, will use a temporary directory for all files used for creating
     /// the archive and deleted upon exit, otherwise, all downloaded files
     /// are kept in the `--cache-dir` and won't be retrieved again
-    #[clap(long)]
+    #[clap(long, env = "XWIN_TEMP")]
     temp: bool,
+    /// The number of threads used to download, unpack, and splat payloads in
+    /// parallel. Defaults to the number of logical CPUs if not specified.
+    #[clap(short, long, env = "XWIN_JOBS")]
+    jobs: Option<usize>,
     /// Specifies the cache directory used to persist downloaded items to disk.
     /// Defaults to `./.xwin-cache` if not specified.
-    #[clap(long)]
+    #[clap(long, env = "XWIN_CACHE_DIR")]
     cache_dir: Option<PathBuf>,
+    /// Restores the `downloads` or finished `splat` from the GitHub Actions
+    /// cache, keyed by the checksums of the selected payloads, and saves it
+    /// once finished if it wasn't restored. Ignored if not running in GitHub
+    /// Actions.
+    #[clap(long, env = "XWIN_GHA_CACHE", possible_values(GHA_CACHE_MODES))]
+    gha_cache: Option<xwin::gha::CacheMode>,
+    /// The url of an `xwin serve` instance, eg. on the local network, that
+    /// payloads are retrieved from before falling back to the Microsoft CDN
+    #[clap(long, env = "XWIN_UPSTREAM")]
+    upstream: Option<String>,
+    /// The proxy to send every request through, eg. `http://proxy:3128`, or
+    /// `socks5h://localhost:1080` for a SOCKS5 proxy that also resolves host
+    /// names. If not specified, the proxy is taken from the `HTTPS_PROXY`,
+    /// `HTTP_PROXY`, or `ALL_PROXY` environment variables, if set.
+    #[clap(long, env = "XWIN_PROXY")]
+    proxy: Option<String>,
+    /// Fails, with exit code 16, if anything would be retrieved from the
+    /// network, eg. the manifest or a payload that isn't already in the
+    /// cache, so that hermetic builds can prove the network was never
+    /// accessed.
+    #[clap(long, env = "XWIN_NO_NETWORK")]
+    no_network: bool,
+    /// The number of connections used to retrieve each payload of at least
+    /// 16 MiB, eg. the larger CAB files, with each connection retrieving a
+    /// range of it in parallel, for networks where a single connection to the
+    /// CDN is the bottleneck. Defaults to 1.
+    #[clap(long, env = "XWIN_DOWNLOAD_CONNECTIONS")]
+    download_connections: Option<u32>,
     /// Specifies a VS manifest to use from a file, rather than downloading it
     /// from the Microsoft site.
-    #[clap(long, conflicts_with_all = &["manifest-version", "channel"])]
+    #[clap(long, env = "XWIN_MANIFEST", conflicts_with_all = &["manifest-version", "channel"])]
     manifest: Option<PathBuf>,
+    /// An offline layout created by `vs_installer.exe --layout`, eg. one
+    /// already maintained for installing Visual Studio, whose channel manifest
+    /// and catalog are used rather than downloading them, and whose payloads
+    /// are read directly rather than downloaded. Payloads missing from the
+    /// layout are still downloaded.
+    #[clap(
+        long,
+        env = "XWIN_VS_LAYOUT",
+        conflicts_with_all = &["manifest", "manifest-version", "channel", "manifest-url"]
+    )]
+    vs_layout: Option<PathBuf>,
+    /// Specifies a TOML or JSON manifest of additional packages, eg. internal
+    /// or third party SDK add-ons, to download, unpack, and splat along with
+    /// the packages from the VS manifest.
+    #[clap(long, env = "XWIN_CUSTOM_MANIFEST")]
+    custom_manifest: Option<PathBuf>,
+    /// Includes the Windows SDK tools, eg. `rc.exe`, `mt.exe`, and
+    /// `midl.exe`, for the selected architectures, which are splatted to
+    /// `sdk/bin/<arch>`. These aren't needed to cross compile, but are needed
+    /// to compile resources and embed manifests, eg. under Wine.
+    #[clap(long, env = "XWIN_INCLUDE_SDK_BIN")]
+    include_sdk_bin: bool,
+    /// Includes `signtool.exe`, and the libraries it loads, for the selected
+    /// architectures, which are splatted to `sdk/bin/<arch>`, so that
+    /// binaries can be signed with the same SDK version, eg. under Wine.
+    #[clap(long, env = "XWIN_INCLUDE_SIGNING_TOOLS")]
+    include_signing_tools: bool,
+    /// The version of the Windows SDK to use, rather than the latest one in
+    /// the manifest. Can be an exact version, eg. `10.0.19041`, a pattern, eg.
+    /// `10.0.19*`, or a comma separated list of comparisons, eg.
+    /// `>=10.0.19041,<10.0.22000`, in which case the newest matching version
+    /// is used.
+    #[clap(long, env = "XWIN_SDK_VERSION")]
+    sdk_version: Option<xwin::version::VersionReq>,
+    /// The version of the MSVC CRT to use, rather than the latest one in the
+    /// manifest, eg. `14.29.16.11`, or a pattern or comparisons the same as
+    /// `--sdk-version`.
+    #[clap(long, env = "XWIN_CRT_VERSION")]
+    crt_version: Option<xwin::version::VersionReq>,
     /// The version to retrieve, can either be a major version of 15 or 16, or
-    /// a "<major>.<minor>" version.
-    #[clap(long, default_value = "16")]
-    manifest_version: String,
-    /// The product channel to use.
-    #[clap(long, default_value = "release")]
-    channel: String,
-    /// The architectures to include
+    /// a "<major>.<minor>" version. Defaults to `16` if not specified.
+    #[clap(long, env = "XWIN_MANIFEST_VERSION")]
+    manifest_version: Option<String>,
+    /// The product channel to use. Defaults to `release` if not specified.
+    #[clap(long, env = "XWIN_CHANNEL")]
+    channel: Option<String>,
+    /// The url to retrieve the top-level channel manifest from, rather than
+    /// the `aka.ms` url for the manifest version and channel.
+    #[clap(long, env = "XWIN_MANIFEST_URL", conflicts_with = "manifest")]
+    manifest_url: Option<String>,
+    /// The expected sha256 of the top-level channel manifest, whether it is
+    /// downloaded or read from `--manifest`. The manifest is rejected if it
+    /// doesn't match.
+    #[clap(long, env = "XWIN_MANIFEST_SHA256")]
+    manifest_sha256: Option<xwin::util::Sha256>,
+    /// The expected sha256 of the package manifest that the channel manifest
+    /// links to, which contains the checksums every payload is verified
+    /// against. The package manifest is rejected if it doesn't match.
+    #[clap(long, env = "XWIN_PACKAGE_MANIFEST_SHA256")]
+    package_manifest_sha256: Option<xwin::util::Sha256>,
+    /// The architectures to include. Defaults to `x86_64` if not specified.
+    /// `all` selects every architecture, and an architecture prefixed with
+    /// `!` is removed from the selection, eg. `all,!x86`.
+    #[clap(
+        long,
+        env = "XWIN_ARCH",
+        possible_values(selector_values(ARCHES, &ARCH_NEGATIONS, arch_triples())),
+        use_value_delimiter = true
+    )]
+    arch: Option<Vec<xwin::Selector<xwin::Arch>>>,
+    /// The variants to include. Defaults to `desktop` if not specified.
+    /// `all` selects every variant, and a variant prefixed with `!` is
+    /// removed from the selection, eg. `all,!spectre`.
     #[clap(
         long,
-        possible_values(ARCHES),
-        use_value_delimiter = true,
-        default_value = "x86_64"
+        env = "XWIN_VARIANT",
+        possible_values(selector_values(VARIANTS, &VARIANT_NEGATIONS, std::iter::empty())),
+        use_value_delimiter = true
     )]
-    arch: Vec<xwin::Arch>,
-    /// The variants to include
+    variant: Option<Vec<xwin::Selector<xwin::Variant>>>,
+    /// The components to include, ie. `crt` for the MSVC CRT and `sdk` for
+    /// the Windows SDK and UCRT. Defaults to both if not specified. The
+    /// payloads of a component that isn't included are never downloaded.
     #[clap(
         long,
-        possible_values(VARIANTS),
-        use_value_delimiter = true,
-        default_value = "desktop"
+        env = "XWIN_COMPONENTS",
+        possible_values(COMPONENTS),
+        use_value_delimiter = true
     )]
-    variant: Vec<xwin::Variant>,
+    components: Option<Vec<xwin::Component>>,
     #[clap(subcommand)]
     cmd: Command,
+    /// The splat profiles from the config file
+    #[clap(skip)]
+    splat_profiles: std::collections::BTreeMap<String, xwin::config::SplatProfile>,
+    /// The command run after a successful splat, from the config file
+    #[clap(skip)]
+    post_splat: Vec<String>,
+    /// The extra downloads from the config file
+    #[clap(skip)]
+    extras: Vec<xwin::extra::ExtraDownload>,
 }
 
-fn main() -> Result<(), Error> {
-    let args = Args::parse();
-    setup_logger(args.json, args.level)?;
-
-    if !args.accept_license {
-        // The license link is the same for every locale, but we should probably
-        // retrieve it from the manifest in the future
-        println!("Do you accept the license at https://go.microsoft.com/fwlink/?LinkId=2086102 (yes | no)?");
+/// Boolean flags can only be enabled on the command line, so the config value
+/// is only used if the flag was neither passed nor set via its environment
+/// variable, which can explicitly disable it, eg. `XWIN_COPY=false`
+fn apply_flag(flag: &mut bool, env: &str, config: Option<bool>) {
+    if !*flag && std::env::var_os(env).is_none() {
+        *flag = config.unwrap_or_default();
+    }
+}
 
-        let mut accept = String::new();
-        std::io::stdin().read_line(&mut accept)?;
+impl Args {
+    /// Fills in any options that weren't specified on the command line or via
+    /// environment variables with the values from the config file, if present
+    fn apply_config(&mut self, config: xwin::config::Config) -> Result<(), Error> {
+        apply_flag(
+            &mut self.accept_license,
+            "XWIN_ACCEPT_LICENSE",
+            config.accept_license,
+        );
+        apply_flag(&mut self.json, "XWIN_JSON", config.json);
+        apply_flag(&mut self.quiet, "XWIN_QUIET", config.quiet);
+        apply_flag(
+            &mut self.no_progress,
+            "XWIN_NO_PROGRESS",
+            config.no_progress,
+        );
+        apply_flag(&mut self.ascii, "XWIN_ASCII", config.ascii);
+        apply_flag(&mut self.timings, "XWIN_TIMINGS", config.timings);
+        apply_flag(&mut self.temp, "XWIN_TEMP", config.temp);
+        apply_flag(&mut self.no_network, "XWIN_NO_NETWORK", config.no_network);
+        apply_flag(
+            &mut self.include_sdk_bin,
+            "XWIN_INCLUDE_SDK_BIN",
+            config.include_sdk_bin,
+        );
+        apply_flag(
+            &mut self.include_signing_tools,
+            "XWIN_INCLUDE_SIGNING_TOOLS",
+            config.include_signing_tools,
+        );
 
-        match accept.trim() {
-            "yes" => println!("license accepted!"),
-            "no" => anyhow::bail!("license not accepted"),
-            other => anyhow::bail!("unknown response to license request {}", other),
+        if self.level.is_none() {
+            self.level = config.log_level.as_deref().map(parse_level).transpose()?;
         }
-    }
 
-    let cwd = PathBuf::from_path_buf(std::env::current_dir().context("unable to retrieve cwd")?)
-        .map_err(|pb| anyhow::anyhow!("cwd {} is not a valid utf-8 path", pb.display()))?;
+        if self.log_file_level.is_none() {
+            self.log_file_level = config
+                .log_file_level
+                .as_deref()
+                .map(parse_level)
+                .transpose()?;
+        }
 
-    let draw_target = xwin::util::ProgressTarget::Stdout;
+        self.log_file = self.log_file.take().or(config.log_file);
+        self.report = self.report.take().or(config.report);
 
-    let ctx = if args.temp {
-        xwin::Ctx::with_temp(draw_target)?
-    } else {
-        let cache_dir = match &args.cache_dir {
-            Some(cd) => cd.clone(),
-            None => cwd.join(".xwin-cache"),
-        };
-        xwin::Ctx::with_dir(cache_dir, draw_target)?
-    };
+        // The manifest and layout conflict with the version and channel, so
+        // only take them from the config if neither was specified on the
+        // command line
+        if self.manifest.is_none()
+            && self.vs_layout.is_none()
+            && self.manifest_version.is_none()
+            && self.channel.is_none()
+        {
+            self.manifest = config.manifest;
+            self.vs_layout = config.vs_layout;
+        }
 
-    let ctx = std::sync::Arc::new(ctx);
+        if self.manifest.is_none() && self.vs_layout.is_none() {
+            self.manifest_version = self.manifest_version.take().or(config.manifest_version);
+            self.channel = self.channel.take().or(config.channel);
+            self.manifest_url = self.manifest_url.take().or(config.manifest_url);
+        }
 
-    let pkg_manifest = load_manifest(&ctx, &args, draw_target)?;
+        self.manifest_sha256 = self.manifest_sha256.take().or(config.manifest_sha256);
+        self.package_manifest_sha256 = self
+            .package_manifest_sha256
+            .take()
+            .or(config.package_manifest_sha256);
 
-    let arches = args.arch.into_iter().fold(0, |acc, arch| acc | arch as u32);
-    let variants = args
-        .variant
-        .into_iter()
-        .fold(0, |acc, var| acc | var as u32);
+        self.jobs = self.jobs.or(config.jobs);
+        self.cache_dir = self.cache_dir.take().or(config.cache_dir);
 
-    let pruned = xwin::prune_pkg_list(&pkg_manifest, arches, variants)?;
+        self.upstream = self.upstream.take().or(config.upstream);
+        self.proxy = self.proxy.take().or(config.proxy);
+        self.download_connections = self.download_connections.or(config.download_connections);
 
-    let op = match args.cmd {
-        Command::List => {
-            print_packages(&pruned);
-            return Ok(());
+        if self.gha_cache.is_none() {
+            self.gha_cache = config.gha_cache.as_deref().map(str::parse).transpose()?;
         }
-        Command::Download => xwin::Ops::Download,
-        Command::Unpack => xwin::Ops::Unpack,
-        Command::Splat {
+        self.custom_manifest = self.custom_manifest.take().or(config.custom_manifest);
+        if self.sdk_version.is_none() {
+            self.sdk_version = config.sdk_version.as_deref().map(str::parse).transpose()?;
+        }
+        if self.crt_version.is_none() {
+            self.crt_version = config.crt_version.as_deref().map(str::parse).transpose()?;
+        }
+        self.extras = config.extras;
+        self.arch = self.arch.take().or(config.arch);
+        self.variant = self.variant.take().or(config.variant);
+        self.components = self.components.take().or(config.components);
+
+        if let Command::Splat {
             include_debug_libs,
             include_debug_symbols,
+            include_debug_libs_for,
+            include_debug_symbols_for,
             disable_symlinks,
             preserve_ms_arch_notation,
-            copy,
             output,
-        } => xwin::Ops::Splat(xwin::SplatConfig {
-            include_debug_libs,
-            include_debug_symbols,
-            enable_symlinks: !disable_symlinks,
-            preserve_ms_arch_notation,
             copy,
-            output: output.unwrap_or_else(|| ctx.work_dir.join("splat")),
-        }),
-    };
+            env_files,
+            lib_symlinks,
+            strict_includes,
+            verify,
+            check,
+            thin_import_libs,
+            symbol_server_layout,
+            split_output_per_arch,
+            patches,
+            checksums,
+            skipped_report,
+            provenance,
+            ..
+        } = &mut self.cmd
+        {
+            let splat = config.splat;
+            self.splat_profiles = splat.profiles;
+            self.post_splat = splat.post_splat.unwrap_or_default();
 
-    let pkgs = pkg_manifest.packages;
+            apply_flag(
+                include_debug_libs,
+                "XWIN_INCLUDE_DEBUG_LIBS",
+                splat.include_debug_libs,
+            );
+            apply_flag(
+                include_debug_symbols,
+                "XWIN_INCLUDE_DEBUG_SYMBOLS",
+                splat.include_debug_symbols,
+            );
+            apply_flag(
+                disable_symlinks,
+                "XWIN_DISABLE_SYMLINKS",
+                splat.disable_symlinks,
+            );
+            apply_flag(
+                preserve_ms_arch_notation,
+                "XWIN_PRESERVE_MS_ARCH_NOTATION",
+                splat.preserve_ms_arch_notation,
+            );
+            apply_flag(copy, "XWIN_COPY", splat.copy);
+            apply_flag(env_files, "XWIN_ENV_FILES", splat.env_files);
+            apply_flag(
+                strict_includes,
+                "XWIN_STRICT_INCLUDES",
+                splat.strict_includes,
+            );
+            apply_flag(verify, "XWIN_VERIFY", splat.verify);
+            apply_flag(check, "XWIN_CHECK", splat.check);
+            apply_flag(
+                thin_import_libs,
+                "XWIN_THIN_IMPORT_LIBS",
+                splat.thin_import_libs,
+            );
+            apply_flag(
+                symbol_server_layout,
+                "XWIN_SYMBOL_SERVER_LAYOUT",
+                splat.symbol_server_layout,
+            );
+            apply_flag(
+                split_output_per_arch,
+                "XWIN_SPLIT_OUTPUT_PER_ARCH",
+                splat.split_output_per_arch,
+            );
 
-    let mp = ia::MultiProgress::with_draw_target(draw_target.into());
-    let work_items: Vec<_> = pruned
-        .into_iter()
-        .map(|pay| {
-            let prefix = match pay.kind {
-                xwin::PayloadKind::CrtHeaders => "CRT.headers".to_owned(),
-                xwin::PayloadKind::CrtLibs => {
-                    format!(
-                        "CRT.libs.{}.{}",
-                        pay.target_arch.map(|ta| ta.as_str()).unwrap_or("all"),
-                        pay.variant.map(|v| v.as_str()).unwrap_or("none")
-                    )
-                }
-                xwin::PayloadKind::SdkHeaders => {
-                    format!(
-                        "SDK.headers.{}.{}",
-                        pay.target_arch.map(|v| v.as_str()).unwrap_or("all"),
-                        pay.variant.map(|v| v.as_str()).unwrap_or("none")
-                    )
-                }
-                xwin::PayloadKind::SdkLibs => {
-                    format!(
-                        "SDK.libs.{}",
-                        pay.target_arch.map(|ta| ta.as_str()).unwrap_or("all")
-                    )
-                }
-                xwin::PayloadKind::SdkStoreLibs => "SDK.libs.store.all".to_owned(),
-                xwin::PayloadKind::Ucrt => "SDK.ucrt.all".to_owned(),
-            };
+            if output.is_none() {
+                *output = splat.output;
+            }
 
-            let pb = mp.add(
-                ia::ProgressBar::with_draw_target(0, draw_target.into()).with_prefix(prefix).with_style(
-                    ia::ProgressStyle::default_bar()
-                        .template("{spinner:.green} {prefix:.bold} [{elapsed}] {wide_bar:.green} {bytes}/{total_bytes} {msg}")
-                        .unwrap()
-                        .progress_chars("█▇▆▅▄▃▂▁  "),
-                ),
-            );
-            xwin::WorkItem {
-                payload: std::sync::Arc::new(pay),
-                progress: pb,
+            if skipped_report.is_none() {
+                *skipped_report = splat.skipped_report;
+            }
+
+            if provenance.is_none() {
+                *provenance = splat.provenance;
+            }
+
+            if patches.is_none() {
+                *patches = splat.patches;
             }
-        })
-        .collect();
 
-    mp.set_move_cursor(true);
+            if lib_symlinks.is_empty() {
+                *lib_symlinks = splat.lib_symlinks.unwrap_or_default();
+            }
+
+            if include_debug_libs_for.is_empty() {
+                *include_debug_libs_for = splat.include_debug_libs_for.unwrap_or_default();
+            }
+
+            if include_debug_symbols_for.is_empty() {
+                *include_debug_symbols_for = splat.include_debug_symbols_for.unwrap_or_default();
+            }
 
-    let res =
-        std::thread::spawn(move || ctx.execute(pkgs, work_items, arches, variants, op)).join();
+            if checksums.is_empty() {
+                *checksums = splat.checksums.unwrap_or_default();
+            }
+        } else if let Command::Env { output, .. }
+        | Command::Generate { output, .. }
+        | Command::Nix { output, .. }
+        | Command::WineSetup { output, .. }
+        | Command::Selftest { output, .. }
+        | Command::Doctor { output }
+        | Command::Hash { output, .. }
+        | Command::Symbols { output, .. }
+        | Command::Verify { output, .. }
+        | Command::Update { output, .. }
+        | Command::Serve { output, .. }
+        | Command::Clean { output, .. } = &mut self.cmd
+        {
+            if output.is_none() {
+                *output = config.splat.output;
+            }
+        }
 
-    res.unwrap()
+        Ok(())
+    }
 }
 
-fn print_packages(payloads: &[xwin::Payload]) {
-    use cli_table::{format::Justify, Cell, Style, Table};
+/// Exit codes for each class of failure, so that wrapper scripts can eg. retry
+/// transient network failures without having to inspect the error output
+fn exit_code(err: &Error) -> i32 {
+    use xwin::error::ErrorKind;
 
-    let (dl, install) = payloads.iter().fold((0, 0), |(dl, install), payload| {
-        (
-            dl + payload.size,
-            install + payload.install_size.unwrap_or_default(),
-        )
-    });
+    match xwin::error::classify(err) {
+        Some(ErrorKind::Network) => 10,
+        Some(ErrorKind::ChecksumMismatch) => 11,
+        Some(ErrorKind::DiskFull) => 12,
+        Some(ErrorKind::MissingPayload) => 13,
+        Some(ErrorKind::PartialSplat) => 14,
+        Some(ErrorKind::Cancelled) => 15,
+        Some(ErrorKind::NetworkDisabled) => 16,
+        None => 1,
+    }
+}
 
-    let totals = vec![
-        "Total".cell().bold(true).justify(Justify::Right),
-        "".cell(),
-        "".cell(),
-        indicatif::HumanBytes(dl).cell().bold(true),
-        indicatif::HumanBytes(install).cell().bold(true),
-    ];
+/// The report of the run that is written to `--report` once it has finished
+struct RunReport {
+    path: PathBuf,
+    report: xwin::report::Report,
+    ctx: Option<std::sync::Arc<xwin::Ctx>>,
+}
 
-    let table = payloads
-        .iter()
-        .map(|payload| {
-            vec![
-                payload.filename.clone().cell().justify(Justify::Right),
-                payload
-                    .target_arch
-                    .map(|a| a.to_string())
-                    .unwrap_or_default()
-                    .cell(),
-                payload
-                    .variant
-                    .map(|v| v.to_string())
-                    .unwrap_or_default()
-                    .cell(),
-                indicatif::HumanBytes(payload.size).cell(),
-                indicatif::HumanBytes(payload.install_size.unwrap_or_default()).cell(),
-            ]
-        })
-        .chain(std::iter::once(totals))
-        .collect::<Vec<_>>()
-        .table()
-        .title(vec![
-            "Name".cell(),
-            "Target".cell(),
-            "Variant".cell(),
-            "Download Size".cell(),
-            "Install Size".cell(),
-        ]);
+impl RunReport {
+    fn write(mut self, error: Option<&Error>) -> Result<(), Error> {
+        if let (Some(_), Some(ctx)) = (error, &self.ctx) {
+            self.report.record_failed(&ctx.outcomes);
+        }
+        self.report
+            .finish(error, self.ctx.as_ref().map(|ctx| &ctx.transfers));
+        self.report.totals.warnings = WARNINGS.load(std::sync::atomic::Ordering::Relaxed);
+        self.report.write(&self.path)
+    }
+}
 
-    let _ = cli_table::print_stdout(table);
+/// The totals that are printed once a command has completed successfully,
+/// unless `--quiet` is passed
+struct RunSummary {
+    start: std::time::Instant,
+    stats: xwin::Stats,
+    /// The context that was used for any downloads
+    ctx: Option<std::sync::Arc<xwin::Ctx>>,
 }
 
-fn load_manifest(
-    ctx: &xwin::Ctx,
-    args: &Args,
-    dt: xwin::util::ProgressTarget,
-) -> anyhow::Result<xwin::manifest::PackageManifest> {
-    let manifest_pb = ia::ProgressBar::with_draw_target(0, dt.into())
-            .with_style(
-            ia::ProgressStyle::default_bar()
-                .template(
-                    "{spinner:.green} {prefix:.bold} [{elapsed}] {wide_bar:.green} {bytes}/{total_bytes} {msg}",
-                )?
-                .progress_chars("█▇▆▅▄▃▂▁  "),
+fn main() {
+    let mut report = None;
+    let mut summary = None;
+    let res = run(&mut report, &mut summary);
+
+    // The report is still written for a failed run, as that is when it is
+    // most useful
+    let res = match (res, report) {
+        (res, Some(report)) => match report.write(res.as_ref().err()) {
+            Ok(()) => res,
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                res.and(Err(err))
+            }
+        },
+        (res, None) => res,
+    };
+
+    if let Err(err) = res {
+        eprintln!("Error: {:?}", err);
+        std::process::exit(exit_code(&err));
+    }
+
+    if let Some(summary) = summary {
+        print_run_summary(
+            &summary.stats,
+            summary.ctx.as_ref().map(|ctx| &ctx.transfers),
+            summary.start.elapsed(),
         );
-    manifest_pb.set_prefix("Manifest");
-    manifest_pb.set_message("📥 downloading");
+    }
+}
 
-    let manifest = match &args.manifest {
-        Some(manifest_path) => {
-            let manifest_content = std::fs::read_to_string(manifest_path)
-                .with_context(|| format!("failed to read path '{}'", manifest_path))?;
-            serde_json::from_str(&manifest_content)
-                .with_context(|| format!("failed to deserialize manifest in '{}'", manifest_path))?
+fn run(report: &mut Option<RunReport>, summary: &mut Option<RunSummary>) -> Result<(), Error> {
+    let start = std::time::Instant::now();
+    let started = std::time::SystemTime::now();
+    let mut args = Args::parse();
+
+    let cwd = PathBuf::from_path_buf(std::env::current_dir().context("unable to retrieve cwd")?)
+        .map_err(|pb| anyhow::anyhow!("cwd {} is not a valid utf-8 path", pb.display()))?;
+
+    let config = match &args.config {
+        Some(path) => Some((path.clone(), xwin::config::Config::load(path)?)),
+        None => xwin::config::Config::discover(&cwd)?,
+    };
+
+    let config_path = match config {
+        Some((path, config)) => {
+            args.apply_config(config)?;
+            Some(path)
         }
-        None => xwin::manifest::get_manifest(
-            ctx,
-            &args.manifest_version,
-            &args.channel,
-            manifest_pb.clone(),
-        )?,
+        None => None,
     };
 
-    let pkg_manifest = xwin::manifest::get_package_manifest(ctx, &manifest, manifest_pb.clone())?;
+    setup_logger(
+        args.json,
+        args.level.unwrap_or(LevelFilter::INFO),
+        args.log_file
+            .as_ref()
+            .map(|lf| (lf, args.log_file_level.unwrap_or(LevelFilter::DEBUG))),
+    )?;
 
-    manifest_pb.finish_with_message("📥 downloaded");
-    Ok(pkg_manifest)
-}
+    if let Some(config_path) = &config_path {
+        tracing::debug!("loaded config from '{}'", config_path);
+    }
 
-#[cfg(test)]
-mod test {
-    #[test]
-    fn cli_help() {
-        use clap::CommandFactory;
+    if !args.quiet {
+        *summary = Some(RunSummary {
+            start,
+            stats: xwin::Stats::default(),
+            ctx: None,
+        });
+    }
 
-        let snapshot_path = format!("{}/tests/snapshots", env!("CARGO_MANIFEST_DIR"));
+    *report = args.report.as_ref().map(|path| RunReport {
+        path: cwd.join(path),
+        report: xwin::report::Report::new(&std::env::args().skip(1).collect::<Vec<_>>(), started),
+        ctx: None,
+    });
 
-        insta::with_settings!({
-            prepend_module_to_snapshot => false,
-            snapshot_path => snapshot_path,
-        }, {
-            // the tests here will force maps to sort
-            snapshot_test_cli_command(super::Args::command().name(env!("CARGO_PKG_NAME")), "xwin".to_owned(), &SnapshotTestDesc {
-                manifest_path: env!("CARGO_MANIFEST_DIR"),
+    if let Some(jobs) = args.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()
+            .context("failed to create the thread pool")?;
+    }
+
+    // Printing the environment and diffing only inspect existing splats, so
+    // don't require accepting the license or retrieving the manifest
+    if let Command::Diff { old, new } = &args.cmd {
+        return print_diff(old, new);
+    }
+
+    let cache_dir = args
+        .cache_dir
+        .clone()
+        .unwrap_or_else(|| cwd.join(".xwin-cache"));
+
+    if let Command::Clean {
+        downloads,
+        unpacked,
+        splat,
+        output,
+        dry_run,
+    } = &args.cmd
+    {
+        let mut dirs = Vec::new();
+        let all = !downloads && !unpacked && !splat;
+
+        if *downloads || all {
+            dirs.push(cache_dir.join("dl"));
+        }
+        if *unpacked || all {
+            dirs.push(cache_dir.join("unpack"));
+        }
+        if *splat {
+            dirs.push(output.clone().unwrap_or_else(|| cache_dir.join("splat")));
+        }
+
+        return clean(&dirs, *dry_run);
+    }
+
+    if let Command::Splat {
+        check_only: Some(output),
+        ..
+    } = &args.cmd
+    {
+        let output = cwd.join(output);
+        let (arches, variants) = match xwin::stamp::Stamp::load(&output) {
+            Ok(stamp) => (
+                xwin::Arch::iter(stamp.arch_mask()?).collect(),
+                stamp.variant_mask()?,
+            ),
+            Err(_) => (
+                selected_arches(&args)?,
+                xwin::Variant::mask(&selected_variants(&args)?),
+            ),
+        };
+
+        return check_sentinels(&output, &arches, variants);
+    }
+
+    if let Command::Cache {
+        cmd: CacheCommand::Stats,
+    } = &args.cmd
+    {
+        return cache_stats(&cache_dir, args.json);
+    }
+
+    if let Command::Hash {
+        output,
+        expected_hash,
+    } = &args.cmd
+    {
+        let output = output.clone().unwrap_or_else(|| cache_dir.join("splat"));
+        return hash(&output, expected_hash.as_deref());
+    }
+
+    if let Command::Doctor { output } = &args.cmd {
+        let output = cwd.join(output.clone().unwrap_or_else(|| cache_dir.join("splat")));
+        return doctor(&args, &output, &cache_dir);
+    }
+
+    if let Command::Symbols { output, cmd } = &args.cmd {
+        let output = output.clone().unwrap_or_else(|| cache_dir.join("splat"));
+        return symbols(&args, &output, &cache_dir, cmd);
+    }
+
+    if let Command::Verify {
+        output,
+        relocatable,
+        patches,
+        known_good,
+    } = &args.cmd
+    {
+        let output = output.clone().unwrap_or_else(|| cache_dir.join("splat"));
+        return verify(
+            &cwd.join(output),
+            *relocatable,
+            *patches,
+            known_good.as_deref(),
+            args.no_network,
+            args.proxy.as_deref(),
+        );
+    }
+
+    if let Command::Serve { addr, output } = &args.cmd {
+        let output = output.clone().unwrap_or_else(|| cache_dir.join("splat"));
+        return xwin::serve::serve(addr, &cache_dir, &output);
+    }
+
+    if let Command::Env { output, .. }
+    | Command::Generate { output, .. }
+    | Command::WineSetup { output, .. }
+    | Command::Selftest { output, .. } = &args.cmd
+    {
+        let arches = selected_arches(&args)?;
+        let variants = xwin::Variant::mask(&selected_variants(&args)?);
+
+        // The paths are used from outside of the cwd, so they must be absolute
+        let output = cwd.join(output.clone().unwrap_or_else(|| cache_dir.join("splat")));
+
+        match &args.cmd {
+            Command::Env { format, .. } => {
+                let arch = single_arch(&arches)?;
+                let layout = xwin::layout::Layout::resolve(&output, arch, variants)?;
+                print!(
+                    "{}",
+                    xwin::generate::env(
+                        &layout,
+                        arch,
+                        format.unwrap_or(xwin::generate::EnvFormat::Sh)
+                    )?
+                );
+                return Ok(());
+            }
+            Command::Generate { generator, .. } => {
+                return generate(&output, &arches, variants, generator);
+            }
+            Command::WineSetup { prefix, copy, .. } => {
+                let prefix = match prefix {
+                    Some(prefix) => cwd.join(prefix),
+                    None => PathBuf::from(
+                        std::env::var("HOME").context("unable to determine home directory")?,
+                    )
+                    .join(".wine"),
+                };
+
+                return wine_setup(&output, &prefix, &arches, variants, *copy);
+            }
+            Command::Selftest {
+                clang_cl, lld_link, ..
+            } => {
+                let toolchain = xwin::selftest::Toolchain::new(clang_cl.clone(), lld_link.clone())?;
+
+                return selftest(&output, &arches, variants, &toolchain);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Fail before prompting for the license if there is nothing to update
+    let update_stamp = match &args.cmd {
+        Command::Update { output, .. } => {
+            let output = cwd.join(output.clone().unwrap_or_else(|| cache_dir.join("splat")));
+            let stamp = xwin::stamp::Stamp::load(&output)?;
+            Some((output, stamp))
+        }
+        _ => None,
+    };
+
+    if !args.accept_license {
+        // The license link is the same for every locale, but we should probably
+        // retrieve it from the manifest in the future
+        eprintln!("Do you accept the license at https://go.microsoft.com/fwlink/?LinkId=2086102 (yes | no)?");
+
+        let mut accept = String::new();
+        std::io::stdin().read_line(&mut accept)?;
+
+        match accept.trim() {
+            "yes" => eprintln!("license accepted!"),
+            "no" => anyhow::bail!("license not accepted"),
+            other => anyhow::bail!("unknown response to license request {}", other),
+        }
+    }
+
+    // Animated progress bars are only useful for an actual human looking at
+    // a terminal, in CI logs they just end up as control character garbage.
+    // All of the interactive output goes to stderr so that stdout can be
+    // piped, eg. `--timings --json`
+    let show_progress = cfg!(feature = "ui") && !args.quiet && !args.no_progress && {
+        use std::io::IsTerminal;
+        std::io::stderr().is_terminal()
+    };
+    let print_summary = !args.quiet && !show_progress;
+
+    let draw_target = if show_progress {
+        ProgressTarget::Stderr
+    } else {
+        ProgressTarget::Hidden
+    };
+
+    let glyphs = Glyphs::detect(args.ascii);
+
+    let overview = Overview::new(draw_target, glyphs);
+    let bars = {
+        let overview = overview.clone();
+        move |name: &str| -> std::sync::Arc<dyn xwin::ProgressSink> {
+            std::sync::Arc::new(overview.bar(name.to_owned()))
+        }
+    };
+
+    let mut ctx = if args.temp {
+        xwin::Ctx::with_temp(bars)?
+    } else {
+        xwin::Ctx::with_dir(cache_dir, bars)?
+    };
+    ctx.cancel = cancel_on_signal()?;
+    ctx.upstream = args.upstream.clone();
+    ctx.no_network = args.no_network;
+    ctx.download_connections = args.download_connections.unwrap_or(1);
+    if let Some(dir) = &args.vs_layout {
+        ctx.vs_layout = Some(xwin::vs_layout::VsLayout::open(&cwd.join(dir))?);
+    }
+    if let Some(proxy) = &args.proxy {
+        ctx.set_proxy(proxy)?;
+    }
+
+    let ctx = std::sync::Arc::new(ctx);
+    if let Some(report) = report {
+        report.ctx = Some(ctx.clone());
+    }
+    if let Some(summary) = summary {
+        summary.ctx = Some(ctx.clone());
+    }
+
+    let (vs_manifest, mut pkg_manifest) = load_manifest(&ctx, &args, draw_target, glyphs)?;
+    let manifest_duration = start.elapsed();
+
+    if print_summary {
+        eprintln!("Manifest: {} packages", pkg_manifest.packages.len());
+    }
+
+    let outputs = splat_outputs(&args, &ctx.work_dir)?;
+
+    // When splatting several profiles, the payloads for all of them are
+    // selected so that they are only downloaded and unpacked once
+    let (arch_list, variants) = if let Some((_, stamp)) = &update_stamp {
+        (
+            xwin::Arch::iter(stamp.arch_mask()?).collect::<Vec<_>>(),
+            stamp.variant_mask()?,
+        )
+    } else if outputs.is_empty() {
+        (
+            selected_arches(&args)?,
+            xwin::Variant::mask(&selected_variants(&args)?),
+        )
+    } else {
+        (
+            xwin::Arch::ALL
+                .iter()
+                .copied()
+                .filter(|arch| outputs.iter().any(|out| out.arches.contains(arch)))
+                .collect::<Vec<_>>(),
+            outputs.iter().fold(0, |acc, out| acc | out.variants),
+        )
+    };
+    let arches = xwin::Arch::mask(&arch_list);
+
+    // An update keeps to the SDK versions the output was splatted with, unless
+    // a different requirement is specified
+    let sdk_version = match (&args.sdk_version, &update_stamp) {
+        (Some(req), _) => Some(req.clone()),
+        (None, Some((_, stamp))) => stamp
+            .sdk_requirement
+            .as_deref()
+            .map(str::parse)
+            .transpose()?,
+        (None, None) => None,
+    };
+    // `configure` offers every version, not just the selected ones
+    let full_manifest = matches!(args.cmd, Command::Configure).then(|| pkg_manifest.clone());
+    if let Some(req) = &sdk_version {
+        let version = xwin::version::select_sdk(&mut pkg_manifest, req)?;
+        tracing::info!("selected Windows SDK {} for '{}'", version, req);
+    }
+    if let Some(req) = &args.crt_version {
+        let version = xwin::version::select_crt(&mut pkg_manifest, req)?;
+        tracing::info!("selected MSVC CRT {} for '{}'", version, req);
+    }
+
+    let mut pruned = xwin::prune_pkg_list(&pkg_manifest, arches, variants)?;
+
+    let sdk_tools = match &update_stamp {
+        Some((_, stamp)) => stamp.sdk_tools(),
+        None => xwin::SdkTools {
+            build: args.include_sdk_bin,
+            signing: args.include_signing_tools,
+        },
+    };
+    if sdk_tools != xwin::SdkTools::default() {
+        pruned.extend(xwin::prune_sdk_bin(&pkg_manifest, arches, sdk_tools)?);
+    }
+
+    if let Some(custom_manifest) = &args.custom_manifest {
+        let custom = xwin::custom::CustomManifest::load(custom_manifest)?;
+        pruned.extend(custom.apply(&mut pkg_manifest, arches, variants)?);
+    }
+
+    let extras = xwin::extra::payloads(&args.extras, &pruned, arches)?;
+    pruned.extend(extras);
+
+    // An update keeps to the components the output was splatted with
+    let components = match &update_stamp {
+        Some((_, stamp)) => stamp.components(),
+        None => args
+            .components
+            .clone()
+            .unwrap_or_else(|| xwin::Component::ALL.to_vec()),
+    };
+    xwin::prune_components(&mut pruned, &components);
+
+    let update = match (&args.cmd, update_stamp) {
+        (Command::Update { dry_run, .. }, Some((output, stamp))) => {
+            let update = stamp.update(&pruned);
+            print_update(&update);
+
+            if update.is_empty() {
+                println!("{} is up to date", output);
+                return Ok(());
+            }
+
+            if *dry_run {
+                return Ok(());
+            }
+
+            let mut config = stamp.config(output)?;
+            config.keep_existing = true;
+
+            // The changed payloads are downloaded and unpacked before the
+            // output is touched, so a failure doesn't leave it half updated
+            pruned = update.splat.clone();
+            Some((stamp, update, config))
+        }
+        _ => None,
+    };
+
+    if let Some(report) = report {
+        report
+            .report
+            .resolved(&vs_manifest, &pruned, sdk_version.as_ref());
+    }
+
+    let mirror = match &args.cmd {
+        Command::Mirror { dir } => Some((cwd.join(dir), pruned.clone())),
+        _ => None,
+    };
+
+    let op = match args.cmd {
+        Command::List => {
+            print_packages(&pruned);
+            return Ok(());
+        }
+        Command::Configure => {
+            let config_path = config_path.unwrap_or_else(|| cwd.join(xwin::config::CONFIG_NAME));
+            return configure(
+                full_manifest.as_ref().unwrap_or(&pkg_manifest),
+                &config_path,
+                ConfigureDefaults {
+                    arches,
+                    variants,
+                    components: &components,
+                    sdk_tools,
+                    crt_version: args.crt_version.as_ref(),
+                    sdk_version: sdk_version.as_ref(),
+                },
+            );
+        }
+        Command::Nix {
+            output,
+            expected_hash,
+        } => {
+            let output = output.unwrap_or_else(|| ctx.work_dir.join("splat"));
+            return nix(&pruned, &pkg_manifest, &output, expected_hash.as_deref());
+        }
+        Command::Env { .. }
+        | Command::Generate { .. }
+        | Command::WineSetup { .. }
+        | Command::Selftest { .. }
+        | Command::Doctor { .. }
+        | Command::Hash { .. }
+        | Command::Symbols { .. }
+        | Command::Verify { .. }
+        | Command::Serve { .. }
+        | Command::Diff { .. }
+        | Command::Cache { .. }
+        | Command::Clean { .. } => unreachable!(),
+        Command::Download | Command::Mirror { .. } => xwin::Ops::Download,
+        Command::Unpack | Command::Update { .. } => xwin::Ops::Unpack,
+        // Each profile is splatted separately once everything is unpacked
+        Command::Splat { .. } if outputs.len() > 1 => xwin::Ops::Unpack,
+        Command::Splat { .. } => xwin::Ops::Splat(outputs[0].config.clone()),
+    };
+
+    // The cache service is only used within GitHub Actions, where it
+    // would otherwise silently be accessed
+    if let (true, Some(_), Ok(url)) = (
+        args.no_network,
+        args.gha_cache,
+        std::env::var("ACTIONS_RESULTS_URL"),
+    ) {
+        return Err(xwin::error::NetworkDisabled { url }.into());
+    }
+
+    let gha_cache = match args.gha_cache {
+        Some(xwin::gha::CacheMode::Splat) if outputs.len() > 1 => {
+            tracing::warn!("--gha-cache splat can't be used when splatting several profiles");
+            None
+        }
+        mode => mode.and_then(|mode| {
+            GhaCache::restore(
+                mode,
+                &pruned,
+                &op,
+                &outputs,
+                &ctx.work_dir,
+                arches,
+                variants,
+            )
+        }),
+    };
+
+    // A restored splat is already finished, but still goes through the steps
+    // that don't write to the output, eg. `--check` and `--provenance`
+    let restored_splat = match &gha_cache {
+        Some(GhaCache {
+            mode: xwin::gha::CacheMode::Splat,
+            restored: true,
+            dir,
+            ..
+        }) => {
+            tracing::info!("restored {} from the GitHub Actions cache", dir);
+            true
+        }
+        _ => false,
+    };
+
+    let pkgs = pkg_manifest.packages;
+
+    let payloads = if outputs.is_empty() {
+        Vec::new()
+    } else {
+        pruned.clone()
+    };
+
+    let execute = |work_items: Vec<xwin::WorkItem>, arches: u32, variants: u32, op: xwin::Ops| {
+        let ctx = ctx.clone();
+        let pkgs = pkgs.clone();
+
+        let res =
+            std::thread::spawn(move || ctx.execute(pkgs, work_items, arches, variants, op)).join();
+
+        match res.unwrap() {
+            Ok(stats) => {
+                overview.finish();
+                Ok(stats)
+            }
+            Err(err) => {
+                // Clear the in-flight bars so they aren't left half drawn
+                // above the error
+                overview.clear();
+                Err(err)
+            }
+        }
+    };
+
+    let mut stats = if restored_splat {
+        xwin::Stats::default()
+    } else {
+        let work_items: Vec<_> = pruned
+            .into_iter()
+            .map(|pay| work_item(pay, &overview))
+            .collect();
+
+        overview.set_work(
+            work_items.len() as u64,
+            match &op {
+                xwin::Ops::Download => 1,
+                xwin::Ops::Unpack => 2,
+                xwin::Ops::Splat(_) => 3,
+            },
+        );
+
+        execute(work_items, arches, variants, op)?
+    };
+
+    let run = SplatRun {
+        sdk_tools,
+        sdk_version: sdk_version.as_ref(),
+        vs_manifest: &vs_manifest,
+        started,
+        cwd: &cwd,
+    };
+
+    if outputs.len() > 1 {
+        for output in &outputs {
+            let work_items: Vec<_> = payloads
+                .iter()
+                .filter(|pay| output.selects(pay))
+                .map(|pay| work_item(pay.clone(), &overview))
+                .collect();
+
+            tracing::info!(
+                "splatting profile '{}' to {}",
+                output.name.as_deref().unwrap_or_default(),
+                output.config.output
+            );
+
+            overview.set_work(work_items.len() as u64, 3);
+            let profile = execute(
+                work_items,
+                output.arch_mask(),
+                output.variants,
+                xwin::Ops::Splat(output.config.clone()),
+            )?;
+
+            if let Some(src) = &output.shares_headers_with {
+                let shared = xwin::share_headers(src, &output.config.output)?;
+                tracing::info!(
+                    "hardlinked {} headers ({}) in {} to {}",
+                    shared.files,
+                    ia::HumanBytes(shared.bytes),
+                    output.config.output,
+                    src
+                );
+            }
+
+            let selected: Vec<_> = payloads
+                .iter()
+                .filter(|pay| output.selects(pay))
+                .cloned()
+                .collect();
+            finish_splat(output, &profile, &selected, &run)?;
+            merge_splat_stats(&mut stats, profile);
+        }
+    } else if let Some(output) = outputs.first() {
+        if restored_splat {
+            let stamp = xwin::stamp::Stamp::load(&output.config.output)?;
+            publish_splat(output, &stamp, &payloads, &run)?;
+        } else {
+            finish_splat(output, &stats, &payloads, &run)?;
+        }
+    }
+
+    if let Some((stamp, update, config)) = update {
+        let removed = update.remove_stale(&config.output)?;
+        tracing::info!("removed {} stale files from {}", removed, config.output);
+
+        let work_items: Vec<_> = update
+            .splat
+            .iter()
+            .map(|pay| work_item(pay.clone(), &overview))
+            .collect();
+
+        overview.set_work(work_items.len() as u64, 3);
+        let splatted = execute(
+            work_items,
+            arches,
+            variants,
+            xwin::Ops::Splat(config.clone()),
+        )?;
+
+        let mut updated = stamp.updated(update, &splatted.splatted_files);
+        for (name, err) in updated.reapply_patches(&stamp, &config.output) {
+            tracing::warn!(
+                "patch {} no longer applies to the updated output: {:#}",
+                name,
+                err
+            );
+        }
+        updated.sdk_requirement = sdk_version.as_ref().map(ToString::to_string);
+        updated.save(&config.output)?;
+        merge_splat_stats(&mut stats, splatted);
+
+        // Regenerate the files that were written by the original splat, as
+        // they no longer match the output
+        if config.output.join("env.sh").exists() {
+            let arch = single_arch(&arch_list)?;
+            let layout = xwin::layout::Layout::resolve(&config.output, arch, variants)?;
+            xwin::generate::write_env_files(&layout, arch)?;
+        }
+
+        let algorithms: Vec<_> = [
+            xwin::checksums::Algorithm::Sha256,
+            xwin::checksums::Algorithm::Blake3,
+        ]
+        .iter()
+        .copied()
+        .filter(|alg| config.output.join(alg.file_name()).exists())
+        .collect();
+        xwin::checksums::write(&config.output, &algorithms)?;
+    }
+
+    if let Some(gha_cache) = gha_cache {
+        gha_cache.save();
+    }
+
+    if let Some((dir, payloads)) = mirror {
+        let index = xwin::mirror::mirror(&ctx, &vs_manifest, &pkgs, &payloads, &dir)?;
+        print_mirror(&dir, &index);
+    }
+
+    if let Some(report) = report {
+        report.report.record(&stats);
+    }
+
+    if !args.quiet {
+        print_stats(&stats, &overview);
+    }
+
+    if args.timings {
+        print_timings(&stats, manifest_duration, start.elapsed(), args.json)?;
+    }
+
+    if let Some(summary) = summary {
+        summary.stats = stats;
+    }
+
+    Ok(())
+}
+
+/// Prints how to use a mirror that was just written
+fn print_mirror(dir: &camino::Utf8Path, index: &xwin::mirror::Index) {
+    println!(
+        "mirrored {} files ({}) to {}",
+        index.files.len(),
+        ia::HumanBytes(index.size()),
+        dir
+    );
+    println!(
+        "use it with: xwin --manifest {} --manifest-sha256 {} --package-manifest-sha256 {} --upstream file://{}",
+        dir.join(xwin::mirror::Index::CHANNEL),
+        index.channel_sha256,
+        index.package_manifest_sha256,
+        dir
+    );
+}
+
+/// An entry in the GitHub Actions cache, which is saved once the run has
+/// finished if it wasn't restored
+struct GhaCache {
+    client: xwin::gha::Client,
+    mode: xwin::gha::CacheMode,
+    key: String,
+    dir: PathBuf,
+    restored: bool,
+}
+
+impl GhaCache {
+    /// Restores the entry if it exists. Failing to use the cache is never
+    /// fatal, it just means everything needs to be done from scratch.
+    fn restore(
+        mode: xwin::gha::CacheMode,
+        payloads: &[xwin::Payload],
+        op: &xwin::Ops,
+        outputs: &[SplatOutput],
+        work_dir: &camino::Utf8Path,
+        arches: u32,
+        variants: u32,
+    ) -> Option<Self> {
+        use xwin::gha::CacheMode;
+
+        let client = match xwin::gha::Client::from_env() {
+            Ok(Some(client)) => client,
+            Ok(None) => {
+                tracing::warn!("--gha-cache is ignored as this is not a GitHub Actions job");
+                return None;
+            }
+            Err(err) => {
+                tracing::warn!("unable to create GitHub Actions cache client: {:#}", err);
+                return None;
+            }
+        };
+
+        let (dir, extra) = match (mode, op, outputs) {
+            (CacheMode::Downloads, ..) => (work_dir.join("dl"), Vec::new()),
+            (CacheMode::Splat, xwin::Ops::Splat(config), [output]) => {
+                let mut extra = vec![
+                    format!("arches={} variants={}", arches, variants),
+                    // Every splat option, including the output, as the env
+                    // files contain absolute paths
+                    serde_json::to_string(config).ok()?,
+                    format!(
+                        "env_files={} checksums={:?} skipped_report={:?}",
+                        output.env_files, output.checksums, output.skipped_report
+                    ),
+                ];
+
+                // The patches can change without their directory changing
+                if let Some(patches) = &config.patches {
+                    match xwin::gha::dir_digest(patches) {
+                        Ok(digest) => extra.push(format!("patches={}", digest)),
+                        Err(err) => {
+                            tracing::warn!(
+                                "--gha-cache is ignored as the patches couldn't be hashed: {:#}",
+                                err
+                            );
+                            return None;
+                        }
+                    }
+                }
+
+                (config.output.clone(), extra)
+            }
+            (CacheMode::Splat, ..) => {
+                tracing::warn!("--gha-cache splat is only used by the splat command");
+                return None;
+            }
+        };
+
+        let key = xwin::gha::key(mode, payloads, &extra);
+
+        let restored = match client.restore(mode, &key, &dir) {
+            Ok(restored) => restored,
+            Err(err) => {
+                tracing::warn!(
+                    "unable to restore '{}' from GitHub Actions cache: {:#}",
+                    key,
+                    err
+                );
+                false
+            }
+        };
+
+        if !restored {
+            tracing::info!("'{}' was not found in the GitHub Actions cache", key);
+        }
+
+        Some(Self {
+            client,
+            mode,
+            key,
+            dir,
+            restored,
+        })
+    }
+
+    fn save(self) {
+        if self.restored {
+            return;
+        }
+
+        match self.client.save(self.mode, &self.key, &self.dir) {
+            Ok(()) => tracing::info!("saved '{}' to the GitHub Actions cache", self.key),
+            Err(err) => {
+                tracing::warn!(
+                    "unable to save '{}' to GitHub Actions cache: {:#}",
+                    self.key,
+                    err
+                )
+            }
+        }
+    }
+}
+
+/// A single splat output, ie. the one specified by the splat options, or one
+/// of the profiles in the config file
+struct SplatOutput {
+    name: Option<String>,
+    config: xwin::SplatConfig,
+    arches: Vec<xwin::Arch>,
+    variants: u32,
+    env_files: bool,
+    checksums: Vec<xwin::checksums::Algorithm>,
+    skipped_report: Option<PathBuf>,
+    provenance: Option<PathBuf>,
+    /// Checks the sentinels of the output once it is finished
+    check: bool,
+    post_splat: Vec<String>,
+    /// Splits the output into an output for each architecture
+    split_per_arch: bool,
+    /// The output of the first architecture of a split output, which the
+    /// headers are hardlinked to
+    shares_headers_with: Option<PathBuf>,
+}
+
+impl SplatOutput {
+    fn arch_mask(&self) -> u32 {
+        xwin::Arch::mask(&self.arches)
+    }
+
+    /// Whether the payload, which was selected for the union of every
+    /// profile, is needed by this output. The Store payloads are always
+    /// needed, as they are always selected.
+    fn selects(&self, payload: &xwin::Payload) -> bool {
+        let arch = match payload.target_arch {
+            Some(arch) => self.arches.contains(&arch),
+            None => true,
+        };
+
+        let variant = match payload.variant {
+            Some(xwin::Variant::Store) | None => true,
+            Some(variant) => self.variants & variant as u32 != 0,
+        };
+
+        arch && variant
+    }
+}
+
+/// Resolves the outputs for the splat command, which is either the single
+/// output specified by the splat options, or each of the selected profiles
+/// with any options they don't specify taken from the splat options
+fn splat_outputs(args: &Args, work_dir: &camino::Utf8Path) -> Result<Vec<SplatOutput>, Error> {
+    let (base, selected) = match &args.cmd {
+        Command::Splat {
+            include_debug_libs,
+            include_debug_symbols,
+            include_debug_libs_for,
+            include_debug_symbols_for,
+            disable_symlinks,
+            preserve_ms_arch_notation,
+            output,
+            copy,
+            env_files,
+            lib_symlinks,
+            strict_includes,
+            verify,
+            check,
+            check_only: _,
+            thin_import_libs,
+            symbol_server_layout,
+            split_output_per_arch,
+            patches,
+            checksums,
+            skipped_report,
+            provenance,
+            profiles,
+        } => (
+            SplatOutput {
+                name: None,
+                config: xwin::SplatConfig {
+                    include_debug_libs: *include_debug_libs,
+                    include_debug_symbols: *include_debug_symbols,
+                    include_debug_libs_for: include_debug_libs_for.clone(),
+                    include_debug_symbols_for: include_debug_symbols_for.clone(),
+                    enable_symlinks: !*disable_symlinks,
+                    preserve_ms_arch_notation: *preserve_ms_arch_notation,
+                    copy: *copy,
+                    output: output.clone().unwrap_or_else(|| work_dir.join("splat")),
+                    lib_symlinks: lib_symlinks.clone(),
+                    strict_includes: *strict_includes,
+                    verify: *verify,
+                    keep_existing: false,
+                    patches: patches.clone(),
+                    thin_import_libs: *thin_import_libs,
+                    symbol_server_layout: *symbol_server_layout,
+                },
+                arches: selected_arches(args)?,
+                variants: xwin::Variant::mask(&selected_variants(args)?),
+                env_files: *env_files,
+                checksums: checksums.clone(),
+                skipped_report: skipped_report.clone(),
+                provenance: provenance.clone(),
+                check: *check,
+                post_splat: args.post_splat.clone(),
+                split_per_arch: *split_output_per_arch,
+                shares_headers_with: None,
+            },
+            profiles,
+        ),
+        _ => return Ok(Vec::new()),
+    };
+
+    let outputs = if args.splat_profiles.is_empty() {
+        anyhow::ensure!(
+            selected.is_empty(),
+            "--profile was specified, but there are no [splat.profiles] in the config"
+        );
+        vec![base]
+    } else {
+        for name in selected {
+            anyhow::ensure!(
+                args.splat_profiles.contains_key(name),
+                "unknown splat profile '{}'",
+                name
+            );
+        }
+
+        let mut outputs = Vec::new();
+        for (name, profile) in &args.splat_profiles {
+            if !selected.is_empty() && !selected.contains(name) {
+                continue;
+            }
+
+            let arches = match &profile.arch {
+                Some(selectors) => xwin::select(selectors, xwin::Arch::ALL),
+                None => base.arches.clone(),
+            };
+            anyhow::ensure!(
+                !arches.is_empty(),
+                "splat profile '{}' doesn't select any architectures",
+                name
+            );
+
+            let variants = match &profile.variant {
+                Some(selectors) => {
+                    xwin::Variant::mask(&xwin::select(selectors, xwin::Variant::ALL))
+                }
+                None => base.variants,
+            };
+            anyhow::ensure!(
+                variants != 0,
+                "splat profile '{}' doesn't select any variants",
+                name
+            );
+
+            let defaults = &base.config;
+            outputs.push(SplatOutput {
+                name: Some(name.clone()),
+                config: xwin::SplatConfig {
+                    include_debug_libs: profile
+                        .include_debug_libs
+                        .unwrap_or(defaults.include_debug_libs),
+                    include_debug_symbols: profile
+                        .include_debug_symbols
+                        .unwrap_or(defaults.include_debug_symbols),
+                    include_debug_libs_for: profile
+                        .include_debug_libs_for
+                        .clone()
+                        .unwrap_or_else(|| defaults.include_debug_libs_for.clone()),
+                    include_debug_symbols_for: profile
+                        .include_debug_symbols_for
+                        .clone()
+                        .unwrap_or_else(|| defaults.include_debug_symbols_for.clone()),
+                    enable_symlinks: profile
+                        .disable_symlinks
+                        .map(|disable| !disable)
+                        .unwrap_or(defaults.enable_symlinks),
+                    preserve_ms_arch_notation: profile
+                        .preserve_ms_arch_notation
+                        .unwrap_or(defaults.preserve_ms_arch_notation),
+                    // The unpacked payloads are shared by every profile, so
+                    // they can't be moved into any one of the outputs
+                    copy: true,
+                    output: profile.output.clone(),
+                    lib_symlinks: profile
+                        .lib_symlinks
+                        .clone()
+                        .unwrap_or_else(|| defaults.lib_symlinks.clone()),
+                    strict_includes: profile.strict_includes.unwrap_or(defaults.strict_includes),
+                    verify: profile.verify.unwrap_or(defaults.verify),
+                    keep_existing: false,
+                    patches: profile.patches.clone().or_else(|| defaults.patches.clone()),
+                    thin_import_libs: profile
+                        .thin_import_libs
+                        .unwrap_or(defaults.thin_import_libs),
+                    symbol_server_layout: profile
+                        .symbol_server_layout
+                        .unwrap_or(defaults.symbol_server_layout),
+                },
+                arches,
+                variants,
+                env_files: profile.env_files.unwrap_or(base.env_files),
+                checksums: profile
+                    .checksums
+                    .clone()
+                    .unwrap_or_else(|| base.checksums.clone()),
+                skipped_report: profile.skipped_report.clone(),
+                provenance: profile.provenance.clone(),
+                check: profile.check.unwrap_or(base.check),
+                post_splat: profile
+                    .post_splat
+                    .clone()
+                    .unwrap_or_else(|| base.post_splat.clone()),
+                split_per_arch: profile.split_output_per_arch.unwrap_or(base.split_per_arch),
+                shares_headers_with: None,
+            });
+        }
+
+        outputs
+    };
+
+    let outputs: Vec<_> = outputs.into_iter().flat_map(split_per_arch).collect();
+
+    // The spectre libs are in separate packages from the regular ones, so
+    // the profiles can't be splatted from the same unpacked payloads
+    let spectre = xwin::Variant::Spectre as u32;
+    anyhow::ensure!(
+        outputs
+            .iter()
+            .all(|out| out.variants & spectre == outputs[0].variants & spectre),
+        "either all or none of the splat profiles must select the spectre variant"
+    );
+
+    for (i, out) in outputs.iter().enumerate() {
+        // Resolved before doing any work, as the env files can only be
+        // written for a single architecture
+        if out.env_files {
+            single_arch(&out.arches)?;
+        }
+
+        anyhow::ensure!(
+            outputs[..i]
+                .iter()
+                .all(|prev| prev.config.output != out.config.output),
+            "splat profiles '{}' and '{}' have the same output {}",
+            outputs[..i]
+                .iter()
+                .find(|prev| prev.config.output == out.config.output)
+                .and_then(|prev| prev.name.as_deref())
+                .unwrap_or_default(),
+            out.name.as_deref().unwrap_or_default(),
+            out.config.output
+        );
+    }
+
+    Ok(outputs)
+}
+
+/// Splits an output into an output for each of its architectures in
+/// `<output>/<arch>`, if it is to be split
+fn split_per_arch(output: SplatOutput) -> Vec<SplatOutput> {
+    if !output.split_per_arch {
+        return vec![output];
+    }
+
+    let first = output.config.output.join(output.arches[0].to_string());
+    output
+        .arches
+        .iter()
+        .map(|arch| {
+            let root = output.config.output.join(arch.to_string());
+
+            SplatOutput {
+                name: Some(match &output.name {
+                    Some(name) => format!("{}/{}", name, arch),
+                    None => arch.to_string(),
+                }),
+                config: xwin::SplatConfig {
+                    output: root.clone(),
+                    // The unpacked payloads are shared by every architecture
+                    copy: output.config.copy || output.arches.len() > 1,
+                    ..output.config.clone()
+                },
+                arches: vec![*arch],
+                variants: output.variants,
+                env_files: output.env_files,
+                checksums: output.checksums.clone(),
+                // Each architecture has its own reports, eg. `skipped.x86_64.json`
+                skipped_report: output
+                    .skipped_report
+                    .as_ref()
+                    .map(|report| arch_path(report, *arch)),
+                provenance: output
+                    .provenance
+                    .as_ref()
+                    .map(|provenance| arch_path(provenance, *arch)),
+                check: output.check,
+                post_splat: output.post_splat.clone(),
+                split_per_arch: false,
+                shares_headers_with: (root != first).then(|| first.clone()),
+            }
+        })
+        .collect()
+}
+
+/// Checks that the output has the sentinels for each of the architectures
+/// and variants, see `splat --check`
+fn check_sentinels(
+    output: &camino::Utf8Path,
+    arches: &[xwin::Arch],
+    variants: u32,
+) -> Result<(), Error> {
+    let issues = xwin::verify::sentinels(output, arches, variants)?;
+    for issue in &issues {
+        println!("{}", issue);
+    }
+
+    anyhow::ensure!(
+        issues.is_empty(),
+        "output {} is missing key headers or libs, found {} issues",
+        output,
+        issues.len()
+    );
+
+    tracing::info!("{} has the key headers and libs", output);
+    Ok(())
+}
+
+/// Inserts the architecture before the extension of the path, eg.
+/// `skipped.x86_64.json`
+fn arch_path(path: &camino::Utf8Path, arch: xwin::Arch) -> PathBuf {
+    path.with_file_name(match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) => format!("{}.{}.{}", stem, arch, ext),
+        _ => format!("{}.{}", path.file_name().unwrap_or_default(), arch),
+    })
+}
+
+/// What every output of a run is splatted from
+struct SplatRun<'a> {
+    sdk_tools: xwin::SdkTools,
+    sdk_version: Option<&'a xwin::version::VersionReq>,
+    vs_manifest: &'a xwin::manifest::Manifest,
+    /// When xwin was invoked, for the provenance
+    started: std::time::SystemTime,
+    cwd: &'a camino::Utf8Path,
+}
+
+/// Writes the files that are generated from a finished splat
+fn finish_splat(
+    output: &SplatOutput,
+    stats: &xwin::Stats,
+    payloads: &[xwin::Payload],
+    run: &SplatRun<'_>,
+) -> Result<(), Error> {
+    let cwd = run.cwd;
+
+    for collision in &stats.symlink_collisions {
+        tracing::warn!(
+            link = %collision.link,
+            kept = collision.kept.as_deref().unwrap_or("<file>"),
+            discarded = %collision.discarded,
+            "symlink collided with an existing file"
+        );
+    }
+
+    if output.env_files {
+        let arch = single_arch(&output.arches)?;
+        let layout =
+            xwin::layout::Layout::resolve(&cwd.join(&output.config.output), arch, output.variants)?;
+        xwin::generate::write_env_files(&layout, arch)?;
+    }
+
+    if let Some(path) = &output.skipped_report {
+        let report = serde_json::to_string_pretty(&stats.skipped)?;
+        std::fs::write(path, report).with_context(|| format!("unable to write {}", path))?;
+    }
+
+    let mut stamp = xwin::stamp::Stamp::new(
+        &output.config,
+        output.arch_mask(),
+        output.variants,
+        run.sdk_tools,
+        payloads,
+        &stats.splatted_files,
+    );
+    stamp.patches = stats.patches.clone();
+    stamp.patches_dir = output.config.patches.as_ref().map(|dir| cwd.join(dir));
+    stamp.sdk_requirement = run.sdk_version.map(ToString::to_string);
+    stamp.save(&output.config.output)?;
+
+    // Written last so that they cover everything else written to the output
+    xwin::checksums::write(&output.config.output, &output.checksums)?;
+
+    publish_splat(output, &stamp, payloads, run)
+}
+
+/// Checks the finished output, and runs the steps that only read it, which
+/// are also run for an output restored from the GitHub Actions cache
+fn publish_splat(
+    output: &SplatOutput,
+    stamp: &xwin::stamp::Stamp,
+    payloads: &[xwin::Payload],
+    run: &SplatRun<'_>,
+) -> Result<(), Error> {
+    let cwd = run.cwd;
+
+    if output.check {
+        check_sentinels(&output.config.output, &output.arches, output.variants)?;
+    }
+
+    if let Some(path) = &output.provenance {
+        xwin::provenance::write(
+            &cwd.join(path),
+            &cwd.join(&output.config.output),
+            &xwin::provenance::Invocation {
+                manifest: run.vs_manifest,
+                payloads,
+                stamp,
+                arguments: std::env::args().skip(1).collect(),
+                started: run.started,
+            },
+        )?;
+    }
+
+    if !output.post_splat.is_empty() {
+        run_post_splat(output, stamp, cwd)?;
+    }
+
+    Ok(())
+}
+
+/// Runs the post splat hook for the output, substituting the `{output}`,
+/// `{arches}`, `{variants}`, `{crt_version}`, `{sdk_version}`, and `{profile}`
+/// placeholders in its arguments, which are also passed as environment variables
+fn run_post_splat(
+    output: &SplatOutput,
+    stamp: &xwin::stamp::Stamp,
+    cwd: &camino::Utf8Path,
+) -> Result<(), Error> {
+    let root = cwd.join(&output.config.output);
+    let vars = [
+        ("output", "XWIN_SPLAT_OUTPUT", root.to_string()),
+        ("arches", "XWIN_SPLAT_ARCHES", stamp.arches.join(",")),
+        ("variants", "XWIN_SPLAT_VARIANTS", stamp.variants.join(",")),
+        (
+            "crt_version",
+            "XWIN_SPLAT_CRT_VERSION",
+            stamp.crt_version().unwrap_or_default().to_owned(),
+        ),
+        (
+            "sdk_version",
+            "XWIN_SPLAT_SDK_VERSION",
+            stamp.sdk_version().unwrap_or_default().to_owned(),
+        ),
+        (
+            "profile",
+            "XWIN_SPLAT_PROFILE",
+            output.name.clone().unwrap_or_default(),
+        ),
+    ];
+
+    let args: Vec<String> = output
+        .post_splat
+        .iter()
+        .map(|arg| {
+            vars.iter().fold(arg.clone(), |arg, (name, _, value)| {
+                arg.replace(&format!("{{{}}}", name), value)
+            })
+        })
+        .collect();
+
+    tracing::info!("running post splat hook '{}'", args.join(" "));
+
+    let mut cmd = std::process::Command::new(&args[0]);
+    cmd.args(&args[1..]).current_dir(cwd);
+    for (_, env, value) in &vars {
+        cmd.env(env, value);
+    }
+
+    let status = cmd
+        .status()
+        .with_context(|| format!("failed to run post splat hook '{}'", args[0]))?;
+    anyhow::ensure!(
+        status.success(),
+        "post splat hook '{}' failed with {}",
+        args.join(" "),
+        status
+    );
+
+    Ok(())
+}
+
+/// Adds the splat of a single profile to the stats of the shared download and
+/// unpack
+fn merge_splat_stats(stats: &mut xwin::Stats, profile: xwin::Stats) {
+    stats.splat += profile.splat;
+    stats.symlinks += profile.symlinks;
+
+    if let Some(thinned) = profile.thinned {
+        *stats.thinned.get_or_insert_with(Default::default) += thinned;
+    }
+
+    if let Some(finalize) = profile.finalize {
+        *stats.finalize.get_or_insert_with(Default::default) += finalize;
+    }
+
+    for timing in profile.timings {
+        let splat = match timing.splat {
+            Some(splat) => splat,
+            None => continue,
+        };
+
+        if let Some(shared) = stats
+            .timings
+            .iter_mut()
+            .find(|shared| shared.payload == timing.payload)
+        {
+            let total = shared.splat.get_or_insert(xwin::PhaseTiming {
+                duration: Default::default(),
+                bytes: 0,
+            });
+            total.duration += splat.duration;
+            total.bytes += splat.bytes;
+        }
+    }
+}
+
+fn work_item(pay: xwin::Payload, overview: &std::sync::Arc<Overview>) -> xwin::WorkItem {
+    let prefix = match pay.kind {
+        xwin::PayloadKind::CrtHeaders => "CRT.headers".to_owned(),
+        xwin::PayloadKind::CrtLibs => {
+            format!(
+                "CRT.libs.{}.{}",
+                pay.target_arch.map(|ta| ta.as_str()).unwrap_or("all"),
+                pay.variant.map(|v| v.as_str()).unwrap_or("none")
+            )
+        }
+        xwin::PayloadKind::SdkHeaders => {
+            format!(
+                "SDK.headers.{}.{}",
+                pay.target_arch.map(|v| v.as_str()).unwrap_or("all"),
+                pay.variant.map(|v| v.as_str()).unwrap_or("none")
+            )
+        }
+        xwin::PayloadKind::SdkLibs => {
+            format!(
+                "SDK.libs.{}",
+                pay.target_arch.map(|ta| ta.as_str()).unwrap_or("all")
+            )
+        }
+        xwin::PayloadKind::SdkStoreLibs => "SDK.libs.store.all".to_owned(),
+        xwin::PayloadKind::SdkBin => {
+            format!(
+                "SDK.bin.{}",
+                pay.target_arch.map(|ta| ta.as_str()).unwrap_or("all")
+            )
+        }
+        xwin::PayloadKind::Ucrt => "SDK.ucrt.all".to_owned(),
+        xwin::PayloadKind::Extra => format!("Extra.{}", pay.filename),
+    };
+
+    xwin::WorkItem {
+        payload: std::sync::Arc::new(pay),
+        progress: std::sync::Arc::new(overview.bar(prefix)),
+    }
+}
+
+fn nix(
+    pruned: &[xwin::Payload],
+    pkg_manifest: &xwin::manifest::PackageManifest,
+    output: &camino::Utf8Path,
+    expected_hash: Option<&str>,
+) -> Result<(), Error> {
+    let inputs = xwin::nix::inputs(pruned, &pkg_manifest.packages)?;
+
+    let output_hash = if output.exists() {
+        Some(xwin::nix::nar_hash(output)?)
+    } else {
+        None
+    };
+
+    if let Some(expected) = expected_hash {
+        let expected = xwin::nix::from_sri(expected)?;
+
+        match &output_hash {
+            Some(actual) if *actual == expected => {}
+            Some(actual) => {
+                return Err(anyhow::Error::from(xwin::error::ChecksumMismatch {
+                    expected,
+                    actual: actual.clone(),
+                })
+                .context(format!("output {} doesn't match the expected hash", output)));
+            }
+            None => anyhow::bail!("output {} doesn't exist, has it been splatted?", output),
+        }
+    }
+
+    let report = serde_json::json!({
+        "outputHash": output_hash.as_ref().map(xwin::nix::to_sri),
+        "inputs": inputs,
+    });
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+fn hash(output: &camino::Utf8Path, expected_hash: Option<&str>) -> Result<(), Error> {
+    let actual = xwin::hash::tree_hash(output)?;
+
+    if let Some(expected) = expected_hash {
+        let expected: xwin::util::Sha256 = expected.parse()?;
+
+        if actual != expected {
+            return Err(
+                anyhow::Error::from(xwin::error::ChecksumMismatch { expected, actual })
+                    .context(format!("output {} doesn't match the expected hash", output)),
+            );
+        }
+    }
+
+    println!("{}", actual);
+    Ok(())
+}
+
+fn doctor(
+    args: &Args,
+    output: &camino::Utf8Path,
+    cache_dir: &camino::Utf8Path,
+) -> Result<(), Error> {
+    let arches = selected_arches(args)?;
+    let variants = xwin::Variant::mask(&selected_variants(args)?);
+
+    let mut findings = xwin::doctor::toolchain();
+    findings.extend(xwin::doctor::output(output, &arches, variants));
+    findings.extend(xwin::doctor::cache(cache_dir));
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&findings)?);
+    } else {
+        for finding in &findings {
+            println!("{}", finding);
+        }
+    }
+
+    let errors = findings
+        .iter()
+        .filter(|finding| finding.severity == xwin::doctor::Severity::Error)
+        .count();
+    anyhow::ensure!(errors == 0, "doctor found {} problems", errors);
+    Ok(())
+}
+
+fn symbols(
+    args: &Args,
+    output: &camino::Utf8Path,
+    cache_dir: &camino::Utf8Path,
+    cmd: &SymbolsCommand,
+) -> Result<(), Error> {
+    use xwin::symbols::SymbolIndex;
+
+    anyhow::ensure!(output.is_dir(), "{} is not a directory", output);
+    let json = args.json;
+
+    let build = || -> Result<SymbolIndex, Error> {
+        let index = SymbolIndex::build(output)?;
+        index.save(output)?;
+        Ok(index)
+    };
+
+    let query = match cmd {
+        SymbolsCommand::Index => {
+            let index = build()?;
+            eprintln!(
+                "indexed {} symbols in {} libraries",
+                index.symbols.len(),
+                index.libs.len()
+            );
+            return Ok(());
+        }
+        SymbolsCommand::Find { symbol } => symbol,
+        SymbolsCommand::Fetch {
+            store,
+            symbol_server,
+            image_dirs,
+        } => {
+            let store = store.clone().unwrap_or_else(|| cache_dir.join("symbols"));
+            let client = if args.no_network {
+                None
+            } else {
+                Some(xwin::http::client(args.proxy.as_deref())?)
+            };
+
+            let fetched = xwin::pdb::fetch(
+                client.as_ref().map(|c| c as &dyn xwin::http::HttpClient),
+                output,
+                image_dirs,
+                symbol_server,
+                &store,
+            )?;
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&fetched)?);
+            } else {
+                for matched in &fetched.missing {
+                    println!(
+                        "{} ({}) not found on {}",
+                        matched.id.name,
+                        matched.id.key(),
+                        symbol_server
+                    );
+                }
+                for unresolved in &fetched.unresolved {
+                    tracing::debug!(
+                        "{} is imported by {} but wasn't found",
+                        unresolved.dll,
+                        unresolved
+                            .libs
+                            .iter()
+                            .map(|lib| lib.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+                }
+                eprintln!(
+                    "retrieved {} PDBs to {}, {} were already present, {} weren't found, {} imported DLLs weren't found",
+                    fetched.fetched.len(),
+                    store,
+                    fetched.existing.len(),
+                    fetched.missing.len(),
+                    fetched.unresolved.len()
+                );
+            }
+
+            return Ok(());
+        }
+    };
+
+    let index = match SymbolIndex::load(output)? {
+        Some(index) => index,
+        None => build()?,
+    };
+
+    let found = index.find(query);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&found)?);
+    } else {
+        for found in &found {
+            let symbols: Vec<_> = found.symbols.iter().copied().collect();
+            println!("{}: {}", found.lib, symbols.join(", "));
+        }
+    }
+
+    anyhow::ensure!(!found.is_empty(), "no libraries export '{}'", query);
+    Ok(())
+}
+
+fn verify(
+    output: &camino::Utf8Path,
+    relocatable: bool,
+    patches: bool,
+    known_good: Option<&str>,
+    no_network: bool,
+    proxy: Option<&str>,
+) -> Result<(), Error> {
+    anyhow::ensure!(
+        relocatable || patches || known_good.is_some(),
+        "no checks were specified, eg. --relocatable, --patches, or --known-good"
+    );
+
+    if let Some(source) = known_good {
+        let list = if source.starts_with("https://") || source.starts_with("http://") {
+            if no_network {
+                return Err(xwin::error::NetworkDisabled {
+                    url: source.to_owned(),
+                }
+                .into());
+            }
+
+            use std::io::Read as _;
+
+            let client = xwin::http::client(proxy)?;
+            let mut body = xwin::http::HttpClient::get(&client, source)
+                .with_context(|| format!("failed to retrieve {}", source))?
+                .body;
+
+            let mut list = Vec::new();
+            body.read_to_end(&mut list)
+                .with_context(|| format!("failed to retrieve {}", source))?;
+            list
+        } else {
+            std::fs::read(source).with_context(|| format!("unable to read {}", source))?
+        };
+
+        let list = xwin::verify::KnownGood::parse(&list)?;
+        let (entry, actual) = xwin::verify::known_good(output, &list)?;
+
+        if actual != entry.hash {
+            return Err(anyhow::Error::from(xwin::error::ChecksumMismatch {
+                expected: entry.hash.clone(),
+                actual,
+            })
+            .context(format!(
+                "output {} doesn't match the known-good hash",
+                output
+            )));
+        }
+
+        println!("{} matches the known-good hash {}", output, actual);
+    }
+
+    if patches {
+        let issues = xwin::verify::patches(output)?;
+        for issue in &issues {
+            println!("{}", issue);
+        }
+
+        anyhow::ensure!(
+            issues.is_empty(),
+            "output {} doesn't have the changes from its patches, found {} issues",
+            output,
+            issues.len()
+        );
+
+        println!("{} has the changes from its patches", output);
+    }
+
+    if relocatable {
+        let issues = xwin::verify::relocatable(output)?;
+        for issue in &issues {
+            println!("{}", issue);
+        }
+
+        anyhow::ensure!(
+            issues.is_empty(),
+            "output {} is not relocatable, found {} issues",
+            output,
+            issues.len()
+        );
+
+        println!("{} is relocatable", output);
+    }
+
+    Ok(())
+}
+
+fn print_timings(
+    stats: &xwin::Stats,
+    manifest: std::time::Duration,
+    total: std::time::Duration,
+    json: bool,
+) -> Result<(), Error> {
+    let mut timings: Vec<_> = stats.timings.iter().collect();
+    timings.sort_by_key(|pt| std::cmp::Reverse(pt.total()));
+
+    if json {
+        let phase = |pt: Option<xwin::PhaseTiming>| {
+            pt.map(|pt| {
+                serde_json::json!({
+                    "secs": pt.duration.as_secs_f64(),
+                    "bytes": pt.bytes,
+                })
+            })
+        };
+
+        let report = serde_json::json!({
+            "manifest_secs": manifest.as_secs_f64(),
+            "finalize_secs": stats.finalize.map(|d| d.as_secs_f64()),
+            "symlinks": stats.symlinks,
+            "total_secs": total.as_secs_f64(),
+            "payloads": timings.iter().map(|pt| serde_json::json!({
+                "payload": pt.payload,
+                "download": phase(pt.download),
+                "unpack": phase(pt.unpack),
+                "splat": phase(pt.splat),
+                "total_secs": pt.total().as_secs_f64(),
+            })).collect::<Vec<_>>(),
+        });
+
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    use cli_table::{format::Justify, Cell, Style, Table};
+
+    let phase = |pt: Option<xwin::PhaseTiming>| {
+        pt.map(|pt| format!("{:.2?} ({})", pt.duration, ia::HumanBytes(pt.bytes)))
+            .unwrap_or_default()
+            .cell()
+    };
+
+    let table = timings
+        .iter()
+        .map(|pt| {
+            vec![
+                pt.payload.clone().cell().justify(Justify::Right),
+                phase(pt.download),
+                phase(pt.unpack),
+                phase(pt.splat),
+                format!("{:.2?}", pt.total()).cell().bold(true),
+            ]
+        })
+        .collect::<Vec<_>>()
+        .table()
+        .title(vec![
+            "Payload".cell(),
+            "Download".cell(),
+            "Unpack".cell(),
+            "Splat".cell(),
+            "Total".cell(),
+        ]);
+
+    let _ = cli_table::print_stdout(table);
+
+    println!("Manifest: {:.2?}", manifest);
+    if let Some(finalize) = stats.finalize {
+        println!("Finalize: {:.2?} ({} symlinks)", finalize, stats.symlinks);
+    }
+    println!("Total: {:.2?}", total);
+
+    Ok(())
+}
+
+fn print_stats(stats: &xwin::Stats, overview: &Overview) {
+    use xwin::progress::Phase;
+
+    for (phase, ps) in [
+        (Phase::Download, &stats.download),
+        (Phase::Unpack, &stats.unpack),
+        (Phase::Splat, &stats.splat),
+    ] {
+        if ps.payloads == 0 {
+            continue;
+        }
+
+        eprintln!(
+            "{}: {} payloads, {} files, {} in {}",
+            overview.phase_name(phase),
+            ps.payloads,
+            ps.files,
+            ia::HumanBytes(ps.bytes),
+            ia::HumanDuration(overview.duration(phase).unwrap_or_default()),
+        );
+    }
+
+    if let Some(finalize) = stats.finalize {
+        eprintln!(
+            "{}: {} symlinks, {} collisions in {}",
+            overview.phase_name(Phase::ScanIncludes),
+            stats.symlinks,
+            stats.symlink_collisions.len(),
+            ia::HumanDuration(finalize)
+        );
+    }
+
+    if let Some(thinned) = stats.thinned {
+        eprintln!(
+            "Thinned {} import libs from {} to {}",
+            thinned.libs,
+            ia::HumanBytes(thinned.before),
+            ia::HumanBytes(thinned.after),
+        );
+    }
+}
+
+/// Prints the totals for the whole run, so that the outcome doesn't have to
+/// be pieced together from the progress output
+fn print_run_summary(
+    stats: &xwin::Stats,
+    transfers: Option<&xwin::Transfers>,
+    total: std::time::Duration,
+) {
+    let warnings = WARNINGS.load(std::sync::atomic::Ordering::Relaxed);
+    let (downloaded, cache_hits) = transfers.map_or((0, 0), |t| (t.downloaded(), t.cache_hits()));
+
+    eprintln!(
+        "Summary: downloaded {} ({} cache hits), unpacked {} files, splatted {} files, created {} symlinks, {} warning{} in {}",
+        ia::HumanBytes(downloaded),
+        cache_hits,
+        stats.unpack.files,
+        stats.splat.files,
+        stats.symlinks,
+        warnings,
+        if warnings == 1 { "" } else { "s" },
+        ia::HumanDuration(total),
+    );
+}
+
+/// The architectures selected by `--arch`, which defaults to `x86_64`
+fn selected_arches(args: &Args) -> Result<Vec<xwin::Arch>, Error> {
+    let arches = match &args.arch {
+        Some(selectors) => xwin::select(selectors, xwin::Arch::ALL),
+        None => vec![xwin::Arch::X86_64],
+    };
+
+    anyhow::ensure!(
+        !arches.is_empty(),
+        "--arch doesn't select any architectures"
+    );
+    Ok(arches)
+}
+
+/// The variants selected by `--variant`, which defaults to `desktop`
+fn selected_variants(args: &Args) -> Result<Vec<xwin::Variant>, Error> {
+    let variants = match &args.variant {
+        Some(selectors) => xwin::select(selectors, xwin::Variant::ALL),
+        None => vec![xwin::Variant::Desktop],
+    };
+
+    anyhow::ensure!(
+        !variants.is_empty(),
+        "--variant doesn't select any variants"
+    );
+    Ok(variants)
+}
+
+/// Most of the commands that operate on an existing splat only make sense for
+/// a single architecture
+fn single_arch(arches: &[xwin::Arch]) -> Result<xwin::Arch, Error> {
+    match arches {
+        [arch] => Ok(*arch),
+        _ => anyhow::bail!("a single --arch must be specified"),
+    }
+}
+
+fn generate(
+    output: &camino::Utf8Path,
+    arches: &[xwin::Arch],
+    variants: u32,
+    generator: &Generator,
+) -> Result<(), Error> {
+    use xwin::{generate as gen, layout::Layout};
+
+    let single_layout = || -> Result<(xwin::Arch, Layout), Error> {
+        let arch = single_arch(arches)?;
+        Ok((arch, Layout::resolve(output, arch, variants)?))
+    };
+
+    let all_layouts = || -> Result<Vec<(xwin::Arch, Layout)>, Error> {
+        arches
+            .iter()
+            .map(|arch| Ok((*arch, Layout::resolve(output, *arch, variants)?)))
+            .collect()
+    };
+
+    let (path, contents) = match generator {
+        Generator::CmakeToolchain { path } => {
+            let (arch, layout) = single_layout()?;
+            (
+                path.clone()
+                    .unwrap_or_else(|| output.join(format!("toolchain-{}.cmake", arch))),
+                gen::cmake_toolchain(&layout, arch),
+            )
+        }
+        Generator::MesonCross { path } => {
+            let (arch, layout) = single_layout()?;
+            (
+                path.clone()
+                    .unwrap_or_else(|| output.join(format!("meson-{}.ini", arch))),
+                gen::meson_cross(&layout, arch),
+            )
+        }
+        Generator::Wrappers { dir } => {
+            let (arch, layout) = single_layout()?;
+            let dir = dir
+                .clone()
+                .unwrap_or_else(|| output.join("bin").join(arch.as_str()));
+
+            write_scripts(
+                &dir,
+                &[
+                    ("xwin-clang-cl", gen::clang_cl_wrapper(&layout, arch)),
+                    ("xwin-lld-link", gen::lld_link_wrapper(&layout, arch)),
+                ],
+            )?;
+
+            return Ok(());
+        }
+        Generator::Zig { dir } => {
+            let (arch, layout) = single_layout()?;
+            let dir = dir
+                .clone()
+                .unwrap_or_else(|| output.join("bin").join(arch.as_str()));
+
+            // The libc file is referenced by the scripts, so it must be absolute
+            let libc = std::env::current_dir()?;
+            let libc = PathBuf::from_path_buf(libc)
+                .map_err(|pb| anyhow::anyhow!("{} is not a valid utf-8 path", pb.display()))?
+                .join(output)
+                .join(format!("zig-libc-{}.txt", arch));
+            write_generated(&libc, &gen::zig_libc(&layout)?)?;
+
+            write_scripts(
+                &dir,
+                &[
+                    ("xwin-zig-cc", gen::zig_wrapper(&layout, arch, &libc, false)),
+                    ("xwin-zig-c++", gen::zig_wrapper(&layout, arch, &libc, true)),
+                ],
+            )?;
+
+            return Ok(());
+        }
+        Generator::WineVcvars { path, bin_dir } => {
+            let (arch, layout) = single_layout()?;
+            (
+                path.clone()
+                    .unwrap_or_else(|| output.join(format!("vcvars-{}.sh", arch))),
+                gen::wine_vcvars(&layout, arch, bin_dir.as_deref()),
+            )
+        }
+        Generator::CompileFlags { path } => {
+            let (arch, layout) = single_layout()?;
+            (
+                path.clone()
+                    .unwrap_or_else(|| PathBuf::from("compile_flags.txt")),
+                gen::compile_flags(&layout, arch),
+            )
+        }
+        Generator::PkgConfig { path } => {
+            let (arch, layout) = single_layout()?;
+            (
+                path.clone().unwrap_or_else(|| {
+                    output
+                        .join("pkgconfig")
+                        .join(arch.as_str())
+                        .join("windows-sdk.pc")
+                }),
+                gen::pkg_config(&layout, arch),
+            )
+        }
+        Generator::CCppProperties { path } => (
+            path.clone()
+                .unwrap_or_else(|| PathBuf::from(".vscode/c_cpp_properties.json")),
+            gen::c_cpp_properties(&all_layouts()?),
+        ),
+        Generator::Bazel => {
+            let layouts = all_layouts()?;
+            write_generated(&output.join("BUILD.bazel"), &gen::bazel_build(&layouts)?)?;
+            (output.join("xwin.json"), gen::bazel_descriptor(&layouts)?)
+        }
+        Generator::Msbuild { dir } => {
+            write_generated(
+                &output.join("Toolchain.props"),
+                &gen::msbuild_toolchain_props(output, &all_layouts()?),
+            )?;
+
+            let root = std::env::current_dir()?;
+            let root = PathBuf::from_path_buf(root)
+                .map_err(|pb| anyhow::anyhow!("{} is not a valid utf-8 path", pb.display()))?
+                .join(output);
+            (
+                dir.clone()
+                    .unwrap_or_default()
+                    .join("Directory.Build.props"),
+                gen::msbuild_directory_props(&root),
+            )
+        }
+        Generator::CargoConfig { path } => (
+            path.clone()
+                .unwrap_or_else(|| output.join("cargo-config.toml")),
+            gen::cargo_config(&all_layouts()?),
+        ),
+    };
+
+    write_generated(&path, &contents)
+}
+
+/// Writes generated shell scripts to the directory and makes them executable
+fn write_scripts(dir: &camino::Utf8Path, scripts: &[(&str, String)]) -> Result<(), Error> {
+    for (name, contents) in scripts {
+        let path = dir.join(name);
+        write_generated(&path, contents)?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+                .with_context(|| format!("unable to make {} executable", path))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes generated contents to the specified path, or stdout if it is `-`
+fn write_generated(path: &camino::Utf8Path, contents: &str) -> Result<(), Error> {
+    if path == "-" {
+        print!("{}", contents);
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).with_context(|| format!("unable to create {}", parent))?;
+    }
+
+    std::fs::write(path, contents).with_context(|| format!("unable to write {}", path))?;
+    println!("wrote {}", path);
+    Ok(())
+}
+
+fn clean(dirs: &[PathBuf], dry_run: bool) -> Result<(), Error> {
+    fn size(path: &std::path::Path) -> Result<u64, Error> {
+        let md = std::fs::symlink_metadata(path)?;
+
+        if md.is_dir() {
+            let mut total = 0;
+            for entry in std::fs::read_dir(path)? {
+                total += size(&entry?.path())?;
+            }
+            Ok(total)
+        } else {
+            Ok(md.len())
+        }
+    }
+
+    let mut reclaimed = 0;
+
+    for dir in dirs {
+        if !dir.exists() {
+            continue;
+        }
+
+        let dir_size =
+            size(dir.as_std_path()).with_context(|| format!("unable to read {}", dir))?;
+        reclaimed += dir_size;
+
+        if dry_run {
+            println!("would remove {} ({})", dir, ia::HumanBytes(dir_size));
+        } else {
+            std::fs::remove_dir_all(dir).with_context(|| format!("unable to remove {}", dir))?;
+            println!("removed {} ({})", dir, ia::HumanBytes(dir_size));
+        }
+    }
+
+    if dry_run {
+        println!("{} would be reclaimed", ia::HumanBytes(reclaimed));
+    } else {
+        println!("{} reclaimed", ia::HumanBytes(reclaimed));
+    }
+
+    Ok(())
+}
+
+fn cache_stats(cache_dir: &camino::Utf8Path, json: bool) -> Result<(), Error> {
+    let stats = xwin::cache::stats(cache_dir)?;
+    let versions = stats.by_version();
+
+    // Access times are reported relative to now, as that is what matters when
+    // deciding what to prune
+    let now = std::time::SystemTime::now();
+    let age = |accessed: Option<std::time::SystemTime>| {
+        accessed.map(|accessed| now.duration_since(accessed).unwrap_or_default())
+    };
+
+    if json {
+        let unix_secs = |accessed: Option<std::time::SystemTime>| {
+            accessed
+                .and_then(|accessed| accessed.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+        };
+
+        let report = serde_json::json!({
+            "root": stats.root,
+            "size": stats.size(),
+            "entries": stats.entries.iter().map(|entry| serde_json::json!({
+                "path": entry.path,
+                "kind": entry.kind.as_str(),
+                "version": entry.version,
+                "size": entry.size,
+                "files": entry.files,
+                "accessed": unix_secs(entry.accessed),
+            })).collect::<Vec<_>>(),
+            "versions": versions.iter().map(|(version, vs)| serde_json::json!({
+                "version": version,
+                "entries": vs.entries,
+                "size": vs.size,
+                "accessed": unix_secs(vs.accessed),
+            })).collect::<Vec<_>>(),
+        });
+
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    use cli_table::{format::Justify, Cell, Table};
+
+    let accessed = |accessed: Option<std::time::SystemTime>| {
+        age(accessed)
+            .map(|age| format!("{} ago", ia::HumanDuration(age)))
+            .unwrap_or_default()
+            .cell()
+    };
+
+    let table = stats
+        .entries
+        .iter()
+        .map(|entry| {
+            vec![
+                entry.path.as_str().cell().justify(Justify::Right),
+                entry.kind.as_str().cell(),
+                entry.version.as_deref().unwrap_or_default().cell(),
+                entry.files.cell(),
+                ia::HumanBytes(entry.size).cell(),
+                accessed(entry.accessed),
+            ]
+        })
+        .collect::<Vec<_>>()
+        .table()
+        .title(vec![
+            "Path".cell(),
+            "Kind".cell(),
+            "Version".cell(),
+            "Files".cell(),
+            "Size".cell(),
+            "Accessed".cell(),
+        ]);
+
+    let _ = cli_table::print_stdout(table);
+
+    let table = versions
+        .iter()
+        .map(|(version, vs)| {
+            vec![
+                version.unwrap_or("unknown").cell().justify(Justify::Right),
+                vs.entries.cell(),
+                ia::HumanBytes(vs.size).cell(),
+                accessed(vs.accessed),
+            ]
+        })
+        .collect::<Vec<_>>()
+        .table()
+        .title(vec![
+            "Version".cell(),
+            "Entries".cell(),
+            "Size".cell(),
+            "Accessed".cell(),
+        ]);
+
+    let _ = cli_table::print_stdout(table);
+
+    println!(
+        "{}: {} entries, {}",
+        stats.root,
+        stats.entries.len(),
+        ia::HumanBytes(stats.size())
+    );
+
+    Ok(())
+}
+
+fn selftest(
+    output: &camino::Utf8Path,
+    arches: &[xwin::Arch],
+    variants: u32,
+    toolchain: &xwin::selftest::Toolchain,
+) -> Result<(), Error> {
+    use cli_table::{Cell, Style, Table};
+
+    let td = tempfile::TempDir::new().context("unable to create temporary directory")?;
+    let dir = PathBuf::from_path_buf(td.path().to_owned())
+        .map_err(|pb| anyhow::anyhow!("{} is not a valid utf-8 path", pb.display()))?;
+
+    let mut outcomes = Vec::new();
+    for arch in arches {
+        let layout = xwin::layout::Layout::resolve(output, *arch, variants)?;
+        outcomes.extend(xwin::selftest::run_arch(toolchain, &layout, *arch, &dir)?);
+    }
+
+    let table = outcomes
+        .iter()
+        .map(|outcome| {
+            vec![
+                outcome.arch.cell(),
+                outcome.language.cell(),
+                if outcome.error.is_some() {
+                    "failed".cell().bold(true)
+                } else {
+                    "ok".cell()
+                },
+            ]
+        })
+        .collect::<Vec<_>>()
+        .table()
+        .title(vec!["Arch".cell(), "Program".cell(), "Result".cell()]);
+
+    let _ = cli_table::print_stdout(table);
+
+    let failed: Vec<_> = outcomes
+        .iter()
+        .filter_map(|outcome| {
+            outcome
+                .error
+                .as_ref()
+                .map(|error| (outcome.arch, outcome.language, error))
+        })
+        .collect();
+
+    for (arch, language, error) in &failed {
+        eprintln!("\n{} {} program:\n{}", arch, language, error);
+    }
+
+    anyhow::ensure!(
+        failed.is_empty(),
+        "{} of {} programs failed to compile or link against {}",
+        failed.len(),
+        outcomes.len(),
+        output
+    );
+
+    Ok(())
+}
+
+fn wine_setup(
+    output: &camino::Utf8Path,
+    prefix: &camino::Utf8Path,
+    arches: &[xwin::Arch],
+    variants: u32,
+    copy: bool,
+) -> Result<(), Error> {
+    let windows = prefix.join("drive_c/windows");
+    anyhow::ensure!(
+        windows.exists(),
+        "{} is not a Wine prefix, it can be created by running `wineboot`",
+        prefix
+    );
+
+    // 32-bit prefixes don't have a syswow64 directory
+    let wow64 = windows.join("syswow64");
+    let is_64 = wow64.exists();
+
+    let mut overrides = std::collections::BTreeSet::new();
+
+    for arch in arches {
+        let sys_dir = match arch {
+            xwin::Arch::X86_64 if is_64 => windows.join("system32"),
+            xwin::Arch::X86 if is_64 => wow64.clone(),
+            xwin::Arch::X86 => windows.join("system32"),
+            xwin::Arch::X86_64 => anyhow::bail!("{} is a 32-bit Wine prefix", prefix),
+            xwin::Arch::Aarch | xwin::Arch::Aarch64 => {
+                anyhow::bail!("{} is not supported by Wine prefixes", arch)
+            }
+        };
+
+        let layout = xwin::layout::Layout::resolve(output, *arch, variants)?;
+
+        let mut dlls = std::collections::BTreeMap::new();
+        for dir in &layout.lib {
+            for entry in
+                std::fs::read_dir(dir).with_context(|| format!("unable to read {}", dir))?
+            {
+                let entry = entry?;
+                let name = match entry.file_name().into_string() {
+                    Ok(name) if name.to_ascii_lowercase().ends_with(".dll") => name,
+                    _ => continue,
+                };
+
+                // Like the linker, the first directory the DLL is found in wins
+                dlls.entry(name.to_ascii_lowercase())
+                    .or_insert_with(|| dir.join(name));
+            }
+        }
+
+        if dlls.is_empty() {
+            tracing::warn!("no DLLs were found in the splatted {} libraries", arch);
+        }
+
+        for (name, src) in dlls {
+            let dst = sys_dir.join(&name);
+
+            // Wine places its own builtin placeholders in the system directories
+            if dst.symlink_metadata().is_ok() {
+                std::fs::remove_file(&dst).with_context(|| format!("unable to remove {}", dst))?;
+            }
+
+            if copy {
+                std::fs::copy(&src, &dst)
+                    .with_context(|| format!("unable to copy {} to {}", src, dst))?;
+            } else {
+                #[cfg(unix)]
+                std::os::unix::fs::symlink(&src, &dst)
+                    .with_context(|| format!("unable to symlink {} to {}", src, dst))?;
+                #[cfg(not(unix))]
+                std::fs::copy(&src, &dst)
+                    .with_context(|| format!("unable to copy {} to {}", src, dst))?;
+            }
+
+            println!("{} -> {}", src, dst);
+            overrides.insert(name.trim_end_matches(".dll").to_owned());
+        }
+    }
+
+    if overrides.is_empty() {
+        return Ok(());
+    }
+
+    // Importing a single .reg file is far faster than spinning up wine for a
+    // `reg add` per DLL
+    let mut reg = String::from("REGEDIT4\n\n[HKEY_CURRENT_USER\\Software\\Wine\\DllOverrides]\n");
+    for name in &overrides {
+        reg.push_str(&format!("\"{}\"=\"native,builtin\"\n", name));
+    }
+
+    let reg_path = prefix.join("drive_c/xwin-dll-overrides.reg");
+    std::fs::write(&reg_path, reg).with_context(|| format!("unable to write {}", reg_path))?;
+
+    let status = std::process::Command::new("wine")
+        .env("WINEPREFIX", prefix)
+        .args(["regedit", "/S", r"C:\xwin-dll-overrides.reg"])
+        .status();
+
+    let _ = std::fs::remove_file(&reg_path);
+    let status = status.context("unable to run wine")?;
+    anyhow::ensure!(
+        status.success(),
+        "failed to register DLL overrides: {}",
+        status
+    );
+
+    println!("registered {} DLL overrides", overrides.len());
+    Ok(())
+}
+
+fn print_diff(old: &camino::Utf8Path, new: &camino::Utf8Path) -> Result<(), Error> {
+    use xwin::diff::{Change, Listing};
+
+    let old = Listing::load(old)?;
+    let new = Listing::load(new)?;
+
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+
+    for change in xwin::diff::diff(&old, &new) {
+        match change {
+            Change::Added { path, hash } => {
+                added += 1;
+                println!("+ {} {}", path, hash);
+            }
+            Change::Removed { path, hash } => {
+                removed += 1;
+                println!("- {} {}", path, hash);
+            }
+            Change::Changed { path, old, new } => {
+                changed += 1;
+                println!("~ {} {} -> {}", path, old, new);
+            }
+        }
+    }
+
+    println!("{} added, {} removed, {} changed", added, removed, changed);
+
+    Ok(())
+}
+
+fn print_update(update: &xwin::stamp::Update) {
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+
+    for old in &update.removed {
+        match update
+            .splat
+            .iter()
+            .find(|pay| pay.filename.as_str() == old.filename)
+        {
+            Some(pay) => {
+                changed += 1;
+                println!("~ {} {} -> {}", old.filename, old.version, pay.version);
+            }
+            None => {
+                removed += 1;
+                println!("- {} {}", old.filename, old.version);
+            }
+        }
+    }
+
+    for pay in &update.splat {
+        if !update
+            .removed
+            .iter()
+            .any(|old| old.filename == pay.filename.as_str())
+        {
+            added += 1;
+            println!("+ {} {}", pay.filename, pay.version);
+        }
+    }
+
+    println!(
+        "{} added, {} removed, {} changed, {} unchanged",
+        added,
+        removed,
+        changed,
+        update.retained.len()
+    );
+}
+
+fn print_packages(payloads: &[xwin::Payload]) {
+    use cli_table::{format::Justify, Cell, Style, Table};
+
+    let (dl, install) = payloads.iter().fold((0, 0), |(dl, install), payload| {
+        (
+            dl + payload.size,
+            install + payload.install_size.unwrap_or_default(),
+        )
+    });
+
+    let totals = vec![
+        "Total".cell().bold(true).justify(Justify::Right),
+        "".cell(),
+        "".cell(),
+        "".cell(),
+        ia::HumanBytes(dl).cell().bold(true),
+        ia::HumanBytes(install).cell().bold(true),
+    ];
+
+    let table = payloads
+        .iter()
+        .map(|payload| {
+            vec![
+                payload.filename.clone().cell().justify(Justify::Right),
+                payload.version.clone().cell(),
+                payload
+                    .target_arch
+                    .map(|a| a.to_string())
+                    .unwrap_or_default()
+                    .cell(),
+                payload
+                    .variant
+                    .map(|v| v.to_string())
+                    .unwrap_or_default()
+                    .cell(),
+                ia::HumanBytes(payload.size).cell(),
+                ia::HumanBytes(payload.install_size.unwrap_or_default()).cell(),
+            ]
+        })
+        .chain(std::iter::once(totals))
+        .collect::<Vec<_>>()
+        .table()
+        .title(vec![
+            "Name".cell(),
+            "Version".cell(),
+            "Target".cell(),
+            "Variant".cell(),
+            "Download Size".cell(),
+            "Install Size".cell(),
+        ]);
+
+    let _ = cli_table::print_stdout(table);
+}
+
+/// The current selection that `xwin configure` starts from
+#[cfg_attr(not(feature = "ui"), allow(dead_code))]
+struct ConfigureDefaults<'a> {
+    arches: u32,
+    variants: u32,
+    components: &'a [xwin::Component],
+    sdk_tools: xwin::SdkTools,
+    crt_version: Option<&'a xwin::version::VersionReq>,
+    sdk_version: Option<&'a xwin::version::VersionReq>,
+}
+
+#[cfg(not(feature = "ui"))]
+fn configure(
+    _pkg_manifest: &xwin::manifest::PackageManifest,
+    _config_path: &camino::Utf8Path,
+    _defaults: ConfigureDefaults<'_>,
+) -> Result<(), Error> {
+    anyhow::bail!("configure requires xwin to be built with the `ui` feature");
+}
+
+/// The splat options `xwin configure` prompts for, and their descriptions
+#[cfg(feature = "ui")]
+const SPLAT_OPTIONS: &[(&str, &str)] = &[
+    ("include_debug_libs", "Include debug libs"),
+    ("include_debug_symbols", "Include debug symbols"),
+    ("disable_symlinks", "Disable symlinks"),
+    (
+        "preserve_ms_arch_notation",
+        "Preserve MS architecture notation",
+    ),
+    ("copy", "Copy instead of move"),
+];
+
+/// What was selected in `xwin configure`
+#[cfg(feature = "ui")]
+struct Selection {
+    arches: Vec<&'static str>,
+    variants: Vec<&'static str>,
+    components: Vec<&'static str>,
+    sdk_tools: xwin::SdkTools,
+    /// `None` leaves the option as it is, `Some(None)` removes it so that
+    /// the latest version is used
+    crt_version: Option<Option<String>>,
+    sdk_version: Option<Option<String>>,
+    splat: Vec<(&'static str, bool)>,
+}
+
+/// Writes the selection to the config, keeping the comments and formatting
+/// of everything else intact
+#[cfg(feature = "ui")]
+fn write_selection(config: &mut toml_edit::Document, selection: &Selection) -> Result<(), Error> {
+    use toml_edit::{value, Array};
+
+    let to_array = |strs: &[&str]| value(strs.iter().copied().collect::<Array>());
+
+    config["arch"] = to_array(&selection.arches);
+    config["variant"] = to_array(&selection.variants);
+    config["components"] = to_array(&selection.components);
+
+    for (key, enabled) in [
+        ("include_sdk_bin", selection.sdk_tools.build),
+        ("include_signing_tools", selection.sdk_tools.signing),
+    ] {
+        if enabled {
+            config[key] = value(true);
+        } else {
+            config.remove(key);
+        }
+    }
+
+    for (key, version) in [
+        ("crt_version", &selection.crt_version),
+        ("sdk_version", &selection.sdk_version),
+    ] {
+        match version {
+            Some(Some(version)) => config[key] = value(version.as_str()),
+            Some(None) => {
+                config.remove(key);
+            }
+            None => {}
+        }
+    }
+
+    if !config.contains_key("splat") {
+        config["splat"] = toml_edit::table();
+    }
+    let splat = config["splat"]
+        .as_table_like_mut()
+        .context("'splat' in config is not a table")?;
+    for (key, enabled) in &selection.splat {
+        // Inserting an existing key would drop the comments above it
+        match splat.get_mut(key) {
+            Some(item) => *item = value(*enabled),
+            None => {
+                splat.insert(key, value(*enabled));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "ui")]
+fn configure(
+    pkg_manifest: &xwin::manifest::PackageManifest,
+    config_path: &camino::Utf8Path,
+    defaults: ConfigureDefaults<'_>,
+) -> Result<(), Error> {
+    use dialoguer::{theme::ColorfulTheme, Confirm, MultiSelect};
+    use std::io::IsTerminal;
+
+    anyhow::ensure!(
+        std::io::stdin().is_terminal(),
+        "configure requires an interactive terminal"
+    );
+
+    // We deliberately don't go through `Config` here, so that the comments,
+    // formatting, and any other options in an existing config are preserved
+    let mut config: toml_edit::Document = if config_path.exists() {
+        std::fs::read_to_string(config_path)
+            .with_context(|| format!("failed to read config '{}'", config_path))?
+            .parse()
+            .with_context(|| format!("failed to deserialize config '{}'", config_path))?
+    } else {
+        Default::default()
+    };
+
+    let theme = ColorfulTheme::default();
+
+    let selected_arches = MultiSelect::with_theme(&theme)
+        .with_prompt("Architectures")
+        .items(ARCHES)
+        .defaults(
+            &ARCHES
+                .iter()
+                .map(|a| Ok(defaults.arches & a.parse::<xwin::Arch>()? as u32 != 0))
+                .collect::<Result<Vec<_>, Error>>()?,
+        )
+        .interact()?;
+    anyhow::ensure!(
+        !selected_arches.is_empty(),
+        "at least one architecture must be selected"
+    );
+
+    let selected_variants = MultiSelect::with_theme(&theme)
+        .with_prompt("Variants")
+        .items(VARIANTS)
+        .defaults(
+            &VARIANTS
+                .iter()
+                .map(|v| Ok(defaults.variants & v.parse::<xwin::Variant>()? as u32 != 0))
+                .collect::<Result<Vec<_>, Error>>()?,
+        )
+        .interact()?;
+    anyhow::ensure!(
+        !selected_variants.is_empty(),
+        "at least one variant must be selected"
+    );
+
+    let component_items = [
+        "MSVC CRT",
+        "Windows SDK and UCRT",
+        "Windows SDK tools, eg. rc.exe and mt.exe",
+        "Windows SDK signing tools, ie. signtool.exe",
+    ];
+    let selected_components = MultiSelect::with_theme(&theme)
+        .with_prompt("Components")
+        .items(&component_items)
+        .defaults(&[
+            defaults.components.contains(&xwin::Component::Crt),
+            defaults.components.contains(&xwin::Component::Sdk),
+            defaults.sdk_tools.build,
+            defaults.sdk_tools.signing,
+        ])
+        .interact()?;
+    let components: Vec<_> = selected_components
+        .iter()
+        .filter_map(|i| COMPONENTS.get(*i).copied())
+        .collect();
+    anyhow::ensure!(
+        !components.is_empty(),
+        "at least one of the CRT or the SDK must be selected"
+    );
+
+    let crt_version = pick_version(
+        &theme,
+        "MSVC CRT version",
+        &pkg_manifest.crt_versions(),
+        defaults.crt_version,
+    )?;
+    let sdk_version = pick_version(
+        &theme,
+        "Windows SDK version",
+        &pkg_manifest.sdk_versions(),
+        defaults.sdk_version,
+    )?;
+
+    let selected_splat = MultiSelect::with_theme(&theme)
+        .with_prompt("Splat options")
+        .items(
+            &SPLAT_OPTIONS
+                .iter()
+                .map(|(_, desc)| *desc)
+                .collect::<Vec<_>>(),
+        )
+        .defaults(
+            &SPLAT_OPTIONS
+                .iter()
+                .map(|(key, _)| {
+                    config
+                        .get("splat")
+                        .and_then(|splat| splat.get(key))
+                        .and_then(|v| v.as_bool())
+                        .unwrap_or(false)
+                })
+                .collect::<Vec<_>>(),
+        )
+        .interact()?;
+
+    let selection = Selection {
+        arches: selected_arches.into_iter().map(|i| ARCHES[i]).collect(),
+        variants: selected_variants.into_iter().map(|i| VARIANTS[i]).collect(),
+        components,
+        sdk_tools: xwin::SdkTools {
+            build: selected_components.contains(&2),
+            signing: selected_components.contains(&3),
+        },
+        crt_version,
+        sdk_version,
+        splat: SPLAT_OPTIONS
+            .iter()
+            .enumerate()
+            .map(|(i, (key, _))| (*key, selected_splat.contains(&i)))
+            .collect(),
+    };
+
+    // Show the packages the selection resolves to before writing it
+    {
+        let mut pkg_manifest = pkg_manifest.clone();
+        let version_req = |version: &Option<Option<String>>, current| match version {
+            Some(Some(version)) => version.parse().map(Some),
+            Some(None) => Ok(None),
+            None => Ok(current),
+        };
+        if let Some(req) = version_req(&selection.crt_version, defaults.crt_version.cloned())? {
+            xwin::version::select_crt(&mut pkg_manifest, &req)?;
+        }
+        if let Some(req) = version_req(&selection.sdk_version, defaults.sdk_version.cloned())? {
+            xwin::version::select_sdk(&mut pkg_manifest, &req)?;
+        }
+
+        let arches = selection
+            .arches
+            .iter()
+            .try_fold(0, |acc, a| a.parse::<xwin::Arch>().map(|a| acc | a as u32))?;
+        let variants = selection.variants.iter().try_fold(0, |acc, v| {
+            v.parse::<xwin::Variant>().map(|v| acc | v as u32)
+        })?;
+
+        let mut pruned = xwin::prune_pkg_list(&pkg_manifest, arches, variants)?;
+        if selection.sdk_tools != xwin::SdkTools::default() {
+            pruned.extend(xwin::prune_sdk_bin(
+                &pkg_manifest,
+                arches,
+                selection.sdk_tools,
+            )?);
+        }
+        let components = selection
+            .components
+            .iter()
+            .map(|c| c.parse())
+            .collect::<Result<Vec<xwin::Component>, _>>()?;
+        xwin::prune_components(&mut pruned, &components);
+        print_packages(&pruned);
+    }
+
+    if !Confirm::with_theme(&theme)
+        .with_prompt(format!("Write selection to '{}'?", config_path))
+        .default(true)
+        .interact()?
+    {
+        return Ok(());
+    }
+
+    write_selection(&mut config, &selection)?;
+    std::fs::write(config_path, config.to_string())
+        .with_context(|| format!("failed to write config '{}'", config_path))?;
+
+    println!("wrote config to '{}'", config_path);
+
+    Ok(())
+}
+
+/// Prompts for one of the versions, newest first, starting from the one the
+/// current requirement resolves to. `None` is returned if the selection is
+/// unchanged, so that eg. a pattern in the config isn't replaced by the
+/// version it currently resolves to
+#[cfg(feature = "ui")]
+fn pick_version(
+    theme: &dyn dialoguer::theme::Theme,
+    prompt: &str,
+    versions: &[String],
+    current: Option<&xwin::version::VersionReq>,
+) -> Result<Option<Option<String>>, Error> {
+    let mut items = vec![format!(
+        "latest ({})",
+        versions.last().map_or("none", String::as_str)
+    )];
+    items.extend(versions.iter().rev().cloned());
+
+    let current = current.map_or(0, |req| {
+        versions
+            .iter()
+            .rev()
+            .position(|version| req.matches(version))
+            .map_or(0, |i| i + 1)
+    });
+
+    let selected = dialoguer::Select::with_theme(theme)
+        .with_prompt(prompt)
+        .items(&items)
+        .default(current)
+        .interact()?;
+
+    Ok(match selected {
+        selected if selected == current => None,
+        0 => Some(None),
+        selected => Some(Some(versions[versions.len() - selected].clone())),
+    })
+}
+
+/// The parts of indicatif that are used, when built without the `ui` feature,
+/// which draw nothing, so that progress is only reported by the summaries
+#[cfg(not(feature = "ui"))]
+mod ia {
+    use std::fmt;
+
+    #[derive(Copy, Clone)]
+    pub struct ProgressDrawTarget;
+
+    impl ProgressDrawTarget {
+        pub fn stderr() -> Self {
+            Self
+        }
+
+        pub fn hidden() -> Self {
+            Self
+        }
+    }
+
+    #[derive(Clone)]
+    pub struct ProgressStyle;
+
+    impl ProgressStyle {
+        pub fn default_bar() -> Self {
+            Self
+        }
+
+        pub fn template(self, _template: &str) -> Result<Self, std::convert::Infallible> {
+            Ok(self)
+        }
+
+        pub fn progress_chars(self, _chars: &str) -> Self {
+            self
+        }
+
+        pub fn tick_chars(self, _chars: &str) -> Self {
+            self
+        }
+    }
+
+    #[derive(Clone)]
+    pub struct ProgressBar;
+
+    impl ProgressBar {
+        pub fn hidden() -> Self {
+            Self
+        }
+
+        pub fn with_draw_target(_len: u64, _target: ProgressDrawTarget) -> Self {
+            Self
+        }
+
+        pub fn with_prefix(self, _prefix: impl Into<String>) -> Self {
+            self
+        }
+
+        pub fn with_style(self, _style: ProgressStyle) -> Self {
+            self
+        }
+
+        pub fn set_prefix(&self, _prefix: impl Into<String>) {}
+        pub fn set_message(&self, _msg: impl Into<String>) {}
+        pub fn set_style(&self, _style: ProgressStyle) {}
+        pub fn set_length(&self, _len: u64) {}
+        pub fn inc_length(&self, _delta: u64) {}
+        pub fn inc(&self, _delta: u64) {}
+        pub fn reset(&self) {}
+        pub fn finish(&self) {}
+        pub fn finish_with_message(&self, _msg: impl Into<String>) {}
+        pub fn finish_and_clear(&self) {}
+    }
+
+    pub struct MultiProgress;
+
+    impl MultiProgress {
+        pub fn with_draw_target(_target: ProgressDrawTarget) -> Self {
+            Self
+        }
+
+        pub fn set_move_cursor(&self, _move_cursor: bool) {}
+
+        pub fn add(&self, pb: ProgressBar) -> ProgressBar {
+            pb
+        }
+
+        pub fn remove(&self, _pb: &ProgressBar) {}
+
+        pub fn clear(&self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Formats bytes with binary units, eg. `1.50 MiB`
+    pub struct HumanBytes(pub u64);
+
+    impl fmt::Display for HumanBytes {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+
+            if self.0 < 1024 {
+                return write!(f, "{} B", self.0);
+            }
+
+            let mut size = self.0 as f64 / 1024.0;
+            let mut unit = 0;
+            while size >= 1024.0 && unit + 1 < UNITS.len() {
+                size /= 1024.0;
+                unit += 1;
+            }
+
+            write!(f, "{:.2} {}", size, UNITS[unit])
+        }
+    }
+
+    /// Formats a duration in its largest whole unit, eg. `3 minutes`
+    pub struct HumanDuration(pub std::time::Duration);
+
+    impl fmt::Display for HumanDuration {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            const UNITS: &[(u64, &str)] = &[(86400, "day"), (3600, "hour"), (60, "minute")];
+
+            let secs = self.0.as_secs();
+            let (count, unit) = UNITS
+                .iter()
+                .find(|(unit_secs, _)| secs >= *unit_secs)
+                .map_or((secs, "second"), |(unit_secs, unit)| {
+                    (secs / unit_secs, *unit)
+                });
+
+            write!(f, "{} {}{}", count, unit, if count == 1 { "" } else { "s" })
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum ProgressTarget {
+    Stderr,
+    Hidden,
+}
+
+impl From<ProgressTarget> for ia::ProgressDrawTarget {
+    fn from(pt: ProgressTarget) -> Self {
+        match pt {
+            ProgressTarget::Stderr => Self::stderr(),
+            ProgressTarget::Hidden => Self::hidden(),
+        }
+    }
+}
+
+/// The characters that progress is drawn with
+#[derive(Copy, Clone)]
+enum Glyphs {
+    Unicode,
+    /// For CI log viewers and serial consoles that mangle anything else
+    Ascii,
+}
+
+impl Glyphs {
+    /// Uses ASCII if it was requested, or the locale isn't UTF-8
+    fn detect(ascii: bool) -> Self {
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .iter()
+            .find_map(|var| std::env::var(var).ok().filter(|val| !val.is_empty()));
+
+        let utf8 = match locale {
+            Some(locale) => {
+                let locale = locale.to_ascii_lowercase();
+                locale.contains("utf-8") || locale.contains("utf8")
+            }
+            None => true,
+        };
+
+        if ascii || !utf8 {
+            Self::Ascii
+        } else {
+            Self::Unicode
+        }
+    }
+
+    /// Prefixes the message with the emoji, unless limited to ASCII
+    fn message(self, emoji: &str, msg: &str) -> String {
+        match self {
+            Self::Unicode => format!("{} {}", emoji, msg),
+            Self::Ascii => msg.to_owned(),
+        }
+    }
+
+    fn style(self, template: &str) -> ia::ProgressStyle {
+        let style = ia::ProgressStyle::default_bar().template(template).unwrap();
+
+        match self {
+            Self::Unicode => style.progress_chars("█▇▆▅▄▃▂▁  "),
+            Self::Ascii => style.progress_chars("#>-").tick_chars("-\\|/ "),
+        }
+    }
+}
+
+fn bytes_style(glyphs: Glyphs) -> ia::ProgressStyle {
+    glyphs.style(
+        "{spinner:.green} {prefix:.bold} [{elapsed}] {wide_bar:.green} {bytes}/{total_bytes} {msg}",
+    )
+}
+
+/// Draws the progress of a single unit of work as a progress bar
+struct Bar(ia::ProgressBar, Glyphs);
+
+impl xwin::ProgressSink for Bar {
+    fn phase(&self, phase: xwin::progress::Phase) {
+        use xwin::progress::Phase;
+
+        match phase {
+            Phase::Download => self.0.set_message(self.1.message("📥", "downloading..")),
+            Phase::Unpack => {
+                self.0.reset();
+                self.0.set_message(self.1.message("📂", "unpacking..."));
+            }
+            Phase::Splat => {
+                self.0.reset();
+                self.0.set_message(self.1.message("📦", "splatting"));
+            }
+            Phase::ScanIncludes => {
+                self.0.set_style(self.1.style(
+                    "{spinner:.green} {prefix:.bold} [{elapsed}] {wide_bar:.green} {pos}/{len}",
+                ));
+                self.0.set_message(self.1.message("🔍", "includes"));
+            }
+            Phase::Symlinks => {
+                self.0.reset();
+                self.0.set_message(self.1.message("🔗", "symlinks"));
+            }
+        }
+    }
+
+    fn set_length(&self, len: u64) {
+        self.0.set_length(len);
+    }
+
+    fn inc_length(&self, delta: u64) {
+        self.0.inc_length(delta);
+    }
+
+    fn inc(&self, delta: u64) {
+        self.0.inc(delta);
+    }
+
+    fn finish(&self, phase: xwin::progress::Phase) {
+        use xwin::progress::Phase;
+
+        match phase {
+            Phase::Download => self.0.finish_with_message("downloaded"),
+            Phase::Unpack => self.0.finish_with_message("unpacked"),
+            Phase::Splat => self.0.finish_with_message(self.1.message("📦", "splatted")),
+            Phase::ScanIncludes | Phase::Symlinks => self.0.finish(),
+        }
+    }
+}
+
+/// The progress of every payload through each phase, drawn as a single bar
+/// above the bars of the payloads that are currently being worked on. Bars
+/// are only drawn while their payload is in a phase, so the display stays the
+/// same height no matter how many payloads there are.
+struct Overview {
+    mp: ia::MultiProgress,
+    pb: ia::ProgressBar,
+    glyphs: Glyphs,
+    /// The number of payloads that have completed the download, unpack, and
+    /// splat phases
+    completed: [std::sync::atomic::AtomicU64; 3],
+    /// When each phase was first started and last finished
+    spans: parking_lot::Mutex<[Option<(std::time::Instant, std::time::Instant)>; 5]>,
+    state: parking_lot::Mutex<OverviewState>,
+}
+
+#[derive(Default)]
+struct OverviewState {
+    payloads: u64,
+    phases: usize,
+    finalizing: bool,
+}
+
+impl Overview {
+    fn new(draw_target: ProgressTarget, glyphs: Glyphs) -> std::sync::Arc<Self> {
+        let mp = ia::MultiProgress::with_draw_target(draw_target.into());
+        mp.set_move_cursor(true);
+
+        let pb = mp.add(
+            ia::ProgressBar::hidden()
+                .with_prefix("Overall")
+                .with_style(glyphs.style(
+                "{spinner:.green} {prefix:.bold} [{elapsed}] {wide_bar:.cyan} {pos}/{len} {msg}",
+            )),
+        );
+
+        std::sync::Arc::new(Self {
+            mp,
+            pb,
+            glyphs,
+            completed: Default::default(),
+            spans: Default::default(),
+            state: Default::default(),
+        })
+    }
+
+    /// Sets the number of payloads, and the number of phases each of them
+    /// goes through, resetting the progress of any previous work
+    fn set_work(&self, payloads: u64, phases: usize) {
+        {
+            let mut state = self.state.lock();
+            state.payloads = payloads;
+            state.phases = phases;
+        }
+
+        for completed in &self.completed {
+            completed.store(0, std::sync::atomic::Ordering::Relaxed);
+        }
+
+        self.pb.set_length(payloads * phases as u64);
+        // Don't include the time taken to retrieve the manifest
+        self.pb.reset();
+        self.update();
+    }
+
+    /// Creates the bar for a unit of work, which isn't drawn until it starts
+    /// a phase
+    fn bar(self: &std::sync::Arc<Self>, name: String) -> PayloadBar {
+        PayloadBar {
+            bar: Bar(
+                ia::ProgressBar::hidden()
+                    .with_prefix(name)
+                    .with_style(bytes_style(self.glyphs)),
+                self.glyphs,
+            ),
+            overview: self.clone(),
+            attached: std::sync::atomic::AtomicBool::new(false),
+        }
+    }
+
+    fn index(phase: xwin::progress::Phase) -> usize {
+        use xwin::progress::Phase;
+
+        match phase {
+            Phase::Download => 0,
+            Phase::Unpack => 1,
+            Phase::Splat => 2,
+            Phase::ScanIncludes => 3,
+            Phase::Symlinks => 4,
+        }
+    }
+
+    fn phase_name(&self, phase: xwin::progress::Phase) -> String {
+        use xwin::progress::Phase;
+
+        let (emoji, name) = match phase {
+            Phase::Download => ("📥", "Download"),
+            Phase::Unpack => ("📂", "Unpack"),
+            Phase::Splat => ("📦", "Splat"),
+            Phase::ScanIncludes => ("🔗", "Finalize"),
+            Phase::Symlinks => ("🔗", "Symlinks"),
+        };
+
+        self.glyphs.message(emoji, name)
+    }
+
+    fn started(&self, phase: xwin::progress::Phase) {
+        let now = std::time::Instant::now();
+        self.spans.lock()[Self::index(phase)].get_or_insert((now, now));
+
+        if matches!(
+            phase,
+            xwin::progress::Phase::ScanIncludes | xwin::progress::Phase::Symlinks
+        ) {
+            self.state.lock().finalizing = true;
+            self.update();
+        }
+    }
+
+    fn finished(&self, phase: xwin::progress::Phase) {
+        if let Some(span) = &mut self.spans.lock()[Self::index(phase)] {
+            span.1 = std::time::Instant::now();
+        }
+
+        if let Some(completed) = self.completed.get(Self::index(phase)) {
+            completed.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            self.pb.inc(1);
+            self.update();
+        }
+    }
+
+    fn update(&self) {
+        use xwin::progress::Phase;
+
+        let state = self.state.lock();
+        let mut msg = [Phase::Download, Phase::Unpack, Phase::Splat]
+            .iter()
+            .take(state.phases)
+            .map(|phase| {
+                format!(
+                    "{} {}/{}",
+                    self.phase_name(*phase),
+                    self.completed[Self::index(*phase)].load(std::sync::atomic::Ordering::Relaxed),
+                    state.payloads
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("  ");
+
+        if state.finalizing {
+            msg.push_str("  ");
+            msg.push_str(&self.phase_name(Phase::ScanIncludes));
+        }
+
+        self.pb.set_message(msg);
+    }
+
+    /// The time between the phase first being started and last being
+    /// finished, across every payload
+    fn duration(&self, phase: xwin::progress::Phase) -> Option<std::time::Duration> {
+        self.spans.lock()[Self::index(phase)].map(|(start, end)| end - start)
+    }
+
+    fn finish(&self) {
+        self.pb.finish_and_clear();
+    }
+
+    fn clear(&self) {
+        let _ = self.mp.clear();
+    }
+}
+
+/// The bar for a single unit of work, which is only drawn below the
+/// [`Overview`] while the work is in a phase
+struct PayloadBar {
+    bar: Bar,
+    overview: std::sync::Arc<Overview>,
+    attached: std::sync::atomic::AtomicBool,
+}
+
+impl xwin::ProgressSink for PayloadBar {
+    fn phase(&self, phase: xwin::progress::Phase) {
+        if !self
+            .attached
+            .swap(true, std::sync::atomic::Ordering::Relaxed)
+        {
+            self.overview.mp.add(self.bar.0.clone());
+        }
+
+        self.overview.started(phase);
+        self.bar.phase(phase);
+    }
+
+    fn set_length(&self, len: u64) {
+        self.bar.set_length(len);
+    }
+
+    fn inc_length(&self, delta: u64) {
+        self.bar.inc_length(delta);
+    }
+
+    fn inc(&self, delta: u64) {
+        self.bar.inc(delta);
+    }
+
+    fn finish(&self, phase: xwin::progress::Phase) {
+        self.bar.0.finish_and_clear();
+        if self
+            .attached
+            .swap(false, std::sync::atomic::Ordering::Relaxed)
+        {
+            self.overview.mp.remove(&self.bar.0);
+        }
+        self.overview.finished(phase);
+    }
+}
+
+fn load_manifest(
+    ctx: &xwin::Ctx,
+    args: &Args,
+    dt: ProgressTarget,
+    glyphs: Glyphs,
+) -> anyhow::Result<(xwin::manifest::Manifest, xwin::manifest::PackageManifest)> {
+    let manifest_pb =
+        ia::ProgressBar::with_draw_target(0, dt.into()).with_style(bytes_style(glyphs));
+    manifest_pb.set_prefix("Manifest");
+    manifest_pb.set_message(glyphs.message("📥", "downloading"));
+
+    let pins = xwin::manifest::Pins {
+        channel_url: args.manifest_url.clone(),
+        channel_sha256: args.manifest_sha256.clone(),
+        package_sha256: args.package_manifest_sha256.clone(),
+    };
+
+    if let Some(layout) = &ctx.vs_layout {
+        let manifest = layout.channel_manifest(&pins)?;
+        let pkg_manifest = layout.package_manifest(&pins)?;
+        manifest_pb.finish_with_message(glyphs.message("📂", "read from layout"));
+        return Ok((manifest, pkg_manifest));
+    }
+
+    let manifest = match &args.manifest {
+        Some(manifest_path) => xwin::manifest::read_manifest(manifest_path, &pins)?,
+        None => xwin::manifest::get_manifest(
+            ctx,
+            args.manifest_version.as_deref().unwrap_or("16"),
+            args.channel.as_deref().unwrap_or("release"),
+            &pins,
+            &Bar(manifest_pb.clone(), glyphs),
+        )?,
+    };
+
+    let pkg_manifest = xwin::manifest::get_package_manifest(
+        ctx,
+        &manifest,
+        &pins,
+        &Bar(manifest_pb.clone(), glyphs),
+    )?;
+
+    manifest_pb.finish_with_message(glyphs.message("📥", "downloaded"));
+    Ok((manifest, pkg_manifest))
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn cli_help() {
+        use clap::CommandFactory;
+
+        let snapshot_path = format!("{}/tests/snapshots", env!("CARGO_MANIFEST_DIR"));
+
+        insta::with_settings!({
+            prepend_module_to_snapshot => false,
+            snapshot_path => snapshot_path,
+        }, {
+            // the tests here will force maps to sort
+            snapshot_test_cli_command(super::Args::command().name(env!("CARGO_PKG_NAME")), "xwin".to_owned(), &SnapshotTestDesc {
+                manifest_path: env!("CARGO_MANIFEST_DIR"),
                 module_path: module_path!(),
                 file: file!(),
                 line: line!(),
@@ -384,6 +4506,52 @@ mod test {
         });
     }
 
+    #[cfg(feature = "ui")]
+    #[test]
+    fn configure_preserves_config() {
+        let mut config: toml_edit::Document = r#"# Our CI only needs x86_64
+arch = ["x86_64"]
+sdk_version = "10.0.19*" # pinned until we drop Windows 10 support
+include_sdk_bin = true
+
+[splat]
+# Keep the cache intact
+copy = true
+"#
+        .parse()
+        .unwrap();
+
+        super::write_selection(
+            &mut config,
+            &super::Selection {
+                arches: vec!["x86_64", "aarch64"],
+                variants: vec!["desktop"],
+                components: vec!["sdk"],
+                sdk_tools: xwin::SdkTools::default(),
+                crt_version: Some(Some("14.29.16.11".to_owned())),
+                sdk_version: None,
+                splat: vec![("copy", true), ("disable_symlinks", false)],
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.to_string(),
+            r#"# Our CI only needs x86_64
+arch = ["x86_64", "aarch64"]
+sdk_version = "10.0.19*" # pinned until we drop Windows 10 support
+variant = ["desktop"]
+components = ["sdk"]
+crt_version = "14.29.16.11"
+
+[splat]
+# Keep the cache intact
+copy = true
+disable_symlinks = false
+"#
+        );
+    }
+
     use clap::{ColorChoice, Command};
 
     pub struct SnapshotTestDesc {
@@ -1,19 +1,42 @@
+//! The Visual Studio manifests that describe the packages, and the payloads
+//! they are made of, which xwin downloads. The manifests can also be retrieved
+//! and queried by other tools, eg.
+//!
+//! ```
+//! let pkg_manifest = xwin::manifest::PackageManifest::parse(br#"{"packages": [{
+//!     "id": "Win10SDK_10.0.22621",
+//!     "version": "10.0.22621.7",
+//!     "type": "Group",
+//!     "payloads": [{
+//!         "fileName": "Installers\\Windows SDK-x86_en-us.msi",
+//!         "sha256": "0000000000000000000000000000000000000000000000000000000000000000",
+//!         "size": 1024,
+//!         "url": "https://example.com/Windows SDK-x86_en-us.msi"
+//!     }]
+//! }]}"#)?;
+//!
+//! assert_eq!(pkg_manifest.sdk_versions(), ["10.0.22621"]);
+//! assert_eq!(pkg_manifest.find("Win10SDK_*").next().unwrap().download_size(), 1024);
+//! # Ok::<_, anyhow::Error>(())
+//! ```
+
 use anyhow::{ensure, Context as _};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::{cmp, collections::BTreeMap};
 
-use crate::Ctx;
+use crate::{progress::ProgressSink, util::Sha256, Ctx, Path};
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Payload {
     #[serde(rename = "fileName")]
     pub file_name: String,
+    #[serde(serialize_with = "crate::util::serialize_sha256")]
     pub sha256: crate::util::Sha256,
     pub size: u64,
     pub url: String,
 }
 
-#[derive(Copy, Clone, Deserialize, PartialEq, Debug)]
+#[derive(Copy, Clone, Deserialize, Serialize, PartialEq, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum Chip {
     X86,
@@ -23,7 +46,7 @@ pub enum Chip {
     Neutral,
 }
 
-#[derive(Copy, Clone, Deserialize, PartialEq, Debug)]
+#[derive(Copy, Clone, Deserialize, Serialize, PartialEq, Debug)]
 pub enum ItemKind {
     /// Unused.
     Bootstrapper,
@@ -57,13 +80,13 @@ pub enum ItemKind {
     Zip,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct InstallSizes {
     pub target_drive: Option<u64>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ManifestItem {
     pub id: String,
@@ -78,6 +101,18 @@ pub struct ManifestItem {
     pub install_sizes: Option<InstallSizes>,
 }
 
+impl ManifestItem {
+    /// The total size of the item's payloads
+    pub fn download_size(&self) -> u64 {
+        self.payloads.iter().map(|payload| payload.size).sum()
+    }
+
+    /// The size of the item once installed, if the manifest specifies it
+    pub fn install_size(&self) -> Option<u64> {
+        self.install_sizes.as_ref()?.target_drive
+    }
+}
+
 impl PartialEq for ManifestItem {
     #[inline]
     fn eq(&self, o: &Self) -> bool {
@@ -105,6 +140,113 @@ impl cmp::PartialOrd for ManifestItem {
 pub struct Manifest {
     #[serde(rename = "channelItems")]
     channel_items: Vec<ManifestItem>,
+    #[serde(skip)]
+    contents: Vec<u8>,
+}
+
+impl Manifest {
+    /// Parses a top-level channel manifest
+    pub fn parse(manifest_bytes: &[u8]) -> Result<Self, anyhow::Error> {
+        parse_manifest(manifest_bytes)
+    }
+
+    /// The items in the channel, eg. the products and the package manifest
+    pub fn items(&self) -> &[ManifestItem] {
+        &self.channel_items
+    }
+
+    /// The payload of the package manifest the channel links to
+    pub fn package_manifest(&self) -> Result<&Payload, anyhow::Error> {
+        package_manifest_payload(self)
+    }
+
+    /// The contents the manifest was parsed from
+    pub fn contents(&self) -> &[u8] {
+        &self.contents
+    }
+}
+
+/// Pins the manifest chain, ie. the top-level channel manifest and the
+/// package manifest it links to, to known contents. Everything xwin downloads
+/// is verified against the checksums in the package manifest, so pinning it
+/// means a manifest that differs from the one that was reviewed is rejected,
+/// rather than trusted because it was served over HTTPS.
+///
+/// The `signature` block of the channel manifest is not verified, so pinning
+/// is the only way to verify the manifests independently of the connection.
+#[derive(Clone, Debug, Default)]
+pub struct Pins {
+    /// Retrieves the channel manifest from this url, rather than the
+    /// `aka.ms` url for the version and channel
+    pub channel_url: Option<String>,
+    /// The expected sha256 of the channel manifest
+    pub channel_sha256: Option<Sha256>,
+    /// The expected sha256 of the package manifest
+    pub package_sha256: Option<Sha256>,
+}
+
+impl Pins {
+    /// The url of the channel manifest, and the name it is cached as
+    fn channel(&self, version: &str, channel: &str) -> (String, String) {
+        match &self.channel_url {
+            // Cache a manifest from a different url separately, so that it
+            // doesn't clobber, or get mistaken for, the one from `aka.ms`
+            Some(url) => (
+                url.clone(),
+                format!("manifest_{}.json", Sha256::digest(url.as_bytes())),
+            ),
+            None => (
+                format!("https://aka.ms/vs/{}/{}/channel", version, channel),
+                format!("manifest_{}.json", version),
+            ),
+        }
+    }
+}
+
+fn parse_manifest(manifest_bytes: &[u8]) -> Result<Manifest, anyhow::Error> {
+    let mut manifest: Manifest =
+        serde_json::from_slice(manifest_bytes).context("unable to parse manifest")?;
+    manifest.contents = manifest_bytes.to_vec();
+
+    if let Some(payload) = manifest
+        .channel_items
+        .iter()
+        .find(|ci| ci.kind == ItemKind::Manifest)
+        .and_then(|ci| ci.payloads.first())
+    {
+        tracing::debug!(
+            "channel manifest sha256 {}, package manifest {}",
+            Sha256::digest(manifest_bytes),
+            payload.url
+        );
+    }
+
+    Ok(manifest)
+}
+
+/// Reads a top-level manifest from disk, verifying it against the pinned
+/// checksum, if there is one
+pub fn read_manifest(path: &Path, pins: &Pins) -> Result<Manifest, crate::Error> {
+    let manifest_bytes =
+        std::fs::read(path).with_context(|| format!("failed to read path '{}'", path))?;
+
+    if let Some(expected) = &pins.channel_sha256 {
+        let actual = Sha256::digest(&manifest_bytes);
+        if actual != *expected {
+            return Err(anyhow::Error::from(crate::error::ChecksumMismatch {
+                expected: expected.clone(),
+                actual,
+            })
+            .context(format!(
+                "manifest in '{}' doesn't match the pinned checksum",
+                path
+            ))
+            .into());
+        }
+    }
+
+    Ok(parse_manifest(&manifest_bytes)
+        .with_context(|| format!("failed to deserialize manifest in '{}'", path))?)
 }
 
 /// Retrieves the top-level manifest which contains license links as well as the
@@ -113,26 +255,34 @@ pub fn get_manifest(
     ctx: &Ctx,
     version: &str,
     channel: &str,
-    progress: indicatif::ProgressBar,
-) -> Result<Manifest, anyhow::Error> {
-    let manifest_bytes = ctx.get_and_validate(
-        format!("https://aka.ms/vs/{}/{}/channel", version, channel),
-        &format!("manifest_{}.json", version),
-        None,
-        progress,
-    )?;
+    pins: &Pins,
+    progress: &dyn ProgressSink,
+) -> Result<Manifest, crate::Error> {
+    let (url, path) = pins.channel(version, channel);
+    let manifest_bytes = ctx.get_and_validate(url, &path, pins.channel_sha256.clone(), progress)?;
 
-    let manifest: Manifest = serde_json::from_slice(&manifest_bytes)?;
-
-    Ok(manifest)
+    Ok(parse_manifest(&manifest_bytes)?)
 }
 
-/// Retrieves the package manifest specified in the input manifest
-pub fn get_package_manifest(
+/// The async equivalent of [`get_manifest`]
+#[cfg(feature = "async")]
+pub async fn get_manifest_async(
     ctx: &Ctx,
-    manifest: &Manifest,
-    progress: indicatif::ProgressBar,
-) -> Result<PackageManifest, anyhow::Error> {
+    version: &str,
+    channel: &str,
+    pins: &Pins,
+    progress: &dyn ProgressSink,
+) -> Result<Manifest, crate::Error> {
+    let (url, path) = pins.channel(version, channel);
+    let manifest_bytes = ctx
+        .get_and_validate_async(url, &path, pins.channel_sha256.clone(), progress)
+        .await?;
+
+    Ok(parse_manifest(&manifest_bytes)?)
+}
+
+/// Locates the payload for the package manifest in the input manifest
+fn package_manifest_payload(manifest: &Manifest) -> Result<&Payload, anyhow::Error> {
     let pkg_manifest = manifest
         .channel_items
         .iter()
@@ -145,25 +295,19 @@ pub fn get_package_manifest(
         "VS package manifest should have exactly 1 payload"
     );
 
-    // While the payload includes a sha256 checksum for the payload it is actually
-    // never correct (even though it is part of the url!) so we have to just download
-    // it without checking, which is terrible but...¯\_(ツ)_/¯
-    let payload = &pkg_manifest.payloads[0];
-
-    let manifest_bytes = ctx.get_and_validate(
-        payload.url.clone(),
-        &format!("pkg_manifest_{}.vsman", payload.sha256),
-        None,
-        progress,
-    )?;
+    Ok(&pkg_manifest.payloads[0])
+}
 
+fn parse_package_manifest(manifest_bytes: &[u8]) -> Result<PackageManifest, anyhow::Error> {
     #[derive(Deserialize)]
     struct PkgManifest {
         packages: Vec<ManifestItem>,
     }
 
+    tracing::debug!("package manifest sha256 {}", Sha256::digest(manifest_bytes));
+
     let manifest: PkgManifest =
-        serde_json::from_slice(&manifest_bytes).context("unable to parse manifest")?;
+        serde_json::from_slice(manifest_bytes).context("unable to parse manifest")?;
 
     let mut packages = BTreeMap::new();
 
@@ -174,6 +318,208 @@ pub fn get_package_manifest(
     Ok(PackageManifest { packages })
 }
 
+/// Retrieves the package manifest specified in the input manifest
+pub fn get_package_manifest(
+    ctx: &Ctx,
+    manifest: &Manifest,
+    pins: &Pins,
+    progress: &dyn ProgressSink,
+) -> Result<PackageManifest, crate::Error> {
+    // While the payload includes a sha256 checksum for the payload it is actually
+    // never correct (even though it is part of the url!) so unless the package
+    // manifest is pinned we have to just download it without checking, which is
+    // terrible but...¯\_(ツ)_/¯
+    let payload = package_manifest_payload(manifest)?;
+
+    let manifest_bytes = ctx.get_and_validate(
+        payload.url.clone(),
+        &format!("pkg_manifest_{}.vsman", payload.sha256),
+        pins.package_sha256.clone(),
+        progress,
+    )?;
+
+    Ok(parse_package_manifest(&manifest_bytes)?)
+}
+
+/// The async equivalent of [`get_package_manifest`]
+#[cfg(feature = "async")]
+pub async fn get_package_manifest_async(
+    ctx: &Ctx,
+    manifest: &Manifest,
+    pins: &Pins,
+    progress: &dyn ProgressSink,
+) -> Result<PackageManifest, crate::Error> {
+    let payload = package_manifest_payload(manifest)?;
+
+    let manifest_bytes = ctx
+        .get_and_validate_async(
+            payload.url.clone(),
+            &format!("pkg_manifest_{}.vsman", payload.sha256),
+            pins.package_sha256.clone(),
+            progress,
+        )
+        .await?;
+
+    Ok(parse_package_manifest(&manifest_bytes)?)
+}
+
+/// Retrieves the channel manifest for the version and channel, and then the
+/// package manifest it links to, using the cache and client of the context
+pub fn get_package_manifest_for(
+    ctx: &Ctx,
+    version: &str,
+    channel: &str,
+    pins: &Pins,
+    progress: &dyn ProgressSink,
+) -> Result<PackageManifest, crate::Error> {
+    let manifest = get_manifest(ctx, version, channel, pins, progress)?;
+    get_package_manifest(ctx, &manifest, pins, progress)
+}
+
+/// Every package in a channel, keyed by id
+#[derive(Serialize, Debug, Clone, Default)]
 pub struct PackageManifest {
     pub packages: BTreeMap<String, ManifestItem>,
 }
+
+impl PackageManifest {
+    /// Parses a package manifest, ie. the `.vsman` the channel manifest links
+    /// to
+    pub fn parse(manifest_bytes: &[u8]) -> Result<Self, anyhow::Error> {
+        parse_package_manifest(manifest_bytes)
+    }
+
+    /// Gets the package with the id
+    pub fn get(&self, id: &str) -> Option<&ManifestItem> {
+        self.packages.get(id)
+    }
+
+    /// The packages whose ids match the pattern, where `*` matches any
+    /// sequence of characters, eg. `Microsoft.VC.*.CRT.Headers.base`
+    pub fn find<'pm>(&'pm self, pattern: &'pm str) -> impl Iterator<Item = &'pm ManifestItem> {
+        self.packages
+            .iter()
+            .filter(move |(id, _)| crate::util::glob(pattern, id))
+            .map(|(_, item)| item)
+    }
+
+    /// The versions of the Windows SDK in the manifest, oldest first
+    pub fn sdk_versions(&self) -> Vec<String> {
+        let mut versions: Vec<_> = self
+            .packages
+            .keys()
+            .filter_map(|id| crate::version::sdk_version(id))
+            .map(String::from)
+            .collect();
+        crate::version::sort(&mut versions);
+        versions
+    }
+
+    /// The versions of the MSVC CRT in the manifest, oldest first, as listed
+    /// by the dependencies of the build tools product
+    pub fn crt_versions(&self) -> Vec<String> {
+        let mut versions: Vec<_> = self
+            .get("Microsoft.VisualStudio.Product.BuildTools")
+            .into_iter()
+            .flat_map(|build_tools| build_tools.dependencies.keys())
+            .filter_map(|key| {
+                key.strip_prefix("Microsoft.VisualStudio.Component.VC.")
+                    .and_then(|s| s.strip_suffix(".x86.x64"))
+            })
+            .filter(|version| crate::version::is_version(version))
+            .map(String::from)
+            .collect();
+        crate::version::sort(&mut versions);
+        versions
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rejects_unpinned_manifest() {
+        let td = tempfile::TempDir::new().unwrap();
+        let path = crate::PathBuf::from_path_buf(td.path().join("channel.json")).unwrap();
+        let contents = br#"{"channelItems":[]}"#;
+        std::fs::write(&path, contents).unwrap();
+
+        let mut pins = Pins {
+            channel_sha256: Some(Sha256::digest(contents)),
+            ..Default::default()
+        };
+        assert!(read_manifest(&path, &pins).is_ok());
+
+        pins.channel_sha256 = Some(Sha256([0; 32]));
+        let err = read_manifest(&path, &pins).err().unwrap();
+        assert_eq!(err.kind(), Some(crate::error::ErrorKind::ChecksumMismatch));
+    }
+
+    #[test]
+    fn queries_package_manifest() {
+        let contents = br#"{"packages": [
+            {
+                "id": "Microsoft.VisualStudio.Product.BuildTools",
+                "version": "17.4.33103.184",
+                "type": "Product",
+                "dependencies": {
+                    "Microsoft.VisualStudio.Component.VC.14.34.17.4.x86.x64": "17.4.33006.217",
+                    "Microsoft.VisualStudio.Component.VC.14.29.16.11.x86.x64": "17.4.33006.217",
+                    "Microsoft.VisualStudio.Component.VC.Tools.x86.x64": "17.4.33006.217"
+                }
+            },
+            {
+                "id": "Win10SDK_10.0.22621",
+                "version": "10.0.22621.7",
+                "type": "Group"
+            },
+            {
+                "id": "Win10SDK_10.0.9200",
+                "version": "10.0.9200.1",
+                "type": "Group"
+            },
+            {
+                "id": "Microsoft.VC.14.34.17.4.CRT.Headers.base",
+                "version": "14.34.31933",
+                "type": "Vsix",
+                "chip": "neutral",
+                "installSizes": { "targetDrive": 4096 },
+                "payloads": [
+                    {
+                        "fileName": "Microsoft.VC.14.34.17.4.CRT.Headers.base.vsix",
+                        "sha256": "0101010101010101010101010101010101010101010101010101010101010101",
+                        "size": 1000,
+                        "url": "https://example.com/a.vsix"
+                    },
+                    {
+                        "fileName": "extra.cab",
+                        "sha256": "0202020202020202020202020202020202020202020202020202020202020202",
+                        "size": 24,
+                        "url": "https://example.com/extra.cab"
+                    }
+                ]
+            }
+        ]}"#;
+
+        let pkg_manifest = PackageManifest::parse(contents).unwrap();
+        assert_eq!(pkg_manifest.sdk_versions(), ["10.0.9200", "10.0.22621"]);
+        assert_eq!(pkg_manifest.crt_versions(), ["14.29.16.11", "14.34.17.4"]);
+
+        let headers: Vec<_> = pkg_manifest.find("Microsoft.VC.*.CRT.Headers.*").collect();
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers[0].download_size(), 1024);
+        assert_eq!(headers[0].install_size(), Some(4096));
+        assert_eq!(headers[0].chip, Some(Chip::Neutral));
+        assert!(pkg_manifest.get("Win10SDK_10.0.19041").is_none());
+
+        // Serializes with the same names it is deserialized from
+        let json = serde_json::to_value(headers[0]).unwrap();
+        assert_eq!(json["type"], "Vsix");
+        assert_eq!(
+            json["payloads"][1]["sha256"],
+            "0202020202020202020202020202020202020202020202020202020202020202"
+        );
+        assert_eq!(json["installSizes"]["targetDrive"], 4096);
+    }
+}
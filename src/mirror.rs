@@ -0,0 +1,278 @@
+//! Mirrors of the manifests and payloads selected by a run, for environments
+//! without access to the Microsoft CDN.
+//!
+//! A mirror is laid out the same as the download cache that `xwin serve`
+//! serves, so it can be served by any static file server, or read directly
+//! from disk with a `file://` url, and passed as the `--upstream` of other
+//! xwin instances.
+//!
+//! * `channel.json` - The channel manifest, to pass as `--manifest`
+//! * `dl/<path>` - The package manifest, and every payload and CAB file
+//! * `index.json` - The [`Index`] of everything in the mirror
+
+use crate::{download, manifest, util::Sha256, Ctx, Path, PathBuf};
+use anyhow::{Context as _, Error};
+use std::collections::BTreeMap;
+
+/// A file in the mirror
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MirrorFile {
+    /// The path of the file, relative to the mirror's `dl` directory
+    pub path: PathBuf,
+    /// The url the file was originally retrieved from
+    pub url: String,
+    #[serde(serialize_with = "crate::util::serialize_sha256")]
+    pub sha256: Sha256,
+    pub size: u64,
+}
+
+/// The contents of a mirror, written to its root as `index.json`
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct Index {
+    /// The sha256 of `channel.json`, to pass as `--manifest-sha256`
+    #[serde(serialize_with = "crate::util::serialize_sha256")]
+    pub channel_sha256: Sha256,
+    /// The sha256 of the package manifest, to pass as
+    /// `--package-manifest-sha256` so that it is retrieved from the mirror
+    #[serde(serialize_with = "crate::util::serialize_sha256")]
+    pub package_manifest_sha256: Sha256,
+    /// Every file in the mirror's `dl` directory, sorted by path
+    pub files: Vec<MirrorFile>,
+}
+
+impl Index {
+    pub const NAME: &'static str = "index.json";
+    /// The name of the channel manifest in the root of the mirror
+    pub const CHANNEL: &'static str = "channel.json";
+
+    /// The total size of the files in the mirror
+    pub fn size(&self) -> u64 {
+        self.files.iter().map(|file| file.size).sum()
+    }
+}
+
+/// Copies the manifests, and the payloads, along with any CAB files they
+/// need, from the download cache of the context to the mirror in `dir`.
+/// Everything must have already been downloaded, eg. with
+/// [`Ops::Download`](crate::Ops::Download). Files that are already present in
+/// the mirror with the right size are left as is, so a mirror can be updated
+/// by mirroring to it again.
+pub fn mirror(
+    ctx: &Ctx,
+    vs_manifest: &manifest::Manifest,
+    pkgs: &BTreeMap<String, manifest::ManifestItem>,
+    payloads: &[crate::Payload],
+    dir: &Path,
+) -> Result<Index, Error> {
+    let dl = ctx.work_dir.join("dl");
+    let mut files = Vec::new();
+
+    std::fs::create_dir_all(dir).with_context(|| format!("unable to create {}", dir))?;
+    let channel_path = dir.join(Index::CHANNEL);
+    std::fs::write(&channel_path, vs_manifest.contents())
+        .with_context(|| format!("unable to write {}", channel_path))?;
+
+    // The package manifest is cached by the checksum the channel lists for it,
+    // which never matches its actual contents
+    let pkg_manifest = vs_manifest.package_manifest()?;
+    let pkg_manifest_path = PathBuf::from(format!("pkg_manifest_{}.vsman", pkg_manifest.sha256));
+    let contents = std::fs::read(dl.join(&pkg_manifest_path))
+        .with_context(|| format!("unable to read cached {}", pkg_manifest_path))?;
+    let package_manifest_sha256 = Sha256::digest(&contents);
+    files.push(MirrorFile {
+        path: pkg_manifest_path,
+        url: pkg_manifest.url.clone(),
+        sha256: package_manifest_sha256.clone(),
+        size: contents.len() as u64,
+    });
+
+    for payload in payloads {
+        files.push(MirrorFile {
+            path: payload.filename.clone(),
+            url: payload.url.clone(),
+            sha256: payload.sha256.clone(),
+            size: payload.size,
+        });
+
+        if payload.extra.is_some() || payload.filename.extension() != Some("msi") {
+            continue;
+        }
+
+        // Only the CABs the MSI actually references are downloaded, which are
+        // cached next to it in a directory named after it
+        let stem = payload.filename.file_stem().unwrap_or_default();
+        for cab in download::cabs(pkgs, payload)? {
+            let path = PathBuf::from(stem).join(&cab.filename);
+            if dl.join(&path).exists() {
+                files.push(MirrorFile {
+                    path,
+                    url: cab.url,
+                    sha256: cab.sha256,
+                    size: cab.size,
+                });
+            }
+        }
+    }
+
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    files.dedup_by(|a, b| a.path == b.path);
+
+    for file in &files {
+        let src = dl.join(&file.path);
+        let dst = dir.join("dl").join(&file.path);
+
+        if std::fs::metadata(&dst).map(|md| md.len()).ok() == Some(file.size) {
+            continue;
+        }
+
+        if let Some(parent) = dst.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::copy(&src, &dst).with_context(|| format!("unable to copy {} to {}", src, dst))?;
+    }
+
+    let index = Index {
+        channel_sha256: Sha256::digest(vs_manifest.contents()),
+        package_manifest_sha256,
+        files,
+    };
+
+    let index_path = dir.join(Index::NAME);
+    std::fs::write(&index_path, serde_json::to_vec_pretty(&index)?)
+        .with_context(|| format!("unable to write {}", index_path))?;
+
+    Ok(index)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mirrors_payloads() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        let pkg_manifest = br#"{"packages":[]}"#;
+        let msi = b"not really an msi";
+        let cab = b"not really a cab";
+
+        let channel = format!(
+            r#"{{"channelItems": [{{
+                "id": "Microsoft.VisualStudio.Manifests.VisualStudio",
+                "version": "17.4.33103.184",
+                "type": "Manifest",
+                "payloads": [{{
+                    "fileName": "VisualStudio.vsman",
+                    "sha256": "{}",
+                    "size": 1,
+                    "url": "https://example.com/VisualStudio.vsman"
+                }}]
+            }}]}}"#,
+            Sha256([1; 32])
+        );
+        let vs_manifest = manifest::Manifest::parse(channel.as_bytes()).unwrap();
+
+        let pkgs = manifest::PackageManifest::parse(
+            format!(
+                r#"{{"packages": [{{
+                    "id": "Win10SDK_10.0.22621",
+                    "version": "10.0.22621.7",
+                    "type": "Msi",
+                    "payloads": [
+                        {{
+                            "fileName": "Installers\\headers.msi",
+                            "sha256": "{}",
+                            "size": {},
+                            "url": "https://example.com/headers.msi"
+                        }},
+                        {{
+                            "fileName": "Installers\\a.cab",
+                            "sha256": "{}",
+                            "size": {},
+                            "url": "https://example.com/a.cab"
+                        }},
+                        {{
+                            "fileName": "Installers\\unused.cab",
+                            "sha256": "{}",
+                            "size": 1,
+                            "url": "https://example.com/unused.cab"
+                        }}
+                    ]
+                }}]}}"#,
+                Sha256::digest(msi),
+                msi.len(),
+                Sha256::digest(cab),
+                cab.len(),
+                Sha256([2; 32]),
+            )
+            .as_bytes(),
+        )
+        .unwrap()
+        .packages;
+
+        let ctx = Ctx::with_dir(root.join("cache"), crate::progress::Hidden).unwrap();
+        let dl = root.join("cache/dl");
+        std::fs::write(
+            dl.join(format!("pkg_manifest_{}.vsman", Sha256([1; 32]))),
+            pkg_manifest,
+        )
+        .unwrap();
+        std::fs::write(dl.join("headers.msi"), msi).unwrap();
+        std::fs::create_dir_all(dl.join("headers")).unwrap();
+        std::fs::write(dl.join("headers/a.cab"), cab).unwrap();
+
+        let payload = crate::Payload {
+            version: "10.0.22621.7".to_owned(),
+            sha256: Sha256::digest(msi),
+            size: msi.len() as u64,
+            ..crate::Payload::test("headers.msi", crate::PayloadKind::SdkHeaders)
+        };
+
+        let dir = root.join("mirror");
+        let index = mirror(&ctx, &vs_manifest, &pkgs, &[payload], &dir).unwrap();
+
+        let paths: Vec<_> = index.files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "headers/a.cab",
+                "headers.msi",
+                &format!("pkg_manifest_{}.vsman", Sha256([1; 32])),
+            ]
+        );
+        assert_eq!(index.package_manifest_sha256, Sha256::digest(pkg_manifest));
+        assert_eq!(std::fs::read(dir.join("dl/headers/a.cab")).unwrap(), cab);
+        assert_eq!(
+            std::fs::read(dir.join(Index::CHANNEL)).unwrap(),
+            channel.as_bytes()
+        );
+
+        // Another instance can retrieve everything from the mirror without
+        // the network
+        let mut ctx = Ctx::with_dir(root.join("offline"), crate::progress::Hidden).unwrap();
+        ctx.upstream = Some(format!("file://{}", dir));
+        ctx.no_network = true;
+        let contents = ctx
+            .get_and_validate(
+                "https://example.com/a.cab",
+                &PathBuf::from("headers/a.cab"),
+                Some(Sha256::digest(cab)),
+                &crate::progress::Hidden,
+            )
+            .unwrap();
+        assert_eq!(&contents[..], cab);
+
+        // But only if it matches the checksum
+        let err = ctx
+            .get_and_validate(
+                "https://example.com/a.cab",
+                &PathBuf::from("headers/a.cab"),
+                Some(Sha256([3; 32])),
+                &crate::progress::Hidden,
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), Some(crate::error::ErrorKind::NetworkDisabled));
+    }
+}
@@ -0,0 +1,243 @@
+//! Support for packaging xwin output with Nix, which requires the hash of the
+//! output tree, as well as the url and hash of every input, to be known ahead
+//! of time for a fixed-output derivation
+
+use crate::{manifest::ManifestItem, util::Sha256, Path, Payload};
+use anyhow::{Context as _, Error};
+use std::{collections::BTreeMap, io::Write};
+
+/// A file that is downloaded to produce the output
+#[derive(serde::Serialize, Debug)]
+pub struct Input {
+    pub name: String,
+    pub url: String,
+    /// The SRI hash of the file, eg. `sha256-<base64>`
+    pub hash: String,
+}
+
+/// Formats the checksum as an SRI hash, as used by Nix
+pub fn to_sri(hash: &Sha256) -> String {
+    format!("sha256-{}", base64::encode(hash.0))
+}
+
+/// Parses an SRI hash, only `sha256` is supported
+pub fn from_sri(sri: &str) -> Result<Sha256, Error> {
+    let b64 = sri
+        .strip_prefix("sha256-")
+        .with_context(|| format!("'{}' is not a sha256 SRI hash", sri))?;
+    let bytes = base64::decode(b64).with_context(|| format!("invalid SRI hash '{}'", sri))?;
+
+    anyhow::ensure!(
+        bytes.len() == 32,
+        "SRI hash '{}' is {} bytes instead of 32",
+        sri,
+        bytes.len()
+    );
+
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&bytes);
+    Ok(Sha256(hash))
+}
+
+/// Gathers every file that needs to be downloaded for the payloads, including
+/// the CABs for MSI payloads, in the order they are downloaded
+pub fn inputs(
+    payloads: &[Payload],
+    pkgs: &BTreeMap<String, ManifestItem>,
+) -> Result<Vec<Input>, Error> {
+    let mut inputs = Vec::new();
+    let mut seen = std::collections::BTreeSet::new();
+
+    for payload in payloads {
+        if seen.insert(payload.url.clone()) {
+            inputs.push(Input {
+                name: payload.filename.to_string(),
+                url: payload.url.clone(),
+                hash: to_sri(&payload.sha256),
+            });
+        }
+
+        if payload.filename.extension() != Some("msi") {
+            continue;
+        }
+
+        for cab in crate::download::cabs(pkgs, payload)? {
+            if seen.insert(cab.url.clone()) {
+                inputs.push(Input {
+                    name: cab.filename.into_string(),
+                    url: cab.url,
+                    hash: to_sri(&cab.sha256),
+                });
+            }
+        }
+    }
+
+    Ok(inputs)
+}
+
+/// Serializes a file system tree in the Nix Archive (NAR) format, which is
+/// what Nix hashes for fixed-output derivations using recursive hashing
+struct Nar<W> {
+    w: W,
+}
+
+impl<W: Write> Nar<W> {
+    fn str(&mut self, s: &[u8]) -> Result<(), Error> {
+        self.w.write_all(&(s.len() as u64).to_le_bytes())?;
+        self.w.write_all(s)?;
+        // Every string is padded to a multiple of 8 bytes
+        self.w.write_all(&[0u8; 8][..(8 - s.len() % 8) % 8])?;
+        Ok(())
+    }
+
+    fn archive(&mut self, path: &Path) -> Result<(), Error> {
+        self.str(b"nix-archive-1")?;
+        self.node(path)
+    }
+
+    fn node(&mut self, path: &Path) -> Result<(), Error> {
+        let md = std::fs::symlink_metadata(path)
+            .with_context(|| format!("unable to read metadata for {}", path))?;
+
+        self.str(b"(")?;
+        self.str(b"type")?;
+
+        if md.file_type().is_symlink() {
+            let target = std::fs::read_link(path)
+                .with_context(|| format!("unable to read symlink {}", path))?;
+            let target = target
+                .to_str()
+                .with_context(|| format!("symlink {} has a non utf-8 target", path))?;
+
+            self.str(b"symlink")?;
+            self.str(b"target")?;
+            self.str(target.as_bytes())?;
+        } else if md.is_dir() {
+            self.str(b"directory")?;
+
+            let mut names = Vec::new();
+            for entry in
+                std::fs::read_dir(path).with_context(|| format!("unable to read {}", path))?
+            {
+                let entry = entry?;
+                names.push(entry.file_name().into_string().map_err(|name| {
+                    anyhow::anyhow!("{} contains non utf-8 entry {:?}", path, name)
+                })?);
+            }
+
+            // Entries are sorted by the bytes of their name
+            names.sort();
+
+            for name in names {
+                self.str(b"entry")?;
+                self.str(b"(")?;
+                self.str(b"name")?;
+                self.str(name.as_bytes())?;
+                self.str(b"node")?;
+                self.node(&path.join(&name))?;
+                self.str(b")")?;
+            }
+        } else {
+            self.str(b"regular")?;
+
+            if is_executable(&md) {
+                self.str(b"executable")?;
+                self.str(b"")?;
+            }
+
+            let contents =
+                std::fs::read(path).with_context(|| format!("unable to read {}", path))?;
+            self.str(b"contents")?;
+            self.str(&contents)?;
+        }
+
+        self.str(b")")
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(md: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    md.permissions().mode() & 0o100 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_md: &std::fs::Metadata) -> bool {
+    false
+}
+
+struct Hasher(ring::digest::Context);
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Computes the hash of the NAR serialization of the tree at `root`, ie. the
+/// hash Nix expects as the `outputHash` of a fixed-output derivation with
+/// `outputHashMode = "recursive"`
+pub fn nar_hash(root: &Path) -> Result<Sha256, Error> {
+    let mut nar = Nar {
+        w: Hasher(ring::digest::Context::new(&ring::digest::SHA256)),
+    };
+    nar.archive(root)?;
+
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(nar.w.0.finish().as_ref());
+    Ok(Sha256(hash))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn serializes() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = crate::PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        std::fs::create_dir(root.join("d")).unwrap();
+        std::fs::write(root.join("d/a.h"), "hi").unwrap();
+
+        let mut nar = Nar { w: Vec::new() };
+        nar.archive(&root.join("d")).unwrap();
+
+        let mut expected = Vec::new();
+        for s in [
+            "nix-archive-1",
+            "(",
+            "type",
+            "directory",
+            "entry",
+            "(",
+            "name",
+            "a.h",
+            "node",
+            "(",
+            "type",
+            "regular",
+            "contents",
+            "hi",
+            ")",
+            ")",
+            ")",
+        ]
+        .iter()
+        {
+            expected.extend_from_slice(&(s.len() as u64).to_le_bytes());
+            expected.extend_from_slice(s.as_bytes());
+            expected.resize(expected.len() + (8 - s.len() % 8) % 8, 0);
+        }
+
+        assert_eq!(nar.w, expected);
+
+        let hash = nar_hash(&root.join("d")).unwrap();
+        assert_eq!(hash, Sha256::digest(&expected));
+        assert_eq!(from_sri(&to_sri(&hash)).unwrap(), hash);
+    }
+}
@@ -0,0 +1,504 @@
+//! Application of user supplied patches to the output once it has been
+//! splatted and finalized, eg. for one-line workarounds in SDK headers that
+//! clang doesn't like, rather than editing the output in wrapper scripts.
+//!
+//! A patches directory contains unified diffs, ie. `*.patch` or `*.diff`
+//! files, which are applied in order of their name with paths relative to the
+//! root of the output, and an optional `files` directory whose contents
+//! replace the files at the same relative paths in the output.
+
+use crate::{util::Sha256, Path, PathBuf};
+use anyhow::{Context as _, Error};
+
+/// A file that was changed by a patch
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PatchedFile {
+    /// The path of the file, relative to the output
+    pub path: PathBuf,
+    /// The checksum of the file once patched
+    #[serde(serialize_with = "crate::util::serialize_sha256")]
+    pub sha256: Sha256,
+}
+
+/// A patch, or file override, that was applied to the output
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AppliedPatch {
+    /// The path of the patch, relative to the patches directory
+    pub name: String,
+    /// The files the patch changed or created
+    #[serde(default)]
+    pub files: Vec<PatchedFile>,
+    /// The files the patch deleted, relative to the output
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<PathBuf>,
+}
+
+/// A single hunk of a unified diff
+struct Hunk<'p> {
+    old_start: usize,
+    /// The lines of the hunk, prefixed by one of ` `, `-`, or `+`
+    lines: Vec<(u8, &'p [u8])>,
+    /// Set if the last new line doesn't end in a newline
+    no_eol: bool,
+}
+
+/// The changes to a single file in a unified diff
+struct FilePatch<'p> {
+    /// The path of the original file, `None` if the file is created
+    old: Option<String>,
+    /// The path of the patched file, `None` if the file is deleted
+    new: Option<String>,
+    hunks: Vec<Hunk<'p>>,
+}
+
+/// Parses the path in a `---` or `+++` header, stripping the `a/` or `b/`
+/// prefixes added by git and anything after a tab, eg. a timestamp
+fn header_path(line: &[u8]) -> Result<Option<String>, Error> {
+    let line = std::str::from_utf8(line).context("patch contains a non utf-8 path")?;
+    let path = line.split('\t').next().unwrap_or_default().trim_end();
+
+    if path == "/dev/null" {
+        return Ok(None);
+    }
+
+    let path = path
+        .strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path);
+    Ok(Some(path.to_owned()))
+}
+
+/// Parses the start of the old range, and the number of old and new lines,
+/// from a hunk header, eg. `@@ -12,7 +12,8 @@`
+fn hunk_range(line: &[u8]) -> Option<(usize, usize, usize)> {
+    let line = std::str::from_utf8(line).ok()?;
+    let mut ranges = line.strip_prefix("@@ -")?.split(' ');
+
+    let range = |range: &str| -> Option<(usize, usize)> {
+        let mut parts = range.split(',');
+        let start = parts.next()?.parse().ok()?;
+        let len = parts.next().map_or(Some(1), |len| len.parse().ok())?;
+        Some((start, len))
+    };
+
+    let (old_start, old_len) = range(ranges.next()?)?;
+    let (_, new_len) = range(ranges.next()?.strip_prefix('+')?)?;
+    Some((old_start, old_len, new_len))
+}
+
+fn parse(patch: &[u8]) -> Result<Vec<FilePatch<'_>>, Error> {
+    let mut files = Vec::new();
+    let mut lines = patch.split(|b| *b == b'\n').map(trim_eol).peekable();
+
+    while let Some(line) = lines.next() {
+        let old = match line.strip_prefix(b"--- ") {
+            Some(old) => header_path(old)?,
+            // Anything outside of a file's changes, eg. a commit message, is ignored
+            None => continue,
+        };
+
+        let new = match lines.next().and_then(|line| line.strip_prefix(b"+++ ")) {
+            Some(new) => header_path(new)?,
+            None => anyhow::bail!("expected a '+++' line following '--- {:?}'", old),
+        };
+
+        let mut fp = FilePatch {
+            old,
+            new,
+            hunks: Vec::new(),
+        };
+
+        while let Some(header) = lines.peek().filter(|line| line.starts_with(b"@@ ")) {
+            let (old_start, mut old_len, mut new_len) = hunk_range(header).with_context(|| {
+                format!("invalid hunk header '{}'", String::from_utf8_lossy(header))
+            })?;
+            lines.next();
+
+            let mut hunk = Hunk {
+                old_start,
+                lines: Vec::new(),
+                no_eol: false,
+            };
+
+            while old_len > 0 || new_len > 0 {
+                let line = lines.next().context("hunk is shorter than its header")?;
+
+                let (kind, line) = match line.split_first() {
+                    Some((kind @ (b' ' | b'-' | b'+'), line)) => (*kind, line),
+                    Some((b'\\', _)) => continue,
+                    // Some editors strip the trailing space of empty context lines
+                    None => (b' ', line),
+                    Some(_) => anyhow::bail!(
+                        "unexpected line in hunk '{}'",
+                        String::from_utf8_lossy(line)
+                    ),
+                };
+
+                if kind != b'+' {
+                    old_len = old_len
+                        .checked_sub(1)
+                        .context("hunk is longer than its header")?;
+                }
+                if kind != b'-' {
+                    new_len = new_len
+                        .checked_sub(1)
+                        .context("hunk is longer than its header")?;
+                }
+
+                hunk.lines.push((kind, line));
+            }
+
+            // The last line of the file doesn't end in a newline
+            if lines
+                .peek()
+                .filter(|line| line.starts_with(b"\\"))
+                .is_some()
+            {
+                lines.next();
+                hunk.no_eol = hunk.lines.last().map(|(kind, _)| *kind) != Some(b'-');
+            }
+
+            fp.hunks.push(hunk);
+        }
+
+        files.push(fp);
+    }
+
+    Ok(files)
+}
+
+fn trim_eol(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// Finds the position closest to `start` where `needle` matches the lines
+fn find(lines: &[Vec<u8>], needle: &[&[u8]], start: usize) -> Option<usize> {
+    let matches = |pos: usize| {
+        pos + needle.len() <= lines.len()
+            && needle.iter().zip(&lines[pos..]).all(|(expected, line)| {
+                *expected == trim_eol(line.strip_suffix(b"\n").unwrap_or(line))
+            })
+    };
+
+    (0..=lines.len()).find_map(|distance| {
+        start
+            .checked_sub(distance)
+            .filter(|pos| matches(*pos))
+            .or_else(|| Some(start + distance).filter(|pos| matches(*pos)))
+    })
+}
+
+/// Applies the hunks to the contents of a file. Hunks that have already been
+/// applied are skipped, so that applying the same patch twice is harmless.
+fn apply_hunks(contents: &[u8], hunks: &[Hunk<'_>], path: &str) -> Result<Vec<u8>, Error> {
+    let mut lines: Vec<Vec<u8>> = contents
+        .split_inclusive(|b| *b == b'\n')
+        .map(|line| line.to_vec())
+        .collect();
+
+    // Added lines use the same line endings as the rest of the file, as many
+    // of the SDK headers use CRLF
+    let eol: &[u8] = match lines.first() {
+        Some(line) if line.ends_with(b"\r\n") => b"\r\n",
+        _ => b"\n",
+    };
+
+    let mut offset = 0isize;
+
+    for (i, hunk) in hunks.iter().enumerate() {
+        let old: Vec<&[u8]> = hunk
+            .lines
+            .iter()
+            .filter(|(kind, _)| *kind != b'+')
+            .map(|(_, line)| *line)
+            .collect();
+        let new: Vec<&[u8]> = hunk
+            .lines
+            .iter()
+            .filter(|(kind, _)| *kind != b'-')
+            .map(|(_, line)| *line)
+            .collect();
+
+        let start = (hunk.old_start.saturating_sub(1) as isize + offset).max(0) as usize;
+
+        let pos = match find(&lines, &old, start) {
+            Some(pos) => pos,
+            None if find(&lines, &new, start).is_some() => {
+                tracing::debug!("hunk {} of {} is already applied", i + 1, path);
+                continue;
+            }
+            None => anyhow::bail!("hunk {} of {} doesn't apply", i + 1, path),
+        };
+
+        let replacement: Vec<Vec<u8>> = new
+            .iter()
+            .enumerate()
+            .map(|(n, line)| {
+                let mut line = line.to_vec();
+                if !(hunk.no_eol && n + 1 == new.len()) {
+                    line.extend_from_slice(eol);
+                }
+                line
+            })
+            .collect();
+
+        offset += replacement.len() as isize - old.len() as isize;
+        lines.splice(pos..pos + old.len(), replacement);
+    }
+
+    Ok(lines.concat())
+}
+
+/// Resolves a path in a patch relative to the output, as long as it doesn't
+/// escape it
+fn resolve(output: &Path, path: &str) -> Result<PathBuf, Error> {
+    let rel = Path::new(path);
+    anyhow::ensure!(
+        rel.components()
+            .all(|comp| matches!(comp, camino::Utf8Component::Normal(_))),
+        "patch path '{}' must be relative to the output, and not contain '..'",
+        path
+    );
+    Ok(output.join(rel))
+}
+
+/// Applies a unified diff to the output
+pub fn apply(name: &str, patch: &[u8], output: &Path) -> Result<AppliedPatch, Error> {
+    apply_to(name, patch, output, |_| true)
+}
+
+/// Applies only the changes of a unified diff to the files, relative to the
+/// output, that match the predicate
+fn apply_to(
+    name: &str,
+    patch: &[u8],
+    output: &Path,
+    matches: impl Fn(&Path) -> bool,
+) -> Result<AppliedPatch, Error> {
+    let mut applied = AppliedPatch {
+        name: name.to_owned(),
+        files: Vec::new(),
+        removed: Vec::new(),
+    };
+
+    for fp in parse(patch).with_context(|| format!("failed to parse patch {}", name))? {
+        let rel = fp
+            .new
+            .as_deref()
+            .or(fp.old.as_deref())
+            .with_context(|| format!("patch {} has a file without a path", name))?;
+        if !matches(Path::new(rel)) {
+            continue;
+        }
+        let path = resolve(output, rel)?;
+
+        let contents = match (&fp.old, path.exists()) {
+            (Some(_), true) => {
+                std::fs::read(&path).with_context(|| format!("unable to read {}", path))?
+            }
+            (Some(_), false) if fp.new.is_none() => {
+                // Already deleted
+                applied.removed.push(rel.into());
+                continue;
+            }
+            (Some(_), false) => anyhow::bail!("{} patches {}, which doesn't exist", name, rel),
+            // Created files are always patched from nothing, so that creating
+            // a file that was already created is detected below
+            (None, _) => Vec::new(),
+        };
+
+        let patched = apply_hunks(&contents, &fp.hunks, rel)
+            .with_context(|| format!("failed to apply patch {}", name))?;
+
+        if fp.new.is_none() {
+            anyhow::ensure!(
+                patched.is_empty(),
+                "{} deletes {}, but it has other contents",
+                name,
+                rel
+            );
+            std::fs::remove_file(&path).with_context(|| format!("unable to remove {}", path))?;
+            applied.removed.push(rel.into());
+            continue;
+        }
+
+        if fp.old.is_none() && path.exists() {
+            let existing =
+                std::fs::read(&path).with_context(|| format!("unable to read {}", path))?;
+            anyhow::ensure!(
+                existing == patched,
+                "{} creates {}, which already exists",
+                name,
+                rel
+            );
+        } else if patched != contents {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("unable to create {}", parent))?;
+            }
+            std::fs::write(&path, &patched).with_context(|| format!("unable to write {}", path))?;
+        }
+
+        applied.files.push(PatchedFile {
+            path: rel.into(),
+            sha256: Sha256::digest(&patched),
+        });
+    }
+
+    Ok(applied)
+}
+
+/// Applies the patches, and file overrides, in the directory to the output
+pub fn apply_dir(patches: &Path, output: &Path) -> Result<Vec<AppliedPatch>, Error> {
+    anyhow::ensure!(
+        patches.is_dir(),
+        "patches directory {} doesn't exist",
+        patches
+    );
+
+    let mut diffs = Vec::new();
+    for entry in
+        std::fs::read_dir(patches).with_context(|| format!("unable to read {}", patches))?
+    {
+        let entry = entry.with_context(|| format!("unable to read entry from {}", patches))?;
+        let path = PathBuf::from_path_buf(entry.path())
+            .map_err(|pb| anyhow::anyhow!("path {} is not a valid utf-8 path", pb.display()))?;
+
+        if path.is_file() && matches!(path.extension(), Some("patch" | "diff")) {
+            diffs.push(path);
+        }
+    }
+    diffs.sort();
+
+    let mut applied = Vec::new();
+
+    for path in diffs {
+        let name = path.file_name().unwrap_or_default();
+        let patch = std::fs::read(&path).with_context(|| format!("unable to read {}", path))?;
+
+        tracing::debug!("applying patch {}", name);
+        applied.push(apply(name, &patch, output)?);
+    }
+
+    let files = patches.join("files");
+    if files.is_dir() {
+        for path in crate::util::walk_files(&files)? {
+            let rel = path.strip_prefix(&files)?;
+            let contents =
+                std::fs::read(&path).with_context(|| format!("unable to read {}", path))?;
+
+            let target = resolve(output, rel.as_str())?;
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("unable to create {}", parent))?;
+            }
+            std::fs::write(&target, &contents)
+                .with_context(|| format!("unable to write {}", target))?;
+
+            tracing::debug!("overrode {}", rel);
+            applied.push(AppliedPatch {
+                name: format!("files/{}", rel),
+                files: vec![PatchedFile {
+                    path: rel.to_owned(),
+                    sha256: Sha256::digest(&contents),
+                }],
+                removed: Vec::new(),
+            });
+        }
+    }
+
+    Ok(applied)
+}
+
+/// Applies a patch, or file override, from the patches directory again, but
+/// only to the files, relative to the output, that were replaced since it was
+/// first applied, eg. by `xwin update` splatting their payload again
+pub fn reapply(
+    patches: &Path,
+    name: &str,
+    replaced: &std::collections::BTreeSet<&Path>,
+    output: &Path,
+) -> Result<AppliedPatch, Error> {
+    if let Some(rel) = name.strip_prefix("files/") {
+        let path = patches.join(name);
+        let contents = std::fs::read(&path).with_context(|| format!("unable to read {}", path))?;
+        let target = resolve(output, rel)?;
+        std::fs::write(&target, &contents)
+            .with_context(|| format!("unable to write {}", target))?;
+
+        return Ok(AppliedPatch {
+            name: name.to_owned(),
+            files: vec![PatchedFile {
+                path: rel.into(),
+                sha256: Sha256::digest(&contents),
+            }],
+            removed: Vec::new(),
+        });
+    }
+
+    let path = patches.join(name);
+    let patch = std::fs::read(&path).with_context(|| format!("unable to read {}", path))?;
+    apply_to(name, &patch, output, |rel| replaced.contains(rel))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn applies_patches() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let output = root.join("splat");
+        let patches = root.join("patches");
+
+        std::fs::create_dir_all(output.join("sdk/include/um")).unwrap();
+        std::fs::create_dir_all(patches.join("files/sdk/include/shared")).unwrap();
+
+        std::fs::write(
+            output.join("sdk/include/um/intsafe.h"),
+            "#pragma once\r\n\r\n#if defined(__clang__)\r\n#define BROKEN 1\r\n#endif\r\n\r\nint a;\r\n",
+        )
+        .unwrap();
+        std::fs::write(output.join("sdk/include/um/gone.h"), "gone\n").unwrap();
+
+        std::fs::write(
+            patches.join("01-intsafe.patch"),
+            "intsafe workaround\n\n\
+             --- a/sdk/include/um/intsafe.h\n\
+             +++ b/sdk/include/um/intsafe.h\n\
+             @@ -2,4 +2,4 @@\n \n #if defined(__clang__)\n-#define BROKEN 1\n+#define BROKEN 0\n #endif\n\
+             --- a/sdk/include/um/gone.h\n\
+             +++ /dev/null\n\
+             @@ -1 +0,0 @@\n-gone\n",
+        )
+        .unwrap();
+        std::fs::write(
+            patches.join("files/sdk/include/shared/extra.h"),
+            "#define EXTRA\n",
+        )
+        .unwrap();
+
+        let applied = apply_dir(&patches, &output).unwrap();
+        assert_eq!(applied.len(), 2);
+        assert_eq!(applied[0].removed, ["sdk/include/um/gone.h"]);
+        assert_eq!(applied[1].name, "files/sdk/include/shared/extra.h");
+
+        let patched = "#pragma once\r\n\r\n#if defined(__clang__)\r\n#define BROKEN 0\r\n#endif\r\n\r\nint a;\r\n";
+        assert_eq!(
+            std::fs::read_to_string(output.join("sdk/include/um/intsafe.h")).unwrap(),
+            patched
+        );
+        assert_eq!(
+            applied[0].files[0].sha256,
+            Sha256::digest(patched.as_bytes())
+        );
+        assert!(!output.join("sdk/include/um/gone.h").exists());
+
+        // Applying the patches again is harmless
+        assert_eq!(apply_dir(&patches, &output).unwrap(), applied);
+
+        std::fs::write(output.join("sdk/include/um/intsafe.h"), "changed\n").unwrap();
+        assert!(apply_dir(&patches, &output).is_err());
+    }
+}
@@ -0,0 +1,873 @@
+//! Retrieval of the PDBs matching the DLLs and executables in a splat output
+//! from a symbol server, such as the public Microsoft one, for the analysis of
+//! crashes in binaries that were cross built against it.
+//!
+//! Each image records the PDB it was built with in the `RSDS` entry of its
+//! debug directory, as the PDB's name along with a GUID and age that identify
+//! the exact build of it. Symbol servers, and the stores they serve, lay out
+//! PDBs by those, ie. `<name>/<GUID><age>/<name>`, eg.
+//! `ucrtbase.pdb/6E3A9D1AE3DE0D7C3E7D3F3B5B6AEB3A1/ucrtbase.pdb`, which is
+//! the layout the PDBs are stored in as well, so that the store can be passed
+//! directly to debuggers and symbolizers, eg. as `srv*<store>`.
+//!
+//! Note that import libraries don't have PDBs of their own, only the DLLs
+//! they import from do, so they are matched to the PDBs of those DLLs, which
+//! need to be in the output or one of the image directories passed to
+//! [`fetch`], as the identity of a PDB is only recorded in the image.
+//!
+//! The PDBs that are kept by a splat can also be stored in the same layout,
+//! see [`SplatConfig::symbol_server_layout`](crate::SplatConfig::symbol_server_layout),
+//! in which case their identity is read from the PDBs themselves.
+
+use crate::{
+    ar::{u16_at, u32_at},
+    http::HttpClient,
+    Path, PathBuf,
+};
+use anyhow::{Context as _, Error};
+use rayon::prelude::*;
+use std::io::Read as _;
+
+/// The public Microsoft symbol server
+pub const MICROSOFT_SYMBOL_SERVER: &str = "https://msdl.microsoft.com/download/symbols";
+
+/// The directory of the output the kept PDBs are stored in with
+/// [`SplatConfig::symbol_server_layout`](crate::SplatConfig::symbol_server_layout)
+pub const SYMBOL_STORE_DIR: &str = "symbols";
+
+const IMAGE_DEBUG_TYPE_CODEVIEW: u32 = 2;
+const MSF_MAGIC: &[u8] = b"Microsoft C/C++ MSF 7.00\r\n\x1aDS\0\0\0";
+/// The stream with the PDB's GUID
+const PDB_INFO_STREAM: usize = 1;
+/// The stream with the age that images record, which can be lower than the
+/// one in the info stream
+const DBI_STREAM: usize = 3;
+const SECTION_HEADER_LEN: usize = 40;
+const DEBUG_DIRECTORY_LEN: usize = 28;
+
+/// Identifies a specific build of a PDB
+#[derive(serde::Serialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PdbId {
+    /// The file name of the PDB, eg. `ucrtbase.pdb`
+    pub name: String,
+    #[serde(serialize_with = "serialize_guid")]
+    pub guid: [u8; 16],
+    pub age: u32,
+}
+
+fn serialize_guid<S: serde::Serializer>(guid: &[u8; 16], s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&format_guid(guid))
+}
+
+/// Formats the GUID as the symbol server does, ie. as uppercase hex without
+/// separators, with the first 3 fields in their native little endian order
+fn format_guid(guid: &[u8; 16]) -> String {
+    use std::fmt::Write as _;
+
+    let mut s = String::with_capacity(32);
+    let _ = write!(
+        s,
+        "{:08X}{:04X}{:04X}",
+        u32::from_le_bytes([guid[0], guid[1], guid[2], guid[3]]),
+        u16::from_le_bytes([guid[4], guid[5]]),
+        u16::from_le_bytes([guid[6], guid[7]]),
+    );
+    for b in &guid[8..] {
+        let _ = write!(s, "{:02X}", b);
+    }
+    s
+}
+
+impl PdbId {
+    /// The key of the PDB in a symbol server, ie. its GUID followed by its
+    /// age in hex
+    pub fn key(&self) -> String {
+        format!("{}{:X}", format_guid(&self.guid), self.age)
+    }
+
+    /// The path of the PDB in a symbol server or store, ie.
+    /// `<name>/<key>/<name>`
+    pub fn store_path(&self) -> PathBuf {
+        let mut path = PathBuf::from(&self.name);
+        path.push(self.key());
+        path.push(&self.name);
+        path
+    }
+}
+
+/// Reads the identity of the PDB from the `RSDS` entry in the debug
+/// directory of a PE image, returning `None` if it doesn't have one
+pub fn image_pdb(image: &[u8]) -> Result<Option<PdbId>, Error> {
+    anyhow::ensure!(image.starts_with(b"MZ"), "not a PE image");
+    let pe = u32_at(image, 0x3c).context("truncated DOS header")? as usize;
+    anyhow::ensure!(
+        image.get(pe..pe + 4) == Some(b"PE\0\0"),
+        "missing PE signature"
+    );
+
+    let coff = pe + 4;
+    let sections = u16_at(image, coff + 2).context("truncated COFF header")? as usize;
+    let optional_len = u16_at(image, coff + 16).context("truncated COFF header")? as usize;
+    let optional = coff + 20;
+
+    // The data directories follow the fixed fields of the optional header,
+    // which are larger for PE32+
+    let (count, dirs) = match u16_at(image, optional).context("truncated optional header")? {
+        0x10b => (optional + 92, optional + 96),
+        0x20b => (optional + 108, optional + 112),
+        magic => anyhow::bail!("unknown optional header magic {:#x}", magic),
+    };
+    if u32_at(image, count).context("truncated optional header")? <= 6 {
+        return Ok(None);
+    }
+
+    let debug_rva = u32_at(image, dirs + 6 * 8).context("truncated data directories")?;
+    let debug_len = u32_at(image, dirs + 6 * 8 + 4).context("truncated data directories")?;
+    if debug_rva == 0 || debug_len == 0 {
+        return Ok(None);
+    }
+
+    let section_table = optional + optional_len;
+    let offset_of = |rva: u32| -> Option<usize> {
+        (0..sections).find_map(|i| {
+            let header = section_table + i * SECTION_HEADER_LEN;
+            let virtual_size = u32_at(image, header + 8)?;
+            let virtual_address = u32_at(image, header + 12)?;
+            let raw_len = u32_at(image, header + 16)?;
+            let raw_offset = u32_at(image, header + 20)?;
+
+            let delta = rva.checked_sub(virtual_address)?;
+            (delta < virtual_size.max(raw_len)).then(|| (raw_offset + delta) as usize)
+        })
+    };
+
+    let debug = offset_of(debug_rva).context("debug directory is outside every section")?;
+    for entry in 0..debug_len as usize / DEBUG_DIRECTORY_LEN {
+        let entry = debug + entry * DEBUG_DIRECTORY_LEN;
+        if u32_at(image, entry + 12).context("truncated debug directory")?
+            != IMAGE_DEBUG_TYPE_CODEVIEW
+        {
+            continue;
+        }
+
+        let len = u32_at(image, entry + 16).context("truncated debug directory")? as usize;
+        let offset = u32_at(image, entry + 24).context("truncated debug directory")? as usize;
+        let cv = image
+            .get(offset..offset + len)
+            .context("truncated CodeView entry")?;
+
+        // Older images might have an NB10 entry instead, which isn't
+        // supported by symbol servers
+        if !cv.starts_with(b"RSDS") || cv.len() < 24 {
+            continue;
+        }
+
+        let mut guid = [0; 16];
+        guid.copy_from_slice(&cv[4..20]);
+        let age = u32_at(cv, 20).context("truncated CodeView entry")?;
+
+        // The path is the absolute one the PDB was written to at build time,
+        // only the file name is used for lookups
+        let path = &cv[24..];
+        let path = &path[..path.iter().position(|b| *b == 0).unwrap_or(path.len())];
+        let path = String::from_utf8_lossy(path);
+        let name = path.rsplit(['\\', '/']).next().unwrap_or_default();
+        if name.is_empty() {
+            continue;
+        }
+
+        return Ok(Some(PdbId {
+            name: name.to_owned(),
+            guid,
+            age,
+        }));
+    }
+
+    Ok(None)
+}
+
+/// Reads a stream from an MSF container, which stores each stream in blocks
+/// that are scattered throughout the file
+fn msf_stream(
+    contents: &[u8],
+    block_size: usize,
+    blocks: &[u8],
+    size: usize,
+) -> Result<Vec<u8>, Error> {
+    let mut stream = Vec::with_capacity(size);
+    for block in blocks.chunks_exact(4).take(size.div_ceil(block_size)) {
+        let block = u32_at(block, 0).context("truncated block list")? as usize;
+        let data = contents
+            .get(block * block_size..(block + 1) * block_size)
+            .context("block is past the end of the file")?;
+        stream.extend_from_slice(data);
+    }
+    anyhow::ensure!(stream.len() >= size, "truncated stream");
+    stream.truncate(size);
+    Ok(stream)
+}
+
+/// Reads the identity of a PDB from its info and DBI streams. Only PDBs in the
+/// MSF 7.0 format, which has been used since Visual Studio 2003, are supported.
+pub fn pdb_id(name: &str, contents: &[u8]) -> Result<PdbId, Error> {
+    anyhow::ensure!(contents.starts_with(MSF_MAGIC), "not an MSF 7.0 PDB");
+
+    let header = MSF_MAGIC.len();
+    let block_size = u32_at(contents, header).context("truncated superblock")? as usize;
+    anyhow::ensure!(
+        [512, 1024, 2048, 4096].contains(&block_size),
+        "invalid block size {}",
+        block_size
+    );
+    let directory_len = u32_at(contents, header + 12).context("truncated superblock")? as usize;
+    let block_map = u32_at(contents, header + 20).context("truncated superblock")? as usize;
+
+    // The block map lists the blocks of the stream directory, which in turn
+    // lists the size and blocks of each stream
+    let directory = msf_stream(
+        contents,
+        block_size,
+        contents
+            .get(block_map * block_size..)
+            .context("block map is past the end of the file")?,
+        directory_len,
+    )
+    .context("unable to read the stream directory")?;
+
+    let count = u32_at(&directory, 0).context("truncated stream directory")? as usize;
+    let sizes = (0..count)
+        .map(|i| {
+            // Deleted streams have a size of -1
+            u32_at(&directory, 4 + i * 4)
+                .map(|size| if size == u32::MAX { 0 } else { size as usize })
+        })
+        .collect::<Option<Vec<_>>>()
+        .context("truncated stream directory")?;
+
+    let stream = |index: usize| -> Result<Vec<u8>, Error> {
+        let size = *sizes.get(index).context("missing stream")?;
+        let first = sizes[..index]
+            .iter()
+            .map(|size| size.div_ceil(block_size))
+            .sum::<usize>();
+        let blocks = directory
+            .get(4 + count * 4 + first * 4..)
+            .context("truncated stream directory")?;
+        msf_stream(contents, block_size, blocks, size)
+    };
+
+    let info = stream(PDB_INFO_STREAM).context("unable to read the PDB info stream")?;
+    let mut guid = [0; 16];
+    guid.copy_from_slice(info.get(12..28).context("truncated PDB info stream")?);
+    let mut age = u32_at(&info, 8).context("truncated PDB info stream")?;
+
+    if let Ok(dbi) = stream(DBI_STREAM) {
+        if let Some(dbi_age) = u32_at(&dbi, 8) {
+            age = dbi_age;
+        }
+    }
+
+    Ok(PdbId {
+        name: name.to_owned(),
+        guid,
+        age,
+    })
+}
+
+/// A PDB that was matched to an image in the output
+#[derive(serde::Serialize, Debug)]
+pub struct Matched {
+    #[serde(flatten)]
+    pub id: PdbId,
+    /// The images that were built with the PDB, relative to the output, or
+    /// absolute if they are in one of the image directories
+    pub images: Vec<PathBuf>,
+    /// The import libs in the output that import from the images
+    pub libs: Vec<PathBuf>,
+    /// The path of the PDB in the store, if it was retrieved now or already
+    /// present
+    pub path: Option<PathBuf>,
+}
+
+/// A DLL that import libs in the output import from, but which wasn't found
+/// in the output or any of the image directories, so its PDB couldn't be
+/// identified
+#[derive(serde::Serialize, Debug)]
+pub struct Unresolved {
+    /// The file name of the DLL, lower cased, eg. `kernel32.dll`
+    pub dll: String,
+    /// The import libs that import from it, relative to the output
+    pub libs: Vec<PathBuf>,
+}
+
+/// The outcome of [`fetch`]
+#[derive(serde::Serialize, Debug, Default)]
+pub struct Fetched {
+    /// The PDBs that were retrieved from the symbol server
+    pub fetched: Vec<Matched>,
+    /// The PDBs that were already present in the store
+    pub existing: Vec<Matched>,
+    /// The PDBs that the symbol server doesn't have
+    pub missing: Vec<Matched>,
+    /// The DLLs imported by import libs whose PDBs couldn't be identified
+    pub unresolved: Vec<Unresolved>,
+}
+
+/// The DLLs that the import lib imports from, lower cased
+fn import_dlls(lib: &[u8]) -> Result<std::collections::BTreeSet<String>, Error> {
+    let mut dlls = std::collections::BTreeSet::new();
+    for member in crate::ar::members(lib)? {
+        if let Some(dll) = member?.import_dll() {
+            let dll = String::from_utf8_lossy(dll).to_lowercase();
+
+            // The UCRT import libs import from the api sets of the UCRT, eg.
+            // `api-ms-win-crt-runtime-l1-1-0.dll`, which forward to ucrtbase
+            let dll = if dll.starts_with("api-ms-win-crt-") {
+                "ucrtbase.dll".to_owned()
+            } else {
+                dll
+            };
+            dlls.insert(dll);
+        }
+    }
+    Ok(dlls)
+}
+
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .filter(|ext| ext.eq_ignore_ascii_case("dll") || ext.eq_ignore_ascii_case("exe"))
+        .is_some()
+}
+
+/// Finds the PDB of every DLL and executable in the output, sorted by
+/// identity, along with the images built with each.
+///
+/// The import libs in the output are matched to the DLLs they import from,
+/// which are looked for in the output, and then directly in each of the image
+/// directories, eg. the `system32` directory of the Windows install a crash
+/// dump came from. Only the images that an import lib imports from are used
+/// from the image directories, and the DLLs that aren't found are returned as
+/// unresolved.
+pub fn match_images(
+    output: &Path,
+    image_dirs: &[PathBuf],
+) -> Result<(Vec<Matched>, Vec<Unresolved>), Error> {
+    let files = crate::util::walk_files(output)?;
+
+    let dlls = files
+        .par_iter()
+        .filter(|path| {
+            path.extension()
+                .filter(|ext| ext.eq_ignore_ascii_case("lib"))
+                .is_some()
+        })
+        .map(|path| -> Result<_, Error> {
+            let contents =
+                std::fs::read(path).with_context(|| format!("unable to read {}", path))?;
+
+            let dlls = import_dlls(&contents).unwrap_or_else(|err| {
+                tracing::debug!("unable to read the imports of {}: {:#}", path, err);
+                Default::default()
+            });
+            Ok((path, dlls))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let rel = |path: &Path| {
+        path.strip_prefix(output)
+            .map_or_else(|_| path.to_owned(), |rel| rel.to_owned())
+    };
+
+    let mut imports = std::collections::BTreeMap::<String, Vec<PathBuf>>::new();
+    for (lib, dlls) in dlls {
+        for dll in dlls {
+            imports.entry(dll).or_default().push(rel(lib));
+        }
+    }
+
+    let mut images: Vec<_> = files.into_iter().filter(|path| is_image(path)).collect();
+    for dir in image_dirs {
+        for entry in std::fs::read_dir(dir).with_context(|| format!("unable to read {}", dir))? {
+            let entry = entry.with_context(|| format!("unable to read entry from {}", dir))?;
+            let imported = entry
+                .file_name()
+                .to_str()
+                .filter(|name| imports.contains_key(&name.to_lowercase()))
+                .is_some();
+            if imported && entry.file_type()?.is_file() {
+                images.push(dir.join(entry.file_name().to_string_lossy().as_ref()));
+            }
+        }
+    }
+
+    let ids = images
+        .par_iter()
+        .map(|path| -> Result<Option<PdbId>, Error> {
+            let contents =
+                std::fs::read(path).with_context(|| format!("unable to read {}", path))?;
+
+            Ok(image_pdb(&contents).unwrap_or_else(|err| {
+                tracing::debug!("unable to read the debug directory of {}: {:#}", path, err);
+                None
+            }))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut matched = std::collections::BTreeMap::<PdbId, Matched>::new();
+    let mut resolved = std::collections::BTreeSet::new();
+    for (path, id) in images.into_iter().zip(ids) {
+        let id = match id {
+            Some(id) => id,
+            None => continue,
+        };
+
+        let entry = matched.entry(id.clone()).or_insert_with(|| Matched {
+            id,
+            images: Vec::new(),
+            libs: Vec::new(),
+            path: None,
+        });
+
+        if let Some(libs) = path
+            .file_name()
+            .and_then(|name| imports.get_key_value(&name.to_lowercase()))
+        {
+            entry.libs.extend(libs.1.iter().cloned());
+            resolved.insert(libs.0.clone());
+        }
+        entry.images.push(rel(&path));
+    }
+
+    let matched = matched
+        .into_values()
+        .map(|mut matched| {
+            matched.libs.sort();
+            matched.libs.dedup();
+            matched
+        })
+        .collect();
+
+    let unresolved = imports
+        .into_iter()
+        .filter(|(dll, _)| !resolved.contains(dll))
+        .map(|(dll, libs)| Unresolved { dll, libs })
+        .collect();
+
+    Ok((matched, unresolved))
+}
+
+/// Retrieves the PDB of every DLL and executable in the output, and of the
+/// DLLs its import libs import from, see [`match_images`], from the symbol
+/// server, eg. [`MICROSOFT_SYMBOL_SERVER`], to the store, in symbol server
+/// layout, see the [module docs](self). PDBs that are already present in the
+/// store aren't retrieved again, and PDBs the server responds to with a 404
+/// are missing, while any other failure is an error.
+///
+/// The client is `None` if the network is disabled, in which case an error is
+/// returned if any of the PDBs isn't already present.
+pub fn fetch(
+    client: Option<&dyn HttpClient>,
+    output: &Path,
+    image_dirs: &[PathBuf],
+    server: &str,
+    store: &Path,
+) -> Result<Fetched, Error> {
+    let server = server.trim_end_matches('/');
+
+    enum Outcome {
+        Fetched,
+        Existing,
+        Missing,
+    }
+
+    let (matched, unresolved) = match_images(output, image_dirs)?;
+
+    let outcomes = matched
+        .into_par_iter()
+        .map(|mut matched| -> Result<(Outcome, Matched), Error> {
+            let rel = matched.id.store_path();
+            let path = store.join(&rel);
+
+            if path.exists() {
+                matched.path = Some(path);
+                return Ok((Outcome::Existing, matched));
+            }
+
+            let url = format!("{}/{}", server, rel.as_str().replace('\\', "/"));
+            let client =
+                client.ok_or_else(|| crate::error::NetworkDisabled { url: url.clone() })?;
+
+            // Symbol servers respond with a 404 for PDBs they don't have
+            let mut res = match client.get(&url) {
+                Ok(res) => res,
+                Err(err)
+                    if err
+                        .chain()
+                        .any(|cause| cause.is::<crate::error::NotFound>()) =>
+                {
+                    tracing::debug!("{} was not found", url);
+                    return Ok((Outcome::Missing, matched));
+                }
+                Err(err) => return Err(err.context(format!("failed to retrieve {}", url))),
+            };
+
+            let mut contents = Vec::with_capacity(res.content_length.unwrap_or(0) as usize);
+            res.body
+                .read_to_end(&mut contents)
+                .with_context(|| format!("failed to retrieve {}", url))?;
+
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("unable to create {}", parent))?;
+            }
+
+            let tmp = path.with_extension("pdb.tmp");
+            std::fs::write(&tmp, &contents).with_context(|| format!("unable to write {}", tmp))?;
+            std::fs::rename(&tmp, &path)
+                .with_context(|| format!("unable to rename {} to {}", tmp, path))?;
+
+            matched.path = Some(path);
+            Ok((Outcome::Fetched, matched))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut fetched = Fetched {
+        unresolved,
+        ..Fetched::default()
+    };
+    for (outcome, matched) in outcomes {
+        match outcome {
+            Outcome::Fetched => fetched.fetched.push(matched),
+            Outcome::Existing => fetched.existing.push(matched),
+            Outcome::Missing => fetched.missing.push(matched),
+        }
+    }
+
+    Ok(fetched)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::Response;
+
+    /// Builds a minimal PE32+ image with a single section that contains the
+    /// debug directory and its `RSDS` entry
+    fn image(pdb: &str, guid: [u8; 16], age: u32) -> Vec<u8> {
+        const SECTION_RVA: u32 = 0x1000;
+        const SECTION_OFFSET: u32 = 0x200;
+
+        let mut image = vec![0; SECTION_OFFSET as usize];
+        image[..2].copy_from_slice(b"MZ");
+        image[0x3c..0x40].copy_from_slice(&0x40u32.to_le_bytes());
+        image[0x40..0x44].copy_from_slice(b"PE\0\0");
+
+        let coff = 0x44;
+        image[coff + 2..coff + 4].copy_from_slice(&1u16.to_le_bytes());
+        image[coff + 16..coff + 18].copy_from_slice(&240u16.to_le_bytes());
+
+        let optional = coff + 20;
+        image[optional..optional + 2].copy_from_slice(&0x20bu16.to_le_bytes());
+        image[optional + 108..optional + 112].copy_from_slice(&16u32.to_le_bytes());
+
+        let mut cv = b"RSDS".to_vec();
+        cv.extend_from_slice(&guid);
+        cv.extend_from_slice(&age.to_le_bytes());
+        cv.extend_from_slice(format!("D:\\a\\_work\\1\\s\\Intermediate\\{}\0", pdb).as_bytes());
+
+        let cv_offset = SECTION_OFFSET + DEBUG_DIRECTORY_LEN as u32;
+        let mut section = vec![0; DEBUG_DIRECTORY_LEN];
+        section[12..16].copy_from_slice(&IMAGE_DEBUG_TYPE_CODEVIEW.to_le_bytes());
+        section[16..20].copy_from_slice(&(cv.len() as u32).to_le_bytes());
+        section[20..24].copy_from_slice(&(SECTION_RVA + DEBUG_DIRECTORY_LEN as u32).to_le_bytes());
+        section[24..28].copy_from_slice(&cv_offset.to_le_bytes());
+        section.extend_from_slice(&cv);
+
+        let dirs = optional + 112;
+        image[dirs + 48..dirs + 52].copy_from_slice(&SECTION_RVA.to_le_bytes());
+        image[dirs + 52..dirs + 56].copy_from_slice(&(DEBUG_DIRECTORY_LEN as u32).to_le_bytes());
+
+        let header = optional + 240;
+        image[header..header + 6].copy_from_slice(b".rdata");
+        image[header + 8..header + 12].copy_from_slice(&(section.len() as u32).to_le_bytes());
+        image[header + 12..header + 16].copy_from_slice(&SECTION_RVA.to_le_bytes());
+        image[header + 16..header + 20].copy_from_slice(&(section.len() as u32).to_le_bytes());
+        image[header + 20..header + 24].copy_from_slice(&SECTION_OFFSET.to_le_bytes());
+
+        image.extend_from_slice(&section);
+        image
+    }
+
+    /// Builds an MSF container with 512 byte blocks, and a block for each
+    /// stream after the superblock, block map, and stream directory
+    fn msf(streams: &[Option<&[u8]>]) -> Vec<u8> {
+        const BLOCK_SIZE: usize = 512;
+
+        let mut directory = (streams.len() as u32).to_le_bytes().to_vec();
+        for stream in streams {
+            let size = stream.map_or(u32::MAX, |stream| stream.len() as u32);
+            directory.extend_from_slice(&size.to_le_bytes());
+        }
+
+        let mut blocks = Vec::new();
+        for stream in streams.iter().flatten() {
+            if stream.is_empty() {
+                continue;
+            }
+            directory.extend_from_slice(&(3 + blocks.len() as u32).to_le_bytes());
+            blocks.push(*stream);
+        }
+
+        let mut superblock = MSF_MAGIC.to_vec();
+        superblock.extend_from_slice(&(BLOCK_SIZE as u32).to_le_bytes());
+        superblock.extend_from_slice(&1u32.to_le_bytes());
+        superblock.extend_from_slice(&(3 + blocks.len() as u32).to_le_bytes());
+        superblock.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        superblock.extend_from_slice(&0u32.to_le_bytes());
+        superblock.extend_from_slice(&1u32.to_le_bytes());
+
+        let mut contents = Vec::new();
+        for block in [&superblock[..], &2u32.to_le_bytes(), &directory]
+            .iter()
+            .chain(blocks.iter())
+        {
+            let start = contents.len();
+            contents.extend_from_slice(block);
+            contents.resize(start + BLOCK_SIZE, 0);
+        }
+        contents
+    }
+
+    #[test]
+    fn identifies_pdbs() {
+        let guid = [7; 16];
+
+        let mut info = 20000404u32.to_le_bytes().to_vec();
+        info.extend_from_slice(&0u32.to_le_bytes());
+        info.extend_from_slice(&5u32.to_le_bytes());
+        info.extend_from_slice(&guid);
+
+        let mut dbi = (-1i32).to_le_bytes().to_vec();
+        dbi.extend_from_slice(&19990903u32.to_le_bytes());
+        dbi.extend_from_slice(&3u32.to_le_bytes());
+
+        // The age is taken from the DBI stream, as it is the one images record
+        let pdb = msf(&[Some(&[]), Some(&info), None, Some(&dbi)]);
+        let id = pdb_id("vcruntime140d.pdb", &pdb).unwrap();
+        assert_eq!(
+            id,
+            image_pdb(&image("vcruntime140d.pdb", guid, 3))
+                .unwrap()
+                .unwrap()
+        );
+
+        let pdb = msf(&[Some(&[]), Some(&info)]);
+        assert_eq!(pdb_id("vcruntime140d.pdb", &pdb).unwrap().age, 5);
+
+        assert!(pdb_id(
+            "vcruntime140d.pdb",
+            b"Microsoft C/C++ program database 2.00"
+        )
+        .is_err());
+        assert!(pdb_id("vcruntime140d.pdb", &pdb[..600]).is_err());
+    }
+
+    /// Builds an import lib with a short import member for each DLL
+    fn import_lib(dlls: &[&str]) -> Vec<u8> {
+        let mut lib = b"!<arch>\n".to_vec();
+        for dll in dlls {
+            let mut data = vec![0, 0, 0xff, 0xff, 0, 0, 0x64, 0x86];
+            data.resize(20, 0);
+            data.extend_from_slice(b"CreateFileW\0");
+            data.extend_from_slice(dll.as_bytes());
+            data.push(0);
+
+            lib.extend_from_slice(
+                format!(
+                    "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+                    "import.dll/",
+                    0,
+                    "",
+                    "",
+                    0,
+                    data.len()
+                )
+                .as_bytes(),
+            );
+            lib.extend_from_slice(&data);
+            if data.len() & 1 == 1 {
+                lib.push(b'\n');
+            }
+        }
+        lib
+    }
+
+    struct Server(std::collections::BTreeMap<String, Vec<u8>>);
+
+    impl HttpClient for Server {
+        fn get(&self, url: &str) -> Result<Response, anyhow::Error> {
+            let body = self.0.get(url).ok_or_else(|| crate::error::NotFound {
+                url: url.to_owned(),
+            })?;
+
+            Ok(Response {
+                content_length: Some(body.len() as u64),
+                body: Box::new(std::io::Cursor::new(body.clone())),
+            })
+        }
+    }
+
+    #[test]
+    fn fetches_pdbs() {
+        let guid = [
+            0x1a, 0x9d, 0x3a, 0x6e, 0xde, 0xe3, 0x7c, 0x0d, 0x3e, 0x7d, 0x3f, 0x3b, 0x5b, 0x6a,
+            0xeb, 0x3a,
+        ];
+        let id = image_pdb(&image("ucrtbase.pdb", guid, 0x1c))
+            .unwrap()
+            .unwrap();
+        assert_eq!(id.name, "ucrtbase.pdb");
+        assert_eq!(id.key(), "6E3A9D1AE3DE0D7C3E7D3F3B5B6AEB3A1C");
+        assert_eq!(
+            id.store_path(),
+            Path::new("ucrtbase.pdb/6E3A9D1AE3DE0D7C3E7D3F3B5B6AEB3A1C/ucrtbase.pdb")
+        );
+        assert!(image_pdb(b"not an image").is_err());
+
+        let td = tempfile::TempDir::new().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let output = root.join("splat");
+        let store = root.join("symbols");
+
+        let bin = output.join("crt/bin/x86_64");
+        std::fs::create_dir_all(&bin).unwrap();
+        std::fs::write(bin.join("ucrtbased.dll"), image("ucrtbased.pdb", guid, 1)).unwrap();
+        std::fs::write(
+            bin.join("vcruntime140d.dll"),
+            image("vcruntime140d.pdb", guid, 2),
+        )
+        .unwrap();
+        std::fs::write(bin.join("not-an-image.dll"), "nope").unwrap();
+
+        let lib = output.join("crt/lib/x86_64");
+        std::fs::create_dir_all(&lib).unwrap();
+        std::fs::write(lib.join("ucrtd.lib"), import_lib(&["ucrtbased.dll"])).unwrap();
+        std::fs::write(
+            lib.join("ucrt.lib"),
+            import_lib(&[
+                "api-ms-win-crt-runtime-l1-1-0.dll",
+                "api-ms-win-crt-stdio-l1-1-0.dll",
+            ]),
+        )
+        .unwrap();
+        std::fs::write(lib.join("kernel32.lib"), import_lib(&["KERNEL32.dll"])).unwrap();
+
+        // Only the images that are imported are used from the image directories
+        let system32 = root.join("system32");
+        std::fs::create_dir_all(&system32).unwrap();
+        std::fs::write(
+            system32.join("kernel32.dll"),
+            image("kernel32.pdb", guid, 3),
+        )
+        .unwrap();
+        std::fs::write(system32.join("user32.dll"), image("user32.pdb", guid, 4)).unwrap();
+
+        let server = Server(
+            std::iter::once((
+                format!(
+                    "{}/ucrtbased.pdb/6E3A9D1AE3DE0D7C3E7D3F3B5B6AEB3A1/ucrtbased.pdb",
+                    MICROSOFT_SYMBOL_SERVER
+                ),
+                b"not really a pdb".to_vec(),
+            ))
+            .collect(),
+        );
+
+        // Failures other than a 404 aren't treated as missing PDBs
+        struct Unreachable;
+
+        impl HttpClient for Unreachable {
+            fn get(&self, url: &str) -> Result<Response, anyhow::Error> {
+                anyhow::bail!("unable to connect to {}", url)
+            }
+        }
+
+        let err = fetch(
+            Some(&Unreachable),
+            &output,
+            &[],
+            MICROSOFT_SYMBOL_SERVER,
+            &store,
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", err).contains("unable to connect"));
+
+        let fetched = fetch(
+            Some(&server),
+            &output,
+            std::slice::from_ref(&system32),
+            MICROSOFT_SYMBOL_SERVER,
+            &store,
+        )
+        .unwrap();
+        assert_eq!(fetched.fetched.len(), 1);
+        assert_eq!(
+            fetched.fetched[0].images,
+            [PathBuf::from("crt/bin/x86_64/ucrtbased.dll")]
+        );
+        assert_eq!(
+            fetched.fetched[0].libs,
+            [PathBuf::from("crt/lib/x86_64/ucrtd.lib")]
+        );
+
+        let missing: Vec<_> = fetched
+            .missing
+            .iter()
+            .map(|matched| (matched.id.name.as_str(), &matched.images, &matched.libs))
+            .collect();
+        assert_eq!(
+            missing,
+            [
+                (
+                    "kernel32.pdb",
+                    &vec![system32.join("kernel32.dll")],
+                    &vec![PathBuf::from("crt/lib/x86_64/kernel32.lib")]
+                ),
+                (
+                    "vcruntime140d.pdb",
+                    &vec![PathBuf::from("crt/bin/x86_64/vcruntime140d.dll")],
+                    &Vec::new()
+                ),
+            ]
+        );
+
+        // The UCRT api sets are resolved to ucrtbase, which isn't present
+        assert_eq!(fetched.unresolved.len(), 1);
+        assert_eq!(fetched.unresolved[0].dll, "ucrtbase.dll");
+        assert_eq!(
+            fetched.unresolved[0].libs,
+            [PathBuf::from("crt/lib/x86_64/ucrt.lib")]
+        );
+        assert_eq!(
+            std::fs::read(
+                store.join("ucrtbased.pdb/6E3A9D1AE3DE0D7C3E7D3F3B5B6AEB3A1/ucrtbased.pdb")
+            )
+            .unwrap(),
+            b"not really a pdb"
+        );
+
+        // PDBs already in the store aren't retrieved again
+        std::fs::remove_file(bin.join("vcruntime140d.dll")).unwrap();
+        let fetched = fetch(None, &output, &[], MICROSOFT_SYMBOL_SERVER, &store).unwrap();
+        assert_eq!(fetched.existing.len(), 1);
+        assert!(fetched.fetched.is_empty());
+
+        std::fs::write(
+            bin.join("vcruntime140d.dll"),
+            image("vcruntime140d.pdb", guid, 2),
+        )
+        .unwrap();
+        let err = fetch(None, &output, &[], MICROSOFT_SYMBOL_SERVER, &store).unwrap_err();
+        assert!(err.is::<crate::error::NetworkDisabled>());
+    }
+}
@@ -0,0 +1,68 @@
+//! Progress reporting, so that embedders can surface the progress of
+//! downloading, unpacking, and splatting in their own UIs. The CLI implements
+//! these with progress bars.
+
+use std::sync::Arc;
+
+/// The phases a unit of work goes through
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Downloading a manifest or payload, measured in bytes
+    Download,
+    /// Decompressing a payload, measured in bytes
+    Unpack,
+    /// Splatting the files of a payload, measured in bytes
+    Splat,
+    /// Scanning the SDK headers for includes that need symlinks, measured in
+    /// files
+    ScanIncludes,
+    /// Adding the symlinks for the includes and libs that don't match the
+    /// casing on disk, measured in includes and lib directories
+    Symlinks,
+}
+
+/// Receives the progress of a single unit of work, eg. a payload.
+///
+/// Every method does nothing by default, so only the ones that are of
+/// interest need to be implemented.
+pub trait ProgressSink: Send + Sync {
+    /// A new phase has started, resetting the position and length
+    fn phase(&self, _phase: Phase) {}
+    /// Sets the total length of the current phase
+    fn set_length(&self, _len: u64) {}
+    /// Increases the total length of the current phase, eg. when another
+    /// download is started
+    fn inc_length(&self, _delta: u64) {}
+    /// Advances the position in the current phase
+    fn inc(&self, _delta: u64) {}
+    /// The phase has completed
+    fn finish(&self, _phase: Phase) {}
+}
+
+/// Creates the [`ProgressSink`] for each unit of work, it is implemented for
+/// any `Fn(&str) -> Arc<dyn ProgressSink>`, which is passed the name of the
+/// unit, eg. the filename of a payload.
+pub trait ProgressFactory: Send + Sync {
+    fn create(&self, name: &str) -> Arc<dyn ProgressSink>;
+}
+
+impl<F> ProgressFactory for F
+where
+    F: Fn(&str) -> Arc<dyn ProgressSink> + Send + Sync,
+{
+    fn create(&self, name: &str) -> Arc<dyn ProgressSink> {
+        self(name)
+    }
+}
+
+/// Discards all progress
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Hidden;
+
+impl ProgressSink for Hidden {}
+
+impl ProgressFactory for Hidden {
+    fn create(&self, _name: &str) -> Arc<dyn ProgressSink> {
+        Arc::new(Self)
+    }
+}
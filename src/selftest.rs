@@ -0,0 +1,155 @@
+//! Compiles and links tiny C and C++ programs against a splat with `clang-cl`
+//! and `lld-link`, to check that the layout is actually usable before wiring
+//! it into a real build
+
+use crate::{layout::Layout, Arch, Path, PathBuf};
+use anyhow::{Context as _, Error};
+use std::process::Command;
+
+const C_PROGRAM: &str = r#"#include <windows.h>
+#include <stdio.h>
+
+int main(void) {
+    printf("xwin selftest %lu\n", GetCurrentProcessId());
+    return 0;
+}
+"#;
+
+const CPP_PROGRAM: &str = r#"#include <windows.h>
+#include <iostream>
+#include <string>
+#include <vector>
+
+int main() {
+    std::vector<std::string> words = {"xwin", "selftest"};
+    for (const auto& word : words) {
+        std::cout << word << ' ';
+    }
+    std::cout << GetCurrentProcessId() << std::endl;
+    return 0;
+}
+"#;
+
+/// The tools used to compile and link the programs
+pub struct Toolchain {
+    pub clang_cl: PathBuf,
+    pub lld_link: PathBuf,
+}
+
+impl Toolchain {
+    /// Uses the specified tools, finding any that weren't specified in `PATH`
+    pub fn new(clang_cl: Option<PathBuf>, lld_link: Option<PathBuf>) -> Result<Self, Error> {
+        let find = |name: &str| {
+            find_program(name).with_context(|| {
+                format!(
+                    "unable to find {} in PATH, it can be specified with --{}",
+                    name, name
+                )
+            })
+        };
+
+        Ok(Self {
+            clang_cl: match clang_cl {
+                Some(clang_cl) => clang_cl,
+                None => find("clang-cl")?,
+            },
+            lld_link: match lld_link {
+                Some(lld_link) => lld_link,
+                None => find("lld-link")?,
+            },
+        })
+    }
+}
+
+/// Finds the first executable with the specified name in `PATH`
+pub fn find_program(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    let exe = format!("{}{}", name, std::env::consts::EXE_SUFFIX);
+
+    std::env::split_paths(&path)
+        .filter_map(|dir| PathBuf::from_path_buf(dir).ok())
+        .map(|dir| dir.join(&exe))
+        .find(|candidate| candidate.is_file())
+}
+
+/// The outcome of compiling and linking one of the programs
+pub struct Outcome {
+    pub arch: Arch,
+    /// The language of the program, ie. `C` or `C++`
+    pub language: &'static str,
+    /// The command that failed, along with its output, if the program
+    /// couldn't be compiled or linked
+    pub error: Option<String>,
+}
+
+/// Runs the command, returning the command line and its output if it fails
+fn run(mut cmd: Command) -> Result<Option<String>, Error> {
+    let output = cmd
+        .output()
+        .with_context(|| format!("failed to run {:?}", cmd))?;
+
+    if output.status.success() {
+        return Ok(None);
+    }
+
+    let mut error = format!("{:?} failed with {}", cmd, output.status);
+    for stream in [&output.stdout, &output.stderr].iter() {
+        let stream = String::from_utf8_lossy(stream);
+        let stream = stream.trim();
+        if !stream.is_empty() {
+            error.push('\n');
+            error.push_str(stream);
+        }
+    }
+
+    Ok(Some(error))
+}
+
+/// Compiles and links a C and a C++ program in `dir`, which must exist,
+/// against the layout for the specified architecture
+pub fn run_arch(
+    toolchain: &Toolchain,
+    layout: &Layout,
+    arch: Arch,
+    dir: &Path,
+) -> Result<Vec<Outcome>, Error> {
+    let mut outcomes = Vec::new();
+
+    for (language, ext, contents) in [("C", "c", C_PROGRAM), ("C++", "cpp", CPP_PROGRAM)].iter() {
+        let stem = format!("selftest-{}-{}", arch, ext);
+        let src = dir.join(format!("{}.{}", stem, ext));
+        let obj = dir.join(format!("{}.obj", stem));
+        let exe = dir.join(format!("{}.exe", stem));
+
+        std::fs::write(&src, contents).with_context(|| format!("unable to write {}", src))?;
+
+        let mut compile = Command::new(&toolchain.clang_cl);
+        compile
+            .arg(format!("--target={}", arch.as_triple()))
+            .args(["/nologo", "/c", "/EHsc", "/W3"])
+            .args(layout.include.iter().map(|inc| format!("/imsvc{}", inc)))
+            .arg(format!("/Fo{}", obj))
+            .arg(&src);
+
+        let mut error = run(compile)?;
+
+        if error.is_none() {
+            let mut link = Command::new(&toolchain.lld_link);
+            link.arg("/nologo")
+                .arg(format!("/machine:{}", arch.as_ms_str()))
+                .args(layout.lib.iter().map(|lib| format!("/libpath:{}", lib)))
+                .arg(format!("/out:{}", exe))
+                .arg(&obj);
+
+            error = run(link)?;
+        }
+
+        outcomes.push(Outcome {
+            arch,
+            language,
+            error,
+        });
+    }
+
+    Ok(outcomes)
+}
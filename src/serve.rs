@@ -0,0 +1,161 @@
+//! A small HTTP server that serves the download cache, and a finished splat,
+//! to other xwin instances, eg. the nodes of a build farm, so that only one
+//! machine needs to retrieve everything from the Microsoft CDN.
+//!
+//! * `GET /dl/<path>` - A file in the download cache, which is where instances
+//!   passed the server as their upstream retrieve payloads from
+//! * `GET /splat.tar.gz` - A gzipped tarball of the splat output
+
+use crate::{Path, PathBuf};
+use anyhow::{Context as _, Error};
+use std::io::{Seek, SeekFrom};
+
+/// Serves the download cache in `cache_dir`, and the splat in `output`,
+/// on `addr` until the process is terminated
+pub fn serve(addr: &str, cache_dir: &Path, output: &Path) -> Result<(), Error> {
+    let server = tiny_http::Server::http(addr)
+        .map_err(|err| anyhow::anyhow!("unable to listen on {}: {}", addr, err))?;
+
+    tracing::info!(
+        "serving {} and {} on {}",
+        cache_dir.join("dl"),
+        output,
+        addr
+    );
+
+    for request in server.incoming_requests() {
+        let dl_dir = cache_dir.join("dl");
+        let output = output.to_owned();
+
+        // Archiving the splat can take a while, so don't block other requests
+        std::thread::spawn(move || {
+            let method = request.method().clone();
+            let url = request.url().to_owned();
+
+            let response = match respond(&method, &url, &dl_dir, &output) {
+                Ok(response) => response,
+                Err(err) => {
+                    tracing::error!("failed to respond to {} {}: {:#}", method, url, err);
+                    tiny_http::Response::empty(500).boxed()
+                }
+            };
+
+            tracing::info!("{} {} {}", method, url, response.status_code().0);
+
+            if let Err(err) = request.respond(response) {
+                tracing::debug!("failed to send response for {}: {}", url, err);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn respond(
+    method: &tiny_http::Method,
+    url: &str,
+    dl_dir: &Path,
+    output: &Path,
+) -> Result<tiny_http::ResponseBox, Error> {
+    use tiny_http::{Method, Response};
+
+    if *method != Method::Get && *method != Method::Head {
+        return Ok(Response::empty(405).boxed());
+    }
+
+    let path = url.split('?').next().unwrap_or_default();
+
+    if path == "/splat.tar.gz" {
+        if !output.is_dir() {
+            return Ok(Response::empty(404).boxed());
+        }
+
+        let mut archive = tempfile::tempfile().context("unable to create temporary file")?;
+        crate::util::tar_gz(output, &mut archive)?;
+        archive.seek(SeekFrom::Start(0))?;
+
+        return Ok(Response::from_file(archive)
+            .with_header(header("Content-Type", "application/gzip"))
+            .boxed());
+    }
+
+    let file = match path
+        .strip_prefix("/dl/")
+        .and_then(|rel| resolve(dl_dir, rel))
+    {
+        Some(file) if file.is_file() => file,
+        _ => return Ok(Response::empty(404).boxed()),
+    };
+
+    let file = std::fs::File::open(&file).with_context(|| format!("unable to open {}", file))?;
+    Ok(Response::from_file(file)
+        .with_header(header("Content-Type", "application/octet-stream"))
+        .boxed())
+}
+
+fn header(name: &str, value: &str) -> tiny_http::Header {
+    tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+/// Resolves the percent encoded path relative to the root, as long as it
+/// doesn't escape it
+fn resolve(root: &Path, rel: &str) -> Option<PathBuf> {
+    let mut path = root.to_owned();
+
+    for segment in rel.split('/') {
+        let segment = percent_decode(segment)?;
+
+        if segment.is_empty()
+            || segment == "."
+            || segment == ".."
+            || segment.contains(['/', '\\', ':'])
+        {
+            return None;
+        }
+
+        path.push(segment);
+    }
+
+    Some(path)
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resolves_paths() {
+        let root = Path::new("/cache/dl");
+
+        assert_eq!(
+            resolve(
+                root,
+                "sdk/Windows%20SDK%20Desktop%20Headers%20x64-x86_en-us.msi"
+            )
+            .unwrap(),
+            "/cache/dl/sdk/Windows SDK Desktop Headers x64-x86_en-us.msi"
+        );
+
+        assert!(resolve(root, "../secret").is_none());
+        assert!(resolve(root, "sdk/%2E%2E/%2E%2E/secret").is_none());
+        assert!(resolve(root, "sdk/..%2Fsecret").is_none());
+        assert!(resolve(root, "/etc/passwd").is_none());
+    }
+}
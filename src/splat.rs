@@ -1,30 +1,181 @@
-use crate::{Arch, Ctx, Error, Path, PathBuf, PayloadKind, Variant};
-use anyhow::Context as _;
+use crate::{
+    error, progress::Phase, util::long_path, Arch, Ctx, Path, PathBuf, PayloadKind, Variant,
+};
+use anyhow::{Context as _, Error};
 use rayon::prelude::*;
 use std::collections::BTreeMap;
 
+#[derive(Clone, serde::Serialize)]
 pub struct SplatConfig {
+    /// Keeps the (non-redistributable) debug versions of the CRT libs
     pub include_debug_libs: bool,
+    /// Keeps the PDBs for the CRT libs
     pub include_debug_symbols: bool,
+    /// Keeps the debug versions of the libs for only these components and
+    /// architectures, when `include_debug_libs` isn't set
+    pub include_debug_libs_for: Vec<DebugTarget>,
+    /// Keeps the PDBs for only these components and architectures, when
+    /// `include_debug_symbols` isn't set
+    pub include_debug_symbols_for: Vec<DebugTarget>,
+    /// Adds symlinks to address casing issues in the CRT and SDK
     pub enable_symlinks: bool,
+    /// Uses the MS names for architecture directories, eg. `x64`
     pub preserve_ms_arch_notation: bool,
+    /// The root output directory
     pub output: PathBuf,
+    /// Copies files from the unpack directory instead of moving them
     pub copy: bool,
+    /// Additional names to symlink libs as, eg. `LIBCMT.lib`, on top of the
+    /// ones referenced by `/DEFAULTLIB` directives in the CRT
+    pub lib_symlinks: Vec<String>,
+    /// Fails the splat if any of the includes in the SDK headers can't be
+    /// resolved, eg. because a payload is missing
+    pub strict_includes: bool,
+    /// Checks that every file was written to the output with the same size
+    /// it had in the unpack directory once each payload is splatted
+    pub verify: bool,
+    /// Splats into the output without deleting the CRT and SDK directories
+    /// first, which is used to update an existing output in place
+    pub keep_existing: bool,
+    /// A directory of patches that are applied to the output once it has
+    /// been finalized, see [`crate::patch`]
+    pub patches: Option<PathBuf>,
+    /// Thins the splatted import libs once the output has been finalized,
+    /// see [`crate::thin`]
+    pub thin_import_libs: bool,
+    /// Stores the kept PDBs in symbol server layout in the `symbols`
+    /// directory of the output, see [`crate::pdb`]
+    pub symbol_server_layout: bool,
     //pub isolated: bool,
 }
 
+impl SplatConfig {
+    /// Creates a config with the same defaults as the CLI, ie. only symlinks
+    /// are enabled
+    pub fn new(output: impl Into<PathBuf>) -> Self {
+        Self {
+            include_debug_libs: false,
+            include_debug_symbols: false,
+            include_debug_libs_for: Vec::new(),
+            include_debug_symbols_for: Vec::new(),
+            enable_symlinks: true,
+            preserve_ms_arch_notation: false,
+            output: output.into(),
+            copy: false,
+            lib_symlinks: Vec::new(),
+            strict_includes: false,
+            verify: false,
+            keep_existing: false,
+            patches: None,
+            thin_import_libs: false,
+            symbol_server_layout: false,
+        }
+    }
+
+    /// Whether the debug libs are kept for the component and architecture
+    pub fn includes_debug_libs(&self, component: DebugComponent, arch: Option<Arch>) -> bool {
+        self.include_debug_libs
+            || self
+                .include_debug_libs_for
+                .iter()
+                .any(|target| target.matches(component, arch))
+    }
+
+    /// Whether the PDBs are kept for the component and architecture
+    pub fn includes_debug_symbols(&self, component: DebugComponent, arch: Option<Arch>) -> bool {
+        self.include_debug_symbols
+            || self
+                .include_debug_symbols_for
+                .iter()
+                .any(|target| target.matches(component, arch))
+    }
+}
+
+/// The components that contain debug libs and PDBs
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DebugComponent {
+    /// The MSVC CRT
+    Crt,
+    /// The Universal CRT in the SDK
+    Ucrt,
+}
+
+impl DebugComponent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Crt => "crt",
+            Self::Ucrt => "ucrt",
+        }
+    }
+}
+
+/// Selects a component, and optionally a single architecture of it, to keep
+/// the debug libs or PDBs for, eg. `crt` or `crt:x86_64`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DebugTarget {
+    pub component: DebugComponent,
+    /// The architecture to keep them for, or every architecture if `None`
+    pub arch: Option<Arch>,
+}
+
+impl DebugTarget {
+    fn matches(&self, component: DebugComponent, arch: Option<Arch>) -> bool {
+        self.component == component && (self.arch.is_none() || self.arch == arch)
+    }
+}
+
+impl std::str::FromStr for DebugTarget {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (component, arch) = match s.split_once(':') {
+            Some((component, arch)) => (component, Some(arch.parse()?)),
+            None => (s, None),
+        };
+
+        let component = match component {
+            "crt" => DebugComponent::Crt,
+            "ucrt" => DebugComponent::Ucrt,
+            o => anyhow::bail!("unknown component '{}', expected 'crt' or 'ucrt'", o),
+        };
+
+        Ok(Self { component, arch })
+    }
+}
+
+impl serde::Serialize for DebugTarget {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl std::fmt::Display for DebugTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.component.as_str())?;
+        if let Some(arch) = self.arch {
+            write!(f, ":{}", arch)?;
+        }
+        Ok(())
+    }
+}
+
 /// There is a massive amount of duplication between SDK headers for the Desktop
 /// and Store variants, so we keep track of them so we only splat one unique file
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
 pub(crate) struct SdkHeaders {
     pub(crate) inner: BTreeMap<u64, PathBuf>,
     pub(crate) root: PathBuf,
+    /// The filename of the payload the headers are from
+    #[serde(default)]
+    pub(crate) payload: String,
 }
 
 impl SdkHeaders {
-    fn new(root: PathBuf) -> Self {
+    fn new(root: PathBuf, payload: &str) -> Self {
         Self {
             inner: BTreeMap::new(),
             root,
+            payload: payload.to_owned(),
         }
     }
 
@@ -46,39 +197,399 @@ pub(crate) struct SplatRoots {
     crt: PathBuf,
     sdk: PathBuf,
     src: PathBuf,
+    lib_symlinks: Vec<String>,
+    strict_includes: bool,
+    /// Symlinks are only added if they were enabled, and the output is on a
+    /// case sensitive filesystem
+    pub(crate) enable_symlinks: bool,
+    cancel: crate::cancel::CancellationToken,
+    collisions: parking_lot::Mutex<Vec<SymlinkCollision>>,
+    skipped: parking_lot::Mutex<Vec<SkippedFile>>,
+    /// The number of symlinks that were created
+    symlinks: std::sync::atomic::AtomicU64,
+}
+
+/// Why a file in a payload wasn't splatted
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipReason {
+    /// A debug version of a CRT lib, see [`SplatConfig::include_debug_libs`]
+    DebugLib,
+    /// A PDB for a CRT lib, see [`SplatConfig::include_debug_symbols`]
+    DebugSymbols,
+    /// A directory of the CRT libs that is only used by the Store variant,
+    /// which wasn't selected
+    StoreVariant,
+    /// An SDK header whose path only differs in case from a header in
+    /// another payload, which is the one that is scanned for includes
+    DuplicateHeader,
+}
+
+/// A file, or directory, in a payload that was deliberately not splatted
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct SkippedFile {
+    /// The path the file would have been splatted to
+    pub path: PathBuf,
+    /// The filename of the payload the file is from
+    pub payload: String,
+    pub reason: SkipReason,
+    /// For duplicates, the path of the file that was used instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kept: Option<PathBuf>,
+}
+
+/// A symlink whose name was already claimed by another file or symlink
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymlinkCollision {
+    /// The path of the symlink
+    pub link: PathBuf,
+    /// The target of the symlink that was kept, or `None` if the path is a
+    /// regular file or directory, which always takes precedence
+    pub kept: Option<String>,
+    /// The target of the symlink that was discarded
+    pub discarded: String,
 }
 
+impl SplatRoots {
+    /// Creates a symlink at `link`. If the name is already taken the
+    /// collision is recorded instead of failing, and if it was taken by
+    /// another symlink, the one with the lowest target is kept so that the
+    /// output doesn't depend on the order payloads were splatted in
+    #[cfg(unix)]
+    fn symlink(&self, original: &str, link: &Path) -> Result<(), Error> {
+        let context = || {
+            error::io(
+                format!("unable to symlink from {} to {}", link, original),
+                link,
+            )
+        };
+
+        let err = match std::os::unix::fs::symlink(original, link) {
+            Ok(()) => {
+                self.symlinks
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                return Ok(());
+            }
+            Err(err) => err,
+        };
+
+        if err.kind() != std::io::ErrorKind::AlreadyExists {
+            return Err(Error::new(err).context(context()));
+        }
+
+        // On case insensitive filesystems, eg. the macOS default, the link is
+        // often just a different casing of the original, so already exists
+        if let Some(parent) = link.parent() {
+            if same_file(&parent.join(original), link) {
+                return Ok(());
+            }
+        }
+
+        let mut collisions = self.collisions.lock();
+
+        // Regular files and directories can't be read as links
+        let existing = std::fs::read_link(link)
+            .ok()
+            .and_then(|target| target.into_os_string().into_string().ok());
+
+        let (kept, discarded) = match existing {
+            Some(existing) if existing == original => return Ok(()),
+            Some(existing) if original < existing.as_str() => {
+                std::fs::remove_file(link).with_context(|| {
+                    error::io(format!("unable to remove symlink {}", link), link)
+                })?;
+                std::os::unix::fs::symlink(original, link).with_context(context)?;
+                (Some(original.to_owned()), existing)
+            }
+            existing => (existing, original.to_owned()),
+        };
+
+        tracing::debug!("symlink {} collided with an existing file", link);
+        collisions.push(SymlinkCollision {
+            link: link.to_owned(),
+            kept,
+            discarded,
+        });
+
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    fn symlink(&self, _original: &str, _link: &Path) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn skip(&self, path: PathBuf, payload: &str, reason: SkipReason, kept: Option<PathBuf>) {
+        tracing::debug!("skipping {}", path);
+        self.skipped.lock().push(SkippedFile {
+            path,
+            payload: payload.to_owned(),
+            reason,
+            kept,
+        });
+    }
+
+    /// The files that were skipped during the splat, sorted by path
+    pub(crate) fn take_skipped(&self) -> Vec<SkippedFile> {
+        let mut skipped = std::mem::take(&mut *self.skipped.lock());
+        skipped.sort_by(|a, b| a.path.cmp(&b.path));
+        skipped
+    }
+
+    /// The number of symlinks that were created during the splat
+    pub(crate) fn symlink_count(&self) -> u64 {
+        self.symlinks.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// The symlink collisions that occurred during the splat, sorted by path
+    pub(crate) fn into_collisions(self) -> Vec<SymlinkCollision> {
+        let mut collisions = self.collisions.into_inner();
+        collisions.sort_by(|a, b| a.link.cmp(&b.link));
+        collisions
+    }
+}
+
+/// Copies a file for `--copy`. On Linux the data is copied in the kernel with
+/// `copy_file_range`, which also lets network filesystems, eg. NFS, copy the
+/// file on the server rather than round tripping the data through us
+#[cfg(target_os = "linux")]
+fn copy_file(src: &Path, dst: &Path) -> std::io::Result<()> {
+    use rustix::io::Errno;
+
+    let src_file = std::fs::File::open(src)?;
+    let metadata = src_file.metadata()?;
+    let dst_file = std::fs::File::create(dst)?;
+
+    let mut remaining = metadata.len();
+    while remaining > 0 {
+        let chunk = remaining.min(1 << 30) as usize;
+
+        match rustix::fs::copy_file_range(&src_file, None, &dst_file, None, chunk) {
+            // The file was truncated while copying it
+            Ok(0) => break,
+            Ok(copied) => remaining -= copied as u64,
+            // Older kernels don't support copying between filesystems, and
+            // some filesystems don't support it at all
+            Err(Errno::NOSYS | Errno::XDEV | Errno::OPNOTSUPP | Errno::INVAL)
+                if remaining == metadata.len() =>
+            {
+                drop(dst_file);
+                return std::fs::copy(src, dst).map(|_| ());
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    // Matches std::fs::copy
+    dst_file.set_permissions(metadata.permissions())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_file(src: &Path, dst: &Path) -> std::io::Result<()> {
+    std::fs::copy(src, dst).map(|_| ())
+}
+
+/// Checks if both paths resolve to the same file
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Checks if the filesystem a directory is on is case sensitive, by creating a
+/// file and checking if a different casing of its name exists
+fn is_case_sensitive(dir: &Path) -> Result<bool, Error> {
+    let probe = dir.join(".xwin-case-probe");
+    std::fs::write(&probe, "")
+        .with_context(|| error::io(format!("unable to create {}", probe), &probe))?;
+
+    let case_sensitive = !dir.join(".XWIN-CASE-PROBE").exists();
+
+    std::fs::remove_file(&probe)
+        .with_context(|| error::io(format!("unable to remove {}", probe), &probe))?;
+    Ok(case_sensitive)
+}
+
+/// Takes an exclusive lock on the output directory for the duration of the
+/// splat, so that two runs writing to the same output fail fast instead of
+/// deleting and moving files out from under each other.
+///
+/// The lock file is a hidden sibling of the output directory so that it
+/// doesn't become part of the output itself.
+pub(crate) fn lock_output(output: &Path) -> Result<std::fs::File, Error> {
+    use fs2::FileExt;
+
+    let name = output
+        .file_name()
+        .with_context(|| format!("output '{}' has no directory name", output))?;
+    let lock_path = output.with_file_name(format!(".{}.xwin-lock", name));
+
+    if let Some(parent) = lock_path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| error::io(format!("unable to create {}", parent), parent))?;
+    }
+
+    let lock = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| error::io(format!("unable to open {}", lock_path), &lock_path))?;
+
+    if let Err(err) = lock.try_lock_exclusive() {
+        if err.kind() == fs2::lock_contended_error().kind() {
+            anyhow::bail!(
+                "'{}' is locked by another xwin process that is splatting to it",
+                output
+            );
+        }
+
+        return Err(err)
+            .with_context(|| error::io(format!("unable to lock {}", lock_path), &lock_path));
+    }
+
+    Ok(lock)
+}
+
+/// Prepares the output directories, which are deleted first unless an
+/// interrupted splat to them is being resumed
 pub(crate) fn prep_splat(
     ctx: std::sync::Arc<Ctx>,
     config: &SplatConfig,
+    resume: bool,
 ) -> Result<SplatRoots, Error> {
     let crt_root = config.output.join("crt");
     let sdk_root = config.output.join("sdk");
 
-    if crt_root.exists() {
-        std::fs::remove_dir_all(&crt_root)
-            .with_context(|| format!("unable to delete existing CRT directory {}", crt_root))?;
+    let resume = resume || config.keep_existing;
+
+    if crt_root.exists() && !resume {
+        std::fs::remove_dir_all(&crt_root).with_context(|| {
+            error::io(
+                format!("unable to delete existing CRT directory {}", crt_root),
+                &crt_root,
+            )
+        })?;
     }
 
-    if sdk_root.exists() {
-        std::fs::remove_dir_all(&sdk_root)
-            .with_context(|| format!("unable to delete existing SDK directory {}", sdk_root))?;
+    if sdk_root.exists() && !resume {
+        std::fs::remove_dir_all(&sdk_root).with_context(|| {
+            error::io(
+                format!("unable to delete existing SDK directory {}", sdk_root),
+                &sdk_root,
+            )
+        })?;
     }
 
-    std::fs::create_dir_all(&crt_root)
-        .with_context(|| format!("unable to create CRT directory {}", crt_root))?;
-    std::fs::create_dir_all(&sdk_root)
-        .with_context(|| format!("unable to create SDK directory {}", sdk_root))?;
+    std::fs::create_dir_all(&crt_root).with_context(|| {
+        error::io(
+            format!("unable to create CRT directory {}", crt_root),
+            &crt_root,
+        )
+    })?;
+    std::fs::create_dir_all(&sdk_root).with_context(|| {
+        error::io(
+            format!("unable to create SDK directory {}", sdk_root),
+            &sdk_root,
+        )
+    })?;
 
     let src_root = ctx.work_dir.join("unpack");
 
+    let enable_symlinks = config.enable_symlinks && {
+        let case_sensitive = is_case_sensitive(&crt_root)?;
+        if !case_sensitive {
+            tracing::info!(
+                "{} is on a case insensitive filesystem, symlinks will not be added",
+                config.output
+            );
+        }
+        case_sensitive
+    };
+
     Ok(SplatRoots {
         crt: crt_root,
         sdk: sdk_root,
         src: src_root,
+        lib_symlinks: config
+            .lib_symlinks
+            .iter()
+            .map(|name| lib_name(name))
+            .collect(),
+        strict_includes: config.strict_includes,
+        enable_symlinks,
+        cancel: ctx.cancel.clone(),
+        collisions: Default::default(),
+        skipped: Default::default(),
+        symlinks: Default::default(),
     })
 }
 
+/// Returns true if more than one of the desktop, onecore, and store variants
+/// are selected, in which case the CRT libs of every variant, including the
+/// store libs that are always splatted, are splatted to a directory named
+/// after the variant, rather than partially overlapping each other
+pub(crate) fn has_variant_dirs(variants: u32) -> bool {
+    (variants & !(Variant::Spectre as u32)).count_ones() > 1
+}
+
+/// The directory, relative to the CRT root, that the CRT libs for a variant
+/// and architecture are splatted to.
+///
+/// | variant | single variant | several variants |
+/// |---|---|---|
+/// | desktop | `lib/<arch>` | `lib/desktop/<arch>` |
+/// | onecore | `lib/onecore/<arch>` | `lib/onecore/<arch>` |
+/// | store | `lib/<arch>` | `lib/store/<arch>` |
+///
+/// The spectre versions of the desktop and onecore libs are splatted to a
+/// `spectre` directory, eg. `lib/spectre/onecore/<arch>`, there is no spectre
+/// version of the store libs.
+pub(crate) fn crt_lib_dir(
+    variant: Variant,
+    arch: Arch,
+    variants: u32,
+    preserve_ms_arch_notation: bool,
+) -> PathBuf {
+    let spectre = (variants & Variant::Spectre as u32) != 0;
+    let variant_dirs = has_variant_dirs(variants);
+
+    let mut dir = PathBuf::from("lib");
+    match variant {
+        Variant::Desktop => {
+            if spectre {
+                dir.push("spectre");
+            }
+            if variant_dirs {
+                dir.push("desktop");
+            }
+        }
+        Variant::OneCore => {
+            if spectre {
+                dir.push("spectre");
+            }
+            dir.push("onecore");
+        }
+        Variant::Store => {
+            if variant_dirs {
+                dir.push("store");
+            }
+        }
+        Variant::Spectre => unreachable!(),
+    }
+
+    dir.push(if preserve_ms_arch_notation {
+        arch.as_ms_str()
+    } else {
+        arch.as_str()
+    });
+    dir
+}
+
 pub(crate) fn splat(
     config: &SplatConfig,
     roots: &SplatRoots,
@@ -86,13 +597,14 @@ pub(crate) fn splat(
     tree: crate::unpack::FileTree,
     arches: u32,
     variants: u32,
-) -> Result<Option<SdkHeaders>, Error> {
+) -> Result<(Option<SdkHeaders>, crate::PhaseStats, Vec<PathBuf>), Error> {
     struct Mapping<'ft> {
         src: PathBuf,
         target: PathBuf,
         tree: &'ft crate::unpack::FileTree,
         kind: PayloadKind,
         variant: Option<Variant>,
+        arch: Option<Arch>,
     }
 
     let mut src = roots.src.join(&item.payload.filename);
@@ -133,59 +645,54 @@ pub(crate) fn splat(
                 tree,
                 kind,
                 variant,
+                arch: None,
             }]
         }
         PayloadKind::CrtLibs => {
             src.push("lib");
-            let mut target = roots.crt.join("lib");
 
             let spectre = (variants & Variant::Spectre as u32) != 0;
-
-            match item
+            let variant = item
                 .payload
                 .variant
-                .context("CRT libs didn't specify a variant")?
-            {
+                .context("CRT libs didn't specify a variant")?;
+            let arch = item
+                .payload
+                .target_arch
+                .context("CRT libs didn't specify an architecture")?;
+
+            match variant {
                 Variant::Desktop => {
                     if spectre {
                         src.push("spectre");
-                        target.push("spectre");
                     }
                 }
                 Variant::OneCore => {
                     if spectre {
                         src.push("spectre");
-                        target.push("spectre");
                     }
 
                     src.push("onecore");
-                    target.push("onecore");
                 }
                 Variant::Store => {}
                 Variant::Spectre => unreachable!(),
             }
-
-            {
-                let arch = item
-                    .payload
-                    .target_arch
-                    .context("CRT libs didn't specify an architecture")?;
-                src.push(arch.as_ms_str());
-                target.push(if config.preserve_ms_arch_notation {
-                    arch.as_ms_str()
-                } else {
-                    arch.as_str()
-                });
-            }
+            src.push(arch.as_ms_str());
 
             let tree = get_tree(&src)?;
 
             vec![Mapping {
                 src,
-                target,
+                target: roots.crt.join(crt_lib_dir(
+                    variant,
+                    arch,
+                    variants,
+                    config.preserve_ms_arch_notation,
+                )),
                 tree,
                 kind,
-                variant,
+                variant: Some(variant),
+                arch: Some(arch),
             }]
         }
         PayloadKind::SdkHeaders => {
@@ -198,6 +705,7 @@ pub(crate) fn splat(
                 tree,
                 kind,
                 variant,
+                arch: None,
             }]
         }
         PayloadKind::SdkLibs => {
@@ -225,6 +733,7 @@ pub(crate) fn splat(
                 tree,
                 kind,
                 variant,
+                arch: item.payload.target_arch,
             }]
         }
         PayloadKind::SdkStoreLibs => {
@@ -246,10 +755,40 @@ pub(crate) fn splat(
                         tree,
                         kind,
                         variant,
+                        arch: Some(arch),
                     })
                 })
                 .collect::<Result<Vec<_>, _>>()?
         }
+        PayloadKind::SdkBin => {
+            src.push("bin");
+            let target = roots.sdk.join("bin");
+
+            // Not every tools installer has tools for every architecture
+            Arch::iter(arches)
+                .filter(|arch| match item.payload.target_arch {
+                    Some(ta) => ta == *arch,
+                    None => true,
+                })
+                .filter_map(|arch| {
+                    let src = src.join(arch.as_ms_str());
+                    let tree = get_tree(&src).ok()?;
+
+                    Some(Mapping {
+                        src,
+                        target: target.join(if config.preserve_ms_arch_notation {
+                            arch.as_ms_str()
+                        } else {
+                            arch.as_str()
+                        }),
+                        tree,
+                        kind,
+                        variant,
+                        arch: Some(arch),
+                    })
+                })
+                .collect()
+        }
         PayloadKind::Ucrt => {
             let inc_src = src.join("include/ucrt");
             let tree = get_tree(&inc_src)?;
@@ -260,6 +799,7 @@ pub(crate) fn splat(
                 tree,
                 kind,
                 variant,
+                arch: None,
             }];
 
             src.push("lib/ucrt");
@@ -278,36 +818,69 @@ pub(crate) fn splat(
                     tree,
                     kind,
                     variant,
+                    arch: Some(arch),
                 });
             }
 
             mappings
         }
-    };
+        PayloadKind::Extra => {
+            let layout = item
+                .payload
+                .extra
+                .as_ref()
+                .context("extra download didn't specify a layout")?;
 
-    let include_debug_libs = config.include_debug_libs;
-    let include_debug_symbols = config.include_debug_symbols;
+            src.push("contents");
+            let tree = get_tree(&src)?;
+
+            vec![Mapping {
+                src,
+                target: config.output.join(&layout.target),
+                tree,
+                kind,
+                variant,
+                arch: item.payload.target_arch,
+            }]
+        }
+    };
 
     let mut results = Vec::new();
 
-    item.progress.reset();
+    item.progress.phase(Phase::Splat);
     item.progress
         .set_length(mappings.iter().map(|map| map.tree.stats().1).sum());
-    item.progress.set_message("📦 splatting");
 
     let filter_store = variants & Variant::Store as u32 == 0;
 
+    /// The headers, stats, and the files written along with their sizes
+    type MappingResult = (Option<SdkHeaders>, crate::PhaseStats, Vec<(PathBuf, u64)>);
+
     mappings
         .into_par_iter()
-        .map(|mapping| -> Result<Option<SdkHeaders>, Error> {
+        .map(|mapping| -> Result<MappingResult, Error> {
             struct Dir<'ft> {
                 src: PathBuf,
                 tar: PathBuf,
                 tree: &'ft crate::unpack::FileTree,
             }
 
+            let debug_component = match mapping.kind {
+                PayloadKind::CrtLibs => Some(DebugComponent::Crt),
+                PayloadKind::Ucrt => Some(DebugComponent::Ucrt),
+                _ => None,
+            };
+            let include_debug_libs = debug_component
+                .filter(|dc| config.includes_debug_libs(*dc, mapping.arch))
+                .is_some();
+            let include_debug_symbols = debug_component
+                .filter(|dc| config.includes_debug_symbols(*dc, mapping.arch))
+                .is_some();
+
             let mut sdk_headers = (mapping.kind == PayloadKind::SdkHeaders)
-                .then(|| SdkHeaders::new(mapping.target.clone()));
+                .then(|| SdkHeaders::new(mapping.target.clone(), item.payload.filename.as_str()));
+            let mut stats = crate::PhaseStats::default();
+            let mut written = Vec::new();
 
             let mut dir_stack = vec![Dir {
                 src: mapping.src,
@@ -316,18 +889,25 @@ pub(crate) fn splat(
             }];
 
             while let Some(Dir { src, mut tar, tree }) = dir_stack.pop() {
-                std::fs::create_dir_all(&tar)
-                    .with_context(|| format!("unable to create {}", tar))?;
+                std::fs::create_dir_all(&*long_path(&tar))
+                    .with_context(|| error::io(format!("unable to create {}", tar), &tar))?;
 
                 for (fname, size) in &tree.files {
+                    roots.cancel.check()?;
+
                     // Even if we don't splat 100% of the source files, we still
                     // want to show that we processed them all
                     item.progress.inc(*size);
 
                     let fname_str = fname.as_str();
-                    if mapping.kind == PayloadKind::CrtLibs || mapping.kind == PayloadKind::Ucrt {
+                    if debug_component.is_some() {
                         if !include_debug_symbols && fname.ends_with(".pdb") {
-                            tracing::debug!("skipping {}", fname);
+                            roots.skip(
+                                tar.join(fname),
+                                item.payload.filename.as_str(),
+                                SkipReason::DebugSymbols,
+                                None,
+                            );
                             continue;
                         }
 
@@ -336,10 +916,15 @@ pub(crate) fn splat(
                                 if stripped.ends_with('d')
                                     || stripped.ends_with("d_netcore")
                                     || stripped
-                                        .strip_suffix(|c: char| c.is_digit(10))
-                                        .map_or(false, |fname| fname.ends_with('d'))
+                                        .strip_suffix(|c: char| c.is_ascii_digit())
+                                        .is_some_and(|fname| fname.ends_with('d'))
                                 {
-                                    tracing::debug!("skipping {}", fname);
+                                    roots.skip(
+                                        tar.join(fname),
+                                        item.payload.filename.as_str(),
+                                        SkipReason::DebugLib,
+                                        None,
+                                    );
                                     continue;
                                 }
                             }
@@ -350,17 +935,52 @@ pub(crate) fn splat(
 
                     let src_path = src.join(fname);
 
+                    // PDBs in the symbol server layout are stored by their
+                    // identity, rather than next to the libs they are for
+                    let dst = if config.symbol_server_layout
+                        && debug_component.is_some()
+                        && fname_str.ends_with(".pdb")
+                    {
+                        let contents = std::fs::read(&*long_path(&src_path))
+                            .with_context(|| format!("unable to read {}", src_path))?;
+                        let id = crate::pdb::pdb_id(fname_str, &contents)
+                            .with_context(|| format!("unable to identify {}", src_path))?;
+
+                        let dst = config
+                            .output
+                            .join(crate::pdb::SYMBOL_STORE_DIR)
+                            .join(id.store_path());
+                        if let Some(parent) = dst.parent() {
+                            std::fs::create_dir_all(&*long_path(parent)).with_context(|| {
+                                error::io(format!("unable to create {}", parent), parent)
+                            })?;
+                        }
+                        std::borrow::Cow::Owned(dst)
+                    } else {
+                        std::borrow::Cow::Borrowed(&tar)
+                    };
+
                     if config.copy {
-                        std::fs::copy(&src_path, &tar)
-                            .with_context(|| format!("failed to copy {} to {}", src_path, tar))?;
+                        copy_file(&long_path(&src_path), &long_path(&dst)).with_context(|| {
+                            error::io(format!("failed to copy {} to {}", src_path, dst), &dst)
+                        })?;
                     } else {
-                        std::fs::rename(&src_path, &tar)
-                            .with_context(|| format!("failed to move {} to {}", src_path, tar))?;
+                        std::fs::rename(&*long_path(&src_path), &*long_path(&dst)).with_context(
+                            || error::io(format!("failed to move {} to {}", src_path, dst), &dst),
+                        )?;
                     }
 
+                    stats.files += 1;
+                    stats.bytes += *size;
+
+                    written.push((dst.into_owned(), *size));
+
                     let kind = mapping.kind;
 
                     let mut add_symlinks = || -> Result<(), Error> {
+                        // Each kind gets its own arm, even if it is empty, so that
+                        // the reason it isn't fixed up here stays next to it
+                        #[allow(clippy::match_same_arms)]
                         match kind {
                             // These are all internally consistent and lowercased, so if
                             // a library is including them with different casing that is
@@ -371,7 +991,10 @@ pub(crate) fn splat(
                             // them all for includes and add those that are referenced
                             // incorrectly, but we wait until after all the of headers
                             // have been unpacked before fixing them
-                            PayloadKind::CrtHeaders | PayloadKind::Ucrt => {}
+                            PayloadKind::CrtHeaders
+                            | PayloadKind::Ucrt
+                            | PayloadKind::SdkBin
+                            | PayloadKind::Extra => {}
                             PayloadKind::SdkHeaders => {
                                 if let Some(sdk_headers) = &mut sdk_headers {
                                     let rel_target_path = sdk_headers.get_relative_path(&tar)?;
@@ -392,27 +1015,15 @@ pub(crate) fn splat(
                                         tar.pop();
                                         tar.push(additional_name);
 
-                                        symlink(fname_str, &tar)?;
+                                        roots.symlink(fname_str, &tar)?;
                                     }
                                 }
                             }
-                            PayloadKind::CrtLibs => {
-                                // While _most_ of the libs *stares at Microsoft.VisualC.STLCLR.dll* are lower case,
-                                // sometimes when they are specified as linker arguments, crates will link with
-                                // SCREAMING as if they are angry at the linker, so fix this in the few "common" cases.
-                                // This list is probably not complete, but that's what PRs are for
-                                if let Some(angry_lib) = match fname_str.strip_suffix(".lib") {
-                                    Some("libcmt") => Some("LIBCMT.lib"),
-                                    Some("msvcrt") => Some("MSVCRT.lib"),
-                                    Some("oldnames") => Some("OLDNAMES.lib"),
-                                    _ => None,
-                                } {
-                                    tar.pop();
-                                    tar.push(angry_lib);
-
-                                    symlink(fname_str, &tar)?;
-                                }
-                            }
+                            // While _most_ of the libs *stares at Microsoft.VisualC.STLCLR.dll* are lower case,
+                            // the objects in them reference other libs with `/DEFAULTLIB` in
+                            // SCREAMING case as if they are angry at the linker, but we wait
+                            // until all of the libs have been splatted before fixing them
+                            PayloadKind::CrtLibs => {}
                             PayloadKind::SdkLibs | PayloadKind::SdkStoreLibs => {
                                 // The SDK libraries are just completely inconsistent, but
                                 // all usage I have ever seen just links them with lowercase
@@ -422,11 +1033,11 @@ pub(crate) fn splat(
                                 // them use an uppercase L for the extension. WTF. This also
                                 // applies to the tlb files, so at least they are consistently
                                 // inconsistent
-                                if fname_str.contains(|c: char| c.is_ascii_uppercase()) {
+                                if fname_str.contains(char::is_uppercase) {
                                     tar.pop();
-                                    tar.push(fname_str.to_ascii_lowercase());
+                                    tar.push(fname_str.to_lowercase());
 
-                                    symlink(fname_str, &tar)?;
+                                    roots.symlink(fname_str, &tar)?;
                                 }
 
                                 // There is also this: https://github.com/time-rs/time/blob/v0.3.2/src/utc_offset.rs#L454
@@ -439,7 +1050,7 @@ pub(crate) fn splat(
                                     tar.pop();
                                     tar.push(additional_name);
 
-                                    symlink(fname_str, &tar)?;
+                                    roots.symlink(fname_str, &tar)?;
                                 }
 
                                 // We also need to support SCREAMING case for the library names
@@ -449,7 +1060,7 @@ pub(crate) fn splat(
                                     tar.push(fname_str.to_ascii_uppercase());
                                     tar.set_extension("lib");
 
-                                    symlink(fname_str, &tar)?;
+                                    roots.symlink(fname_str, &tar)?;
                                 }
                             }
                         }
@@ -457,7 +1068,7 @@ pub(crate) fn splat(
                         Ok(())
                     };
 
-                    if config.enable_symlinks {
+                    if roots.enable_symlinks {
                         add_symlinks()?;
                     }
 
@@ -470,7 +1081,14 @@ pub(crate) fn splat(
                 // uwp and store subdirectories
                 if mapping.variant == Some(Variant::Store) && filter_store {
                     if mapping.kind == PayloadKind::CrtLibs {
-                        tracing::debug!("skipping CRT subdirs");
+                        for (dir, _) in &tree.dirs {
+                            roots.skip(
+                                tar.join(dir),
+                                item.payload.filename.as_str(),
+                                SkipReason::StoreVariant,
+                                None,
+                            );
+                        }
 
                         item.progress
                             .inc(tree.dirs.iter().map(|(_, ft)| ft.stats().1).sum());
@@ -488,27 +1106,70 @@ pub(crate) fn splat(
                 }
             }
 
-            Ok(sdk_headers)
+            Ok((sdk_headers, stats, written))
         })
         .collect_into_vec(&mut results);
 
-    item.progress.finish_with_message("📦 splatted");
+    item.progress.finish(Phase::Splat);
 
-    let headers = results.into_iter().collect::<Result<Vec<_>, _>>()?;
+    let mut stats = crate::PhaseStats {
+        payloads: 1,
+        ..Default::default()
+    };
+    let mut headers = None;
+    let mut written = Vec::new();
+
+    for (sdk_headers, mapping_stats, mapping_written) in
+        results.into_iter().collect::<Result<Vec<_>, _>>()?
+    {
+        stats += mapping_stats;
+        headers = headers.or(sdk_headers);
+        written.extend(mapping_written);
+    }
 
-    Ok(headers.into_iter().find_map(|headers| headers))
-}
+    if config.verify {
+        verify_written(&written)
+            .with_context(|| format!("failed to verify splat of {}", item.payload.filename))?;
+    }
 
-#[cfg(unix)]
-#[inline]
-fn symlink(original: &str, link: &Path) -> Result<(), Error> {
-    std::os::unix::fs::symlink(original, link)
-        .with_context(|| format!("unable to symlink from {} to {}", link, original))
+    let mut files = written
+        .into_iter()
+        .map(|(path, _)| Ok(path.strip_prefix(&config.output)?.to_owned()))
+        .collect::<Result<Vec<_>, Error>>()?;
+    files.sort();
+
+    Ok((headers, stats, files))
 }
 
-#[cfg(windows)]
-fn symlink(_original: &str, _link: &Path) -> Result<(), Error> {
-    Ok(())
+/// Checks that each of the files that was splatted exists with the size it
+/// had in the unpack directory, catching copies and renames that silently
+/// failed, eg. on a flaky network filesystem
+fn verify_written(written: &[(PathBuf, u64)]) -> Result<(), Error> {
+    let mut mismatched: Vec<_> = written
+        .par_iter()
+        .filter_map(|(path, size)| match std::fs::metadata(path) {
+            Ok(md) if md.len() == *size => None,
+            Ok(md) => Some(format!(
+                "  {} is {} bytes, expected {}",
+                path,
+                md.len(),
+                size
+            )),
+            Err(err) => Some(format!("  {} could not be read: {}", path, err)),
+        })
+        .collect();
+
+    if mismatched.is_empty() {
+        return Ok(());
+    }
+
+    mismatched.sort();
+
+    anyhow::bail!(
+        "{} splatted files don't match the unpacked files:\n{}",
+        mismatched.len(),
+        mismatched.join("\n")
+    );
 }
 
 pub(crate) fn finalize_splat(
@@ -529,22 +1190,47 @@ pub(crate) fn finalize_splat(
 
     for hdrs in &sdk_headers {
         for (k, v) in &hdrs.inner {
-            let existing = files.insert(
-                k,
-                Header {
-                    root: hdrs,
-                    path: v.clone(),
-                },
-            );
+            match files.entry(k) {
+                std::collections::hash_map::Entry::Vacant(entry) => {
+                    entry.insert(Header {
+                        root: hdrs,
+                        path: v.clone(),
+                    });
+                }
+                // Keep the lowest path so that the outcome doesn't depend on
+                // the order the payloads were splatted in
+                std::collections::hash_map::Entry::Occupied(mut entry) => {
+                    let (kept, discarded) = if *v < entry.get().path {
+                        let previous = entry.insert(Header {
+                            root: hdrs,
+                            path: v.clone(),
+                        });
+                        (v.clone(), previous)
+                    } else {
+                        (
+                            entry.get().path.clone(),
+                            Header {
+                                root: hdrs,
+                                path: v.clone(),
+                            },
+                        )
+                    };
 
-            if let Some(existing) = existing {
-                panic!("already have {} matching {}", existing.path, v);
+                    roots.skip(
+                        discarded.path,
+                        &discarded.root.payload,
+                        SkipReason::DuplicateHeader,
+                        Some(kept),
+                    );
+                }
             }
         }
     }
 
-    let mut includes: std::collections::HashSet<
-        _,
+    // Each include, along with the first header that included it, if any
+    let mut includes: std::collections::HashMap<
+        PathBuf,
+        Option<&Path>,
         std::hash::BuildHasherDefault<twox_hash::XxHash64>,
     > = Default::default();
 
@@ -565,92 +1251,911 @@ pub(crate) fn finalize_splat(
                     return None;
                 }
 
-                rp.contains(|c: char| c.is_ascii_uppercase())
-                    .then(|| PathBuf::from(rp.to_ascii_lowercase()))
+                rp.contains(char::is_uppercase)
+                    .then(|| (PathBuf::from(rp.to_lowercase()), None))
             })
     }));
 
-    let regex = regex::bytes::Regex::new(r#"#include\s+(?:"|<)([^">]+)(?:"|>)?"#).unwrap();
+    // There is a um/gl directory, but of course the OpenGL headers are
+    // always included via GL/ instead :p
+    includes.extend(
+        ["GL/gl.h", "GL/glu.h"]
+            .iter()
+            .map(|include| (PathBuf::from(include), None)),
+    );
 
-    let pb = indicatif::ProgressBar::with_draw_target(files.len() as u64, ctx.draw_target.into())
-        .with_style(
-            indicatif::ProgressStyle::default_bar()
-                .template(
-                    "{spinner:.green} {prefix:.bold} [{elapsed}] {wide_bar:.green} {pos}/{len}",
-                )?
-                .progress_chars("█▇▆▅▄▃▂▁  "),
-        );
+    // The includes scanned by a previous splat, which are reused for any
+    // file whose contents haven't changed
+    let cache_path = ctx.work_dir.join(IncludeCache::NAME);
+    let cached = IncludeCache::load(&cache_path);
+    let mut cache = IncludeCache::default();
 
-    pb.set_prefix("symlinks");
-    pb.set_message("🔍 includes");
+    let pb = ctx.progress.create("symlinks");
+    pb.phase(Phase::ScanIncludes);
+    pb.set_length(files.len() as u64);
 
     // Scan all of the files in the include directory for includes so that
     // we can add symlinks to at least make the SDK headers internally consistent
     for file in files.values() {
-        // Of course, there are files with non-utf8 encoding :p
-        let contents =
-            std::fs::read(&file.path).with_context(|| format!("unable to read {}", file.path))?;
-
-        for caps in regex.captures_iter(&contents) {
-            let rel_path = std::str::from_utf8(&caps[1]).with_context(|| {
-                format!(
-                    "{} contained an include with non-utf8 characters",
-                    file.path
-                )
-            })?;
+        ctx.cancel.check()?;
 
-            // TODO: Some includes, particularly in [wrl](https://docs.microsoft.com/en-us/cpp/cppcx/wrl/windows-runtime-cpp-template-library-wrl?view=msvc-170)
-            // use incorrect `\` path separators, this is hopefully not an issue
-            // since no one cares about that target? But if it is a problem
-            // we'll need to actually modify the include to fix the path. :-/
-            if !includes.contains(Path::new(rel_path)) {
-                includes.insert(PathBuf::from(rel_path));
+        // Of course, there are files with non-utf8 encoding :p
+        let contents = std::fs::read(&file.path)
+            .with_context(|| error::io(format!("unable to read {}", file.path), &file.path))?;
+
+        let key = IncludeCache::key(&file.path, &contents);
+        let scanned = match cached.files.get(&key).cloned() {
+            Some(scanned) => scanned,
+            None => scan_includes(&file.path, &contents)
+                .into_iter()
+                .map(|include| {
+                    std::str::from_utf8(include)
+                        .map(str::to_owned)
+                        .with_context(|| {
+                            format!(
+                                "{} contained an include with non-utf8 characters",
+                                file.path
+                            )
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+
+        for rel_path in &scanned {
+            match includes.get_mut(Path::new(rel_path)) {
+                // Keep the lowest path so that reports are deterministic
+                Some(Some(by)) if file.path.as_path() < *by => *by = &file.path,
+                Some(_) => {}
+                None => {
+                    includes.insert(PathBuf::from(rel_path), Some(&file.path));
+                }
             }
         }
 
+        cache.files.insert(key, scanned);
         pb.inc(1);
     }
 
-    pb.finish();
+    pb.finish(Phase::ScanIncludes);
 
-    for include in includes {
-        let lower_hash = calc_lower_hash(include.as_str());
+    // Failing to write the cache just means the next splat is slower
+    if let Err(err) = cache.save(&cache_path) {
+        tracing::warn!("unable to write include cache {}: {:#}", cache_path, err);
+    }
 
+    // Directories are shared between includes, so only link them once
+    let mut dir_links = std::collections::BTreeSet::new();
+    let mut unresolved = Vec::new();
+    let crt_include = roots.crt.join("include");
+
+    pb.phase(Phase::Symlinks);
+    pb.set_length(includes.len() as u64);
+
+    for (include, included_by) in includes {
+        ctx.cancel.check()?;
+        pb.inc(1);
+
+        // Some includes, particularly in [wrl](https://docs.microsoft.com/en-us/cpp/cppcx/wrl/windows-runtime-cpp-template-library-wrl?view=msvc-170)
+        // use `\` path separators, which are just part of the filename on
+        // other platforms
+        let normalized = PathBuf::from(include.as_str().replace('\\', "/"));
+        let lower_hash = calc_lower_hash(normalized.as_str());
+
+        #[allow(clippy::single_match_else)]
         match files.get(&lower_hash) {
-            Some(disk_file) => match (disk_file.path.file_name(), include.file_name()) {
-                (Some(disk_name), Some(include_name)) if disk_name != include_name => {
+            Some(disk_file) => {
+                if normalized != include {
+                    // Add a file with the literal name of the include to the
+                    // include root that points to the actual file, which
+                    // fixes any casing issues in the same go
+                    let rel_path = disk_file.root.get_relative_path(&disk_file.path)?;
+
                     let mut link = disk_file.path.clone();
-                    link.pop();
-                    link.push(include_name);
-                    symlink(disk_name, &link)?;
+                    for _ in rel_path.components() {
+                        link.pop();
+                    }
+                    link.push(include.as_str());
+
+                    roots.symlink(rel_path.as_str(), &link)?;
+                    continue;
+                }
+
+                // Fix the casing of any directories in the include as well,
+                // eg. `GL/gl.h` when the directory on disk is `gl`
+                let rel_path = disk_file.root.get_relative_path(&disk_file.path)?;
+                if let (Some(disk_dirs), Some(include_dirs)) = (rel_path.parent(), include.parent())
+                {
+                    let mut dir = disk_file.path.clone();
+                    for _ in rel_path.components() {
+                        dir.pop();
+                    }
+
+                    for (disk_dir, include_dir) in disk_dirs.iter().zip(include_dirs.iter()) {
+                        let link = dir.join(include_dir);
+                        if disk_dir != include_dir && !dir_links.contains(&link) {
+                            roots.symlink(disk_dir, &link)?;
+                            dir_links.insert(link);
+                        }
+                        dir.push(disk_dir);
+                    }
+                }
+
+                match (disk_file.path.file_name(), include.file_name()) {
+                    (Some(disk_name), Some(include_name)) if disk_name != include_name => {
+                        let mut link = disk_file.path.clone();
+                        link.pop();
+                        link.push(include_name);
+                        roots.symlink(disk_name, &link)?;
+                    }
+                    _ => {}
                 }
-                _ => {}
-            },
+            }
             None => {
                 tracing::debug!(
                     "SDK include for '{}' was not found in the SDK headers",
                     include
                 );
+
+                // The SDK headers also include the CRT headers, which are all
+                // lower case
+                let in_crt = crt_include.join(&normalized).exists()
+                    || crt_include
+                        .join(normalized.as_str().to_lowercase())
+                        .exists();
+
+                if let (Some(included_by), false) = (included_by, in_crt) {
+                    unresolved.push((include, included_by));
+                }
             }
         }
     }
 
-    // There is a um/gl directory, but of course there is an include for GL/
-    // instead, so fix that as well :p
-    symlink("gl", &roots.sdk.join("include/um/GL"))?;
+    if roots.strict_includes && !unresolved.is_empty() {
+        unresolved.sort();
+
+        let report: Vec<_> = unresolved
+            .iter()
+            .map(|(include, included_by)| format!("  {} (included by {})", include, included_by))
+            .collect();
+
+        anyhow::bail!(
+            "{} SDK includes could not be resolved:\n{}",
+            unresolved.len(),
+            report.join("\n")
+        );
+    }
+
+    link_default_libs(ctx, roots, pb.as_ref())?;
+    pb.finish(Phase::Symlinks);
 
     Ok(())
 }
 
+/// The CRT libs reference the other libs they depend on with `/DEFAULTLIB`
+/// directives embedded in their objects, with whatever casing the author
+/// felt like, eg. `/DEFAULTLIB:"LIBCMT"`, so every lib that is referenced, or
+/// that was specified by the user, but doesn't exist with that exact name,
+/// gets a symlink in each of the lib directories that contain it
+fn link_default_libs(
+    ctx: &Ctx,
+    roots: &SplatRoots,
+    pb: &dyn crate::progress::ProgressSink,
+) -> Result<(), Error> {
+    struct LibDir {
+        path: PathBuf,
+        /// Every entry in the directory, including symlinks
+        names: std::collections::BTreeSet<String>,
+        /// The case folded names of the actual files in the directory
+        files: BTreeMap<String, String>,
+        /// The libs and objects that can contain directives
+        libs: Vec<PathBuf>,
+    }
+
+    let mut dirs: Vec<_> = [roots.crt.join("lib"), roots.sdk.join("lib")]
+        .iter()
+        .filter(|dir| dir.exists())
+        .cloned()
+        .collect();
+    let mut lib_dirs = Vec::new();
+
+    while let Some(path) = dirs.pop() {
+        let mut lib_dir = LibDir {
+            names: Default::default(),
+            files: Default::default(),
+            libs: Vec::new(),
+            path,
+        };
+
+        for entry in std::fs::read_dir(&lib_dir.path)
+            .with_context(|| error::io(format!("unable to read {}", lib_dir.path), &lib_dir.path))?
+        {
+            let entry = entry?;
+            let name = entry.file_name().into_string().map_err(|name| {
+                anyhow::anyhow!("{} contains non utf-8 entry {:?}", lib_dir.path, name)
+            })?;
+            let file_type = entry.file_type()?;
+
+            if file_type.is_dir() {
+                dirs.push(lib_dir.path.join(&name));
+                continue;
+            }
+
+            if file_type.is_file() {
+                let folded = caseless::default_case_fold_str(&name);
+                if lib_dir.path.starts_with(&roots.crt)
+                    && (folded.ends_with(".lib") || folded.ends_with(".obj"))
+                {
+                    lib_dir.libs.push(lib_dir.path.join(&name));
+                }
+                lib_dir.files.insert(folded, name.clone());
+            }
+
+            lib_dir.names.insert(name);
+        }
+
+        lib_dirs.push(lib_dir);
+    }
+
+    let directive =
+        regex::bytes::Regex::new(r#"(?i)[/-]defaultlib:(?:"([^"]+)"|([^\s"\x00]+))"#).unwrap();
+
+    let referenced = lib_dirs
+        .iter()
+        .flat_map(|dir| dir.libs.iter())
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|lib| -> Result<std::collections::BTreeSet<String>, Error> {
+            ctx.cancel.check()?;
+
+            let contents = std::fs::read(lib)
+                .with_context(|| error::io(format!("unable to read {}", lib), lib))?;
+
+            Ok(directive
+                .captures_iter(&contents)
+                .filter_map(|caps| {
+                    let name = caps.get(1).or_else(|| caps.get(2))?.as_bytes();
+                    std::str::from_utf8(name).ok().map(lib_name)
+                })
+                .collect())
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let referenced: std::collections::BTreeSet<_> = referenced
+        .into_iter()
+        .flatten()
+        .chain(roots.lib_symlinks.iter().cloned())
+        .collect();
+
+    pb.inc_length(lib_dirs.len() as u64);
+
+    for mut lib_dir in lib_dirs {
+        pb.inc(1);
+
+        for name in &referenced {
+            if lib_dir.names.contains(name) {
+                continue;
+            }
+
+            if let Some(file) = lib_dir.files.get(&caseless::default_case_fold_str(name)) {
+                roots.symlink(file, &lib_dir.path.join(name))?;
+                lib_dir.names.insert(name.clone());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The extension of a lib name is optional, and defaults to `.lib`
+fn lib_name(name: &str) -> String {
+    if name.contains('.') {
+        name.to_owned()
+    } else {
+        format!("{}.lib", name)
+    }
+}
+
+/// The includes scanned from each SDK header, persisted in the work directory
+/// between splats, keyed by the hash of each header's contents
+#[derive(serde::Serialize, serde::Deserialize, Default)]
+struct IncludeCache {
+    /// Bumped whenever the scanner finds different includes, to discard the
+    /// results of older versions
+    version: u32,
+    files: BTreeMap<u64, Vec<String>>,
+}
+
+impl IncludeCache {
+    const NAME: &'static str = "include-cache.json";
+    const VERSION: u32 = 2;
+
+    fn load(path: &Path) -> Self {
+        let cache = std::fs::read(path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice::<Self>(&contents).ok());
+
+        match cache {
+            Some(cache) if cache.version == Self::VERSION => cache,
+            _ => Self::default(),
+        }
+    }
+
+    fn save(mut self, path: &Path) -> Result<(), Error> {
+        self.version = Self::VERSION;
+        std::fs::write(path, serde_json::to_vec(&self)?)?;
+        Ok(())
+    }
+
+    /// IDL files are scanned differently, so the extension is part of the key
+    fn key(path: &Path, contents: &[u8]) -> u64 {
+        let mut hasher = twox_hash::XxHash64::with_seed(0);
+        hasher.write(contents);
+        if let Some(ext) = path.extension() {
+            hasher.write(ext.to_ascii_lowercase().as_bytes());
+        }
+        hasher.finish()
+    }
+}
+
+/// Every file is scanned for `#include` and `#import`, which covers headers
+/// as well as inline files (`.inl`) and resource scripts (`.rc`, `.rh`), and IDL
+/// files are additionally scanned for `import` statements
+fn scan_includes<'c>(path: &Path, contents: &'c [u8]) -> Vec<&'c [u8]> {
+    let mut found = Vec::new();
+    let mut rest = contents;
+
+    while let Some(hash) = memchr::memchr(b'#', rest) {
+        rest = skip(&rest[hash + 1..], |b| b == b' ' || b == b'\t');
+
+        // `#import` is the MSVC directive for type libraries, eg. `#import "msxml6.tlb"`
+        let args = match rest
+            .strip_prefix(b"include")
+            .or_else(|| rest.strip_prefix(b"import"))
+        {
+            Some(args) => args,
+            None => continue,
+        };
+
+        let path = skip(args, |b| b.is_ascii_whitespace());
+        if path.len() == args.len() {
+            continue;
+        }
+
+        let close = match path.first() {
+            Some(b'"') => b'"',
+            Some(b'<') => b'>',
+            _ => continue,
+        };
+
+        let path = &path[1..];
+        let len = path
+            .iter()
+            .position(|&b| b == close || b == b'"' || b == b'\n' || b == b'\r')
+            .unwrap_or(path.len());
+
+        if len > 0 {
+            found.push(&path[..len]);
+        }
+
+        rest = &path[len..];
+    }
+
+    let is_idl = matches!(path.extension(), Some(ext) if ["idl", "acf", "odl"]
+        .iter()
+        .any(|idl| ext.eq_ignore_ascii_case(idl)));
+
+    if is_idl {
+        scan_imports(contents, &mut found);
+    }
+
+    found
+}
+
+/// midl imports other IDL files with eg. `import "oaidl.idl", "ocidl.idl";`,
+/// which must be at the start of a line
+fn scan_imports<'c>(contents: &'c [u8], found: &mut Vec<&'c [u8]>) {
+    let mut rest = contents;
+
+    while !rest.is_empty() {
+        let line = skip(rest, |b| b.is_ascii_whitespace());
+        rest = match memchr::memchr(b'\n', rest) {
+            Some(eol) => &rest[eol + 1..],
+            None => &[],
+        };
+
+        let args = match line.strip_prefix(b"import") {
+            Some(args) if matches!(args.first(), Some(b) if b.is_ascii_whitespace()) => args,
+            _ => continue,
+        };
+
+        let mut args = match memchr::memchr(b';', args) {
+            Some(end) => {
+                // The statement can span multiple lines
+                rest = &args[end + 1..];
+                &args[..end]
+            }
+            None => continue,
+        };
+
+        while let Some(open) = memchr::memchr(b'"', args) {
+            let quoted = &args[open + 1..];
+            let len = match memchr::memchr(b'"', quoted) {
+                Some(len) => len,
+                None => break,
+            };
+
+            if len > 0 {
+                found.push(&quoted[..len]);
+            }
+            args = &quoted[len + 1..];
+        }
+    }
+}
+
+/// The headers that were shared between the outputs of a splat split by
+/// architecture
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SharedHeaders {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Replaces the CRT and SDK headers in `dst` that are identical to the ones at
+/// the same paths in `src` with hardlinks to them, so that the outputs of a
+/// splat split by architecture only store the arch independent headers once.
+/// Headers that can't be hardlinked, eg. because the outputs are on different
+/// filesystems, are left as copies.
+pub fn share_headers(src: &Path, dst: &Path) -> Result<SharedHeaders, Error> {
+    let mut headers = Vec::new();
+    for dir in ["crt/include", "sdk/include"] {
+        let dir = dst.join(dir);
+        if dir.is_dir() {
+            headers.extend(crate::util::walk_files(&dir)?);
+        }
+    }
+
+    let shared = headers
+        .par_iter()
+        .map(|path| -> Result<Option<u64>, Error> {
+            let original = src.join(path.strip_prefix(dst)?);
+
+            let (original_md, md) = match (std::fs::symlink_metadata(&original), path.metadata()) {
+                (Ok(original_md), Ok(md)) if original_md.is_file() => (original_md, md),
+                _ => return Ok(None),
+            };
+
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                if original_md.dev() == md.dev() && original_md.ino() == md.ino() {
+                    return Ok(None);
+                }
+            }
+
+            if original_md.len() != md.len() || std::fs::read(&original)? != std::fs::read(path)? {
+                return Ok(None);
+            }
+
+            // Link to a temporary name first so that the header is never
+            // missing if linking fails part way
+            let link = PathBuf::from(format!("{}.xwin-link", path));
+            if let Err(err) = std::fs::hard_link(&original, &link) {
+                tracing::debug!("unable to hardlink {} to {}: {}", path, original, err);
+                return Ok(None);
+            }
+            std::fs::rename(&link, path)
+                .with_context(|| format!("unable to replace {} with a hardlink", path))?;
+
+            Ok(Some(md.len()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(shared
+        .into_iter()
+        .flatten()
+        .fold(SharedHeaders::default(), |acc, bytes| SharedHeaders {
+            files: acc.files + 1,
+            bytes: acc.bytes + bytes,
+        }))
+}
+
+/// Skips the leading bytes that match the predicate
+fn skip(bytes: &[u8], pred: impl Fn(u8) -> bool) -> &[u8] {
+    let start = bytes.iter().position(|&b| !pred(b)).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
 use std::hash::Hasher;
 
+/// Hashes the path with Unicode case folding, so that every casing of a path
+/// has the same hash
 #[inline]
 fn calc_lower_hash(path: &str) -> u64 {
     let mut hasher = twox_hash::XxHash64::with_seed(0);
+    hasher.write(caseless::default_case_fold_str(path).as_bytes());
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scans_includes() {
+        let header = b"#include <WinDef.h>\n#include \"specstrings.h\"\nimport \"unknwn.idl\";\n#import \"MsXml6.tlb\"";
+        assert_eq!(
+            scan_includes(Path::new("um/windows.h"), header),
+            vec![&b"WinDef.h"[..], b"specstrings.h", b"MsXml6.tlb"]
+        );
+
+        let idl = b"import \"OAIdl.idl\",\n    \"ocidl.idl\";\n#include \"winerror.h\"\n";
+        assert_eq!(
+            scan_includes(Path::new("um/objidl.IDL"), idl),
+            vec![&b"winerror.h"[..], b"OAIdl.idl", b"ocidl.idl"]
+        );
 
-    for c in path.chars().map(|c| c.to_ascii_lowercase() as u8) {
-        hasher.write_u8(c);
+        let odd =
+            b"#  include\t<sal.h>\n#include <unterminated\n#define include\n#include\"nope.h\"";
+        assert_eq!(
+            scan_includes(Path::new("um/odd.h"), odd),
+            vec![&b"sal.h"[..], b"unterminated"]
+        );
     }
 
-    hasher.finish()
+    #[test]
+    fn qualifies_crt_lib_dirs() {
+        let dir = |variant, variants| crt_lib_dir(variant, Arch::X86_64, variants, false);
+
+        let desktop = Variant::Desktop as u32;
+        assert_eq!(dir(Variant::Desktop, desktop), "lib/x86_64");
+        assert_eq!(dir(Variant::Store, desktop), "lib/x86_64");
+
+        let mixed = desktop | Variant::OneCore as u32;
+        assert_eq!(dir(Variant::Desktop, mixed), "lib/desktop/x86_64");
+        assert_eq!(dir(Variant::OneCore, mixed), "lib/onecore/x86_64");
+        assert_eq!(dir(Variant::Store, mixed), "lib/store/x86_64");
+
+        let spectre = mixed | Variant::Spectre as u32;
+        assert_eq!(dir(Variant::Desktop, spectre), "lib/spectre/desktop/x86_64");
+        assert_eq!(dir(Variant::Store, spectre), "lib/store/x86_64");
+    }
+
+    /// Creates the SDK headers in a temporary splat output, returning the
+    /// include directory they were written to
+    #[cfg(unix)]
+    fn sdk_headers(td: &tempfile::TempDir, headers: &[(&str, &str)]) -> (PathBuf, SdkHeaders) {
+        let include = PathBuf::from_path_buf(td.path().join("sdk/include")).unwrap();
+        let mut sdk_headers = SdkHeaders::new(include.clone(), "headers.msi");
+
+        for (path, contents) in headers {
+            let path = include.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, contents).unwrap();
+
+            let rel = sdk_headers.get_relative_path(&path).unwrap();
+            sdk_headers
+                .inner
+                .insert(calc_lower_hash(rel.as_str()), path.clone());
+        }
+
+        (include, sdk_headers)
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn detects_case_sensitivity() {
+        let td = tempfile::TempDir::new().unwrap();
+        let dir = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        assert!(is_case_sensitive(&dir).unwrap());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn copies_files() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        let contents: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        std::fs::write(root.join("src.lib"), &contents).unwrap();
+        copy_file(&root.join("src.lib"), &root.join("dst.lib")).unwrap();
+
+        assert_eq!(std::fs::read(root.join("dst.lib")).unwrap(), contents);
+    }
+
+    #[test]
+    fn verifies_written() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        std::fs::write(root.join("a.lib"), "abc").unwrap();
+
+        verify_written(&[(root.join("a.lib"), 3)]).unwrap();
+
+        let err = verify_written(&[(root.join("a.lib"), 4), (root.join("b.lib"), 1)])
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("2 splatted files"));
+        assert!(err.contains("a.lib is 3 bytes, expected 4"));
+    }
+
+    #[test]
+    fn folds_case() {
+        assert_eq!(calc_lower_hash("WinDef.h"), calc_lower_hash("windef.h"));
+        assert_eq!(calc_lower_hash("Ärger.h"), calc_lower_hash("äRGER.H"));
+        assert_eq!(calc_lower_hash("Straße.h"), calc_lower_hash("STRASSE.H"));
+        // Non-ASCII characters were previously truncated to a single byte
+        assert_ne!(calc_lower_hash("\u{0104}.h"), calc_lower_hash("\u{4}.h"));
+    }
+
+    #[cfg(unix)]
+    fn roots(td: &tempfile::TempDir) -> SplatRoots {
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        SplatRoots {
+            crt: root.join("crt"),
+            sdk: root.join("sdk"),
+            src: root.join("unpack"),
+            lib_symlinks: Vec::new(),
+            strict_includes: false,
+            enable_symlinks: true,
+            cancel: Default::default(),
+            collisions: Default::default(),
+            skipped: Default::default(),
+            symlinks: Default::default(),
+        }
+    }
+
+    #[test]
+    fn locks_output() {
+        let td = tempfile::TempDir::new().unwrap();
+        let output = PathBuf::from_path_buf(td.path().join("splat")).unwrap();
+
+        let lock = lock_output(&output).unwrap();
+        assert!(td.path().join(".splat.xwin-lock").exists());
+
+        let err = lock_output(&output).unwrap_err();
+        assert!(err.to_string().contains("locked by another xwin process"));
+
+        drop(lock);
+        lock_output(&output).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shares_headers() {
+        use std::os::unix::fs::MetadataExt;
+
+        let td = tempfile::TempDir::new().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        for arch in ["x86_64", "aarch64"] {
+            let out = root.join(arch);
+            std::fs::create_dir_all(out.join("sdk/include/um")).unwrap();
+            std::fs::create_dir_all(out.join(format!("sdk/lib/um/{}", arch))).unwrap();
+            std::fs::write(out.join("sdk/include/um/windows.h"), "// windows").unwrap();
+            std::fs::write(out.join("sdk/include/um/differs.h"), arch).unwrap();
+            std::fs::write(out.join(format!("sdk/lib/um/{}/kernel32.lib", arch)), "").unwrap();
+        }
+
+        let shared = share_headers(&root.join("x86_64"), &root.join("aarch64")).unwrap();
+        assert_eq!(shared.files, 1);
+        assert_eq!(shared.bytes, 10);
+
+        let ino = |path: &str| std::fs::metadata(root.join(path)).unwrap().ino();
+        assert_eq!(
+            ino("x86_64/sdk/include/um/windows.h"),
+            ino("aarch64/sdk/include/um/windows.h")
+        );
+        assert_ne!(
+            ino("x86_64/sdk/include/um/differs.h"),
+            ino("aarch64/sdk/include/um/differs.h")
+        );
+
+        // Already shared headers are skipped
+        let shared = share_headers(&root.join("x86_64"), &root.join("aarch64")).unwrap();
+        assert_eq!(shared.files, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fixes_includes() {
+        let td = tempfile::TempDir::new().unwrap();
+        let (include, sdk_headers) = sdk_headers(
+            &td,
+            &[
+                (
+                    "um/Windows.h",
+                    "#include <WinDef.h>\n#include \"wrl\\Client.h\"\n#include <WRL/Implements.h>",
+                ),
+                ("um/gl/gl.h", ""),
+                ("shared/windef.h", ""),
+                ("winrt/wrl/client.h", ""),
+                ("winrt/wrl/implements.h", ""),
+            ],
+        );
+
+        let ctx = Ctx::with_temp(crate::progress::Hidden).unwrap();
+        finalize_splat(&ctx, &roots(&td), vec![sdk_headers]).unwrap();
+
+        assert_eq!(
+            std::fs::read_link(include.join("shared/WinDef.h")).unwrap(),
+            std::path::Path::new("windef.h")
+        );
+        assert_eq!(
+            std::fs::read_link(include.join("winrt/wrl\\Client.h")).unwrap(),
+            std::path::Path::new("wrl/client.h")
+        );
+        assert_eq!(
+            std::fs::read_link(include.join("winrt/WRL")).unwrap(),
+            std::path::Path::new("wrl")
+        );
+        assert!(include.join("winrt/WRL/Implements.h").exists());
+        assert!(include.join("um/GL/gl.h").exists());
+        // Lower case variants of mixed case headers
+        assert!(include.join("um/windows.h").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn caches_includes() {
+        let td = tempfile::TempDir::new().unwrap();
+        let headers = [("um/Windows.h", "#include <WinDef.h>"), ("um/windef.h", "")];
+
+        let ctx = Ctx::with_temp(crate::progress::Hidden).unwrap();
+        let (include, headers_1) = sdk_headers(&td, &headers);
+        finalize_splat(&ctx, &roots(&td), vec![headers_1]).unwrap();
+
+        let cache_path = ctx.work_dir.join(IncludeCache::NAME);
+        let mut cache = IncludeCache::load(&cache_path);
+        let key = IncludeCache::key(Path::new("Windows.h"), headers[0].1.as_bytes());
+        assert_eq!(cache.files[&key], vec!["WinDef.h".to_owned()]);
+
+        // The cached includes are used instead of rescanning the file
+        cache.files.insert(key, vec!["WINDEF.H".to_owned()]);
+        cache.save(&cache_path).unwrap();
+
+        std::fs::remove_dir_all(&include).unwrap();
+        let (include, headers_2) = sdk_headers(&td, &headers);
+        finalize_splat(&ctx, &roots(&td), vec![headers_2]).unwrap();
+
+        assert!(include.join("um/WINDEF.H").exists());
+        assert!(!include.join("um/WinDef.h").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_duplicate_headers() {
+        let td = tempfile::TempDir::new().unwrap();
+        let (include, headers_1) = sdk_headers(&td, &[("um/WinDef.h", "")]);
+        let (_include, mut headers_2) = sdk_headers(&td, &[("um/windef.h", "")]);
+        headers_2.payload = "other.msi".to_owned();
+
+        let ctx = Ctx::with_temp(crate::progress::Hidden).unwrap();
+        let roots = roots(&td);
+        finalize_splat(&ctx, &roots, vec![headers_2, headers_1]).unwrap();
+
+        assert_eq!(
+            roots.take_skipped(),
+            vec![SkippedFile {
+                path: include.join("um/windef.h"),
+                payload: "other.msi".to_owned(),
+                reason: SkipReason::DuplicateHeader,
+                kept: Some(include.join("um/WinDef.h")),
+            }]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_unresolved_includes() {
+        let td = tempfile::TempDir::new().unwrap();
+        let (_include, sdk_headers) = sdk_headers(
+            &td,
+            &[
+                (
+                    "um/Windows.h",
+                    "#include <stdlib.h>\n#include <winrt/base.h>\n#include <windef.h>",
+                ),
+                ("um/windef.h", ""),
+            ],
+        );
+
+        let mut roots = roots(&td);
+        std::fs::create_dir_all(roots.crt.join("include")).unwrap();
+        std::fs::write(roots.crt.join("include/stdlib.h"), "").unwrap();
+
+        let ctx = Ctx::with_temp(crate::progress::Hidden).unwrap();
+        roots.strict_includes = true;
+
+        let err = finalize_splat(&ctx, &roots, vec![sdk_headers]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "1 SDK includes could not be resolved:\n  winrt/base.h (included by {}/sdk/include/um/Windows.h)",
+                td.path().display()
+            )
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_default_libs() {
+        let td = tempfile::TempDir::new().unwrap();
+        let mut roots = roots(&td);
+        roots.lib_symlinks.push(lib_name("WS2_32"));
+
+        let crt = roots.crt.join("lib/x86_64");
+        let sdk = roots.sdk.join("lib/um/x86_64");
+        std::fs::create_dir_all(&crt).unwrap();
+        std::fs::create_dir_all(&sdk).unwrap();
+
+        std::fs::write(
+            crt.join("libcmt.lib"),
+            b"\0 /DEFAULTLIB:\"LIBCMT\" /DEFAULTLIB:\"OLDNAMES\" \0-defaultlib:Kernel32.lib\0",
+        )
+        .unwrap();
+        std::fs::write(crt.join("oldnames.lib"), "").unwrap();
+        std::fs::write(sdk.join("kernel32.Lib"), "").unwrap();
+        std::fs::write(sdk.join("ws2_32.lib"), "").unwrap();
+
+        let ctx = Ctx::with_temp(crate::progress::Hidden).unwrap();
+        finalize_splat(&ctx, &roots, Vec::new()).unwrap();
+
+        for (link, target) in [
+            (crt.join("LIBCMT.lib"), "libcmt.lib"),
+            (crt.join("OLDNAMES.lib"), "oldnames.lib"),
+            (sdk.join("Kernel32.lib"), "kernel32.Lib"),
+            (sdk.join("WS2_32.lib"), "ws2_32.lib"),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                std::fs::read_link(link).unwrap(),
+                std::path::Path::new(target)
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_collisions() {
+        let td = tempfile::TempDir::new().unwrap();
+        let roots = roots(&td);
+        std::fs::create_dir_all(&roots.sdk).unwrap();
+
+        let file = roots.sdk.join("winsock2.h");
+        std::fs::write(&file, "").unwrap();
+        roots.symlink("WinSock2.h", &file).unwrap();
+
+        let link = roots.sdk.join("WS2_32.lib");
+        roots.symlink("ws2_32.lib", &link).unwrap();
+        roots.symlink("Ws2_32.lib", &link).unwrap();
+        roots.symlink("Ws2_32.lib", &link).unwrap();
+
+        // Emulates a case insensitive filesystem, where the link resolves to
+        // the original
+        let alias = roots.sdk.join("WinDef.h");
+        std::fs::write(roots.sdk.join("windef.h"), "").unwrap();
+        std::fs::hard_link(roots.sdk.join("windef.h"), &alias).unwrap();
+        roots.symlink("windef.h", &alias).unwrap();
+
+        assert_eq!(
+            std::fs::read_link(&link).unwrap(),
+            std::path::Path::new("Ws2_32.lib")
+        );
+        assert_eq!(
+            roots.into_collisions(),
+            vec![
+                SymlinkCollision {
+                    link,
+                    kept: Some("Ws2_32.lib".to_owned()),
+                    discarded: "ws2_32.lib".to_owned(),
+                },
+                SymlinkCollision {
+                    link: file,
+                    kept: None,
+                    discarded: "WinSock2.h".to_owned(),
+                },
+            ]
+        );
+    }
 }
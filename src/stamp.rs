@@ -0,0 +1,684 @@
+//! The stamp that is written to the root of every splat output, recording the
+//! options it was splatted with along with the payloads and the files each of
+//! them was splatted to, so that `xwin update` can upgrade the output in place
+//! to a newer SDK or CRT by only splatting the payloads that changed, and
+//! removing the files of the payloads that no longer exist upstream.
+
+use crate::{util::Sha256, Path, PathBuf, Payload, PayloadKind};
+use anyhow::{Context as _, Error};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A payload that was splatted to the output
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct StampedPayload {
+    pub filename: String,
+    pub version: String,
+    #[serde(serialize_with = "crate::util::serialize_sha256")]
+    pub sha256: Sha256,
+    pub kind: PayloadKind,
+    /// The files the payload was splatted to, relative to the output
+    #[serde(default)]
+    pub files: Vec<PathBuf>,
+}
+
+impl StampedPayload {
+    fn new(payload: &Payload, splatted: &BTreeMap<String, Vec<PathBuf>>) -> Self {
+        Self {
+            filename: payload.filename.to_string(),
+            version: payload.version.clone(),
+            sha256: payload.sha256.clone(),
+            kind: payload.kind,
+            files: splatted
+                .get(payload.filename.as_str())
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// The options that affect which files are splatted, see [`crate::SplatConfig`]
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StampedOptions {
+    pub include_debug_libs: bool,
+    pub include_debug_symbols: bool,
+    #[serde(default)]
+    pub include_debug_libs_for: Vec<String>,
+    #[serde(default)]
+    pub include_debug_symbols_for: Vec<String>,
+    pub enable_symlinks: bool,
+    pub preserve_ms_arch_notation: bool,
+    #[serde(default)]
+    pub lib_symlinks: Vec<String>,
+    #[serde(default)]
+    pub strict_includes: bool,
+    #[serde(default)]
+    pub thin_import_libs: bool,
+    #[serde(default)]
+    pub symbol_server_layout: bool,
+    #[serde(default)]
+    pub include_sdk_bin: bool,
+    #[serde(default)]
+    pub include_signing_tools: bool,
+}
+
+impl StampedOptions {
+    pub fn new(config: &crate::SplatConfig, sdk_tools: crate::SdkTools) -> Self {
+        Self {
+            include_debug_libs: config.include_debug_libs,
+            include_debug_symbols: config.include_debug_symbols,
+            include_debug_libs_for: config
+                .include_debug_libs_for
+                .iter()
+                .map(|target| target.to_string())
+                .collect(),
+            include_debug_symbols_for: config
+                .include_debug_symbols_for
+                .iter()
+                .map(|target| target.to_string())
+                .collect(),
+            enable_symlinks: config.enable_symlinks,
+            preserve_ms_arch_notation: config.preserve_ms_arch_notation,
+            lib_symlinks: config.lib_symlinks.clone(),
+            strict_includes: config.strict_includes,
+            thin_import_libs: config.thin_import_libs,
+            symbol_server_layout: config.symbol_server_layout,
+            include_sdk_bin: sdk_tools.build,
+            include_signing_tools: sdk_tools.signing,
+        }
+    }
+}
+
+/// The options of a splat with the same defaults as the CLI
+impl Default for StampedOptions {
+    fn default() -> Self {
+        Self::new(
+            &crate::SplatConfig::new(PathBuf::new()),
+            crate::SdkTools::default(),
+        )
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Stamp {
+    version: u32,
+    pub arches: Vec<String>,
+    pub variants: Vec<String>,
+    pub options: StampedOptions,
+    /// The payloads, sorted by filename
+    pub payloads: Vec<StampedPayload>,
+    /// The directories, relative to the output, that the CRT libs were
+    /// splatted to for each variant and architecture, which differ depending
+    /// on whether one or several variants were splatted
+    #[serde(default)]
+    pub crt_lib_dirs: BTreeMap<String, BTreeMap<String, PathBuf>>,
+    /// The `--sdk-version` requirement the SDK was selected with, which is
+    /// also used to select the SDK when the output is updated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sdk_requirement: Option<String>,
+    /// The patches that were applied to the output once it was splatted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patches: Vec<crate::patch::AppliedPatch>,
+    /// The absolute path of the directory the patches were applied from,
+    /// which they are applied from again when an update replaces the files
+    /// they changed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub patches_dir: Option<PathBuf>,
+}
+
+impl Stamp {
+    pub const NAME: &'static str = ".xwin-stamp.json";
+    const VERSION: u32 = 1;
+
+    /// Creates the stamp for a splat of the payloads, taking the files each of
+    /// them was splatted to from [`crate::Stats::splatted_files`]
+    pub fn new(
+        config: &crate::SplatConfig,
+        arches: u32,
+        variants: u32,
+        sdk_tools: crate::SdkTools,
+        payloads: &[Payload],
+        splatted: &BTreeMap<String, Vec<PathBuf>>,
+    ) -> Self {
+        let mut payloads: Vec<_> = payloads
+            .iter()
+            .map(|payload| StampedPayload::new(payload, splatted))
+            .collect();
+        payloads.sort_by(|a, b| a.filename.cmp(&b.filename));
+
+        // The store libs are always splatted, as they contain libraries that
+        // are linked by default
+        let crt_lib_dirs = crate::Variant::iter_variants(variants | crate::Variant::Store as u32)
+            .filter(|variant| *variant != crate::Variant::Spectre)
+            .map(|variant| {
+                let dirs = crate::Arch::iter(arches)
+                    .map(|arch| {
+                        let dir = crate::splat::crt_lib_dir(
+                            variant,
+                            arch,
+                            variants,
+                            config.preserve_ms_arch_notation,
+                        );
+                        (arch.as_str().to_owned(), Path::new("crt").join(dir))
+                    })
+                    .collect();
+                (variant.as_str().to_owned(), dirs)
+            })
+            .collect();
+
+        Self {
+            version: Self::VERSION,
+            arches: crate::Arch::iter(arches)
+                .map(|arch| arch.as_str().to_owned())
+                .collect(),
+            variants: crate::Variant::iter_variants(variants)
+                .map(|variant| variant.as_str().to_owned())
+                .collect(),
+            options: StampedOptions::new(config, sdk_tools),
+            payloads,
+            crt_lib_dirs,
+            sdk_requirement: None,
+            patches: Vec::new(),
+            patches_dir: None,
+        }
+    }
+
+    /// Loads the stamp from the root of the output
+    pub fn load(output: &Path) -> Result<Self, Error> {
+        let path = output.join(Self::NAME);
+        let contents = std::fs::read(&path).with_context(|| {
+            format!("unable to read {}, was {} splatted by xwin?", path, output)
+        })?;
+        let stamp: Self =
+            serde_json::from_slice(&contents).with_context(|| format!("invalid stamp {}", path))?;
+
+        anyhow::ensure!(
+            stamp.version == Self::VERSION,
+            "stamp {} has unsupported version {}",
+            path,
+            stamp.version
+        );
+
+        Ok(stamp)
+    }
+
+    /// Writes the stamp to the root of the output
+    pub fn save(&self, output: &Path) -> Result<(), Error> {
+        let path = output.join(Self::NAME);
+        std::fs::write(&path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("unable to write {}", path))
+    }
+
+    /// The mask of the architectures the output was splatted for
+    pub fn arch_mask(&self) -> Result<u32, Error> {
+        self.arches
+            .iter()
+            .try_fold(0, |acc, arch| Ok(acc | arch.parse::<crate::Arch>()? as u32))
+    }
+
+    /// The mask of the variants the output was splatted for
+    pub fn variant_mask(&self) -> Result<u32, Error> {
+        self.variants.iter().try_fold(0, |acc, variant| {
+            Ok(acc | variant.parse::<crate::Variant>()? as u32)
+        })
+    }
+
+    /// The version of the CRT that was splatted, if any
+    pub fn crt_version(&self) -> Option<&str> {
+        self.version_of(PayloadKind::CrtHeaders)
+    }
+
+    /// The version of the SDK that was splatted, if any
+    pub fn sdk_version(&self) -> Option<&str> {
+        self.version_of(PayloadKind::SdkHeaders)
+    }
+
+    fn version_of(&self, kind: PayloadKind) -> Option<&str> {
+        self.payloads
+            .iter()
+            .find(|sp| sp.kind == kind)
+            .map(|sp| sp.version.as_str())
+    }
+
+    /// The components that were splatted, ie. the ones that have payloads
+    pub fn components(&self) -> Vec<crate::Component> {
+        crate::Component::ALL
+            .iter()
+            .copied()
+            .filter(|component| {
+                self.payloads
+                    .iter()
+                    .any(|sp| sp.kind.component() == Some(*component))
+            })
+            .collect()
+    }
+
+    pub fn sdk_tools(&self) -> crate::SdkTools {
+        crate::SdkTools {
+            build: self.options.include_sdk_bin,
+            signing: self.options.include_signing_tools,
+        }
+    }
+
+    /// Recreates the config the output was splatted with
+    pub fn config(&self, output: PathBuf) -> Result<crate::SplatConfig, Error> {
+        let targets = |targets: &[String]| {
+            targets
+                .iter()
+                .map(|target| target.parse())
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(crate::SplatConfig {
+            include_debug_libs: self.options.include_debug_libs,
+            include_debug_symbols: self.options.include_debug_symbols,
+            include_debug_libs_for: targets(&self.options.include_debug_libs_for)?,
+            include_debug_symbols_for: targets(&self.options.include_debug_symbols_for)?,
+            enable_symlinks: self.options.enable_symlinks,
+            preserve_ms_arch_notation: self.options.preserve_ms_arch_notation,
+            lib_symlinks: self.options.lib_symlinks.clone(),
+            strict_includes: self.options.strict_includes,
+            thin_import_libs: self.options.thin_import_libs,
+            symbol_server_layout: self.options.symbol_server_layout,
+            ..crate::SplatConfig::new(output)
+        })
+    }
+
+    /// Determines what needs to be done to update the output to the payloads
+    /// selected from a newer manifest
+    pub fn update(&self, payloads: &[Payload]) -> Update {
+        let stamped: BTreeMap<_, _> = self
+            .payloads
+            .iter()
+            .map(|sp| (sp.filename.as_str(), sp))
+            .collect();
+
+        let unchanged = |payload: &Payload| {
+            stamped
+                .get(payload.filename.as_str())
+                .filter(|sp| sp.sha256 == payload.sha256)
+                .copied()
+        };
+
+        // The SDK headers are scanned together to add the symlinks for their
+        // includes, so if any of them change they are all splatted again
+        let headers_changed = payloads
+            .iter()
+            .filter(|payload| payload.kind == PayloadKind::SdkHeaders)
+            .any(|payload| unchanged(payload).is_none())
+            || self
+                .payloads
+                .iter()
+                .filter(|sp| sp.kind == PayloadKind::SdkHeaders)
+                .any(|sp| {
+                    !payloads
+                        .iter()
+                        .any(|payload| payload.filename.as_str() == sp.filename)
+                });
+
+        let mut update = Update::default();
+        let mut selected = BTreeSet::new();
+
+        for payload in payloads {
+            selected.insert(payload.filename.as_str());
+
+            match unchanged(payload) {
+                Some(sp) if !(headers_changed && sp.kind == PayloadKind::SdkHeaders) => {
+                    update.retained.push(sp.clone());
+                }
+                _ => {
+                    if let Some(sp) = stamped.get(payload.filename.as_str()) {
+                        update.removed.push((*sp).clone());
+                    }
+                    update.splat.push(payload.clone());
+                }
+            }
+        }
+
+        update.removed.extend(
+            self.payloads
+                .iter()
+                .filter(|sp| !selected.contains(sp.filename.as_str()))
+                .cloned(),
+        );
+
+        update.retained.sort_by(|a, b| a.filename.cmp(&b.filename));
+        update.removed.sort_by(|a, b| a.filename.cmp(&b.filename));
+        update
+            .splat
+            .sort_by(|a, b| a.filename.as_str().cmp(b.filename.as_str()));
+        update
+    }
+
+    /// Creates the stamp for the output once the update has been applied
+    pub fn updated(&self, update: Update, splatted: &BTreeMap<String, Vec<PathBuf>>) -> Self {
+        // The patched files of the payloads that were splatted again have
+        // been replaced, so the patches no longer apply to them
+        let replaced: BTreeSet<_> = update
+            .removed
+            .iter()
+            .flat_map(|sp| sp.files.iter())
+            .collect();
+
+        let mut patches = self.patches.clone();
+        for patch in &mut patches {
+            patch.files.retain(|pf| !replaced.contains(&pf.path));
+            patch.removed.retain(|path| !replaced.contains(path));
+        }
+        patches.retain(|patch| !patch.files.is_empty() || !patch.removed.is_empty());
+
+        let mut payloads = update.retained;
+        payloads.extend(
+            update
+                .splat
+                .iter()
+                .map(|payload| StampedPayload::new(payload, splatted)),
+        );
+        payloads.sort_by(|a, b| a.filename.cmp(&b.filename));
+
+        Self {
+            payloads,
+            patches,
+            ..self.clone()
+        }
+    }
+
+    /// Applies the patches of the stamp the output had before it was updated
+    /// again, to the files the update replaced with unpatched versions,
+    /// returning the patches that couldn't be applied, eg. because the new
+    /// version of a file no longer matches the patch
+    pub fn reapply_patches(&mut self, previous: &Stamp, output: &Path) -> Vec<(String, Error)> {
+        let mut failed = Vec::new();
+
+        for prev in &previous.patches {
+            let current = self
+                .patches
+                .iter()
+                .position(|patch| patch.name == prev.name);
+
+            let replaced: BTreeSet<&Path> = prev
+                .files
+                .iter()
+                .map(|pf| pf.path.as_path())
+                .chain(prev.removed.iter().map(PathBuf::as_path))
+                .filter(|path| match current {
+                    Some(i) => {
+                        let cur = &self.patches[i];
+                        !cur.files.iter().any(|pf| pf.path == *path)
+                            && !cur.removed.iter().any(|removed| removed == path)
+                    }
+                    None => true,
+                })
+                .collect();
+
+            if replaced.is_empty() {
+                continue;
+            }
+
+            let reapplied = match &self.patches_dir {
+                Some(dir) => crate::patch::reapply(dir, &prev.name, &replaced, output),
+                None => Err(anyhow::anyhow!(
+                    "the directory it was applied from wasn't recorded"
+                )),
+            };
+
+            match (reapplied, current) {
+                (Ok(applied), Some(i)) => {
+                    let cur = &mut self.patches[i];
+                    cur.files.extend(applied.files);
+                    cur.removed.extend(applied.removed);
+                }
+                (Ok(applied), None) => self.patches.push(applied),
+                (Err(err), _) => failed.push((prev.name.clone(), err)),
+            }
+        }
+
+        failed
+    }
+}
+
+/// The changes needed to update an output, see [`Stamp::update`]
+#[derive(Default, Debug)]
+pub struct Update {
+    /// The payloads that haven't changed, whose files are kept
+    pub retained: Vec<StampedPayload>,
+    /// The payloads that have changed or are no longer selected, whose files
+    /// are removed
+    pub removed: Vec<StampedPayload>,
+    /// The payloads that need to be splatted
+    pub splat: Vec<Payload>,
+}
+
+impl Update {
+    /// True if the output is already up to date
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.splat.is_empty()
+    }
+
+    /// Removes the files of the removed payloads from the output, along with
+    /// any symlinks left dangling and directories left empty, returning the
+    /// number of files removed. Files that were also splatted by a retained
+    /// payload are kept.
+    pub fn remove_stale(&self, output: &Path) -> Result<usize, Error> {
+        let keep: BTreeSet<_> = self
+            .retained
+            .iter()
+            .flat_map(|sp| sp.files.iter())
+            .collect();
+
+        let mut removed = 0;
+        for path in self
+            .removed
+            .iter()
+            .flat_map(|sp| sp.files.iter())
+            .filter(|path| !keep.contains(path))
+        {
+            let path = output.join(path);
+            if std::fs::symlink_metadata(&path).is_ok() {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("unable to remove {}", path))?;
+                removed += 1;
+            }
+        }
+
+        for dir in ["crt", "sdk"] {
+            let dir = output.join(dir);
+            if dir.exists() {
+                sweep(&dir)?;
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+/// Recursively removes dangling symlinks and empty directories, returning true
+/// if the directory itself is now empty
+fn sweep(dir: &Path) -> Result<bool, Error> {
+    let mut empty = true;
+
+    for entry in std::fs::read_dir(dir).with_context(|| format!("unable to read {}", dir))? {
+        let entry = entry.with_context(|| format!("unable to read entry from {}", dir))?;
+        let path = PathBuf::from_path_buf(entry.path())
+            .map_err(|pb| anyhow::anyhow!("path {} is not a valid utf-8 path", pb.display()))?;
+
+        let ft = entry.file_type()?;
+        if ft.is_symlink() && std::fs::metadata(&path).is_err() {
+            std::fs::remove_file(&path).with_context(|| format!("unable to remove {}", path))?;
+        } else if ft.is_dir() && sweep(&path)? {
+            std::fs::remove_dir(&path).with_context(|| format!("unable to remove {}", path))?;
+        } else {
+            empty = false;
+        }
+    }
+
+    Ok(empty)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn payload(filename: &str, sha: u8, kind: PayloadKind) -> Payload {
+        Payload {
+            sha256: Sha256([sha; 32]),
+            ..Payload::test(filename, kind)
+        }
+    }
+
+    #[test]
+    fn updates_changed_payloads() {
+        let old = [
+            payload("crt.vsix", 1, PayloadKind::CrtHeaders),
+            payload("libs.msi", 1, PayloadKind::SdkLibs),
+            payload("headers_a.msi", 1, PayloadKind::SdkHeaders),
+            payload("headers_b.msi", 1, PayloadKind::SdkHeaders),
+            payload("gone.msi", 1, PayloadKind::SdkLibs),
+        ];
+
+        let splatted = old
+            .iter()
+            .map(|pay| {
+                (
+                    pay.filename.to_string(),
+                    vec![PathBuf::from(format!("sdk/{}", pay.filename))],
+                )
+            })
+            .collect();
+
+        let stamp = Stamp::new(
+            &crate::SplatConfig::new("splat"),
+            crate::Arch::X86_64 as u32,
+            crate::Variant::Desktop as u32,
+            crate::SdkTools::default(),
+            &old,
+            &splatted,
+        );
+
+        let td = tempfile::TempDir::new().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        stamp.save(&root).unwrap();
+        let stamp = Stamp::load(&root).unwrap();
+        assert_eq!(stamp.arches, ["x86_64"]);
+        assert_eq!(stamp.arch_mask().unwrap(), crate::Arch::X86_64 as u32);
+
+        assert!(stamp.update(&old).is_empty());
+
+        let new = [
+            payload("crt.vsix", 1, PayloadKind::CrtHeaders),
+            payload("libs.msi", 2, PayloadKind::SdkLibs),
+            payload("headers_a.msi", 1, PayloadKind::SdkHeaders),
+            payload("headers_b.msi", 2, PayloadKind::SdkHeaders),
+        ];
+
+        let update = stamp.update(&new);
+        let names = |sps: &[StampedPayload]| -> Vec<String> {
+            sps.iter().map(|sp| sp.filename.clone()).collect()
+        };
+
+        assert_eq!(names(&update.retained), ["crt.vsix"]);
+        // Every header payload is splatted again if any of them changed
+        assert_eq!(
+            names(&update.removed),
+            ["gone.msi", "headers_a.msi", "headers_b.msi", "libs.msi"]
+        );
+        assert_eq!(
+            update
+                .splat
+                .iter()
+                .map(|pay| pay.filename.as_str())
+                .collect::<Vec<_>>(),
+            ["headers_a.msi", "headers_b.msi", "libs.msi"]
+        );
+
+        std::fs::create_dir_all(root.join("sdk/nested")).unwrap();
+        std::fs::create_dir_all(root.join("crt")).unwrap();
+        for sp in &stamp.payloads {
+            std::fs::write(root.join(&sp.files[0]), "").unwrap();
+        }
+
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("../gone.msi", root.join("sdk/nested/Gone.msi")).unwrap();
+
+        assert_eq!(update.remove_stale(&root).unwrap(), 4);
+        assert!(root.join("sdk/crt.vsix").exists());
+        assert!(!root.join("sdk/gone.msi").exists());
+        assert!(!root.join("sdk/nested").exists());
+        assert!(root.join("crt").exists());
+
+        let updated = stamp.updated(update, &BTreeMap::new());
+        assert_eq!(
+            names(&updated.payloads),
+            ["crt.vsix", "headers_a.msi", "headers_b.msi", "libs.msi"]
+        );
+        assert_eq!(updated.payloads[3].sha256, Sha256([2; 32]));
+    }
+
+    #[test]
+    fn reapplies_patches() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+        let output = root.join("splat");
+        let patches = root.join("patches");
+
+        std::fs::create_dir_all(output.join("sdk/include")).unwrap();
+        std::fs::create_dir_all(patches.join("files/sdk/include")).unwrap();
+        std::fs::write(
+            patches.join("fix.patch"),
+            "--- a/sdk/include/a.h\n+++ b/sdk/include/a.h\n@@ -1 +1 @@\n-broken\n+fixed\n",
+        )
+        .unwrap();
+        std::fs::write(patches.join("files/sdk/include/b.h"), "override").unwrap();
+
+        let splat = |output: &Path| {
+            std::fs::write(output.join("sdk/include/a.h"), "broken\n").unwrap();
+            std::fs::write(output.join("sdk/include/b.h"), "original").unwrap();
+        };
+        splat(&output);
+
+        let old = [payload("headers.msi", 1, PayloadKind::SdkHeaders)];
+        let splatted: BTreeMap<_, _> = std::iter::once((
+            "headers.msi".to_owned(),
+            vec!["sdk/include/a.h".into(), "sdk/include/b.h".into()],
+        ))
+        .collect();
+
+        let mut stamp = Stamp::new(
+            &crate::SplatConfig::new(output.clone()),
+            crate::Arch::X86_64 as u32,
+            crate::Variant::Desktop as u32,
+            crate::SdkTools::default(),
+            &old,
+            &splatted,
+        );
+        stamp.patches = crate::patch::apply_dir(&patches, &output).unwrap();
+        stamp.patches_dir = Some(patches);
+
+        // The update splats the headers again, replacing the patched files
+        let update = stamp.update(&[payload("headers.msi", 2, PayloadKind::SdkHeaders)]);
+        splat(&output);
+
+        let mut updated = stamp.updated(update, &splatted);
+        assert!(updated.patches.is_empty());
+        assert!(updated.reapply_patches(&stamp, &output).is_empty());
+
+        assert_eq!(
+            std::fs::read_to_string(output.join("sdk/include/a.h")).unwrap(),
+            "fixed\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(output.join("sdk/include/b.h")).unwrap(),
+            "override"
+        );
+        assert_eq!(updated.patches, stamp.patches);
+
+        // Patches that no longer apply are reported by name
+        splat(&output);
+        std::fs::write(output.join("sdk/include/a.h"), "changed\n").unwrap();
+        let update = stamp.update(&[payload("headers.msi", 3, PayloadKind::SdkHeaders)]);
+        let mut updated = stamp.updated(update, &splatted);
+        let failed = updated.reapply_patches(&stamp, &output);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, "fix.patch");
+        assert_eq!(updated.patches.len(), 1);
+    }
+}
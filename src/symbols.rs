@@ -0,0 +1,295 @@
+//! An index of the symbols exported by each library in a splat output, so that
+//! the library that needs to be linked for a particular function, eg.
+//! `CreateFileW` from `kernel32.lib`, can be looked up rather than guessed.
+//!
+//! The symbols are read from the first linker member of each `.lib` archive,
+//! which lists every public symbol defined by the archive's members, including
+//! the `__imp_` symbols of import libraries.
+
+use crate::{Path, PathBuf};
+use anyhow::{Context as _, Error};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Reads the public symbols from the first linker member of an archive,
+/// returning an empty list if the archive doesn't have one
+pub fn archive_symbols(contents: &[u8]) -> Result<Vec<String>, Error> {
+    let member = match crate::ar::members(contents)?.next() {
+        Some(member) => member?,
+        None => return Ok(Vec::new()),
+    };
+
+    if member.name() != crate::ar::LINKER_MEMBER_NAME {
+        return Ok(Vec::new());
+    }
+    let member = &member.data;
+
+    // A big endian count of symbols, followed by the offset of the member
+    // that defines each one, and then their null terminated names
+    let count = member
+        .get(..4)
+        .map(|count| u32::from_be_bytes([count[0], count[1], count[2], count[3]]) as usize)
+        .context("truncated linker member")?;
+    let names = count
+        .checked_mul(4)
+        .and_then(|offsets| member.get(4 + offsets..))
+        .context("truncated linker member")?;
+
+    Ok(names
+        .split(|b| *b == 0)
+        .take(count)
+        .filter(|name| !name.is_empty())
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .collect())
+}
+
+/// The symbols exported by every library in a splat output
+#[derive(serde::Serialize, serde::Deserialize, Default, Debug)]
+pub struct SymbolIndex {
+    /// Bumped whenever the format changes, to discard older indices
+    version: u32,
+    /// The path of each library, relative to the output
+    pub libs: Vec<PathBuf>,
+    /// Each symbol, along with the indices in `libs` of the libraries that
+    /// export it
+    pub symbols: BTreeMap<String, Vec<u32>>,
+}
+
+/// The libraries that export the symbols matching a query
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+pub struct Found<'index> {
+    /// The path of the library, relative to the output
+    pub lib: &'index Path,
+    /// The symbols in the library that matched, without any `__imp_` prefix
+    pub symbols: BTreeSet<&'index str>,
+}
+
+impl SymbolIndex {
+    /// The name of the index in the root of the output
+    pub const NAME: &'static str = ".xwin-symbols.json";
+    const VERSION: u32 = 1;
+
+    /// Reads the symbols of every `.lib` in the output. Symlinks are ignored,
+    /// as they only add alternate casings of the same libraries.
+    pub fn build(root: &Path) -> Result<Self, Error> {
+        let mut lib_paths: Vec<_> = crate::util::walk_files(root)?
+            .into_iter()
+            .filter(|path| {
+                path.extension()
+                    .filter(|ext| ext.eq_ignore_ascii_case("lib"))
+                    .is_some()
+            })
+            .collect();
+        lib_paths.sort();
+
+        let exports = lib_paths
+            .par_iter()
+            .map(|path| -> Result<Vec<String>, Error> {
+                let contents =
+                    std::fs::read(path).with_context(|| format!("unable to read {}", path))?;
+
+                Ok(archive_symbols(&contents).unwrap_or_else(|err| {
+                    tracing::debug!("unable to read symbols from {}: {:#}", path, err);
+                    Vec::new()
+                }))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut index = Self {
+            version: Self::VERSION,
+            ..Default::default()
+        };
+
+        for (path, symbols) in lib_paths.iter().zip(exports) {
+            if symbols.is_empty() {
+                continue;
+            }
+
+            let lib = index.libs.len() as u32;
+            index.libs.push(
+                path.strip_prefix(root)
+                    .map_or_else(|_| path.clone(), |rel| rel.to_owned()),
+            );
+
+            for symbol in symbols {
+                let libs = index.symbols.entry(symbol).or_default();
+                if libs.last() != Some(&lib) {
+                    libs.push(lib);
+                }
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// Loads the index from the root of the output, returning `None` if it
+    /// doesn't exist, was written by a different version, or is older than
+    /// the output's stamp, ie. the output was updated after it was written
+    pub fn load(root: &Path) -> Result<Option<Self>, Error> {
+        let path = root.join(Self::NAME);
+
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|md| md.modified()).ok();
+        match (
+            modified(&path),
+            modified(&root.join(crate::stamp::Stamp::NAME)),
+        ) {
+            (None, _) => return Ok(None),
+            (Some(index), Some(stamp)) if stamp > index => return Ok(None),
+            _ => {}
+        }
+
+        let contents = std::fs::read(&path).with_context(|| format!("unable to read {}", path))?;
+        let index: Self = serde_json::from_slice(&contents)
+            .with_context(|| format!("unable to deserialize {}", path))?;
+
+        Ok((index.version == Self::VERSION).then_some(index))
+    }
+
+    /// Writes the index to the root of the output
+    pub fn save(&self, root: &Path) -> Result<(), Error> {
+        let path = root.join(Self::NAME);
+        std::fs::write(&path, serde_json::to_vec(self)?)
+            .with_context(|| format!("unable to write {}", path))
+    }
+
+    /// Finds the libraries that export a symbol. The query matches the
+    /// symbol's name as is, as well as with the decorations of the x86
+    /// calling conventions, eg. `_CreateFileW@28` for `CreateFileW`, and can
+    /// contain `*` to match any sequence of characters, eg. `CreateFile*`.
+    pub fn find(&self, query: &str) -> Vec<Found<'_>> {
+        let mut found = BTreeMap::<usize, BTreeSet<&str>>::new();
+
+        for (symbol, libs) in &self.symbols {
+            let name = symbol.strip_prefix("__imp_").unwrap_or(symbol);
+            if !matches_symbol(query, name) {
+                continue;
+            }
+
+            for lib in libs {
+                found.entry(*lib as usize).or_default().insert(name);
+            }
+        }
+
+        found
+            .into_iter()
+            .filter_map(|(lib, symbols)| {
+                Some(Found {
+                    lib: self.libs.get(lib)?,
+                    symbols,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Removes the decorations added to C symbols by the x86 calling conventions,
+/// ie. `_name` for cdecl, `_name@N` for stdcall, and `@name@N` for fastcall
+fn undecorate(name: &str) -> Option<&str> {
+    let name = name.strip_prefix(['_', '@'])?;
+
+    match name.rsplit_once('@') {
+        Some((name, size)) if !size.is_empty() && size.bytes().all(|b| b.is_ascii_digit()) => {
+            Some(name)
+        }
+        _ => Some(name),
+    }
+}
+
+fn matches_symbol(query: &str, name: &str) -> bool {
+    let matches = |name: &str| {
+        if query.contains('*') {
+            crate::util::glob(query, name)
+        } else {
+            query == name
+        }
+    };
+
+    matches(name) || undecorate(name).filter(|name| matches(name)).is_some()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Builds an archive with only a first linker member
+    fn archive(symbols: &[&str]) -> Vec<u8> {
+        let mut member = (symbols.len() as u32).to_be_bytes().to_vec();
+        for _ in symbols {
+            member.extend_from_slice(&0u32.to_be_bytes());
+        }
+        for symbol in symbols {
+            member.extend_from_slice(symbol.as_bytes());
+            member.push(0);
+        }
+
+        let mut contents = b"!<arch>\n".to_vec();
+        contents.extend_from_slice(
+            format!(
+                "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+                "/",
+                0,
+                "",
+                "",
+                0,
+                member.len()
+            )
+            .as_bytes(),
+        );
+        contents.extend_from_slice(&member);
+        contents
+    }
+
+    #[test]
+    fn finds_symbols() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        let write = |path: &str, symbols: &[&str]| {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, archive(symbols)).unwrap();
+        };
+
+        write(
+            "sdk/lib/um/x86_64/kernel32.lib",
+            &["CreateFileA", "CreateFileW", "__imp_CreateFileW"],
+        );
+        write(
+            "sdk/lib/um/x86/kernel32.lib",
+            &["_CreateFileW@28", "__imp__CreateFileW@28"],
+        );
+        write("sdk/lib/um/x86_64/user32.lib", &["MessageBoxW"]);
+        std::fs::write(root.join("sdk/lib/um/x86_64/not-an-archive.lib"), "nope").unwrap();
+
+        assert_eq!(
+            archive_symbols(&archive(&["a", "b"])).unwrap(),
+            vec!["a".to_owned(), "b".to_owned()]
+        );
+        assert!(archive_symbols(b"nope").is_err());
+
+        let index = SymbolIndex::build(&root).unwrap();
+        assert_eq!(index.libs.len(), 3);
+
+        index.save(&root).unwrap();
+        let index = SymbolIndex::load(&root).unwrap().unwrap();
+
+        let found = index.find("CreateFileW");
+        assert_eq!(
+            found,
+            vec![
+                Found {
+                    lib: Path::new("sdk/lib/um/x86/kernel32.lib"),
+                    symbols: ["_CreateFileW@28"].iter().copied().collect(),
+                },
+                Found {
+                    lib: Path::new("sdk/lib/um/x86_64/kernel32.lib"),
+                    symbols: ["CreateFileW"].iter().copied().collect(),
+                },
+            ]
+        );
+
+        let found = index.find("CreateFile*");
+        assert_eq!(found[1].symbols.len(), 2);
+        assert!(index.find("MessageBoxA").is_empty());
+    }
+}
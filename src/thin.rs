@@ -0,0 +1,367 @@
+//! Thinning of import libraries, for outputs that are only ever linked
+//! against. The import libs in the SDK are archives of tiny short import
+//! members, along with a few regular COFF objects for the import descriptors,
+//! which carry `.debug$S` sections that the linker has no use for. On top of
+//! that every archive has a second linker member, which duplicates the symbol
+//! table of the first one in a different order, for faster lookups by
+//! `link.exe`.
+//!
+//! Thinning drops the debug sections of the descriptor objects and the second
+//! linker member, which both `link.exe` and `lld-link` fall back from to the
+//! first linker member, and leaves the libs otherwise identical.
+
+use crate::{
+    ar::{u16_at, u32_at, Member, LINKER_MEMBER_NAME, MEMBER_HEADER_LEN},
+    Path, PathBuf,
+};
+use anyhow::{Context as _, Error};
+use rayon::prelude::*;
+
+const COFF_HEADER_LEN: usize = 20;
+const SECTION_HEADER_LEN: usize = 40;
+const SYMBOL_LEN: usize = 18;
+
+/// The totals of a thinning pass
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Thinned {
+    /// The number of import libs that were thinned
+    pub libs: u64,
+    /// The total size of the libs before they were thinned
+    pub before: u64,
+    /// The total size of the libs after they were thinned
+    pub after: u64,
+}
+
+impl std::ops::AddAssign for Thinned {
+    fn add_assign(&mut self, rhs: Self) {
+        self.libs += rhs.libs;
+        self.before += rhs.before;
+        self.after += rhs.after;
+    }
+}
+
+fn set_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Removes the `.debug$` sections from a COFF object, returning `None` if it
+/// doesn't have any
+fn strip_debug_sections(obj: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    let truncated = || anyhow::anyhow!("truncated object");
+
+    let num_sections = u16_at(obj, 2).ok_or_else(truncated)? as usize;
+    let symbol_table = u32_at(obj, 8).ok_or_else(truncated)? as usize;
+    let num_symbols = u32_at(obj, 12).ok_or_else(truncated)? as usize;
+    let optional_header = u16_at(obj, 16).ok_or_else(truncated)? as usize;
+
+    let sections_start = COFF_HEADER_LEN + optional_header;
+    let sections_end = sections_start + num_sections * SECTION_HEADER_LEN;
+    anyhow::ensure!(obj.len() >= sections_end, "truncated section table");
+
+    let is_debug =
+        |index: usize| obj[sections_start + index * SECTION_HEADER_LEN..].starts_with(b".debug$");
+
+    // The headers of the debug sections are kept, so only the ones that
+    // still have data need to be stripped
+    let has_data = |index: usize| {
+        u32_at(obj, sections_start + index * SECTION_HEADER_LEN + 16).unwrap_or_default() != 0
+    };
+    if !(0..num_sections).any(|index| is_debug(index) && has_data(index)) {
+        return Ok(None);
+    }
+
+    let mut thin = obj[..sections_end].to_vec();
+
+    for index in 0..num_sections {
+        let header = sections_start + index * SECTION_HEADER_LEN;
+        let raw_size = u32_at(obj, header + 16).ok_or_else(truncated)? as usize;
+        let raw_ptr = u32_at(obj, header + 20).ok_or_else(truncated)? as usize;
+        let reloc_ptr = u32_at(obj, header + 24).ok_or_else(truncated)? as usize;
+        let num_relocs = u16_at(obj, header + 32).ok_or_else(truncated)? as usize;
+
+        if is_debug(index) {
+            for field in &[16, 20, 24] {
+                set_u32(&mut thin, header + field, 0);
+            }
+            thin[header + 32..header + 34].copy_from_slice(&0u16.to_le_bytes());
+            continue;
+        }
+
+        if raw_ptr != 0 && raw_size != 0 {
+            let data = obj.get(raw_ptr..raw_ptr + raw_size).ok_or_else(truncated)?;
+            let new_ptr = thin.len() as u32;
+            thin.extend_from_slice(data);
+            set_u32(&mut thin, header + 20, new_ptr);
+        }
+
+        if reloc_ptr != 0 && num_relocs != 0 {
+            // Relocations are 10 bytes each
+            let relocs = obj
+                .get(reloc_ptr..reloc_ptr + num_relocs * 10)
+                .ok_or_else(truncated)?;
+            let new_ptr = thin.len() as u32;
+            thin.extend_from_slice(relocs);
+            set_u32(&mut thin, header + 24, new_ptr);
+        }
+    }
+
+    if symbol_table != 0 {
+        // The string table immediately follows the symbols, and starts with
+        // its own size
+        let strings = symbol_table + num_symbols * SYMBOL_LEN;
+        let strings_len = u32_at(obj, strings).unwrap_or(4) as usize;
+        let symbols = obj
+            .get(symbol_table..strings + strings_len)
+            .ok_or_else(truncated)?;
+
+        let new_ptr = thin.len() as u32;
+        thin.extend_from_slice(symbols);
+        set_u32(&mut thin, 8, new_ptr);
+    }
+
+    Ok(Some(thin))
+}
+
+/// Thins an import library, returning `None` if it isn't an import library,
+/// or if there is nothing to remove
+pub fn thin_import_lib(contents: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    let mut members = crate::ar::members(contents)?.collect::<Result<Vec<_>, _>>()?;
+
+    let is_linker_member = |index: usize| {
+        members
+            .get(index)
+            .filter(|member| member.name() == LINKER_MEMBER_NAME)
+            .is_some()
+    };
+    if !members.iter().any(Member::is_short_import) || !is_linker_member(0) {
+        return Ok(None);
+    }
+
+    let mut changed = false;
+
+    // The second linker member is the only other one named `/`
+    if is_linker_member(1) {
+        members.remove(1);
+        changed = true;
+    }
+
+    for member in members.iter_mut().skip(1) {
+        if member.is_anonymous() || member.name().starts_with(b"//") {
+            continue;
+        }
+
+        if let Some(thin) = strip_debug_sections(&member.data)? {
+            member.data = thin.into();
+            changed = true;
+        }
+    }
+
+    if !changed {
+        return Ok(None);
+    }
+
+    // The member offsets in the first linker member need to be updated for
+    // the new positions of the members
+    let mut offsets = std::collections::HashMap::new();
+    let mut offset = 8;
+    for member in &members {
+        offsets.insert(member.offset as u32, offset as u32);
+        offset += MEMBER_HEADER_LEN + member.data.len() + (member.data.len() & 1);
+    }
+
+    let mut linker = members[0].data.to_vec();
+    let count = linker
+        .get(..4)
+        .map(|count| u32::from_be_bytes([count[0], count[1], count[2], count[3]]) as usize)
+        .context("truncated linker member")?;
+    anyhow::ensure!(linker.len() >= 4 + count * 4, "truncated linker member");
+
+    for i in 0..count {
+        let pos = 4 + i * 4;
+        let old = u32::from_be_bytes([
+            linker[pos],
+            linker[pos + 1],
+            linker[pos + 2],
+            linker[pos + 3],
+        ]);
+        let new = offsets
+            .get(&old)
+            .with_context(|| format!("linker member references unknown offset {}", old))?;
+        linker[pos..pos + 4].copy_from_slice(&new.to_be_bytes());
+    }
+    members[0].data = linker.into();
+
+    let mut thin = Vec::with_capacity(offset);
+    thin.extend_from_slice(b"!<arch>\n");
+    for member in &members {
+        thin.extend_from_slice(&member.header[..48]);
+        thin.extend_from_slice(format!("{:<10}", member.data.len()).as_bytes());
+        thin.extend_from_slice(b"`\n");
+        thin.extend_from_slice(&member.data);
+        if member.data.len() & 1 == 1 {
+            thin.push(b'\n');
+        }
+    }
+
+    Ok(Some(thin))
+}
+
+/// Thins every import library in the list of files, relative to the root
+pub fn thin_libs(root: &Path, files: &[PathBuf]) -> Result<Thinned, Error> {
+    files
+        .par_iter()
+        .filter(|path| {
+            path.extension()
+                .filter(|ext| ext.eq_ignore_ascii_case("lib"))
+                .is_some()
+        })
+        .map(|path| -> Result<Thinned, Error> {
+            let path = root.join(path);
+
+            // Symlinks just point to another lib, which is thinned itself
+            let md = std::fs::symlink_metadata(&path)
+                .with_context(|| format!("unable to stat {}", path))?;
+            if !md.is_file() {
+                return Ok(Thinned::default());
+            }
+
+            let contents =
+                std::fs::read(&path).with_context(|| format!("unable to read {}", path))?;
+            let thin = match thin_import_lib(&contents) {
+                Ok(Some(thin)) => thin,
+                Ok(None) => return Ok(Thinned::default()),
+                Err(err) => {
+                    tracing::debug!("unable to thin {}: {:#}", path, err);
+                    return Ok(Thinned::default());
+                }
+            };
+
+            let tmp = path.with_extension("lib.thin");
+            std::fs::write(&tmp, &thin).with_context(|| format!("unable to write {}", tmp))?;
+            std::fs::rename(&tmp, &path)
+                .with_context(|| format!("unable to rename {} to {}", tmp, path))?;
+
+            Ok(Thinned {
+                libs: 1,
+                before: contents.len() as u64,
+                after: thin.len() as u64,
+            })
+        })
+        .try_reduce(Thinned::default, |mut total, thinned| {
+            total += thinned;
+            Ok(total)
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn member(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut member = format!(
+            "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+            name,
+            0,
+            "",
+            "",
+            0,
+            data.len()
+        )
+        .into_bytes();
+        member.extend_from_slice(data);
+        if data.len() & 1 == 1 {
+            member.push(b'\n');
+        }
+        member
+    }
+
+    /// An object with a `.text` and `.debug$S` section, and a single symbol
+    fn object() -> Vec<u8> {
+        let mut obj = vec![0u8; COFF_HEADER_LEN + 2 * SECTION_HEADER_LEN];
+        obj[..2].copy_from_slice(&0x8664u16.to_le_bytes());
+        obj[2..4].copy_from_slice(&2u16.to_le_bytes());
+
+        let text = obj.len();
+        obj.extend_from_slice(b"code");
+        let debug = obj.len();
+        obj.extend_from_slice(&[0xdb; 33]);
+        let symbols = obj.len();
+        obj.extend_from_slice(&[0x5a; SYMBOL_LEN]);
+        obj.extend_from_slice(&4u32.to_le_bytes());
+
+        set_u32(&mut obj, 8, symbols as u32);
+        set_u32(&mut obj, 12, 1);
+
+        for (index, (name, ptr, size)) in [(".text", text, 4), (".debug$S", debug, 33)]
+            .iter()
+            .enumerate()
+        {
+            let header = COFF_HEADER_LEN + index * SECTION_HEADER_LEN;
+            obj[header..header + name.len()].copy_from_slice(name.as_bytes());
+            set_u32(&mut obj, header + 16, *size as u32);
+            set_u32(&mut obj, header + 20, *ptr as u32);
+        }
+
+        obj
+    }
+
+    #[test]
+    fn thins_import_libs() {
+        let short_import = [0, 0, 0xff, 0xff, 0, 0, 0x64, 0x86, 1, 2, 3];
+        let obj = object();
+
+        let first_len = 4 + 2 * 4 + "__IMPORT_DESCRIPTOR_a\0CreateFileW\0".len();
+        let mut lib = b"!<arch>\n".to_vec();
+        let obj_offset = lib.len() + 2 * MEMBER_HEADER_LEN + first_len + 7 + 1;
+        let import_offset = obj_offset + MEMBER_HEADER_LEN + obj.len() + (obj.len() & 1);
+
+        let mut first = 2u32.to_be_bytes().to_vec();
+        first.extend_from_slice(&(obj_offset as u32).to_be_bytes());
+        first.extend_from_slice(&(import_offset as u32).to_be_bytes());
+        first.extend_from_slice(b"__IMPORT_DESCRIPTOR_a\0CreateFileW\0");
+        assert_eq!(first.len(), first_len);
+
+        lib.extend(member("/", &first));
+        lib.extend(member("/", b"second!"));
+        assert_eq!(lib.len(), obj_offset);
+        lib.extend(member("a.dll/", &obj));
+        assert_eq!(lib.len(), import_offset);
+        lib.extend(member("a.dll/", &short_import));
+
+        let thin = thin_import_lib(&lib).unwrap().unwrap();
+        assert!(thin.len() < lib.len());
+
+        let members = crate::ar::members(&thin)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(members.len(), 3);
+
+        // The offsets of the members are updated
+        let offsets: Vec<_> = members[0].data[4..12]
+            .chunks(4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .collect();
+        assert_eq!(offsets, vec![members[1].offset, members[2].offset]);
+        assert_eq!(&*members[2].data, &short_import[..]);
+
+        // The debug section is gone, but everything else is intact
+        let obj = &members[1].data;
+        assert_eq!(obj.len(), object().len() - 33);
+        let text = u32_at(obj, COFF_HEADER_LEN + 20).unwrap() as usize;
+        assert_eq!(&obj[text..text + 4], b"code");
+        assert_eq!(
+            u32_at(obj, COFF_HEADER_LEN + SECTION_HEADER_LEN + 16),
+            Some(0)
+        );
+        let symbols = u32_at(obj, 8).unwrap() as usize;
+        assert_eq!(obj[symbols], 0x5a);
+
+        // Thinning is idempotent, and static libs are left alone
+        assert!(thin_import_lib(&thin).unwrap().is_none());
+        let mut static_lib = b"!<arch>\n".to_vec();
+        static_lib.extend(member("/", &0u32.to_be_bytes()));
+        static_lib.extend(member("a.obj/", &object()));
+        assert!(thin_import_lib(&static_lib).unwrap().is_none());
+    }
+}
@@ -1,5 +1,11 @@
-use crate::{download::PayloadContents, Ctx, Error, Path, PathBuf};
-use anyhow::Context as _;
+use crate::{
+    download::PayloadContents,
+    error,
+    progress::{Phase, ProgressSink},
+    util::long_path,
+    Ctx, Path, PathBuf,
+};
+use anyhow::{Context as _, Error};
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub(crate) struct UnpackMeta {
@@ -75,8 +81,11 @@ fn read_unpack_dir(root: PathBuf) -> Result<FileTree, Error> {
     let mut root_tree = FileTree::new();
 
     fn read(src: PathBuf, tree: &mut FileTree) -> Result<(), Error> {
-        for entry in std::fs::read_dir(&src).with_context(|| format!("unable to read {}", src))? {
-            let entry = entry.with_context(|| format!("unable to read entry from {}", src))?;
+        for entry in std::fs::read_dir(&src)
+            .with_context(|| error::io(format!("unable to read {}", src), &src))?
+        {
+            let entry = entry
+                .with_context(|| error::io(format!("unable to read entry from {}", src), &src))?;
 
             let src_name = PathBuf::from_path_buf(entry.file_name().into()).map_err(|_pb| {
                 anyhow::anyhow!(
@@ -123,8 +132,7 @@ pub(crate) fn unpack(
     item: &crate::WorkItem,
     contents: PayloadContents,
 ) -> Result<FileTree, Error> {
-    item.progress.reset();
-    item.progress.set_message("📂 unpacking...");
+    item.progress.phase(Phase::Unpack);
 
     let output_dir = match ctx.prep_unpack(&item.payload)? {
         crate::ctx::Unpack::Present { output_dir, .. } => {
@@ -165,6 +173,8 @@ pub(crate) fn unpack(
             let mut total_compressed = 0;
 
             for findex in to_extract {
+                ctx.cancel.check()?;
+
                 let mut file = zip.by_index(findex).unwrap();
                 let zip_path = Path::new(file.name());
                 let mut fs_path = output_dir.clone();
@@ -178,17 +188,21 @@ pub(crate) fn unpack(
 
                 if let Some(parent) = fs_path.parent() {
                     if !parent.exists() {
-                        std::fs::create_dir_all(parent)
-                            .with_context(|| format!("unable to create unpack dir '{}'", parent))?;
+                        std::fs::create_dir_all(&*long_path(parent)).with_context(|| {
+                            error::io(format!("unable to create unpack dir '{}'", parent), parent)
+                        })?;
                     }
                 }
 
-                let mut dest = std::fs::File::create(&fs_path).with_context(|| {
-                    format!(
-                        "unable to create {} to decompress {} from {}",
-                        fs_path,
-                        file.name(),
-                        pkg,
+                let mut dest = std::fs::File::create(&*long_path(&fs_path)).with_context(|| {
+                    error::io(
+                        format!(
+                            "unable to create {} to decompress {} from {}",
+                            fs_path,
+                            file.name(),
+                            pkg,
+                        ),
+                        &fs_path,
                     )
                 })?;
 
@@ -211,6 +225,120 @@ pub(crate) fn unpack(
 
             (tree, total_compressed)
         }
+        PayloadContents::Extra(contents) => {
+            use crate::extra::{archive_path, Unpack};
+            use std::io::Read;
+
+            let layout = item
+                .payload
+                .extra
+                .as_ref()
+                .context("extra download doesn't specify a layout")?;
+
+            // The contents are unpacked to a subdirectory so that they can't
+            // be confused with the metadata we write to the unpack directory
+            let mut tree = FileTree::new();
+            let mut write = |path: PathBuf, reader: &mut dyn Read| -> Result<(), Error> {
+                ctx.cancel.check()?;
+
+                let tree_path = Path::new("contents").join(path);
+                let fs_path = output_dir.join(&tree_path);
+
+                if let Some(parent) = fs_path.parent() {
+                    if !parent.exists() {
+                        std::fs::create_dir_all(&*long_path(parent)).with_context(|| {
+                            error::io(format!("unable to create unpack dir '{}'", parent), parent)
+                        })?;
+                    }
+                }
+
+                let mut dest = std::fs::File::create(&*long_path(&fs_path)).with_context(|| {
+                    error::io(
+                        format!("unable to create {} to unpack {}", fs_path, pkg),
+                        &fs_path,
+                    )
+                })?;
+
+                let size = std::io::copy(reader, &mut dest)
+                    .with_context(|| format!("unable to unpack {} from {}", tree_path, pkg))?;
+
+                item.progress.inc(size);
+                tree.push(&tree_path, size);
+                Ok(())
+            };
+
+            match layout.unpack {
+                Unpack::Zip => {
+                    let mut zip = zip::ZipArchive::new(std::io::Cursor::new(contents.clone()))
+                        .with_context(|| format!("invalid zip {}", pkg))?;
+
+                    let mut total_uncompressed = 0;
+                    for findex in 0..zip.len() {
+                        total_uncompressed += zip.by_index_raw(findex)?.size();
+                    }
+                    item.progress.set_length(total_uncompressed);
+
+                    for findex in 0..zip.len() {
+                        let mut file = zip.by_index(findex)?;
+                        if file.is_dir() {
+                            continue;
+                        }
+
+                        if let Some(path) = archive_path(file.name(), layout.strip_components) {
+                            write(path, &mut file)?;
+                        }
+                    }
+                }
+                Unpack::Tar | Unpack::TarGz => {
+                    // Tarballs don't have an index, so they are decompressed
+                    // up front to know the size to display progress for
+                    let tarball = if layout.unpack == Unpack::TarGz {
+                        let mut tarball = Vec::new();
+                        flate2::read::GzDecoder::new(contents.as_ref())
+                            .read_to_end(&mut tarball)
+                            .with_context(|| format!("invalid gzip {}", pkg))?;
+                        bytes::Bytes::from(tarball)
+                    } else {
+                        contents.clone()
+                    };
+
+                    item.progress.set_length(tarball.len() as u64);
+
+                    let mut archive = tar::Archive::new(tarball.as_ref());
+                    for entry in archive
+                        .entries()
+                        .with_context(|| format!("invalid tarball {}", pkg))?
+                    {
+                        let mut entry =
+                            entry.with_context(|| format!("invalid tarball {}", pkg))?;
+
+                        let entry_path = entry.path_bytes();
+                        let entry_path = String::from_utf8_lossy(&entry_path).into_owned();
+
+                        if !entry.header().entry_type().is_file() {
+                            if !entry.header().entry_type().is_dir() {
+                                tracing::debug!(
+                                    "skipping {} in {}, which is not a file",
+                                    entry_path,
+                                    pkg
+                                );
+                            }
+                            continue;
+                        }
+
+                        if let Some(path) = archive_path(&entry_path, layout.strip_components) {
+                            write(path, &mut entry)?;
+                        }
+                    }
+                }
+                Unpack::None => {
+                    item.progress.set_length(contents.len() as u64);
+                    write(pkg.clone(), &mut contents.as_ref())?;
+                }
+            }
+
+            (tree, contents.len() as u64)
+        }
         PayloadContents::Msi { msi, cabs } => {
             let mut msi = msi::Package::open(std::io::Cursor::new(msi))
                 .with_context(|| format!("unable to read MSI from {}", pkg))?;
@@ -232,16 +360,9 @@ pub(crate) fn unpack(
             }
 
             let components = {
-                #[derive(Debug)]
-                struct Dir {
-                    id: String,
-                    parent: Option<String>,
-                    path: PathBuf,
-                }
-
                 // Collect the directories that can be referenced by a component
                 // that are reference by files. Ugh.
-                let mut directories: Vec<_> = msi
+                let directories: std::collections::BTreeMap<_, _> = msi
                     .select_rows(msi::Select::table("Directory"))
                     .with_context(|| format!("MSI {} has no 'Directory' table", pkg))?
                     .map(|row| -> Result<_, _> {
@@ -252,24 +373,27 @@ pub(crate) fn unpack(
                         // ...
                         anyhow::ensure!(row.len() >= 3, "invalid row in 'Directory'");
 
-                        Ok(Dir {
-                            id: row[0]
+                        Ok((
+                            row[0]
                                 .as_str()
                                 .context("directory name is not a string")?
                                 .to_owned(),
-                            // This can be `null`
-                            parent: row[1].as_str().map(String::from),
-                            path: fix_name(&row[2])?.into(),
-                        })
+                            MsiDir {
+                                // This can be `null`
+                                parent: row[1].as_str().map(String::from),
+                                default_dir: row[2]
+                                    .as_str()
+                                    .context("directory location is not a string")?
+                                    .to_owned(),
+                            },
+                        ))
                     })
                     .collect::<Result<_, _>>()
                     .with_context(|| format!("unable to read directories for {}", pkg))?;
 
-                directories.sort_by(|a, b| a.id.cmp(&b.id));
-
                 let components: std::collections::BTreeMap<_, _> = msi
                     .select_rows(msi::Select::table("Component"))
-                    .with_context(|| format!("MSI {} has no 'Directory' table", pkg))?
+                    .with_context(|| format!("MSI {} has no 'Component' table", pkg))?
                     .map(|row| -> Result<_, _> {
                         // Columns:
                         // 0 - Component (name, really, id)
@@ -277,59 +401,19 @@ pub(crate) fn unpack(
                         // 2 - Directory_ (directory id)
                         anyhow::ensure!(row.len() >= 3, "invalid row in 'Component'");
 
-                        // The recursion depth for directory lookup is quite shallow
-                        // typically, the full path to a file would be something like
-                        // `Program Files/Windows Kits/10/Lib/10.0.19041.0/um/x64`
-                        // but this a terrible path, so we massage it to instead be
-                        // `lib/um/x64`
-                        fn build_dir(dirs: &[Dir], id: &str, dir: &mut PathBuf) {
-                            #[allow(clippy::single_match_else)]
-                            let cur_dir = match dirs.binary_search_by(|d| d.id.as_str().cmp(id)) {
-                                Ok(i) => &dirs[i],
-                                Err(_) => {
-                                    tracing::warn!("unable to find directory {}", id);
-                                    return;
-                                }
-                            };
-
-                            match cur_dir.path.file_name() {
-                                Some("Lib") => {
-                                    dir.push("lib");
-                                }
-                                Some("Include") => {
-                                    dir.push("include");
-                                }
-                                other => {
-                                    if let Some(parent) = &cur_dir.parent {
-                                        build_dir(dirs, parent, dir);
-                                    }
-
-                                    if let Some(other) = other {
-                                        // Ignore the SDK version directory between
-                                        // Lib/Include and the actual subdirs we care about
-                                        if !other.starts_with(|c: char| c.is_digit(10)) {
-                                            dir.push(other);
-                                        }
-                                    }
-                                }
-                            }
-                        }
-
                         let component_id = row[0]
                             .as_str()
                             .context("component id is not a string")?
                             .to_owned();
 
-                        let mut dir = PathBuf::new();
-                        build_dir(
+                        let install_dir = msi_install_dir(
                             &directories,
                             row[2]
                                 .as_str()
                                 .context("component directory is not a string")?,
-                            &mut dir,
                         );
 
-                        Ok((component_id, dir))
+                        Ok((component_id, msi_unpack_dir(&install_dir)))
                     })
                     .collect::<Result<_, _>>()
                     .with_context(|| format!("unable to read components for {}", pkg))?;
@@ -520,6 +604,8 @@ pub(crate) fn unpack(
                     let cab_path = &cabs[chunk.cab_index].path;
 
                     for file in chunk.files {
+                        ctx.cancel.check()?;
+
                         let mut cab_file = match cab.read_file(file.id.as_str()) {
                             Ok(cf) => cf,
                             Err(e) => Err(e).with_context(|| {
@@ -531,14 +617,14 @@ pub(crate) fn unpack(
 
                         if let Some(parent) = unpack_path.parent() {
                             if !parent.exists() {
-                                std::fs::create_dir_all(parent)?;
+                                std::fs::create_dir_all(&*long_path(parent))?;
                             }
                         }
 
-                        let unpacked_file = std::fs::File::create(&unpack_path)?;
+                        let unpacked_file = std::fs::File::create(&*long_path(&unpack_path))?;
 
                         struct Wrapper<'pb> {
-                            pb: &'pb indicatif::ProgressBar,
+                            pb: &'pb dyn ProgressSink,
                             uf: std::fs::File,
                         }
 
@@ -556,7 +642,7 @@ pub(crate) fn unpack(
                         let size = std::io::copy(
                             &mut cab_file,
                             &mut Wrapper {
-                                pb: &item.progress,
+                                pb: item.progress.as_ref(),
                                 uf: unpacked_file,
                             },
                         )?;
@@ -575,9 +661,9 @@ pub(crate) fn unpack(
     let tree_path = format!("{}/tree.txt", output_dir);
 
     std::fs::write(&tree_path, format!("{:#?}", tree).as_bytes())
-        .with_context(|| format!("failed to write {}", tree_path))?;
+        .with_context(|| error::io(format!("failed to write {}", tree_path), tree_path.as_ref()))?;
 
-    item.progress.finish_with_message("unpacked");
+    item.progress.finish(Phase::Unpack);
 
     let (num_files, decompressed) = tree.stats();
 
@@ -593,3 +679,129 @@ pub(crate) fn unpack(
 
     Ok(tree)
 }
+
+/// A row in the `Directory` table of an MSI
+struct MsiDir {
+    parent: Option<String>,
+    /// The name of the directory, which is `<target>:<source>` if the
+    /// directory has a different name in the source image, and each name is
+    /// `<short>|<long>` if it isn't a valid 8.3 name, or `.` if the directory
+    /// is the same as its parent
+    default_dir: String,
+}
+
+/// Reconstructs the path a directory in an MSI is installed to by following
+/// its parents in the `Directory` table, eg.
+/// `SourceDir/Windows Kits/10/Include/10.0.19041.0/um`
+fn msi_install_dir(dirs: &std::collections::BTreeMap<String, MsiDir>, id: &str) -> PathBuf {
+    let mut names = Vec::new();
+    let mut next = Some(id);
+
+    // The table shouldn't contain cycles, but bound the walk regardless
+    while let Some(id) = next.take() {
+        if names.len() > dirs.len() {
+            tracing::warn!("the parents of directory {} contain a cycle", id);
+            break;
+        }
+
+        let dir = if let Some(dir) = dirs.get(id) {
+            dir
+        } else {
+            tracing::warn!("unable to find directory {}", id);
+            break;
+        };
+
+        let target = dir.default_dir.split(':').next().unwrap_or_default();
+        let name = target.rsplit('|').next().unwrap_or_default();
+        if !name.is_empty() && name != "." {
+            names.push(name);
+        }
+
+        // The root directory is its own parent in some MSIs
+        next = dir.parent.as_deref().filter(|parent| *parent != id);
+    }
+
+    names.iter().rev().collect()
+}
+
+/// The path a directory installed by an MSI is unpacked to. The typical
+/// install path of eg. `Program Files/Windows Kits/10/Lib/10.0.19041.0/um/x64`
+/// is terrible, so it is rooted at its innermost `Lib`, `Include`, or `bin`
+/// directory, regardless of where that is in the MSI, and the SDK version
+/// directories below it are removed, eg. `lib/um/x64`
+fn msi_unpack_dir(install_dir: &crate::Path) -> PathBuf {
+    let components: Vec<_> = install_dir.iter().collect();
+
+    let root = components.iter().rposition(|name| {
+        ["Lib", "Include", "bin"]
+            .iter()
+            .any(|root| name.eq_ignore_ascii_case(root))
+    });
+
+    let mut dir = PathBuf::new();
+    let rest = match root {
+        Some(i) => {
+            dir.push(components[i].to_ascii_lowercase());
+            &components[i + 1..]
+        }
+        None => &components[..],
+    };
+
+    for name in rest {
+        if !name.starts_with(|c: char| c.is_ascii_digit()) {
+            dir.push(name);
+        }
+    }
+
+    dir
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn msi_directories() {
+        let dirs: std::collections::BTreeMap<_, _> = [
+            ("TARGETDIR", None, "SourceDir"),
+            ("ProgramFilesFolder", Some("TARGETDIR"), "."),
+            (
+                "KitsRoot",
+                Some("ProgramFilesFolder"),
+                "WINDOW~1|Windows Kits",
+            ),
+            ("Kits10", Some("KitsRoot"), "10"),
+            ("IncludeRoot", Some("Kits10"), "Include:SrcInc"),
+            ("IncludeVersion", Some("IncludeRoot"), "10.0.22621.0"),
+            ("um", Some("IncludeVersion"), "um"),
+            ("LibRoot", Some("Kits10"), "LIB|Lib"),
+            ("LibVersion", Some("LibRoot"), "10.0.22621.0"),
+            ("LibUm", Some("LibVersion"), "um"),
+            ("LibUmX64", Some("LibUm"), "x64"),
+            ("Catalogs", Some("Kits10"), "Catalogs"),
+            ("Loop", Some("Loop"), "loop"),
+        ]
+        .iter()
+        .map(|(id, parent, default_dir)| {
+            (
+                (*id).to_string(),
+                MsiDir {
+                    parent: parent.map(String::from),
+                    default_dir: (*default_dir).to_string(),
+                },
+            )
+        })
+        .collect();
+
+        let dir = |id: &str| msi_unpack_dir(&msi_install_dir(&dirs, id));
+
+        assert_eq!(
+            msi_install_dir(&dirs, "um"),
+            "SourceDir/Windows Kits/10/Include/10.0.22621.0/um"
+        );
+        assert_eq!(dir("um"), "include/um");
+        assert_eq!(dir("LibUmX64"), "lib/um/x64");
+        assert_eq!(dir("Catalogs"), "SourceDir/Windows Kits/Catalogs");
+        assert_eq!(dir("Loop"), "loop");
+    }
+}
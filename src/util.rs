@@ -1,23 +1,6 @@
 use anyhow::Error;
 use std::fmt;
 
-#[derive(Copy, Clone)]
-pub enum ProgressTarget {
-    Stdout,
-    Stderr,
-    Hidden,
-}
-
-impl From<ProgressTarget> for indicatif::ProgressDrawTarget {
-    fn from(pt: ProgressTarget) -> Self {
-        match pt {
-            ProgressTarget::Stdout => Self::stdout(),
-            ProgressTarget::Stderr => Self::stderr(),
-            ProgressTarget::Hidden => Self::hidden(),
-        }
-    }
-}
-
 #[derive(Clone, PartialEq, Eq)]
 pub struct Sha256(pub [u8; 32]);
 
@@ -123,10 +106,151 @@ impl Sha256 {
     }
 }
 
+/// Recursively gathers the paths of every regular file in the directory.
+/// Symlinks are ignored.
+pub(crate) fn walk_files(dir: &crate::Path) -> Result<Vec<crate::PathBuf>, Error> {
+    use anyhow::Context as _;
+
+    fn walk(dir: &crate::Path, paths: &mut Vec<crate::PathBuf>) -> Result<(), Error> {
+        for entry in std::fs::read_dir(dir).with_context(|| format!("unable to read {}", dir))? {
+            let entry = entry.with_context(|| format!("unable to read entry from {}", dir))?;
+            let path = crate::PathBuf::from_path_buf(entry.path())
+                .map_err(|pb| anyhow::anyhow!("path {} is not a valid utf-8 path", pb.display()))?;
+
+            let ft = entry.file_type()?;
+            if ft.is_dir() {
+                walk(&path, paths)?;
+            } else if ft.is_file() {
+                paths.push(path);
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut paths = Vec::new();
+    walk(dir, &mut paths)?;
+    Ok(paths)
+}
+
+/// Converts a path to an extended-length path on Windows, ie. `\\?\C:\...`,
+/// so that unpacking and splatting can create files deeper than `MAX_PATH`,
+/// eg. the `winrt/impl` headers of the SDK. Other platforms don't have such a
+/// limit, so the path is returned as is.
+pub(crate) fn long_path(path: &crate::Path) -> std::borrow::Cow<'_, crate::Path> {
+    #[cfg(windows)]
+    {
+        let absolute = if path.is_absolute() {
+            Some(path.as_str().to_owned())
+        } else {
+            std::env::current_dir()
+                .ok()
+                .and_then(|cwd| cwd.to_str().map(|cwd| format!("{}\\{}", cwd, path)))
+        };
+
+        if let Some(extended) = absolute.as_deref().and_then(extended_length) {
+            return std::borrow::Cow::Owned(extended.into());
+        }
+    }
+
+    std::borrow::Cow::Borrowed(path)
+}
+
+/// Converts an absolute Windows path, either on a drive or a UNC share, to an
+/// extended-length path. Windows doesn't normalize extended-length paths, so
+/// separators are converted to `\`, and `.` and `..` components are resolved.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn extended_length(path: &str) -> Option<String> {
+    if path.starts_with(r"\\?\") || path.starts_with(r"\\.\") {
+        return None;
+    }
+
+    let path = path.replace('/', "\\");
+    let (mut prefix, rest) = if let Some(unc) = path.strip_prefix(r"\\") {
+        let mut parts = unc.splitn(3, '\\');
+        let server = parts.next().filter(|server| !server.is_empty())?;
+        let share = parts.next().filter(|share| !share.is_empty())?;
+        (
+            format!(r"\\?\UNC\{}\{}", server, share),
+            parts.next().unwrap_or_default().to_owned(),
+        )
+    } else {
+        let bytes = path.as_bytes();
+        if bytes.len() < 3 || !bytes[0].is_ascii_alphabetic() || &bytes[1..3] != b":\\" {
+            return None;
+        }
+        (format!(r"\\?\{}", &path[..2]), path[3..].to_owned())
+    };
+
+    let mut components = Vec::new();
+    for component in rest.split('\\') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+
+    for component in components {
+        prefix.push('\\');
+        prefix.push_str(component);
+    }
+
+    Some(prefix)
+}
+
+/// Matches a pattern where `*` matches any sequence of characters
+pub(crate) fn glob(pattern: &str, s: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == s,
+        Some((prefix, rest)) => match s.strip_prefix(prefix) {
+            Some(s) => (0..=s.len())
+                .filter(|i| s.is_char_boundary(*i))
+                .any(|i| glob(rest, &s[i..])),
+            None => false,
+        },
+    }
+}
+
+/// Writes the contents of the directory as a gzipped tarball. Symlinks are
+/// archived as is, rather than the files they point to.
+pub(crate) fn tar_gz(dir: &crate::Path, w: impl std::io::Write) -> Result<(), Error> {
+    use anyhow::Context as _;
+
+    let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+        w,
+        flate2::Compression::default(),
+    ));
+    builder.follow_symlinks(false);
+    builder
+        .append_dir_all(".", dir)
+        .with_context(|| format!("unable to archive {}", dir))?;
+    builder.into_inner()?.finish()?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn extended_length_paths() {
+        assert_eq!(
+            extended_length("C:/xwin/splat/sdk/include/winrt/../cppwinrt/./winrt").unwrap(),
+            r"\\?\C:\xwin\splat\sdk\include\cppwinrt\winrt"
+        );
+        assert_eq!(
+            extended_length(r"\\server\share\xwin\..\..\splat").unwrap(),
+            r"\\?\UNC\server\share\splat"
+        );
+        assert_eq!(extended_length(r"C:\").unwrap(), r"\\?\C:");
+        assert!(extended_length(r"\\?\C:\xwin").is_none());
+        assert!(extended_length("xwin/splat").is_none());
+        assert!(extended_length("/xwin/splat").is_none());
+    }
+
     #[test]
     fn sha256() {
         let buffer = [3u8; 11];
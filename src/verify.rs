@@ -0,0 +1,596 @@
+//! Checks of a finished splat output, eg. that nothing in it depends on the
+//! path it was splatted to, so that it can be cached and restored, or mounted,
+//! at a different prefix without breaking, that it matches a published
+//! known-good hash, or that it has the headers and libs that any build needs
+
+use crate::{
+    layout::Layout,
+    stamp::{Stamp, StampedOptions},
+    util::Sha256,
+    Arch, Component, Path, PathBuf, Variant,
+};
+use anyhow::{Context as _, Error};
+use std::fmt;
+
+/// Something in the output that would break if it was moved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// A symlink with an absolute target
+    AbsoluteSymlink { path: PathBuf, target: String },
+    /// A symlink whose target is outside of the output
+    EscapingSymlink { path: PathBuf, target: String },
+    /// A file that contains the absolute path of the output, eg. a generated
+    /// script or props file
+    AbsolutePath { path: PathBuf, line: usize },
+    /// A file that no longer has the changes from a patch that was applied
+    /// to it
+    Unpatched { path: PathBuf, patch: String },
+    /// A file that was deleted by a patch, but exists again
+    Unremoved { path: PathBuf, patch: String },
+    /// The include and lib directories of an architecture and variant
+    /// couldn't be resolved, eg. because it wasn't splatted
+    Unresolved {
+        arch: Arch,
+        variant: Variant,
+        reason: String,
+    },
+    /// One of the [`SENTINELS`] is missing for an architecture and variant
+    MissingSentinel {
+        arch: Arch,
+        variant: Variant,
+        name: &'static str,
+    },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AbsoluteSymlink { path, target } => {
+                write!(f, "{} is a symlink to the absolute path {}", path, target)
+            }
+            Self::EscapingSymlink { path, target } => {
+                write!(
+                    f,
+                    "{} is a symlink to {} outside of the output",
+                    path, target
+                )
+            }
+            Self::AbsolutePath { path, line } => {
+                write!(
+                    f,
+                    "{}:{} contains the absolute path of the output",
+                    path, line
+                )
+            }
+            Self::Unpatched { path, patch } => {
+                write!(f, "{} doesn't have the changes from patch {}", path, patch)
+            }
+            Self::Unremoved { path, patch } => {
+                write!(f, "{} was removed by patch {}, but exists", path, patch)
+            }
+            Self::Unresolved {
+                arch,
+                variant,
+                reason,
+            } => {
+                write!(f, "{} {} can't be used: {}", arch, variant, reason)
+            }
+            Self::MissingSentinel {
+                arch,
+                variant,
+                name,
+            } => {
+                write!(f, "{} is missing for {} {}", name, arch, variant)
+            }
+        }
+    }
+}
+
+/// Recursively gathers every regular file and symlink in the directory
+fn walk(dir: &Path, files: &mut Vec<PathBuf>, links: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in std::fs::read_dir(dir).with_context(|| format!("unable to read {}", dir))? {
+        let entry = entry.with_context(|| format!("unable to read entry from {}", dir))?;
+        let path = PathBuf::from_path_buf(entry.path())
+            .map_err(|pb| anyhow::anyhow!("path {} is not a valid utf-8 path", pb.display()))?;
+
+        let ft = entry.file_type()?;
+        if ft.is_symlink() {
+            links.push(path);
+        } else if ft.is_dir() {
+            walk(&path, files, links)?;
+        } else if ft.is_file() {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that the output at `root` can be moved to a different prefix, ie.
+/// that every symlink is relative and stays within the output, and that no
+/// file contains the absolute path of the output, in either its `/` or `\`
+/// form. The paths in the returned issues are relative to the root.
+pub fn relocatable(root: &Path) -> Result<Vec<Issue>, Error> {
+    use rayon::prelude::*;
+
+    anyhow::ensure!(root.is_dir(), "{} is not a directory", root);
+
+    let mut files = Vec::new();
+    let mut links = Vec::new();
+    walk(root, &mut files, &mut links)?;
+    links.sort();
+
+    let rel = |path: &Path| path.strip_prefix(root).unwrap_or(path).to_owned();
+
+    let mut issues = Vec::new();
+
+    for link in links {
+        let target = std::fs::read_link(&link)
+            .with_context(|| format!("unable to read symlink {}", link))?;
+        let target = target
+            .to_str()
+            .with_context(|| format!("symlink {} has a non utf-8 target", link))?
+            .to_owned();
+
+        if Path::new(&target).is_absolute() || target.starts_with('/') {
+            issues.push(Issue::AbsoluteSymlink {
+                path: rel(&link),
+                target,
+            });
+            continue;
+        }
+
+        // Resolve the target lexically, as it may not exist, and following
+        // it could leave the output
+        let mut resolved = rel(link.parent().unwrap_or(root));
+        let mut escapes = false;
+        for comp in Path::new(&target.replace('\\', "/")).components() {
+            match comp {
+                camino::Utf8Component::ParentDir => escapes |= !resolved.pop(),
+                camino::Utf8Component::Normal(name) => resolved.push(name),
+                _ => {}
+            }
+        }
+
+        if escapes {
+            issues.push(Issue::EscapingSymlink {
+                path: rel(&link),
+                target,
+            });
+        }
+    }
+
+    let mut needles = vec![root.as_str().trim_end_matches('/').to_owned()];
+    // The output may also be referenced by its canonical path, eg. if the
+    // root was passed via a symlink
+    if let Some(canonical) = std::fs::canonicalize(root)
+        .ok()
+        .and_then(|pb| pb.to_str().map(str::to_owned))
+    {
+        needles.push(canonical.trim_end_matches('/').to_owned());
+    }
+    let windows: Vec<_> = needles.iter().map(|n| n.replace('/', "\\")).collect();
+    needles.extend(windows);
+    needles.sort();
+    needles.dedup();
+
+    let finders: Vec<_> = needles
+        .iter()
+        .map(|needle| memchr::memmem::Finder::new(needle.as_bytes()))
+        .collect();
+
+    let mut found = files
+        .into_par_iter()
+        .map(|path| -> Result<Option<Issue>, Error> {
+            let contents =
+                std::fs::read(&path).with_context(|| format!("unable to read {}", path))?;
+
+            let offset = finders
+                .iter()
+                .filter_map(|finder| finder.find(&contents))
+                .min();
+
+            Ok(offset.map(|offset| Issue::AbsolutePath {
+                path: rel(&path),
+                line: memchr::memchr_iter(b'\n', &contents[..offset]).count() + 1,
+            }))
+        })
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    found.sort_by_key(|issue| issue.to_string());
+    issues.extend(found);
+
+    Ok(issues)
+}
+
+/// Checks that every file that was changed by a patch, as recorded in the
+/// stamp of the output at `root`, still has the changes, eg. that it wasn't
+/// replaced by `xwin update` or edited afterwards
+pub fn patches(root: &Path) -> Result<Vec<Issue>, Error> {
+    let stamp = Stamp::load(root)?;
+    let mut issues = Vec::new();
+
+    for patch in &stamp.patches {
+        for pf in &patch.files {
+            let path = root.join(&pf.path);
+            let unpatched = match std::fs::read(&path) {
+                Ok(contents) => Sha256::digest(&contents) != pf.sha256,
+                Err(_) => true,
+            };
+
+            if unpatched {
+                issues.push(Issue::Unpatched {
+                    path: pf.path.clone(),
+                    patch: patch.name.clone(),
+                });
+            }
+        }
+
+        for removed in &patch.removed {
+            if std::fs::symlink_metadata(root.join(removed)).is_ok() {
+                issues.push(Issue::Unremoved {
+                    path: removed.clone(),
+                    patch: patch.name.clone(),
+                });
+            }
+        }
+    }
+
+    Ok(issues)
+}
+
+/// An entry in a list of known-good hashes, eg. published alongside an xwin
+/// release for common version combinations, identifying a splat by the
+/// versions of the CRT and SDK, and the architectures, variants, and options
+/// it was splatted with
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct KnownGood {
+    pub crt: String,
+    pub sdk: String,
+    pub arches: Vec<String>,
+    pub variants: Vec<String>,
+    /// The splat options, which default to those of the CLI
+    #[serde(default)]
+    pub options: StampedOptions,
+    /// The [`crate::hash::content_hash`] of the splat
+    pub hash: Sha256,
+}
+
+impl KnownGood {
+    /// Parses a list of known-good hashes, which is a JSON array of entries
+    pub fn parse(contents: &[u8]) -> Result<Vec<Self>, Error> {
+        serde_json::from_slice(contents).context("invalid known-good hash list")
+    }
+
+    fn matches(&self, stamp: &Stamp) -> Result<bool, Error> {
+        let arches = self.arches.iter().try_fold(0, |acc, arch| {
+            Ok::<_, Error>(acc | arch.parse::<crate::Arch>()? as u32)
+        })?;
+        let variants = self.variants.iter().try_fold(0, |acc, variant| {
+            Ok::<_, Error>(acc | variant.parse::<crate::Variant>()? as u32)
+        })?;
+
+        Ok(stamp.crt_version() == Some(self.crt.as_str())
+            && stamp.sdk_version() == Some(self.sdk.as_str())
+            && stamp.arch_mask()? == arches
+            && stamp.variant_mask()? == variants
+            && stamp.options == self.options)
+    }
+}
+
+/// Finds the entry in the list for the splat at `root`, which is identified by
+/// the stamp written to it, returning the entry along with the actual
+/// [`crate::hash::content_hash`] of the output
+pub fn known_good<'list>(
+    root: &Path,
+    list: &'list [KnownGood],
+) -> Result<(&'list KnownGood, Sha256), Error> {
+    let stamp = Stamp::load(root)?;
+
+    anyhow::ensure!(
+        stamp.patches.is_empty(),
+        "{} was patched by {}, so it can't match a known-good hash",
+        root,
+        stamp
+            .patches
+            .iter()
+            .map(|patch| patch.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let mut found = None;
+    for entry in list {
+        if entry.matches(&stamp)? {
+            found = Some(entry);
+            break;
+        }
+    }
+
+    let entry = found.with_context(|| {
+        format!(
+            "there is no known-good hash for CRT {} and SDK {} splatted for {} ({})",
+            stamp.crt_version().unwrap_or("<none>"),
+            stamp.sdk_version().unwrap_or("<none>"),
+            stamp.arches.join(","),
+            stamp.variants.join(",")
+        )
+    })?;
+
+    Ok((entry, crate::hash::content_hash(root)?))
+}
+
+/// Whether a sentinel is a header or a lib
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SentinelKind {
+    Header,
+    Lib,
+}
+
+/// A file that every output that was splatted with its component has, and
+/// which a build is almost guaranteed to need
+#[derive(Copy, Clone, Debug)]
+pub struct Sentinel {
+    pub component: Component,
+    pub kind: SentinelKind,
+    /// The name of the file, which is matched case insensitively
+    pub name: &'static str,
+    /// Whether the file is also expected for the Store variant, which
+    /// doesn't have the static CRT
+    pub store: bool,
+}
+
+const fn sentinel(component: Component, kind: SentinelKind, name: &'static str) -> Sentinel {
+    Sentinel {
+        component,
+        kind,
+        name,
+        store: true,
+    }
+}
+
+/// The files that are checked by [`sentinels`]
+pub const SENTINELS: &[Sentinel] = &[
+    sentinel(Component::Crt, SentinelKind::Header, "vcruntime.h"),
+    sentinel(Component::Sdk, SentinelKind::Header, "stdio.h"),
+    sentinel(Component::Sdk, SentinelKind::Header, "windows.h"),
+    sentinel(Component::Sdk, SentinelKind::Header, "winsock2.h"),
+    sentinel(Component::Crt, SentinelKind::Lib, "msvcrt.lib"),
+    Sentinel {
+        store: false,
+        ..sentinel(Component::Crt, SentinelKind::Lib, "libcmt.lib")
+    },
+    sentinel(Component::Sdk, SentinelKind::Lib, "ucrt.lib"),
+    sentinel(Component::Sdk, SentinelKind::Lib, "kernel32.lib"),
+    sentinel(Component::Sdk, SentinelKind::Lib, "ws2_32.lib"),
+];
+
+fn find_in(dirs: &[PathBuf], name: &str) -> bool {
+    dirs.iter().any(|dir| {
+        std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .any(|entry| {
+                entry
+                    .file_name()
+                    .to_str()
+                    .filter(|entry| entry.eq_ignore_ascii_case(name))
+                    .is_some()
+            })
+    })
+}
+
+/// Quickly checks that the output at `root` has each of the [`SENTINELS`] for
+/// every architecture and variant, in the directories that
+/// [`Layout::resolve`] resolves for them, to catch eg. payloads that weren't
+/// selected before the first build does. Only the sentinels of the components
+/// the output was splatted with are checked. Spectre is checked along with
+/// each of the other variants, rather than on its own.
+pub fn sentinels(root: &Path, arches: &[Arch], variants: u32) -> Result<Vec<Issue>, Error> {
+    anyhow::ensure!(root.is_dir(), "{} is not a directory", root);
+
+    let spectre = variants & Variant::Spectre as u32;
+    let mut non_spectre: Vec<_> = Variant::iter_variants(variants & !spectre).collect();
+    if non_spectre.is_empty() {
+        non_spectre.push(Variant::Desktop);
+    }
+
+    let mut issues = Vec::new();
+    for arch in arches {
+        for variant in &non_spectre {
+            let layout = match Layout::resolve(root, *arch, *variant as u32 | spectre) {
+                Ok(layout) => layout,
+                Err(err) => {
+                    issues.push(Issue::Unresolved {
+                        arch: *arch,
+                        variant: *variant,
+                        reason: format!("{:#}", err),
+                    });
+                    continue;
+                }
+            };
+
+            let has = |component: Component| {
+                root.join(component.as_str())
+                    .read_dir()
+                    .ok()
+                    .and_then(|mut entries| entries.next())
+                    .is_some()
+            };
+
+            for sentinel in SENTINELS {
+                if !has(sentinel.component) || (*variant == Variant::Store && !sentinel.store) {
+                    continue;
+                }
+
+                let dirs = match sentinel.kind {
+                    SentinelKind::Header => &layout.include,
+                    SentinelKind::Lib => &layout.lib,
+                };
+
+                if !find_in(dirs, sentinel.name) {
+                    issues.push(Issue::MissingSentinel {
+                        arch: *arch,
+                        variant: *variant,
+                        name: sentinel.name,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(issues)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn finds_absolute_paths() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        std::fs::create_dir_all(root.join("sdk/lib/um")).unwrap();
+        std::fs::write(root.join("sdk/lib/um/kernel32.lib"), "lib").unwrap();
+        std::fs::write(
+            root.join("env.sh"),
+            format!("# env\nexport LIB={}/sdk/lib/um\n", root),
+        )
+        .unwrap();
+
+        std::os::unix::fs::symlink("kernel32.lib", root.join("sdk/lib/um/Kernel32.lib")).unwrap();
+        assert!(relocatable(&root).unwrap().len() == 1);
+
+        std::os::unix::fs::symlink(root.join("sdk/lib/um/kernel32.lib"), root.join("abs.lib"))
+            .unwrap();
+        std::os::unix::fs::symlink("../../../../outside.lib", root.join("sdk/lib/um/out.lib"))
+            .unwrap();
+
+        let issues = relocatable(&root).unwrap();
+        assert_eq!(
+            issues,
+            vec![
+                Issue::AbsoluteSymlink {
+                    path: "abs.lib".into(),
+                    target: root.join("sdk/lib/um/kernel32.lib").into_string(),
+                },
+                Issue::EscapingSymlink {
+                    path: "sdk/lib/um/out.lib".into(),
+                    target: "../../../../outside.lib".into(),
+                },
+                Issue::AbsolutePath {
+                    path: "env.sh".into(),
+                    line: 2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_known_good() {
+        use crate::{Payload, PayloadKind};
+
+        let td = tempfile::TempDir::new().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        std::fs::create_dir_all(root.join("sdk/include")).unwrap();
+        std::fs::write(root.join("sdk/include/a.h"), "a").unwrap();
+
+        let payload = |filename: &str, version: &str, kind| Payload {
+            version: version.into(),
+            ..Payload::test(filename, kind)
+        };
+
+        Stamp::new(
+            &crate::SplatConfig::new(root.clone()),
+            crate::Arch::X86_64 as u32,
+            crate::Variant::Desktop as u32,
+            crate::SdkTools::default(),
+            &[
+                payload("crt.vsix", "14.29.16.10", PayloadKind::CrtHeaders),
+                payload("headers.msi", "10.0.19041", PayloadKind::SdkHeaders),
+            ],
+            &Default::default(),
+        )
+        .save(&root)
+        .unwrap();
+
+        let hash = crate::hash::content_hash(&root).unwrap();
+        let list = KnownGood::parse(
+            format!(
+                r#"[
+                    {{ "crt": "14.29.16.10", "sdk": "10.0.19041", "arches": ["x86_64", "aarch64"], "variants": ["desktop"], "hash": "{0}" }},
+                    {{ "crt": "14.29.16.10", "sdk": "10.0.19041", "arches": ["x86_64"], "variants": ["desktop"], "hash": "{0}" }}
+                ]"#,
+                hash
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let (entry, actual) = known_good(&root, &list).unwrap();
+        assert_eq!(entry.arches, ["x86_64"]);
+        assert_eq!(entry.hash, actual);
+
+        assert!(known_good(&root, &list[..1]).is_err());
+    }
+
+    #[test]
+    fn checks_sentinels() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        for file in &[
+            "crt/include/vcruntime.h",
+            "crt/lib/x86_64/msvcrt.lib",
+            "crt/lib/x86_64/libcmt.lib",
+            "sdk/include/ucrt/stdio.h",
+            "sdk/include/um/Windows.h",
+            "sdk/include/shared/sdkddkver.h",
+            "sdk/include/winrt/roapi.h",
+            "sdk/include/cppwinrt/base.h",
+            "sdk/lib/ucrt/x86_64/ucrt.lib",
+            "sdk/lib/um/x86_64/kernel32.Lib",
+            "sdk/lib/um/x86_64/ws2_32.lib",
+        ] {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+
+        let issues = sentinels(&root, &[Arch::X86_64], Variant::Desktop as u32).unwrap();
+        assert_eq!(
+            issues,
+            vec![Issue::MissingSentinel {
+                arch: Arch::X86_64,
+                variant: Variant::Desktop,
+                name: "winsock2.h",
+            }]
+        );
+
+        std::fs::write(root.join("sdk/include/um/WinSock2.h"), "").unwrap();
+        assert!(sentinels(&root, &[Arch::X86_64], Variant::Desktop as u32)
+            .unwrap()
+            .is_empty());
+
+        // An architecture that wasn't splatted can't be resolved at all
+        let issues = sentinels(&root, &[Arch::Aarch64], Variant::Desktop as u32).unwrap();
+        assert!(matches!(
+            issues.as_slice(),
+            [Issue::Unresolved {
+                arch: Arch::Aarch64,
+                ..
+            }]
+        ));
+
+        // Only the components that were splatted are checked
+        std::fs::remove_dir_all(root.join("crt")).unwrap();
+        assert!(sentinels(&root, &[Arch::X86_64], Variant::Desktop as u32)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,299 @@
+//! Matching of Windows SDK versions against a requirement, eg. `10.0.19*` or
+//! `>=10.0.19041,<10.0.22000`, so that the newest SDK of a particular line can
+//! be selected from the manifest rather than always the latest one
+
+use crate::manifest;
+use anyhow::{Context as _, Error};
+use std::cmp::Ordering;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Comparator {
+    /// A pattern where `*` matches any sequence of characters, eg. `10.0.19*`
+    Wildcard(String),
+    Op(Op, Vec<u32>),
+}
+
+/// A version requirement, which is a comma separated list of comparators that
+/// must all match, each of which is either a version optionally prefixed by
+/// one of `=`, `>`, `>=`, `<`, or `<=`, or a pattern containing `*`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionReq {
+    comparators: Vec<Comparator>,
+    req: String,
+}
+
+fn parse_version(version: &str) -> Option<Vec<u32>> {
+    version.split('.').map(|comp| comp.parse().ok()).collect()
+}
+
+/// Checks if the string is a dotted version, eg. `14.34.17.4`
+pub(crate) fn is_version(version: &str) -> bool {
+    parse_version(version).is_some()
+}
+
+/// Sorts versions from oldest to newest, removing duplicates
+pub(crate) fn sort(versions: &mut Vec<String>) {
+    versions.sort_by(|a, b| {
+        compare(
+            &parse_version(a).unwrap_or_default(),
+            &parse_version(b).unwrap_or_default(),
+        )
+    });
+    versions.dedup();
+}
+
+/// Compares two versions, treating missing trailing components as zero
+fn compare(a: &[u32], b: &[u32]) -> Ordering {
+    (0..a.len().max(b.len()))
+        .map(|i| {
+            a.get(i)
+                .copied()
+                .unwrap_or_default()
+                .cmp(&b.get(i).copied().unwrap_or_default())
+        })
+        .find(|ord| *ord != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+impl std::str::FromStr for VersionReq {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut comparators = Vec::new();
+
+        for comp in s.split(',').map(str::trim) {
+            anyhow::ensure!(!comp.is_empty(), "empty version requirement in '{}'", s);
+
+            if comp.contains('*') {
+                comparators.push(Comparator::Wildcard(
+                    comp.strip_prefix('=').unwrap_or(comp).to_owned(),
+                ));
+                continue;
+            }
+
+            let (op, version) = [
+                (">=", Op::Ge),
+                ("<=", Op::Le),
+                (">", Op::Gt),
+                ("<", Op::Lt),
+                ("=", Op::Eq),
+            ]
+            .iter()
+            .find_map(|(prefix, op)| comp.strip_prefix(prefix).map(|v| (*op, v.trim())))
+            .unwrap_or((Op::Eq, comp));
+
+            let version = parse_version(version)
+                .with_context(|| format!("invalid version '{}' in '{}'", version, s))?;
+            comparators.push(Comparator::Op(op, version));
+        }
+
+        Ok(Self {
+            comparators,
+            req: s.to_owned(),
+        })
+    }
+}
+
+impl std::fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.req)
+    }
+}
+
+impl VersionReq {
+    /// Checks if the version, eg. `10.0.19041`, matches every comparator
+    pub fn matches(&self, version: &str) -> bool {
+        let parsed = parse_version(version);
+
+        self.comparators.iter().all(|comp| match comp {
+            Comparator::Wildcard(pattern) => crate::util::glob(pattern, version),
+            Comparator::Op(op, req) => match &parsed {
+                Some(parsed) => {
+                    let ord = compare(parsed, req);
+                    match op {
+                        Op::Eq => ord == Ordering::Equal,
+                        Op::Gt => ord == Ordering::Greater,
+                        Op::Ge => ord != Ordering::Less,
+                        Op::Lt => ord == Ordering::Less,
+                        Op::Le => ord != Ordering::Greater,
+                    }
+                }
+                None => false,
+            },
+        })
+    }
+}
+
+/// The version of a Windows SDK package, eg. `10.0.19041` for
+/// `Win10SDK_10.0.19041`
+pub(crate) fn sdk_version(id: &str) -> Option<&str> {
+    id.strip_prefix("Win10SDK_")
+        .filter(|version| version.starts_with("10."))
+        .and_then(|version| {
+            version
+                .split(|c: char| !c.is_ascii_digit() && c != '.')
+                .next()
+        })
+}
+
+/// Removes the Windows SDK packages whose versions don't match the requirement
+/// from the package manifest, so that the newest SDK that does match is the
+/// one that is selected, returning its version
+pub fn select_sdk(
+    pkg_manifest: &mut manifest::PackageManifest,
+    req: &VersionReq,
+) -> Result<String, Error> {
+    let available = pkg_manifest.sdk_versions();
+
+    let selected = available
+        .iter()
+        .rev()
+        .find(|version| req.matches(version))
+        .cloned()
+        .with_context(|| {
+            format!(
+                "no Windows SDK version matches '{}', the available versions are {}",
+                req,
+                available.join(", ")
+            )
+        })
+        .context(crate::error::MissingPayload)?;
+
+    pkg_manifest.packages.retain(|id, _| {
+        sdk_version(id)
+            .filter(|version| *version != selected)
+            .is_none()
+    });
+
+    Ok(selected)
+}
+
+/// Removes the MSVC CRT versions that don't match the requirement from the
+/// dependencies of the build tools product, so that the newest CRT that does
+/// match is the one that is selected, returning its version
+pub fn select_crt(
+    pkg_manifest: &mut manifest::PackageManifest,
+    req: &VersionReq,
+) -> Result<String, Error> {
+    let available = pkg_manifest.crt_versions();
+
+    let selected = available
+        .iter()
+        .rev()
+        .find(|version| req.matches(version))
+        .cloned()
+        .with_context(|| {
+            format!(
+                "no MSVC CRT version matches '{}', the available versions are {}",
+                req,
+                available.join(", ")
+            )
+        })
+        .context(crate::error::MissingPayload)?;
+
+    if let Some(build_tools) = pkg_manifest
+        .packages
+        .get_mut("Microsoft.VisualStudio.Product.BuildTools")
+    {
+        build_tools.dependencies.retain(|key, _| {
+            key.strip_prefix("Microsoft.VisualStudio.Component.VC.")
+                .and_then(|s| s.strip_suffix(".x86.x64"))
+                .filter(|version| is_version(version) && *version != selected)
+                .is_none()
+        });
+    }
+
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn matches_versions() {
+        let req: VersionReq = "10.0.19*".parse().unwrap();
+        assert!(req.matches("10.0.19041"));
+        assert!(!req.matches("10.0.20348"));
+
+        let req: VersionReq = ">=10.0.19041, <10.0.22000".parse().unwrap();
+        assert!(req.matches("10.0.19041"));
+        assert!(req.matches("10.0.20348"));
+        assert!(!req.matches("10.0.18362"));
+        assert!(!req.matches("10.0.22000"));
+
+        let req: VersionReq = "10.0.19041".parse().unwrap();
+        assert!(req.matches("10.0.19041.0"));
+        assert!(!req.matches("10.0.19042"));
+
+        assert!("10.0.x".parse::<VersionReq>().is_err());
+        assert!(">=10.0.19041,".parse::<VersionReq>().is_err());
+
+        let item = |id: &str| {
+            (
+                id.to_owned(),
+                manifest::ManifestItem {
+                    id: id.to_owned(),
+                    version: "1".to_owned(),
+                    kind: manifest::ItemKind::Msi,
+                    chip: None,
+                    payloads: Vec::new(),
+                    dependencies: Default::default(),
+                    install_sizes: None,
+                },
+            )
+        };
+
+        let mut pkg_manifest = manifest::PackageManifest {
+            packages: [
+                "Win10SDK_10.0.18362",
+                "Win10SDK_10.0.19041",
+                "Win10SDK_10.0.20348",
+                "Win10SDK_10.0.22000",
+                "Microsoft.Windows.UniversalCRT.HeadersLibsSources.Msi",
+            ]
+            .iter()
+            .map(|id| item(id))
+            .collect(),
+        };
+
+        let req: VersionReq = ">=10.0.19041,<10.0.22000".parse().unwrap();
+        assert_eq!(select_sdk(&mut pkg_manifest, &req).unwrap(), "10.0.20348");
+        let ids: Vec<_> = pkg_manifest.packages.keys().map(String::as_str).collect();
+        assert_eq!(
+            ids,
+            [
+                "Microsoft.Windows.UniversalCRT.HeadersLibsSources.Msi",
+                "Win10SDK_10.0.20348"
+            ]
+        );
+
+        let err = select_sdk(&mut pkg_manifest, &"10.0.17*".parse().unwrap()).unwrap_err();
+        assert!(format!("{:#}", err).contains("the available versions are 10.0.20348"));
+
+        let (id, mut build_tools) = item("Microsoft.VisualStudio.Product.BuildTools");
+        build_tools.dependencies = [
+            "Microsoft.VisualStudio.Component.VC.14.29.16.11.x86.x64",
+            "Microsoft.VisualStudio.Component.VC.14.34.17.4.x86.x64",
+            "Microsoft.VisualStudio.Component.VC.Tools.x86.x64",
+        ]
+        .iter()
+        .map(|dep| ((*dep).to_string(), serde_json::Value::Null))
+        .collect();
+        pkg_manifest.packages.insert(id, build_tools);
+
+        let req: VersionReq = "14.29*".parse().unwrap();
+        assert_eq!(select_crt(&mut pkg_manifest, &req).unwrap(), "14.29.16.11");
+        assert_eq!(pkg_manifest.crt_versions(), ["14.29.16.11"]);
+        assert!(select_crt(&mut pkg_manifest, &"14.34*".parse().unwrap()).is_err());
+    }
+}
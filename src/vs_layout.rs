@@ -0,0 +1,316 @@
+//! Offline layouts created by `vs_installer.exe --layout`, which many
+//! organizations already maintain, and which can be used as the source of the
+//! manifests and payloads rather than the Microsoft CDN.
+//!
+//! A layout contains the channel manifest, as `ChannelManifest.json`, the
+//! package manifest, as `Catalog.json`, and a directory for each package named
+//! after its id, version, and any chip, language, or product architecture, eg.
+//! `Win10SDK_10.0.22621,version=10.0.22621.7`, which contains the payloads
+//! of the package at their `fileName`.
+
+use crate::{manifest, util::Sha256, Path, PathBuf};
+use anyhow::{Context as _, Error};
+use std::collections::BTreeMap;
+
+/// The package as it appears in `Catalog.json`, with only the fields needed
+/// to locate its directory in the layout
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CatalogItem {
+    id: String,
+    version: String,
+    chip: Option<String>,
+    language: Option<String>,
+    product_arch: Option<String>,
+    #[serde(default)]
+    payloads: Vec<CatalogPayload>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CatalogPayload {
+    file_name: String,
+    url: String,
+}
+
+#[derive(serde::Deserialize)]
+struct Catalog {
+    packages: Vec<CatalogItem>,
+}
+
+/// The directory of a package in the layout, eg.
+/// `Microsoft.VC.14.29.16.10.CRT.x64.Desktop,version=14.29.30136,chip=x64`
+struct PackageDir {
+    name: String,
+    /// The lowercased attributes following the id, eg. `version` and `chip`
+    attrs: BTreeMap<String, String>,
+}
+
+impl PackageDir {
+    fn parse(name: &str) -> Option<(String, Self)> {
+        let mut parts = name.split(',');
+        let id = parts.next()?.to_lowercase();
+
+        let attrs = parts
+            .map(|attr| {
+                let (key, value) = attr.split_once('=')?;
+                Some((key.to_lowercase(), value.to_lowercase()))
+            })
+            .collect::<Option<BTreeMap<_, _>>>()?;
+
+        attrs.contains_key("version").then(|| {
+            (
+                id,
+                Self {
+                    name: name.to_owned(),
+                    attrs,
+                },
+            )
+        })
+    }
+
+    /// Checks that every attribute of the directory matches the package
+    fn matches(&self, item: &CatalogItem) -> bool {
+        self.attrs.iter().all(|(key, value)| {
+            let expected = match key.as_str() {
+                "version" => Some(&item.version),
+                "chip" => item.chip.as_ref(),
+                "language" => item.language.as_ref(),
+                "productarch" => item.product_arch.as_ref(),
+                _ => return true,
+            };
+
+            expected
+                .filter(|expected| expected.eq_ignore_ascii_case(value))
+                .is_some()
+        })
+    }
+}
+
+/// An offline layout, see the [module docs](self)
+pub struct VsLayout {
+    root: PathBuf,
+    /// The path of each payload in the layout, keyed by its url
+    payloads: BTreeMap<String, PathBuf>,
+}
+
+impl VsLayout {
+    /// The name of the channel manifest in the root of the layout
+    pub const CHANNEL_MANIFEST: &'static str = "ChannelManifest.json";
+    /// The name of the package manifest in the root of the layout
+    pub const CATALOG: &'static str = "Catalog.json";
+
+    /// Reads the catalog of the layout, and locates the directory of each of
+    /// its packages
+    pub fn open(root: &Path) -> Result<Self, Error> {
+        let catalog_path = root.join(Self::CATALOG);
+        let contents = std::fs::read(&catalog_path).with_context(|| {
+            format!("unable to read {}, is {} a VS layout?", catalog_path, root)
+        })?;
+        let catalog: Catalog = serde_json::from_slice(&contents)
+            .with_context(|| format!("unable to deserialize {}", catalog_path))?;
+
+        let mut dirs = BTreeMap::<String, Vec<PackageDir>>::new();
+        for entry in std::fs::read_dir(root).with_context(|| format!("unable to read {}", root))? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            if let Some((id, dir)) = entry.file_name().to_str().and_then(PackageDir::parse) {
+                dirs.entry(id).or_default().push(dir);
+            }
+        }
+
+        let mut payloads = BTreeMap::new();
+        for item in &catalog.packages {
+            let dir = match dirs
+                .get(&item.id.to_lowercase())
+                .and_then(|dirs| dirs.iter().find(|dir| dir.matches(item)))
+            {
+                Some(dir) => dir,
+                None => continue,
+            };
+
+            for payload in &item.payloads {
+                let mut path = root.join(&dir.name);
+                path.extend(payload.file_name.split('\\'));
+                payloads.insert(payload.url.clone(), path);
+            }
+        }
+
+        tracing::debug!(
+            "found {} payloads for {} packages in {}",
+            payloads.len(),
+            catalog.packages.len(),
+            root
+        );
+
+        Ok(Self {
+            root: root.to_owned(),
+            payloads,
+        })
+    }
+
+    /// The root directory of the layout
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The path of a payload in the layout, keyed by its original url. Note
+    /// that the payload might not actually be present, eg. if the layout was
+    /// only partially downloaded.
+    pub fn payload(&self, url: &str) -> Option<&Path> {
+        self.payloads.get(url).map(PathBuf::as_path)
+    }
+
+    /// Reads the channel manifest of the layout, verifying it against the
+    /// pinned checksum, if there is one
+    pub fn channel_manifest(
+        &self,
+        pins: &manifest::Pins,
+    ) -> Result<manifest::Manifest, crate::Error> {
+        manifest::read_manifest(&self.root.join(Self::CHANNEL_MANIFEST), pins)
+    }
+
+    /// Reads the package manifest of the layout, verifying it against the
+    /// pinned checksum, if there is one
+    pub fn package_manifest(
+        &self,
+        pins: &manifest::Pins,
+    ) -> Result<manifest::PackageManifest, crate::Error> {
+        let path = self.root.join(Self::CATALOG);
+        let contents = std::fs::read(&path).with_context(|| format!("unable to read {}", path))?;
+
+        if let Some(expected) = &pins.package_sha256 {
+            let actual = Sha256::digest(&contents);
+            if actual != *expected {
+                return Err(anyhow::Error::from(crate::error::ChecksumMismatch {
+                    expected: expected.clone(),
+                    actual,
+                })
+                .context(format!("{} doesn't match the pinned checksum", path))
+                .into());
+            }
+        }
+
+        Ok(manifest::PackageManifest::parse(&contents)
+            .with_context(|| format!("unable to deserialize {}", path))?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn locates_payloads() {
+        let td = tempfile::TempDir::new().unwrap();
+        let root = PathBuf::from_path_buf(td.path().to_owned()).unwrap();
+
+        let msi = b"not really an msi";
+        let catalog = format!(
+            r#"{{"packages": [
+                {{
+                    "id": "Win10SDK_10.0.22621",
+                    "version": "10.0.22621.7",
+                    "type": "Msi",
+                    "payloads": [{{
+                        "fileName": "Installers\\headers.msi",
+                        "sha256": "{}",
+                        "size": {},
+                        "url": "https://example.com/headers.msi"
+                    }}]
+                }},
+                {{
+                    "id": "Microsoft.VC.14.34.17.4.CRT.x64.Desktop",
+                    "version": "14.34.31933",
+                    "type": "Vsix",
+                    "chip": "x64",
+                    "payloads": [{{
+                        "fileName": "payload.vsix",
+                        "sha256": "{}",
+                        "size": 1,
+                        "url": "https://example.com/x64.vsix"
+                    }}]
+                }},
+                {{
+                    "id": "Missing",
+                    "version": "1.0",
+                    "type": "Vsix",
+                    "payloads": [{{
+                        "fileName": "payload.vsix",
+                        "sha256": "{}",
+                        "size": 1,
+                        "url": "https://example.com/missing.vsix"
+                    }}]
+                }}
+            ]}}"#,
+            Sha256::digest(msi),
+            msi.len(),
+            Sha256([1; 32]),
+            Sha256([2; 32]),
+        );
+        std::fs::write(root.join(VsLayout::CATALOG), &catalog).unwrap();
+
+        let sdk = root.join("Win10SDK_10.0.22621,version=10.0.22621.7/Installers");
+        std::fs::create_dir_all(&sdk).unwrap();
+        std::fs::write(sdk.join("headers.msi"), msi).unwrap();
+        std::fs::create_dir_all(
+            root.join("Microsoft.VC.14.34.17.4.CRT.x64.Desktop,version=14.34.31933,chip=x86"),
+        )
+        .unwrap();
+        std::fs::create_dir_all(
+            root.join("Microsoft.VC.14.34.17.4.CRT.x64.Desktop,version=14.34.31933,chip=x64"),
+        )
+        .unwrap();
+
+        let layout = VsLayout::open(&root).unwrap();
+        assert_eq!(
+            layout.payload("https://example.com/headers.msi"),
+            Some(sdk.join("headers.msi").as_path())
+        );
+        assert_eq!(
+            layout.payload("https://example.com/x64.vsix"),
+            Some(
+                root.join(
+                    "Microsoft.VC.14.34.17.4.CRT.x64.Desktop,version=14.34.31933,chip=x64/payload.vsix"
+                )
+                .as_path()
+            )
+        );
+        assert!(layout.payload("https://example.com/missing.vsix").is_none());
+        assert_eq!(
+            layout
+                .package_manifest(&Default::default())
+                .unwrap()
+                .packages
+                .len(),
+            3
+        );
+
+        // Payloads in the layout are used rather than the network
+        let mut ctx = crate::Ctx::with_temp(crate::progress::Hidden).unwrap();
+        ctx.vs_layout = Some(layout);
+        ctx.no_network = true;
+        let contents = ctx
+            .get_and_validate(
+                "https://example.com/headers.msi",
+                &"headers.msi",
+                Some(Sha256::digest(msi)),
+                &crate::progress::Hidden,
+            )
+            .unwrap();
+        assert_eq!(&contents[..], msi);
+
+        let err = ctx
+            .get_and_validate(
+                "https://example.com/missing.vsix",
+                &"missing.vsix",
+                Some(Sha256([2; 32])),
+                &crate::progress::Hidden,
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), Some(crate::error::ErrorKind::NetworkDisabled));
+    }
+}
@@ -5,18 +5,20 @@ use xwin::PathBuf;
 fn verify_deterministic() {
     let ctx = xwin::Ctx::with_dir(
         PathBuf::from(".xwin-cache/deterministic"),
-        xwin::util::ProgressTarget::Hidden,
+        xwin::progress::Hidden,
     )
     .unwrap();
 
     let ctx = std::sync::Arc::new(ctx);
 
-    let hidden = indicatif::ProgressBar::hidden();
+    let hidden: std::sync::Arc<dyn xwin::ProgressSink> =
+        std::sync::Arc::new(xwin::progress::Hidden);
 
     let manifest_contents = std::fs::read_to_string("tests/deterministic_manifest.json").unwrap();
     let manifest: xwin::manifest::Manifest = serde_json::from_str(&manifest_contents).unwrap();
     let pkg_manifest =
-        xwin::manifest::get_package_manifest(&ctx, &manifest, hidden.clone()).unwrap();
+        xwin::manifest::get_package_manifest(&ctx, &manifest, &Default::default(), hidden.as_ref())
+            .unwrap();
 
     let pruned = xwin::prune_pkg_list(
         &pkg_manifest,
@@ -33,10 +35,19 @@ fn verify_deterministic() {
     let op = xwin::Ops::Splat(xwin::SplatConfig {
         include_debug_libs: false,
         include_debug_symbols: false,
+        include_debug_libs_for: Vec::new(),
+        include_debug_symbols_for: Vec::new(),
         enable_symlinks: true,
         preserve_ms_arch_notation: false,
         copy: true,
         output: output_dir.clone(),
+        lib_symlinks: Vec::new(),
+        strict_includes: false,
+        verify: false,
+        keep_existing: false,
+        patches: None,
+        thin_import_libs: false,
+        symbol_server_layout: false,
     });
 
     let output_dir = PathBuf::from_path_buf(output_dir.canonicalize().unwrap()).unwrap();